@@ -4,19 +4,157 @@ import { readFile, writeFile } from 'node:fs/promises';
 import {
   processImage,
   processImageSync,
+  processImageRaw,
+  processImageEx,
+  processImageFromConfig,
+  splitColorAndMatte,
+  splitColorAndMatteSync,
+  processImageLayers,
+  processImageLayersSync,
+  processImageWeightMaps,
+  processImageWeightMapsSync,
+  separateLayers,
+  separateLayersSync,
+  recolor,
+  recolorSync,
+  processImageTiles,
+  processImageStream,
+  processImageToDataUrl,
+  processImageToDataUrlSync,
+  processImageWithMask,
+  fitSubject,
+  fitSubjectSync,
+  exportFavicon,
+  exportFaviconSync,
+  processWithReference,
+  processWithReferenceSync,
+  subjectAverageColor,
+  subjectAverageColorSync,
   detectBackgroundColor,
+  detectBackgroundColorDebug,
+  detectBackgroundColorRgba,
+  detectBackgroundColors,
+  detectBackgroundUniformity,
+  detectChromaKey,
+  detectSolidColor,
+  assessRemovability,
   parseColor,
   colorToNormalized,
   normalizedToColor,
+  srgbToLinear,
+  linearToSrgb,
+  rgbToHsl,
+  hslToRgb,
+  rgbToHsv,
+  hsvToRgb,
+  rgbToLab,
+  labToRgb,
+  rgbToOklab,
+  oklabToRgb,
+  colorDistance,
   trimImage,
+  quantize,
+  quantizeSync,
+  extractPalette,
   unmixColor,
+  unmixColors,
   computeUnmixResultColor,
   compositeOverBackground,
   getDefaultThreshold,
 } from '../index.js';
 import { readFileSync } from 'node:fs';
+import { inflateSync } from 'node:zlib';
 
 const INPUT_PATH = join(import.meta.dirname, 'assets', 'image.png');
+const PIXEL_1X1_PATH = join(import.meta.dirname, 'assets', 'pixel-1x1.png');
+const PIXEL_2X2_PATH = join(import.meta.dirname, 'assets', 'pixel-2x2.png');
+const PIXEL_TRANSLUCENT_PATH = join(import.meta.dirname, 'assets', 'pixel-translucent.png');
+const SPLIT_4X2_PATH = join(import.meta.dirname, 'assets', 'split-4x2.png');
+const PIXEL_NOISY_PATH = join(import.meta.dirname, 'assets', 'pixel-noisy.png');
+const INDEXED_TRANSPARENT_PATH = join(import.meta.dirname, 'assets', 'indexed-transparent.png');
+const REFERENCE_BACKGROUND_PATH = join(import.meta.dirname, 'assets', 'reference-background-4x2.png');
+const REFERENCE_SUBJECT_PATH = join(import.meta.dirname, 'assets', 'reference-subject-4x2.png');
+const BLOCKY_16X8_PATH = join(import.meta.dirname, 'assets', 'blocky-16x8.png');
+const ANIMATED_16X8_PATH = join(import.meta.dirname, 'assets', 'animated-16x8.gif');
+const APNG_16X8_PATH = join(import.meta.dirname, 'assets', 'animated-16x8.png');
+const ANIMATED_WEBP_16X8_PATH = join(import.meta.dirname, 'assets', 'animated-16x8.webp');
+const ANIMATED_INDEXED_TRANSPARENT_PATH = join(import.meta.dirname, 'assets', 'animated-indexed-transparent-4x4.png');
+const ANIMATED_DISPLAY_P3_ROTATED_PATH = join(import.meta.dirname, 'assets', 'animated-display-p3-rotated-4x2.png');
+const TWO_PAGE_TIFF_PATH = join(import.meta.dirname, 'assets', 'two-page-16x8.tiff');
+const EXIF_ROTATED_4X2_PATH = join(import.meta.dirname, 'assets', 'exif-rotated-4x2.png');
+const DISPLAY_P3_4X2_PATH = join(import.meta.dirname, 'assets', 'display-p3-4x2.png');
+const METADATA_4X2_PATH = join(import.meta.dirname, 'assets', 'metadata-4x2.png');
+const GRAY_8X4_PATH = join(import.meta.dirname, 'assets', 'gray-8x4.png');
+const RENDER_16X8_PATH = join(import.meta.dirname, 'assets', 'render-16x8.exr');
+const LOGO_SVG_PATH = join(import.meta.dirname, 'assets', 'logo-on-white.svg');
+const CMYK_16X8_PATH = join(import.meta.dirname, 'assets', 'cmyk-16x8.jpg');
+const YCCK_16X8_PATH = join(import.meta.dirname, 'assets', 'ycck-16x8.jpg');
+
+function readPngSize(png: Buffer) {
+  return { width: png.readUInt32BE(16), height: png.readUInt32BE(20) };
+}
+
+// Minimal decoder for the 8-bit, non-interlaced PNGs produced by bgone
+function decodePng(png: Buffer) {
+  const { width, height } = readPngSize(png);
+  const channels = { 0: 1, 2: 3, 4: 2, 6: 4 }[png[25]]!;
+  const chunks: Buffer[] = [];
+  let offset = 8;
+  while (offset < png.length) {
+    const length = png.readUInt32BE(offset);
+    const type = png.toString('ascii', offset + 4, offset + 8);
+    if (type === 'IDAT') chunks.push(png.subarray(offset + 8, offset + 8 + length));
+    offset += length + 12;
+  }
+  const raw = inflateSync(Buffer.concat(chunks));
+  const stride = width * channels;
+  const data = Buffer.alloc(stride * height);
+  for (let y = 0; y < height; y++) {
+    const filter = raw[y * (stride + 1)];
+    for (let i = 0; i < stride; i++) {
+      const value = raw[y * (stride + 1) + 1 + i];
+      const a = i >= channels ? data[y * stride + i - channels] : 0;
+      const b = y > 0 ? data[(y - 1) * stride + i] : 0;
+      const c = i >= channels && y > 0 ? data[(y - 1) * stride + i - channels] : 0;
+      const p = a + b - c;
+      const [pa, pb, pc] = [Math.abs(p - a), Math.abs(p - b), Math.abs(p - c)];
+      const paeth = pa <= pb && pa <= pc ? a : pb <= pc ? b : c;
+      const predictor = [0, a, b, (a + b) >> 1, paeth][filter];
+      data[y * stride + i] = (value + predictor) & 0xff;
+    }
+  }
+  return { width, height, channels, data };
+}
+
+// List the chunk types of a PNG in order
+function readChunkTypes(png: Buffer) {
+  const types = [];
+  let offset = 8;
+  while (offset < png.length) {
+    types.push(png.toString('ascii', offset + 4, offset + 8));
+    offset += png.readUInt32BE(offset) + 12;
+  }
+  return types;
+}
+
+// Read the x/y position and unit of a PNG oFFs chunk, if present
+function readOffsChunk(png: Buffer) {
+  let offset = 8;
+  while (offset < png.length) {
+    const length = png.readUInt32BE(offset);
+    if (png.toString('ascii', offset + 4, offset + 8) === 'oFFs') {
+      const data = png.subarray(offset + 8, offset + 8 + length);
+      return { x: data.readInt32BE(0), y: data.readInt32BE(4), unit: data[8] };
+    }
+    offset += length + 12;
+  }
+  return null;
+}
+
+function readFirstPixel(png: Buffer) {
+  const { channels, data } = decodePng(png);
+  return Array.from(data.subarray(0, channels));
+}
 
 // ============================================================================
 // processImage (async)
@@ -118,6 +256,483 @@ test('processImage - with auto foreground color deduction', async (t) => {
   t.true(output.length > 0);
 });
 
+test('processImage - with uniform deduction weighting', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  const output = await processImage({
+    input: inputBuffer,
+    foregroundColors: ['auto'],
+    strictMode: true,
+    trim: false,
+    deductionWeighting: 'uniform',
+  });
+
+  t.true(Buffer.isBuffer(output));
+  t.true(output.length > 0);
+});
+
+test('processImage - with fewer deduction sample colors', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  const output = await processImage({
+    input: inputBuffer,
+    foregroundColors: ['auto', 'auto'],
+    strictMode: true,
+    trim: false,
+    deductionSampleColors: 20,
+  });
+
+  t.true(Buffer.isBuffer(output));
+  t.true(output.length > 0);
+});
+
+test('processImage - with a deduction budget', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  for (const budget of [{ deductionMaxEvaluations: 1 }, { deductionTimeBudgetMs: 0 }]) {
+    const output = await processImage({
+      input: inputBuffer,
+      foregroundColors: ['auto', 'auto'],
+      strictMode: true,
+      trim: false,
+      ...budget,
+    });
+
+    t.true(Buffer.isBuffer(output));
+    t.true(output.length > 0);
+  }
+});
+
+test('processImage - with adaptive background', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  const output = await processImage({
+    input: inputBuffer,
+    strictMode: false,
+    trim: false,
+    adaptiveBackground: true,
+  });
+
+  t.true(Buffer.isBuffer(output));
+  t.true(output.length > 0);
+});
+
+test('processImage - removes pixels within the background tolerance outright', async (t) => {
+  // 8x8 near-white noise (250-255) with a single red pixel
+  const data = Buffer.alloc(8 * 8 * 4, 255);
+  for (let i = 0; i < 64; i++) data.fill(250 + ((i * 7) % 6), i * 4, i * 4 + 3);
+  data.writeUInt32BE(0xff0000ff, 27 * 4);
+  const input = { data, width: 8, height: 8 };
+  const backgroundAlpha = (output: { data: Buffer }) => {
+    let sum = 0;
+    for (let i = 0; i < 64; i++) if (i !== 27) sum += output.data[i * 4 + 3];
+    return sum;
+  };
+
+  t.true(backgroundAlpha(await processImageRaw({ input, backgroundColor: '#ffffff', strictMode: false, trim: false })) > 0);
+  for (const backgroundColor of ['#ffffff~0.04', '#ffffff~4%', '#fffffff5']) {
+    const output = await processImageRaw({ input, backgroundColor, strictMode: false, trim: false });
+    t.is(backgroundAlpha(output), 0);
+    t.is(output.data[27 * 4 + 3], 255);
+  }
+  await t.throwsAsync(processImageRaw({ input, backgroundColor: '#ffffff~10', strictMode: false, trim: false }), {
+    message: /between 0.0 and 1.0/,
+  });
+});
+
+test('processImage - measures color closeness perceptually with deltaE2000', async (t) => {
+  // A dark green pixel is within the RGB threshold of black, but perceptually distinct from it
+  const input = { data: Buffer.from([0, 12, 0, 255, 255, 255, 255, 255]), width: 2, height: 1 };
+  const options = { input, backgroundColor: '#ffffff', foregroundColors: ['#000000'], strictMode: false, trim: false };
+
+  const rgb = await processImageRaw({ ...options, thresholdSpace: 'rgb' });
+  const perceptual = await processImageRaw({ ...options, thresholdSpace: 'deltaE2000' });
+  t.deepEqual([...rgb.data.subarray(0, 4)], [0, 0, 0, 251]);
+  t.deepEqual([...perceptual.data.subarray(0, 4)], [0, 12, 0, 255]);
+  await t.throwsAsync(processImageRaw({ ...options, thresholdSpace: 'lab' }), { message: /Invalid threshold space/ });
+});
+
+test('processImage - accepts thresholds in 8-bit units', async (t) => {
+  // The dark green pixel lies about 9.8 levels off the line between black and white
+  const input = { data: Buffer.from([0, 12, 0, 255, 255, 255, 255, 255]), width: 2, height: 1 };
+  const options = { input, backgroundColor: '#ffffff', foregroundColors: ['#000000'], strictMode: false, trim: false };
+
+  const loose = await processImageRaw({ ...options, threshold: 10, thresholdUnits: '8bit' });
+  const tight = await processImageRaw({ ...options, threshold: 9, thresholdUnits: '8bit' });
+  t.deepEqual([...loose.data.subarray(0, 4)], [0, 0, 0, 251]);
+  t.deepEqual([...tight.data.subarray(0, 4)], [0, 12, 0, 255]);
+  t.deepEqual(tight.data, (await processImageRaw({ ...options, threshold: 9 / 255 })).data);
+  await t.throwsAsync(processImageRaw({ ...options, thresholdUnits: 'percent' }), { message: /Invalid threshold units/ });
+  await t.throwsAsync(processImageRaw({ ...options, threshold: 9, thresholdUnits: '8bit', thresholdSpace: 'deltaE2000' }));
+});
+
+test('processImage - unmixes in OKLab', async (t) => {
+  // The OKLab midpoint of red and blue, next to the blue background
+  const input = { data: Buffer.from([140, 83, 162, 255, 0, 0, 255, 255]), width: 2, height: 1 };
+  const options = { input, backgroundColor: '#0000ff', foregroundColors: ['#ff0000'], strictMode: true, trim: false };
+
+  const srgb = await processImageRaw({ ...options, unmixSpace: 'srgb' });
+  const oklab = await processImageRaw({ ...options, unmixSpace: 'oklab' });
+  t.deepEqual([...srgb.data.subarray(0, 4)], [255, 0, 0, 117]);
+  t.deepEqual([...oklab.data.subarray(0, 8)], [255, 0, 0, 127, 0, 0, 0, 0]);
+  await t.throwsAsync(processImageRaw({ ...options, unmixSpace: 'lab' }), { message: /Invalid unmix space/ });
+});
+
+test('processImage - unmixes in linear light', async (t) => {
+  // #bcbcbc is a 50% blend of black over white in linear light
+  const input = { data: Buffer.from([188, 188, 188, 255, 255, 255, 255, 255]), width: 2, height: 1 };
+  const options = { input, backgroundColor: '#ffffff', foregroundColors: ['#000000'], strictMode: true, trim: false };
+
+  t.is((await processImageRaw(options)).data[3], 67);
+  t.is((await processImageRaw({ ...options, linearLight: true })).data[3], 127);
+  await t.throwsAsync(processImageRaw({ ...options, linearLight: true, unmixSpace: 'oklab' }), {
+    message: /cannot be combined/,
+  });
+});
+
+test('processImage - unmixes additive and screen blends over black', async (t) => {
+  // Pure red added at half strength onto a dark gray background
+  const input = { data: Buffer.from([192, 64, 64, 255]), width: 1, height: 1 };
+  const options = { input, backgroundColor: '#404040', strictMode: false, trim: false };
+
+  t.deepEqual(Array.from((await processImageRaw({ ...options, blendMode: 'add' })).data), [255, 0, 0, 128]);
+  // Screening red at 0.4 onto the same background gives 1 - (1 - 64 / 255) * 0.6, about 140 in red
+  const screened = { ...options, input: { ...input, data: Buffer.from([140, 64, 64, 255]) } };
+  t.deepEqual(Array.from((await processImageRaw({ ...screened, blendMode: 'screen' })).data), [255, 0, 0, 101]);
+  await t.throwsAsync(processImageRaw({ ...options, blendMode: 'overlay' }), { message: /Invalid blend mode/ });
+  await t.throwsAsync(processImageRaw({ ...options, blendMode: 'add', unmixSpace: 'oklab' }), {
+    message: /cannot be combined/,
+  });
+});
+
+test('processImage - unmixes multiplied ink over white, with its density as alpha', async (t) => {
+  // Blue ink at densities 1, 0.5 and 0.25 multiplied onto yellowish paper
+  const input = { data: Buffer.from([30, 52, 96, 255, 135, 130, 112, 255, 188, 169, 120, 255]), width: 3, height: 1 };
+  const options = { input, backgroundColor: '#f0d080', foregroundColors: ['#2040c0'], strictMode: true, trim: false };
+
+  const output = await processImageRaw({ ...options, blendMode: 'multiply' });
+  t.deepEqual(Array.from(output.data), [32, 64, 192, 255, 32, 64, 192, 128, 32, 64, 192, 64]);
+  // Alpha-over unmixing leaves even undiluted ink translucent
+  t.is((await processImageRaw(options)).data[3], 240);
+});
+
+test('processImage - unmixes each pixel against the nearest of several background colors', async (t) => {
+  // 16x8 white on the left, #e0e0e0 on the right, with a red 4x4 square across the seam
+  const isSubject = (x: number, y: number) => x >= 6 && x < 10 && y >= 2 && y < 6;
+  const data = Buffer.alloc(16 * 8 * 4, 255);
+  for (let y = 0; y < 8; y++) {
+    for (let x = 0; x < 16; x++) {
+      const i = (y * 16 + x) * 4;
+      if (isSubject(x, y)) data.writeUInt32BE(0xff0000ff, i);
+      else if (x >= 8) data.fill(224, i, i + 3);
+    }
+  }
+  const input = { data, width: 16, height: 8 };
+
+  const single = await processImageRaw({ input, backgroundColor: '#ffffff', strictMode: false, trim: false });
+  t.is(single.data[(0 * 16 + 12) * 4 + 3], 31);
+
+  const output = await processImageRaw({ input, backgroundColor: ['#ffffff', '#e0e0e0'], strictMode: false, trim: false });
+  for (let y = 0; y < 8; y++) {
+    for (let x = 0; x < 16; x++) {
+      t.is(output.data[(y * 16 + x) * 4 + 3], isSubject(x, y) ? 255 : 0);
+    }
+  }
+  await t.throwsAsync(processImageRaw({ input, backgroundColor: [], strictMode: false, trim: false }), {
+    message: /must not be empty/,
+  });
+});
+
+test('processImage - removes a gradient background', async (t) => {
+  // 32x16 gray sweep from 160 to 255 with a red square in the middle
+  const data = Buffer.alloc(32 * 16 * 4, 255);
+  for (let y = 0; y < 16; y++) {
+    for (let x = 0; x < 32; x++) {
+      const i = (y * 32 + x) * 4;
+      const subject = x >= 12 && x < 20 && y >= 4 && y < 12;
+      data.fill(subject ? 0 : Math.round(160 + (95 * x) / 31), i, i + 3);
+      if (subject) data[i] = 255;
+    }
+  }
+  const output = await processImageRaw({ input: { data, width: 32, height: 16 }, strictMode: false, trim: false, gradientBackground: true });
+
+  for (let y = 0; y < 16; y++) {
+    for (let x = 0; x < 32; x++) {
+      const subject = x >= 12 && x < 20 && y >= 4 && y < 12;
+      t.is(output.data[(y * 32 + x) * 4 + 3], subject ? 255 : 0);
+    }
+  }
+});
+
+test('processImage - throws when gradient background is combined with adaptive background', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  await t.throwsAsync(
+    processImage({ input: inputBuffer, strictMode: false, trim: false, gradientBackground: true, adaptiveBackground: true }),
+    { message: /cannot be combined/ },
+  );
+});
+
+test('processImage - unmixes each pixel against a background plate', async (t) => {
+  // 8x8 checkerboard backdrop with a red 2x2 subject in the middle
+  const plate = Buffer.alloc(8 * 8 * 4, 255);
+  for (let i = 0; i < 64; i++) plate.fill(((i % 8) + Math.floor(i / 8)) % 2 ? 40 : 220, i * 4, i * 4 + 3);
+  const data = Buffer.from(plate);
+  for (const i of [27, 28, 35, 36]) data.writeUInt32BE(0xff0000ff, i * 4);
+
+  const output = await processImageRaw({
+    input: { data, width: 8, height: 8 },
+    backgroundPlate: { data: plate, width: 8, height: 8 },
+    strictMode: false,
+    trim: false,
+  });
+
+  for (let i = 0; i < 64; i++) {
+    t.is(output.data[i * 4 + 3], [27, 28, 35, 36].includes(i) ? 255 : 0);
+  }
+  await t.throwsAsync(
+    processImageRaw({ input: { data, width: 8, height: 8 }, backgroundPlate: { data: plate, width: 4, height: 16 }, strictMode: false, trim: false }),
+    { message: /same size/ },
+  );
+});
+
+test('processImage - follows vignetting with a tiled background', async (t) => {
+  // 64x64 gray falling off from 240 in the center to 200 in the corners, with a red square
+  const isSubject = (x: number, y: number) => x >= 24 && x < 40 && y >= 24 && y < 40;
+  const data = Buffer.alloc(64 * 64 * 4, 255);
+  for (let y = 0; y < 64; y++) {
+    for (let x = 0; x < 64; x++) {
+      const i = (y * 64 + x) * 4;
+      const r = Math.hypot(x - 31.5, y - 31.5) / 45;
+      if (isSubject(x, y)) data.writeUInt32BE(0xdc0000ff, i);
+      else data.fill(Math.round(240 - 40 * r * r), i, i + 3);
+    }
+  }
+  const input = { data, width: 64, height: 64 };
+  const maxBackgroundAlpha = (output: { data: Buffer }) => {
+    let max = 0;
+    for (let i = 0; i < 64 * 64; i++) {
+      if (!isSubject(i % 64, Math.floor(i / 64))) max = Math.max(max, output.data[i * 4 + 3]);
+    }
+    return max;
+  };
+
+  const solid = await processImageRaw({ input, strictMode: false, trim: false });
+  const tiled = await processImageRaw({ input, strictMode: false, trim: false, tiledBackground: true, backgroundTileSize: 8 });
+
+  t.true(maxBackgroundAlpha(solid) > 128);
+  t.true(maxBackgroundAlpha(tiled) < 64);
+  t.is(tiled.data[(32 * 64 + 32) * 4 + 3], 255);
+  await t.throwsAsync(
+    processImageRaw({ input, strictMode: false, trim: false, tiledBackground: true, backgroundTileSize: 0 }),
+    { message: /at least 1/ },
+  );
+});
+
+test('processImage - preserves shadows cast on the background as translucent black', async (t) => {
+  // 16x16 beige with a red block and a shadow darkening the beige to 70% below it
+  const data = Buffer.alloc(16 * 16 * 4, 255);
+  for (let y = 0; y < 16; y++) {
+    for (let x = 0; x < 16; x++) {
+      const i = (y * 16 + x) * 4;
+      if (x >= 4 && x < 12 && y >= 4 && y < 10) data.writeUInt32BE(0xdc0000ff, i);
+      else if (x >= 4 && x < 12 && y >= 10 && y < 12) data.writeUInt32BE(0xa89a85ff, i);
+      else data.writeUInt32BE(0xf0dcbeff, i);
+    }
+  }
+  const options = { input: { data, width: 16, height: 16 }, foregroundColors: ['#dc0000'], strictMode: true, trim: false };
+  const shadow = (output: { data: Buffer }) => [...output.data.subarray((10 * 16 + 8) * 4, (10 * 16 + 8) * 4 + 4)];
+
+  t.deepEqual(shadow(await processImageRaw(options)), [220, 0, 0, 80]);
+  const preserved = await processImageRaw({ ...options, preserveShadows: true });
+  t.deepEqual(shadow(preserved), [0, 0, 0, 77]);
+  t.is(preserved.data[(6 * 16 + 8) * 4 + 3], 255);
+  t.is(preserved.data[3], 0);
+});
+
+test('processImage - strips a solid frame before detecting the background', async (t) => {
+  // 20x20 gray with a 3 pixel white frame and a red 4x4 square in the middle
+  const framed = (frame: boolean) => {
+    const data = Buffer.alloc(20 * 20 * 4, 255);
+    for (let y = 0; y < 20; y++) {
+      for (let x = 0; x < 20; x++) {
+        const i = (y * 20 + x) * 4;
+        if (x >= 8 && x < 12 && y >= 8 && y < 12) data.writeUInt32BE(0xdc0000ff, i);
+        else if (frame && x >= 3 && y >= 3 && x < 17 && y < 17) data.fill(128, i, i + 3);
+      }
+    }
+    return { data, width: 20, height: 20 };
+  };
+
+  const plain = await processImageRaw({ input: framed(true), strictMode: false, trim: false });
+  t.true(plain.data[(5 * 20 + 5) * 4 + 3] > 0);
+
+  const stripped = await processImageRaw({ input: framed(true), strictMode: false, trim: false, stripFrame: true });
+  t.is(stripped.width, 14);
+  t.is(stripped.height, 14);
+  t.is(stripped.data[3], 0);
+  t.is(stripped.data[(7 * 14 + 7) * 4 + 3], 255);
+
+  const ex = await processImageEx({ input: framed(true), strictMode: false, trim: true, stripFrame: true });
+  t.deepEqual(ex.frame, { top: 3, right: 3, bottom: 3, left: 3 });
+  t.is(ex.width, 4);
+
+  // A plain white background running up to the subject is not a frame
+  const unframed = await processImageEx({ input: framed(false), strictMode: false, trim: false, stripFrame: true });
+  t.is(unframed.width, 20);
+  t.is(unframed.frame, undefined);
+});
+
+test('processImage - removes a checkerboard transparency grid', async (t) => {
+  // 24x16 grid of 4 pixel gray and white tiles, shifted by (2, 1), with a red 6x6 square
+  const isSubject = (x: number, y: number) => x >= 9 && x < 15 && y >= 5 && y < 11;
+  const data = Buffer.alloc(24 * 16 * 4, 255);
+  for (let y = 0; y < 16; y++) {
+    for (let x = 0; x < 24; x++) {
+      const i = (y * 24 + x) * 4;
+      if (isSubject(x, y)) data.writeUInt32BE(0xff0000ff, i);
+      else if ((Math.floor((x + 2) / 4) + Math.floor((y + 1) / 4)) % 2) data.fill(204, i, i + 3);
+    }
+  }
+  const input = { data, width: 24, height: 16 };
+
+  const output = await processImageRaw({ input, strictMode: false, trim: false, checkerboardBackground: true });
+  for (let y = 0; y < 16; y++) {
+    for (let x = 0; x < 24; x++) {
+      t.is(output.data[(y * 24 + x) * 4 + 3], isSubject(x, y) ? 255 : 0);
+    }
+  }
+  await t.throwsAsync(
+    processImageRaw({ input: readFileSync(SPLIT_4X2_PATH), strictMode: false, trim: false, checkerboardBackground: true, backgroundColor: '#ffffff' }),
+    { message: /cannot be combined/ },
+  );
+});
+
+test('processImage - connected removal keeps enclosed background-colored areas', async (t) => {
+  // 7x7 white with a black square outline around a white 3x3 interior
+  const data = Buffer.alloc(7 * 7 * 4, 255);
+  for (let y = 1; y < 6; y++) {
+    for (let x = 1; x < 6; x++) {
+      if (x === 1 || x === 5 || y === 1 || y === 5) data.writeUInt32BE(0x000000ff, (y * 7 + x) * 4);
+    }
+  }
+  const input = { data, width: 7, height: 7 };
+  const center = (3 * 7 + 3) * 4;
+
+  const removed = await processImageRaw({ input, strictMode: false, trim: false });
+  t.is(removed.data[center + 3], 0);
+
+  const kept = await processImageRaw({ input, strictMode: false, trim: false, connectedRemoval: true });
+  t.deepEqual([...kept.data.subarray(center, center + 4)], [255, 255, 255, 255]);
+  t.is(kept.data[3], 0);
+});
+
+test('processImage - protects holes enclosed by opaque content', async (t) => {
+  // 7x7 white with a black square outline around a near-white 3x3 interior
+  const data = Buffer.alloc(7 * 7 * 4, 255);
+  for (let y = 1; y < 6; y++) {
+    for (let x = 1; x < 6; x++) {
+      if (x === 1 || x === 5 || y === 1 || y === 5) data.writeUInt32BE(0x000000ff, (y * 7 + x) * 4);
+    }
+  }
+  data.writeUInt32BE(0xf0f0f0ff, (3 * 7 + 3) * 4);
+  const input = { data, width: 7, height: 7 };
+
+  const output = await processImageRaw({ input, strictMode: false, trim: false, protectHoles: true });
+  for (let y = 0; y < 7; y++) {
+    for (let x = 0; x < 7; x++) {
+      t.is(output.data[(y * 7 + x) * 4 + 3], x > 0 && x < 6 && y > 0 && y < 6 ? 255 : 0);
+    }
+  }
+  t.deepEqual([...output.data.subarray((3 * 7 + 3) * 4, (3 * 7 + 3) * 4 + 4)], [240, 240, 240, 255]);
+  await t.throwsAsync(processImageTiles({ input, strictMode: false, trim: false, protectHoles: true }, () => {}), {
+    message: /not supported with tiled processing/,
+  });
+});
+
+test('processImageTiles - reports every tile and resolves to the full image', async (t) => {
+  const input = readFileSync(PIXEL_2X2_PATH);
+  const tiles: any[] = [];
+  const output = await processImageTiles(
+    { input, strictMode: false, trim: false },
+    (err, tile) => {
+      if (!err) tiles.push(tile);
+    },
+    1,
+    true,
+  );
+
+  // Tile callbacks and the promise resolution are delivered independently
+  for (let i = 0; i < 100 && tiles.length < 4; i++) {
+    await new Promise((resolve) => setImmediate(resolve));
+  }
+
+  const expected = processImageSync({ input, strictMode: false, trim: false });
+  t.deepEqual(decodePng(output!).data, decodePng(expected).data);
+  t.is(tiles.length, 4);
+  for (const tile of tiles) {
+    t.is(tile.width, 1);
+    t.is(tile.height, 1);
+    const offset = (tile.y * 2 + tile.x) * 4;
+    t.deepEqual(readFirstPixel(tile.data), Array.from(decodePng(expected).data.subarray(offset, offset + 4)));
+  }
+});
+
+test('processImageTiles - waits for a slow callback instead of queueing every tile', async (t) => {
+  const size = 48;
+  const data = Buffer.alloc(size * size * 4, 255);
+  for (let i = 0; i < data.length; i += 8) data[i] = 0;
+  let received = 0;
+  await processImageTiles(
+    { input: { data, width: size, height: size }, backgroundColor: '#ffffff', strictMode: false, trim: false },
+    (err) => {
+      if (err) return;
+      received++;
+      const start = performance.now();
+      while (performance.now() - start < 0.2);
+    },
+    1,
+  );
+
+  // Processing can only finish once no more than a few tiles are left waiting for the callback
+  t.true(received >= size * size - 16);
+});
+
+test('processImageTiles - resolves to null unless the full image is asked for', async (t) => {
+  const input = readFileSync(PIXEL_2X2_PATH);
+  let received = 0;
+  const output = await processImageTiles({ input, strictMode: false, trim: false }, () => received++, 1);
+
+  t.is(output, null);
+  for (let i = 0; i < 100 && received < 4; i++) {
+    await new Promise((resolve) => setImmediate(resolve));
+  }
+  t.is(received, 4);
+});
+
+test('processImageTiles - encodes tiles in the output format and bit depth', async (t) => {
+  const input = readFileSync(PIXEL_2X2_PATH);
+  const tiles: any[] = [];
+  for (const output of [{ outputFormat: 'webp' }, { outputFormat: 'raw', outputBitDepth: 16 }]) {
+    await processImageTiles(
+      { input, strictMode: false, trim: false, ...output },
+      (err, tile) => {
+        if (!err) tiles.push(tile);
+      },
+      1,
+    );
+  }
+  for (let i = 0; i < 100 && tiles.length < 8; i++) {
+    await new Promise((resolve) => setImmediate(resolve));
+  }
+
+  t.is(tiles.length, 8);
+  t.is(tiles.filter((tile) => tile.data.toString('ascii', 8, 12) === 'WEBP').length, 4);
+  // One 16-bit RGBA pixel per tile
+  t.is(tiles.filter((tile) => tile.data.length === 8).length, 4);
+  const full = await processImageTiles({ input, strictMode: false, trim: false, outputBitDepth: 16 }, () => {}, 1, true);
+  t.deepEqual(full, processImageSync({ input, strictMode: false, trim: false, outputBitDepth: 16 }));
+});
+
+test('processImageTiles - throws on zero tile size', (t) => {
+  t.throws(() => processImageTiles({ input: readFileSync(PIXEL_2X2_PATH), strictMode: false, trim: false }, () => {}, 0));
+});
+
 // ============================================================================
 // processImageSync
 // ============================================================================
@@ -143,8 +758,1257 @@ test('processImageSync - with explicit background color', (t) => {
     trim: false,
   });
 
-  t.true(Buffer.isBuffer(output));
-  t.true(output.length > 0);
+  t.true(Buffer.isBuffer(output));
+  t.true(output.length > 0);
+});
+
+test('processImageSync - premultiplied input alpha avoids dark edges', (t) => {
+  const input = readFileSync(PIXEL_TRANSLUCENT_PATH);
+  const straight = processImageSync({
+    input,
+    backgroundColor: '#ffffff',
+    strictMode: false,
+    trim: false,
+  });
+  const premultiplied = processImageSync({
+    input,
+    backgroundColor: '#ffffff',
+    strictMode: false,
+    trim: false,
+    inputAlphaMode: 'premultiplied',
+  });
+
+  t.deepEqual(readFirstPixel(premultiplied), [255, 0, 0, 128]);
+  t.true(readFirstPixel(straight)[0] < 255);
+});
+
+test('processImageSync - throws on invalid input alpha mode', (t) => {
+  t.throws(() =>
+    processImageSync({
+      input: readFileSync(PIXEL_TRANSLUCENT_PATH),
+      strictMode: false,
+      trim: false,
+      inputAlphaMode: 'associated',
+    }),
+  );
+});
+
+test('processImageSync - existing alpha can be combined with the computed alpha', (t) => {
+  const input = readFileSync(PIXEL_TRANSLUCENT_PATH);
+  const options = { input, backgroundColor: '#ffffff', strictMode: false, trim: false };
+
+  t.is(readFirstPixel(processImageSync({ ...options, existingAlpha: 'multiply' }))[3], 128);
+  t.is(readFirstPixel(processImageSync({ ...options, existingAlpha: 'ignore' }))[3], 255);
+});
+
+test('processImageSync - throws on invalid existing alpha', (t) => {
+  t.throws(() =>
+    processImageSync({
+      input: readFileSync(PIXEL_TRANSLUCENT_PATH),
+      strictMode: false,
+      trim: false,
+      existingAlpha: 'max',
+    }),
+  );
+});
+
+test('processImageSync - background luminance bias picks the lightest edge color', (t) => {
+  const output = processImageSync({
+    input: readFileSync(SPLIT_4X2_PATH),
+    backgroundLuminanceBias: 'lightest',
+    strictMode: false,
+    trim: true,
+  });
+
+  t.deepEqual(readPngSize(output), { width: 2, height: 2 });
+  t.deepEqual(readFirstPixel(output), [0, 0, 0, 255]);
+});
+
+test('processImageSync - embeds the crop offset of a trimmed image', (t) => {
+  const output = processImageSync({
+    input: readFileSync(SPLIT_4X2_PATH),
+    backgroundLuminanceBias: 'darkest',
+    strictMode: false,
+    trim: true,
+    embedCropOffset: true,
+  });
+
+  t.deepEqual(readPngSize(output), { width: 2, height: 2 });
+  t.deepEqual(readFirstPixel(output), [255, 255, 255, 255]);
+  t.deepEqual(readOffsChunk(output), { x: 2, y: 0, unit: 0 });
+});
+
+test('processImageSync - crop offset is only embedded when trimming', (t) => {
+  const options = {
+    input: readFileSync(SPLIT_4X2_PATH),
+    backgroundLuminanceBias: 'darkest',
+    strictMode: false,
+  };
+
+  t.is(readOffsChunk(processImageSync({ ...options, trim: false, embedCropOffset: true })), null);
+  t.is(readOffsChunk(processImageSync({ ...options, trim: true })), null);
+});
+
+test('processImageSync - encodes lossless WebP output', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: true };
+  const png = processImageSync(options);
+  const webp = processImageSync({ ...options, outputFormat: 'webp' });
+
+  t.is(webp.toString('ascii', 0, 4), 'RIFF');
+  t.is(webp.toString('ascii', 8, 12), 'WEBP');
+  // Decoding the WebP (and re-encoding as PNG) gives back exactly the PNG result
+  t.deepEqual(decodePng(trimImage(webp)).data, decodePng(png).data);
+});
+
+test('processImageSync - encodes lossy WebP output with exact alpha', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: true, outputFormat: 'webp' };
+  const exact = decodePng(trimImage(processImageSync(options)));
+  const webp = processImageSync({ ...options, webpLossless: false, webpQuality: 1 });
+  const lossy = decodePng(trimImage(webp));
+  let alphaDiffers = false;
+  let colorDiffers = false;
+  for (let i = 0; i < exact.data.length; i += 4) {
+    if (lossy.data[i + 3] !== exact.data[i + 3]) alphaDiffers = true;
+    if (lossy.data[i] !== exact.data[i] && exact.data[i + 3] > 0) colorDiffers = true;
+  }
+
+  // A VP8 (lossy) bitstream with a separate alpha chunk, rather than VP8L (lossless)
+  t.true(webp.includes('VP8 '));
+  t.true(webp.includes('ALPH'));
+  t.false(webp.includes('VP8L'));
+  t.is(lossy.width, exact.width);
+  t.false(alphaDiffers);
+  t.true(colorDiffers);
+  t.throws(() => processImageSync({ ...options, webpLossless: false, webpQuality: 0 }), { message: /WebP quality/ });
+  t.throws(() => processImageSync({ ...options, webpQuality: 101 }), { message: /WebP quality/ });
+});
+
+test('processImageSync - encodes lossy animated WebP frames', (t) => {
+  const options = { input: readFileSync(ANIMATED_WEBP_16X8_PATH), strictMode: false, trim: false };
+  const webp = processImageSync({ ...options, webpLossless: false });
+  const frames = [];
+  for (let offset = 12; offset < webp.length; offset += 8 + webp.readUInt32LE(offset + 4) + (webp.readUInt32LE(offset + 4) % 2)) {
+    if (webp.toString('ascii', offset, offset + 4) === 'ANMF') frames.push(webp.toString('ascii', offset + 24, offset + 28));
+  }
+
+  // Each frame holds its alpha and VP8 chunks directly, without a still image's VP8X header
+  t.true(frames.length > 1);
+  t.true(frames.every((chunk) => chunk === 'ALPH'));
+  t.false(webp.includes('VP8L'));
+});
+
+test('processImageSync - encodes AVIF output with alpha', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: true, outputFormat: 'avif', avifSpeed: 10 };
+  const avif = processImageSync(options);
+
+  t.is(avif.toString('ascii', 4, 8), 'ftyp');
+  t.is(avif.toString('ascii', 8, 12), 'avif');
+  t.true(avif.includes('auxC'));
+  t.true(processImageSync({ ...options, avifQuality: 1 }).length < avif.length);
+  t.throws(() => processImageSync({ ...options, avifQuality: 0 }));
+  t.throws(() => processImageSync({ ...options, avifSpeed: 11 }));
+});
+
+test('processImageSync - flattens JPEG output over the matte color', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: false, outputFormat: 'jpeg' };
+  const white = processImageSync(options);
+  const black = processImageSync({ ...options, jpegMatteColor: '#000000' });
+
+  t.deepEqual([...white.subarray(0, 3)], [0xff, 0xd8, 0xff]);
+  t.notDeepEqual(black, white);
+  t.true(processImageSync({ ...options, jpegQuality: 10 }).length < white.length);
+  t.throws(() => processImageSync({ ...options, jpegQuality: 0 }));
+  t.throws(() => processImageSync({ ...options, jpegMatteColor: 'nope' }));
+});
+
+test('processImageSync - returns raw RGBA bytes', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: false };
+  const png = decodePng(processImageSync(options));
+  const raw = processImageSync({ ...options, outputFormat: 'raw' });
+
+  t.is(raw.length, png.width * png.height * 4);
+  t.deepEqual(raw, png.data);
+});
+
+test('processImageSync - encodes 16-bit PNG and TIFF output', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: false, outputBitDepth: 16 };
+  const png = processImageSync(options);
+  const tiff = processImageSync({ ...options, outputFormat: 'tiff' });
+  const raw = processImageSync({ ...options, outputFormat: 'raw' });
+  const { width, height } = readPngSize(png);
+
+  // IHDR bit depth and color type (6 = RGBA)
+  t.is(png[24], 16);
+  t.is(png[25], 6);
+  t.true(['II*\0', 'MM\0*'].includes(tiff.toString('latin1', 0, 4)));
+  t.is(raw.length, width * height * 8);
+  t.throws(() => processImageSync({ ...options, outputBitDepth: 12 }));
+  t.throws(() => processImageSync({ ...options, outputFormat: 'webp' }));
+  t.throws(() => processImageSync({ ...options, outline: { color: '#000000', width: 2 } }));
+});
+
+test('processImageSync - keeps animated GIFs animated', (t) => {
+  const options = { input: readFileSync(ANIMATED_16X8_PATH), strictMode: false, trim: true };
+  const gif = processImageSync(options);
+  // Delays (in 1/100 s) of the graphic control extensions, one per frame
+  const delays: number[] = [];
+  for (let i = gif.indexOf(Buffer.from([0x21, 0xf9, 0x04])); i >= 0; i = gif.indexOf(Buffer.from([0x21, 0xf9, 0x04]), i + 1)) {
+    delays.push(gif.readUInt16LE(i + 4));
+  }
+
+  t.is(gif.toString('ascii', 0, 6), 'GIF89a');
+  t.true(gif.includes('NETSCAPE2.0'));
+  // Trimmed to the union of the moving square's positions
+  t.is(gif.readUInt16LE(6), 12);
+  t.is(gif.readUInt16LE(8), 4);
+  t.deepEqual(delays, [10, 20, 5]);
+  t.is(processImageSync({ ...options, outputFormat: 'png' }).toString('ascii', 1, 4), 'PNG');
+});
+
+test('processImageSync - keeps APNG frame timing', (t) => {
+  const options = { input: readFileSync(APNG_16X8_PATH), strictMode: false, trim: true };
+  const apng = processImageSync(options);
+  // Delays (numerator / denominator seconds) of the frame control chunks, one per frame
+  const delays: number[] = [];
+  for (let offset = 8; offset < apng.length; offset += apng.readUInt32BE(offset) + 12) {
+    if (apng.toString('ascii', offset + 4, offset + 8) === 'fcTL') {
+      delays.push((apng.readUInt16BE(offset + 28) * 1000) / apng.readUInt16BE(offset + 30));
+    }
+  }
+
+  t.true(apng.includes('acTL'));
+  t.deepEqual(readPngSize(apng), { width: 12, height: 4 });
+  t.deepEqual(delays, [100, 200, 50]);
+  t.is(processImageSync({ ...options, outputFormat: 'gif' }).toString('ascii', 0, 6), 'GIF89a');
+  t.true(processImageSync({ input: readFileSync(ANIMATED_16X8_PATH), strictMode: false, trim: false, outputFormat: 'apng' }).includes('acTL'));
+});
+
+test('processImageSync - keeps transparent palette pixels of animations transparent', (t) => {
+  const output = processImageSync({ input: readFileSync(ANIMATED_INDEXED_TRANSPARENT_PATH), strictMode: false, trim: true });
+
+  t.true(output.includes('acTL'));
+  t.deepEqual(readPngSize(output), { width: 2, height: 2 });
+  t.deepEqual(readFirstPixel(output), [255, 0, 0, 255]);
+});
+
+test('processImageSync - orients and converts animation frames like still images', (t) => {
+  // Stored as 4x2 with orientation 6, white on the left and Display P3 (0, 200, 0) on the right
+  const options = { input: readFileSync(ANIMATED_DISPLAY_P3_ROTATED_PATH), strictMode: false, trim: false };
+  const converted = processImageSync({ ...options, autoOrient: false });
+  const unconverted = processImageSync({ ...options, autoOrient: false, convertToSrgb: false });
+
+  t.true(converted.includes('acTL'));
+  t.deepEqual(readPngSize(processImageSync(options)), { width: 2, height: 4 });
+  t.deepEqual(readPngSize(converted), { width: 4, height: 2 });
+  t.deepEqual([...decodePng(unconverted).data.subarray(8, 12)], [0, 200, 0, 255]);
+  t.notDeepEqual([...decodePng(converted).data.subarray(8, 12)], [0, 200, 0, 255]);
+});
+
+test('processImageSync - keeps animated WebP frame timing', (t) => {
+  const options = { input: readFileSync(ANIMATED_WEBP_16X8_PATH), strictMode: false, trim: true };
+  const webp = processImageSync(options);
+  // Durations (in ms) of the animation frame chunks, one per frame
+  const durations: number[] = [];
+  for (let offset = 12; offset < webp.length; offset += 8 + webp.readUInt32LE(offset + 4) + (webp.readUInt32LE(offset + 4) % 2)) {
+    if (webp.toString('ascii', offset, offset + 4) === 'ANMF') durations.push(webp.readUIntLE(offset + 20, 3));
+  }
+
+  t.is(webp.toString('ascii', 8, 12), 'WEBP');
+  t.true(webp.includes('ANIM'));
+  // Canvas size minus one, trimmed to the union of the moving square's positions
+  t.is(webp.readUIntLE(24, 3) + 1, 12);
+  t.is(webp.readUIntLE(27, 3) + 1, 4);
+  t.deepEqual(durations, [100, 200, 50]);
+  t.true(processImageSync({ input: readFileSync(ANIMATED_16X8_PATH), strictMode: false, trim: false, outputFormat: 'webp' }).includes('ANMF'));
+});
+
+test('processImageSync - reads the first page of TIFF inputs', (t) => {
+  const options = { input: readFileSync(TWO_PAGE_TIFF_PATH), strictMode: false, trim: true };
+  // The first page holds a 4x4 square; the second is a solid color
+  t.deepEqual(readPngSize(processImageSync(options)), { width: 4, height: 4 });
+
+  const tiff = processImageSync({ ...options, trim: false, outputFormat: 'tiff' });
+  t.deepEqual(readPngSize(trimImage(tiff)), { width: 4, height: 4 });
+  t.is(trimImage(tiff, null, 'tiff').toString('latin1', 0, 4), 'II*\0');
+});
+
+test('processImageSync - honors EXIF orientation unless disabled', (t) => {
+  // Stored as 4x2 with orientation 6 (rotate 90 degrees clockwise)
+  const options = { input: readFileSync(EXIF_ROTATED_4X2_PATH), strictMode: false, trim: false };
+  t.deepEqual(readPngSize(processImageSync(options)), { width: 2, height: 4 });
+  t.deepEqual(readPngSize(processImageSync({ ...options, autoOrient: false })), { width: 4, height: 2 });
+  t.deepEqual(readPngSize(trimImage(readFileSync(EXIF_ROTATED_4X2_PATH))), { width: 2, height: 4 });
+});
+
+test('processImageSync - converts ICC-tagged inputs to sRGB', (t) => {
+  // White on the left, Display P3 (0, 200, 0) on the right
+  const options = { input: readFileSync(DISPLAY_P3_4X2_PATH), strictMode: false, trim: false, outputFormat: 'raw' };
+  const converted = processImageSync(options);
+  const unconverted = processImageSync({ ...options, convertToSrgb: false });
+
+  t.deepEqual([...unconverted.subarray(8, 12)], [0, 200, 0, 255]);
+  t.notDeepEqual([...converted.subarray(8, 12)], [0, 200, 0, 255]);
+  t.true(processImageSync({ ...options, outputFormat: 'png', tagSrgb: true }).includes('sRGB'));
+  t.false(processImageSync({ ...options, outputFormat: 'png' }).includes('sRGB'));
+});
+
+test('processImageSync - maps given colors into the color space of unconverted inputs', (t) => {
+  const options = {
+    input: readFileSync(DISPLAY_P3_4X2_PATH),
+    backgroundColor: '#ffffff',
+    foregroundColors: ['#00cc00'],
+    strictMode: true,
+    trim: false,
+    outputFormat: 'raw',
+  };
+
+  // sRGB (0, 204, 0) is (92, 201, 59) in Display P3
+  t.deepEqual([...processImageSync(options).subarray(8, 12)], [0, 204, 0, 255]);
+  t.deepEqual([...processImageSync({ ...options, convertToSrgb: false }).subarray(8, 12)], [92, 201, 59, 255]);
+});
+
+test('processImageSync - converts CMYK and YCCK JPEGs to RGB', (t) => {
+  // White paper on the left, 100% magenta and yellow on the right: plain CMYK, and Adobe YCCK
+  for (const path of [CMYK_16X8_PATH, YCCK_16X8_PATH]) {
+    const options = { backgroundColor: '#ffffff', strictMode: false, trim: false, outputFormat: 'raw' };
+    const output = processImageSync({ input: readFileSync(path), ...options });
+
+    t.is(output.length, 16 * 8 * 4);
+    t.deepEqual([...output.subarray(0, 4)], [0, 0, 0, 0]);
+    t.deepEqual([...output.subarray(60, 64)], [255, 0, 0, 255]);
+  }
+});
+
+test('processImageSync - unmixes OpenEXR renders in linear light', (t) => {
+  // Linear white with a red block, a 50% red/white mix at its edges and a 3.0 highlight inside
+  const options = {
+    input: readFileSync(RENDER_16X8_PATH),
+    foregroundColors: ['#ff0000'],
+    strictMode: true,
+    trim: false,
+    outputFormat: 'raw',
+  };
+  const output = processImageSync(options);
+  const pixel = (x: number, y: number) => [...output.subarray((y * 16 + x) * 4, (y * 16 + x) * 4 + 4)];
+
+  t.deepEqual(pixel(0, 0), [0, 0, 0, 0]);
+  t.deepEqual(pixel(5, 3), [255, 0, 0, 255]);
+  t.deepEqual(pixel(7, 4), [255, 0, 0, 255]);
+  t.deepEqual(pixel(4, 3).slice(0, 3), [255, 0, 0]);
+  t.true(Math.abs(pixel(4, 3)[3] - 128) <= 1);
+  t.deepEqual(processImageSync({ ...options, hdrToneMapping: 'reinhard' }), output);
+  t.throws(() => processImageSync({ ...options, hdrToneMapping: 'aces' }), { message: /Invalid tone mapping/ });
+});
+
+test('processImageSync - rasterizes SVG inputs', (t) => {
+  // A 40x20 white canvas with a 20x10 red rectangle in the middle
+  const options = { input: readFileSync(LOGO_SVG_PATH), strictMode: false, trim: false };
+
+  t.deepEqual(readPngSize(processImageSync(options)), { width: 40, height: 20 });
+  t.deepEqual(readPngSize(processImageSync({ ...options, trim: true })), { width: 20, height: 10 });
+  t.deepEqual(readPngSize(processImageSync({ ...options, svgDpi: 192 })), { width: 80, height: 40 });
+  t.deepEqual(readPngSize(processImageSync({ ...options, svgWidth: 100 })), { width: 100, height: 50 });
+  t.deepEqual(readPngSize(processImageSync({ ...options, svgWidth: 100, svgHeight: 30 })), { width: 60, height: 30 });
+  t.deepEqual(detectBackgroundColor(options.input), { r: 255, g: 255, b: 255 });
+  t.throws(() => processImageSync({ ...options, svgDpi: 0 }), { message: /SVG DPI must be positive/ });
+});
+
+test('processImageSync - keeps DPI, gamma and text chunks of PNG inputs', (t) => {
+  // White on the left, red on the right, with gAMA, pHYs and tEXt before IDAT and iTXt after
+  const input = readFileSync(METADATA_4X2_PATH);
+  const options = { input, strictMode: false, trim: true };
+  const output = processImageSync(options);
+
+  t.deepEqual(readPngSize(output), { width: 2, height: 2 });
+  t.deepEqual(
+    readChunkTypes(output).filter((type) => ['gAMA', 'pHYs', 'tEXt', 'iTXt'].includes(type)),
+    ['pHYs', 'gAMA', 'tEXt', 'iTXt'],
+  );
+  t.true(output.includes('bgone fixture'));
+  t.true(output.includes('trailing text'));
+  t.true(trimImage(input).includes('pHYs'));
+
+  const stripped = readChunkTypes(processImageSync({ ...options, preserveMetadata: false }));
+  t.false(['gAMA', 'pHYs', 'tEXt', 'iTXt'].some((type) => stripped.includes(type)));
+  t.false(trimImage(input, { preserveMetadata: false }).includes('pHYs'));
+});
+
+test('processImageSync - keeps grayscale inputs grayscale', (t) => {
+  // White with a black and a mid-gray line, stored as 8-bit grayscale
+  const input = readFileSync(GRAY_8X4_PATH);
+  const output = processImageSync({ input, strictMode: false, trim: true });
+  const decoded = decodePng(output);
+
+  t.is(output[25], 4);
+  t.deepEqual({ width: decoded.width, height: decoded.height, channels: decoded.channels }, { width: 6, height: 2, channels: 2 });
+  t.deepEqual([...decoded.data.subarray(0, 2)], [0, 255]);
+  t.is(trimImage(input)[25], 4);
+  t.is(processImageSync({ input, strictMode: false, trim: true, outputBitDepth: 16 })[25], 4);
+  t.is(processImageSync({ input: readFileSync(INPUT_PATH), strictMode: false, trim: true })[25], 6);
+});
+
+test('processImageSync - applies PNG encoder settings', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: false };
+  const balanced = processImageSync(options);
+  const fastest = processImageSync({ ...options, pngCompression: 'fastest', pngFilter: 'none' });
+  const smallest = processImageSync({ ...options, pngCompression: 'smallest' });
+  const interlaced = processImageSync({ ...options, pngInterlaced: true });
+
+  t.deepEqual(decodePng(fastest).data, decodePng(balanced).data);
+  t.true(smallest.length <= fastest.length);
+  // The interlace method is the last byte of the IHDR chunk
+  t.is(balanced[28], 0);
+  t.is(interlaced[28], 1);
+  t.deepEqual(readPngSize(interlaced), readPngSize(balanced));
+  t.throws(() => processImageSync({ ...options, pngCompression: 'max' }));
+  t.throws(() => processImageSync({ ...options, pngFilter: 'median' }));
+});
+
+test('processImageSync - encodes indexed PNG output with tRNS transparency', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: false };
+  const rgba = processImageSync(options);
+  const indexed = processImageSync({ ...options, outputFormat: 'indexed', paletteColors: 16, paletteDither: true });
+
+  // IHDR bit depth and color type (3 = indexed)
+  t.true(indexed[24] <= 4);
+  t.is(indexed[25], 3);
+  t.true(indexed.includes('PLTE'));
+  t.true(indexed.includes('tRNS'));
+  t.deepEqual(readPngSize(indexed), readPngSize(rgba));
+  t.true(indexed.length < rgba.length);
+  t.throws(() => processImageSync({ ...options, outputFormat: 'indexed', paletteColors: 0 }));
+  t.throws(() => processImageSync({ ...options, outputFormat: 'indexed', paletteColors: 257 }));
+});
+
+test('processImageSync - throws on invalid output format', (t) => {
+  t.throws(() =>
+    processImageSync({ input: readFileSync(PIXEL_NOISY_PATH), strictMode: false, trim: false, outputFormat: 'bmp' }),
+  );
+});
+
+test('processImageSync - reconstruction tolerance accepts near-exact opaque solutions', (t) => {
+  const options = {
+    input: readFileSync(PIXEL_NOISY_PATH),
+    foregroundColors: ['#136d54', '#d2e4f4'],
+    backgroundColor: '#ffffff',
+    strictMode: true,
+    trim: false,
+  };
+  const strict = processImageSync(options);
+  const tolerant = processImageSync({ ...options, reconstructionTolerance: 0.05 });
+
+  t.true(readFirstPixel(strict)[3] < 200);
+  t.is(readFirstPixel(tolerant)[3], 255);
+});
+
+test('processImageSync - unmixes opaque blends of three out of four foreground colors', (t) => {
+  // A corner where three flat colors meet, averaged by anti-aliasing
+  const data = Buffer.alloc(4 * 4 * 4, 255);
+  data.set([172, 125, 103], 5 * 4);
+  const output = processImageSync({
+    input: { data, width: 4, height: 4 },
+    foregroundColors: ['#e63946', '#2a9d8f', '#264653', '#f4a261'],
+    backgroundColor: '#ffffff',
+    strictMode: true,
+    trim: false,
+    outputFormat: 'raw',
+  });
+
+  t.deepEqual([...output.subarray(5 * 4, 6 * 4)], [172, 125, 103, 255]);
+});
+
+test('processImageSync - unmix search depth limits how many foreground colors a pixel blends', (t) => {
+  const data = Buffer.alloc(4 * 4 * 4, 255);
+  data.set([172, 125, 103], 5 * 4);
+  const options = {
+    input: { data, width: 4, height: 4 },
+    foregroundColors: ['#e63946', '#2a9d8f', '#264653', '#f4a261'],
+    backgroundColor: '#ffffff',
+    strictMode: true,
+    trim: false,
+    outputFormat: 'raw',
+  };
+  const alphas = (unmixSearchDepth?: number) =>
+    processImageLayersSync({ ...options, unmixSearchDepth }).map((layer) => layer[5 * 4 + 3]);
+
+  // All four colors take a share by default, but three of them reproduce the pixel exactly
+  t.true(alphas().every((alpha) => alpha > 0));
+  t.deepEqual(alphas(3), [86, 85, 0, 84]);
+  t.deepEqual([...processImageSync({ ...options, unmixSearchDepth: 3 }).subarray(5 * 4, 6 * 4)], [172, 125, 103, 255]);
+  // A pair can't, so the best pair is translucent
+  t.is(alphas(2).filter((alpha) => alpha > 0).length, 2);
+  t.true(processImageSync({ ...options, unmixSearchDepth: 2 })[5 * 4 + 3] < 255);
+  t.throws(() => processImageSync({ ...options, unmixSearchDepth: 0 }), { message: /at least 1/ });
+  // 6 of 12 colors would take 924 unmixing solves per pixel
+  const twelve = Array.from({ length: 12 }, (_, i) => `hsl(${i * 30}, 80%, 50%)`);
+  t.throws(() => processImageSync({ ...options, foregroundColors: twelve, unmixSearchDepth: 6 }), {
+    message: /924 combinations/,
+  });
+  t.is(processImageSync({ ...options, foregroundColors: twelve, unmixSearchDepth: 2 }).length, 4 * 4 * 4);
+});
+
+test('processImageSync - throws on negative reconstruction tolerance', (t) => {
+  t.throws(() =>
+    processImageSync({
+      input: readFileSync(PIXEL_NOISY_PATH),
+      strictMode: false,
+      trim: false,
+      reconstructionTolerance: -0.01,
+    }),
+  );
+});
+
+test('processImageSync - background epsilon zeroes out near-background pixels', (t) => {
+  const data = Buffer.alloc(4 * 4);
+  const colors = [[255, 255, 255], [253, 254, 255], [250, 250, 250], [255, 0, 0]];
+  colors.forEach((color, i) => data.set([...color, 255], i * 4));
+  const options = {
+    input: { data, width: 4, height: 1 },
+    backgroundColor: '#ffffff',
+    strictMode: false,
+    trim: false,
+    outputFormat: 'raw',
+  };
+  const exact = processImageSync(options);
+  const tolerant = processImageSync({ ...options, backgroundEpsilon: 3 });
+
+  t.is(exact[7], 2);
+  t.is(tolerant[7], 0);
+  t.is(tolerant[11], exact[11]);
+  t.deepEqual([...tolerant.subarray(12, 16)], [255, 0, 0, 255]);
+  t.throws(() => processImageSync({ ...options, backgroundEpsilon: -1 }));
+});
+
+test('processImageSync - deblocking smooths JPEG block boundaries', (t) => {
+  const options = {
+    input: readFileSync(BLOCKY_16X8_PATH),
+    backgroundColor: '#c8c8c8',
+    strictMode: false,
+    trim: false,
+  };
+  const alphaRow = (png: Buffer) => {
+    const { data } = decodePng(png);
+    return Array.from({ length: 16 }, (_, x) => data[x * 4 + 3]);
+  };
+  const blocky = alphaRow(processImageSync(options));
+  const deblocked = alphaRow(processImageSync({ ...options, deblockEdges: true }));
+
+  // The hard step at the block boundary becomes a ramp
+  t.is(blocky[7], 0);
+  t.true(deblocked[7] > 0);
+  t.true(deblocked[8] < blocky[8]);
+  t.is(deblocked[15], blocky[15]);
+});
+
+test('processImageSync - white balances off-white backgrounds', (t) => {
+  // An off-white (240, 232, 220) background with a (200, 20, 20) square in the middle
+  const data = Buffer.alloc(8 * 8 * 4);
+  for (let i = 0; i < 64; i++) {
+    const inside = [3, 4].includes(i % 8) && [3, 4].includes(Math.floor(i / 8));
+    data.set([...(inside ? [200, 20, 20] : [240, 232, 220]), 255], i * 4);
+  }
+  const options = {
+    input: { data, width: 8, height: 8 },
+    backgroundColor: '#ffffff',
+    strictMode: false,
+    trim: false,
+    outputFormat: 'raw',
+  };
+  const uncorrected = processImageSync(options);
+  const corrected = processImageSync({ ...options, autoWhiteBalance: true });
+
+  // Uncorrected, the cast leaves a translucent veil over the whole background
+  t.deepEqual([...uncorrected.subarray(0, 4)], [146, 87, 0, 35]);
+  t.deepEqual([...corrected.subarray(0, 4)], [0, 0, 0, 0]);
+  t.deepEqual([...corrected.subarray(27 * 4, 28 * 4)], [209, 0, 1, 233]);
+});
+
+test('processImageSync - throws on invalid gamut mapping', (t) => {
+  t.throws(() =>
+    processImageSync({
+      input: readFileSync(PIXEL_NOISY_PATH),
+      strictMode: false,
+      trim: false,
+      gamutMapping: 'wrap',
+    }),
+  );
+});
+
+test('processImageSync - distance alpha model gives a softer matte', (t) => {
+  const options = {
+    input: readFileSync(PIXEL_NOISY_PATH),
+    backgroundColor: '#ffffff',
+    strictMode: false,
+    trim: false,
+  };
+  const minAlpha = readFirstPixel(processImageSync(options));
+  const distance = readFirstPixel(processImageSync({ ...options, alphaModel: 'distance' }));
+  const farDistance = readFirstPixel(
+    processImageSync({ ...options, alphaModel: 'distance', alphaOpaqueDistance: 2 }),
+  );
+
+  t.true(minAlpha[3] < 255);
+  t.deepEqual(distance, [99, 154, 139, 255]);
+  // Alpha never drops below the minimum needed to reconstruct the pixel
+  t.deepEqual(farDistance, minAlpha);
+});
+
+test('processImageSync - throws on invalid alpha model', (t) => {
+  t.throws(() =>
+    processImageSync({
+      input: readFileSync(PIXEL_NOISY_PATH),
+      strictMode: false,
+      trim: false,
+      alphaModel: 'linear',
+    }),
+  );
+  t.throws(() =>
+    processImageSync({
+      input: readFileSync(PIXEL_NOISY_PATH),
+      strictMode: false,
+      trim: false,
+      alphaModel: 'distance',
+      alphaOpaqueDistance: 0,
+    }),
+  );
+});
+
+test('processImageSync - keeps transparent palette pixels transparent', (t) => {
+  const output = processImageSync({
+    input: readFileSync(INDEXED_TRANSPARENT_PATH),
+    strictMode: false,
+    trim: true,
+  });
+
+  t.deepEqual(readPngSize(output), { width: 2, height: 2 });
+  t.deepEqual(readFirstPixel(output), [255, 0, 0, 255]);
+});
+
+test('processImageSync - foreground color tolerance snaps nearby pixels in strict mode', (t) => {
+  const options = {
+    input: readFileSync(PIXEL_NOISY_PATH),
+    backgroundColor: '#ffffff',
+    strictMode: true,
+    trim: false,
+  };
+  const exact = readFirstPixel(processImageSync({ ...options, foregroundColors: ['#5a9688'] }));
+  const tolerant = readFirstPixel(processImageSync({ ...options, foregroundColors: ['#5a9688@0.05'] }));
+
+  t.true(exact[3] < 255);
+  t.deepEqual(tolerant, [90, 150, 136, 255]);
+});
+
+test('processImageSync - accepts RGB objects and arrays as colors', (t) => {
+  const options = { input: readFileSync(PIXEL_NOISY_PATH), strictMode: true, trim: false };
+  const expected = readFirstPixel(
+    processImageSync({ ...options, backgroundColor: '#ffffff', foregroundColors: ['#5a9688'] }),
+  );
+
+  t.deepEqual(
+    readFirstPixel(
+      processImageSync({ ...options, backgroundColor: [255, 255, 255], foregroundColors: [{ r: 90, g: 150, b: 136 }] }),
+    ),
+    expected,
+  );
+  t.deepEqual(
+    readFirstPixel(
+      processImageSync({
+        ...options,
+        backgroundColor: [{ r: 255, g: 255, b: 255 }],
+        foregroundColors: [[90, 150, 136]],
+      }),
+    ),
+    expected,
+  );
+  t.throws(() => processImageSync({ ...options, backgroundColor: [255, 255] }), { message: /3 channels/ });
+});
+
+test('processImageSync - hex alpha sets the color tolerance', (t) => {
+  const options = {
+    input: readFileSync(PIXEL_NOISY_PATH),
+    backgroundColor: '#ffffff',
+    strictMode: true,
+    trim: false,
+  };
+
+  t.deepEqual(readFirstPixel(processImageSync({ ...options, foregroundColors: ['#5a9688f3'] })), [90, 150, 136, 255]);
+  t.throws(() => processImageSync({ ...options, foregroundColors: ['#5a9688f3@0.05'] }), { message: /tolerance/ });
+});
+
+test('processImageSync - throws on invalid foreground color tolerance', (t) => {
+  t.throws(() =>
+    processImageSync({
+      input: readFileSync(PIXEL_NOISY_PATH),
+      foregroundColors: ['#5a9688@wide'],
+      strictMode: true,
+      trim: false,
+    }),
+  );
+});
+
+// ============================================================================
+// processImageRaw
+// ============================================================================
+
+test('processImageRaw - returns the RGBA bytes with their dimensions', async (t) => {
+  const options = { input: await readFile(INPUT_PATH), strictMode: false, trim: true };
+  const png = decodePng(await processImage(options));
+  const raw = await processImageRaw({ ...options, outputFormat: 'webp' });
+
+  t.is(raw.width, png.width);
+  t.is(raw.height, png.height);
+  t.deepEqual(raw.data, png.data);
+});
+
+test('processImageEx - reports the output size and format', async (t) => {
+  const options = { input: await readFile(INPUT_PATH), strictMode: false, trim: true };
+  const result = await processImageEx(options);
+
+  t.deepEqual(result.data, await processImage(options));
+  t.deepEqual({ width: result.width, height: result.height }, readPngSize(result.data));
+  t.is(result.format, 'png');
+  t.true(result.trimmed);
+
+  const untrimmed = await processImageEx({ ...options, trim: false, outputFormat: 'webp' });
+  t.is(untrimmed.format, 'webp');
+  t.false(untrimmed.trimmed);
+  t.is((await processImageEx({ ...options, input: readFileSync(ANIMATED_16X8_PATH) })).format, 'gif');
+});
+
+test('processImageEx - flags a single-color input that becomes fully transparent', async (t) => {
+  const input = readFileSync(PIXEL_1X1_PATH);
+  const solid = await processImageEx({ input, strictMode: false, trim: false, outputFormat: 'raw' });
+
+  t.true(solid.solidColor);
+  t.deepEqual([...solid.data], [0, 0, 0, 0]);
+  t.false((await processImageEx({ input, strictMode: false, trim: false, backgroundColor: '#ffffff' })).solidColor);
+  t.false((await processImageEx({ input: await readFile(INPUT_PATH), strictMode: false, trim: true })).solidColor);
+});
+
+test('processImageEx - reports how uniformly the detected background lines the edges', async (t) => {
+  const input = readFileSync(SPLIT_4X2_PATH);
+  const result = await processImageEx({ input, strictMode: false, trim: false });
+  const lightest = await processImageEx({ input, strictMode: false, trim: false, backgroundLuminanceBias: 'lightest' });
+
+  t.is(result.backgroundUniformity, 0.625);
+  t.is(lightest.backgroundUniformity, 0.375);
+  t.is((await processImageEx({ input, strictMode: false, trim: false, backgroundColor: '#ffffff' })).backgroundUniformity, undefined);
+  t.is((await processImageEx({ input: readFileSync(PIXEL_1X1_PATH), strictMode: false, trim: false })).backgroundUniformity, 1);
+});
+
+test('processImageWithMask - returns the output and its alpha in one call', async (t) => {
+  const options = { input: await readFile(INPUT_PATH), strictMode: false, trim: true };
+  const { image, mask } = await processImageWithMask(options);
+
+  t.deepEqual(image, await processImage(options));
+  const decoded = decodePng(image);
+  const decodedMask = decodePng(mask);
+  t.is(decodedMask.channels, 1);
+  t.deepEqual({ width: decodedMask.width, height: decodedMask.height }, readPngSize(image));
+  t.deepEqual(
+    decodedMask.data,
+    decoded.data.filter((_, i) => i % 4 === 3),
+  );
+});
+
+test('processImage - accepts Uint8Array and ArrayBuffer input', async (t) => {
+  const options = { input: await readFile(INPUT_PATH), strictMode: false, trim: true };
+  const expected = await processImage(options);
+  const bytes = new Uint8Array(options.input);
+  // A view into the middle of a larger buffer
+  const padded = new Uint8Array(bytes.length + 8);
+  padded.set(bytes, 4);
+
+  t.deepEqual(await processImage({ ...options, input: bytes }), expected);
+  t.deepEqual(await processImage({ ...options, input: bytes.buffer }), expected);
+  t.deepEqual(await processImage({ ...options, input: padded.subarray(4, 4 + bytes.length) }), expected);
+  t.deepEqual(detectBackgroundColor(bytes.buffer), detectBackgroundColor(options.input));
+  t.deepEqual(trimImage(bytes), trimImage(options.input));
+  t.is(assessRemovability(bytes.buffer), assessRemovability(options.input));
+});
+
+test('processImage - accepts raw RGBA input', async (t) => {
+  const options = { input: await readFile(INPUT_PATH), strictMode: false, trim: true };
+  const decoded = decodePng(trimImage(options.input));
+  const input = { data: decoded.data, width: decoded.width, height: decoded.height };
+
+  // Raw pixels carry none of the PNG input's metadata chunks, so only the pixels compare equal
+  const output = await processImage({ ...options, input });
+  t.deepEqual(decodePng(output), decodePng(await processImage(options)));
+  t.deepEqual(detectBackgroundColor(input), detectBackgroundColor(options.input));
+  t.deepEqual(decodePng(trimImage(input)), decodePng(trimImage(options.input)));
+  t.throws(() => processImageSync({ ...options, input: { ...input, width: input.width + 1 } }));
+});
+
+// ============================================================================
+// processImageStream
+// ============================================================================
+
+test('processImageStream - streams the same PNG as processImage', async (t) => {
+  const options = { input: await readFile(INPUT_PATH), strictMode: false, trim: true };
+  const chunks: Buffer[] = [];
+  await processImageStream(options, (err, chunk) => {
+    if (!err) chunks.push(chunk);
+  });
+
+  t.true(chunks.length > 1);
+  t.deepEqual(Buffer.concat(chunks), await processImage(options));
+  await t.throwsAsync(() => processImageStream({ ...options, outputFormat: 'webp' }, () => {}));
+});
+
+test('processImageStream - ends a slow stream with an empty chunk', async (t) => {
+  const size = 640;
+  const data = Buffer.alloc(size * size * 4);
+  for (let i = 0; i < data.length; i++) data[i] = i % 4 === 3 ? 255 : (i * 7919) % 251;
+  const options = { input: { data, width: size, height: size }, strictMode: false, trim: false, pngCompression: 'none' };
+  const chunks: Buffer[] = [];
+  let ended = () => {};
+  const end = new Promise<void>((resolve) => (ended = resolve));
+  await processImageStream(options, (err, chunk) => {
+    if (err) return;
+    chunks.push(chunk);
+    if (chunk.length === 0) ended();
+    const start = performance.now();
+    while (performance.now() - start < 5);
+  });
+  await end;
+
+  // Uncompressed, the output spans many chunks, and only the last one is empty
+  t.true(chunks.length > 16);
+  t.is(chunks.findIndex((chunk) => chunk.length === 0), chunks.length - 1);
+  t.deepEqual(Buffer.concat(chunks), await processImage(options));
+});
+
+// ============================================================================
+// Data URLs
+// ============================================================================
+
+test('processImage - accepts data URL input', async (t) => {
+  const options = { input: await readFile(INPUT_PATH), strictMode: false, trim: true };
+  const input = `data:image/png;base64,${options.input.toString('base64')}`;
+
+  t.deepEqual(await processImage({ ...options, input }), await processImage(options));
+  t.deepEqual(detectBackgroundColor(input), detectBackgroundColor(options.input));
+  t.deepEqual(trimImage(input), trimImage(options.input));
+  t.throws(() => processImageSync({ ...options, input: 'data:image/png,not-base64' }));
+  t.throws(() => processImageSync({ ...options, input: 'data:image/png;base64,!!!!' }));
+});
+
+test('processImageToDataUrl - returns the output as a data URL', async (t) => {
+  const options = { input: await readFile(INPUT_PATH), strictMode: false, trim: true };
+  const png = await processImage(options);
+
+  t.is(await processImageToDataUrl(options), `data:image/png;base64,${png.toString('base64')}`);
+  t.true(processImageToDataUrlSync({ ...options, outputFormat: 'webp' }).startsWith('data:image/webp;base64,'));
+  t.throws(() => processImageToDataUrlSync({ ...options, outputFormat: 'raw' }));
+});
+
+// ============================================================================
+// processImageFromConfig
+// ============================================================================
+
+test('processImageFromConfig - applies a JSON preset', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  const preset = JSON.stringify({ backgroundColor: '#ffffff', foregroundColors: ['#000000'], trim: true });
+  const output = await processImageFromConfig(inputBuffer, preset);
+
+  t.true(Buffer.isBuffer(output));
+  t.true(output.length > 0);
+});
+
+test('processImageFromConfig - throws on unknown fields', (t) => {
+  const inputBuffer = readFileSync(INPUT_PATH);
+  t.throws(() => processImageFromConfig(inputBuffer, JSON.stringify({ trimm: true })));
+});
+
+// ============================================================================
+// splitColorAndMatte
+// ============================================================================
+
+test('splitColorAndMatte - recombining color and matte reproduces the RGBA output', async (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: true };
+  const rgba = decodePng(processImageSync(options));
+  const { color, matte } = await splitColorAndMatte(options);
+  const rgb = decodePng(color);
+  const alpha = decodePng(matte);
+
+  t.is(rgb.channels, 4);
+  t.is(alpha.channels, 1);
+  t.deepEqual([rgb.width, rgb.height], [rgba.width, rgba.height]);
+  t.deepEqual([alpha.width, alpha.height], [rgba.width, rgba.height]);
+
+  const recombined = Buffer.from(rgb.data);
+  for (let i = 0; i < rgba.width * rgba.height; i++) {
+    recombined[i * 4 + 3] = alpha.data[i];
+  }
+  t.true(rgb.data.every((value, i) => i % 4 !== 3 || value === 255));
+  t.true(recombined.equals(rgba.data));
+});
+
+test('splitColorAndMatteSync - encodes the color and matte in the output format', (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: true };
+  const { color, matte } = splitColorAndMatteSync({ ...options, outputFormat: 'raw' });
+  const rgba = processImageSync({ ...options, outputFormat: 'raw' });
+
+  t.is(color.length, rgba.length);
+  t.is(matte.length, rgba.length / 4);
+  t.true(color.every((value, i) => (i % 4 === 3 ? value === 255 : value === rgba[i])));
+  t.true(matte.every((value, i) => value === rgba[i * 4 + 3]));
+  const tiff = splitColorAndMatteSync({ ...options, outputFormat: 'tiff' });
+  t.is(tiff.color.subarray(0, 2).toString(), 'II');
+  t.is(tiff.matte.subarray(0, 2).toString(), 'II');
+  t.is(splitColorAndMatteSync({ ...options, outputFormat: 'webp' }).matte.toString('ascii', 8, 12), 'WEBP');
+});
+
+test('splitColorAndMatte - writes a 16-bit matte at 16-bit output depth', async (t) => {
+  const options = { input: readFileSync(INPUT_PATH), strictMode: false, trim: true, outputBitDepth: 16 };
+  const { color, matte } = await splitColorAndMatte(options);
+  const rgba = processImageSync({ ...options, outputFormat: 'raw' });
+  const raw = splitColorAndMatteSync({ ...options, outputFormat: 'raw' });
+
+  // IHDR bit depth and color type: 16-bit RGBA color, 16-bit grayscale matte
+  t.deepEqual([color[24], color[25]], [16, 6]);
+  t.deepEqual([matte[24], matte[25]], [16, 0]);
+  t.is(raw.matte.length, rgba.length / 4);
+  for (let i = 0; i < raw.matte.length / 2; i++) {
+    t.is(raw.matte.readUInt16LE(i * 2), rgba.readUInt16LE(i * 8 + 6));
+  }
+  t.throws(() => splitColorAndMatteSync({ ...options, outputFormat: 'webp' }), { message: /16-bit output/ });
+});
+
+// ============================================================================
+// processImageLayers
+// ============================================================================
+
+test('processImageLayers - returns one layer per foreground color', async (t) => {
+  const layers = await processImageLayers({
+    input: readFileSync(INPUT_PATH),
+    foregroundColors: ['auto', 'auto'],
+    strictMode: true,
+    trim: false,
+  });
+
+  t.is(layers.length, 2);
+  t.true(layers.every((layer) => Buffer.isBuffer(layer) && layer.length > 0));
+});
+
+test('processImageLayersSync - uses the unmix weight as alpha', (t) => {
+  const [layer] = processImageLayersSync({
+    input: readFileSync(PIXEL_2X2_PATH),
+    foregroundColors: ['#ff0000'],
+    strictMode: true,
+    trim: true,
+  });
+
+  t.deepEqual(readPngSize(layer), { width: 1, height: 1 });
+  t.deepEqual(readFirstPixel(layer), [255, 0, 0, 255]);
+});
+
+test('processImageLayersSync - auto primary foreground adds the dominant interior color', (t) => {
+  const layers = processImageLayersSync({
+    input: readFileSync(PIXEL_2X2_PATH),
+    strictMode: true,
+    trim: true,
+    autoPrimaryForeground: true,
+  });
+
+  t.is(layers.length, 1);
+  t.deepEqual(readFirstPixel(layers[0]), [255, 0, 0, 255]);
+});
+
+test('processImageLayers - throws without foreground colors', async (t) => {
+  await t.throwsAsync(processImageLayers({ input: readFileSync(INPUT_PATH), strictMode: false, trim: false }));
+  t.throws(() => processImageLayersSync({ input: readFileSync(INPUT_PATH), strictMode: false, trim: false }));
+});
+
+test('processImageLayers - encodes the layers in the output format', async (t) => {
+  const options = { input: readFileSync(PIXEL_2X2_PATH), foregroundColors: ['#ff0000'], strictMode: true, trim: true };
+
+  t.deepEqual([...(await processImageLayers({ ...options, outputFormat: 'raw' }))[0]], [255, 0, 0, 255]);
+  t.is(processImageLayersSync({ ...options, outputFormat: 'webp' })[0].toString('ascii', 8, 12), 'WEBP');
+});
+
+// ============================================================================
+// separateLayers
+// ============================================================================
+
+test('separateLayers - layers composite over the background into the input', async (t) => {
+  // Red and blue at 0.4 each over white
+  const options = {
+    input: { data: Buffer.from([153, 51, 153, 255]), width: 1, height: 1 },
+    backgroundColor: '#ffffff',
+    foregroundColors: ['#ff0000', '#0000ff'],
+    strictMode: true,
+    trim: false,
+  };
+  const [red, blue] = await separateLayers(options);
+
+  // Blue covers 0.4 of the pixel, red covers 0.4 of the 0.6 blue lets through
+  t.deepEqual(readFirstPixel(red), [255, 0, 0, 170]);
+  t.deepEqual(readFirstPixel(blue), [0, 0, 255, 102]);
+  t.is(readFirstPixel(processImageLayersSync(options)[0])[3], 102);
+});
+
+test('separateLayers - throws without foreground colors', async (t) => {
+  await t.throwsAsync(separateLayers({ input: readFileSync(INPUT_PATH), strictMode: false, trim: false }));
+  t.throws(() => separateLayersSync({ input: readFileSync(INPUT_PATH), strictMode: false, trim: false }));
+});
+
+test('separateLayers - encodes the layers in the output format', async (t) => {
+  const options = { input: readFileSync(PIXEL_2X2_PATH), foregroundColors: ['#ff0000'], strictMode: true, trim: true };
+
+  t.deepEqual([...(await separateLayers({ ...options, outputFormat: 'raw' }))[0]], [255, 0, 0, 255]);
+  t.is(separateLayersSync({ ...options, outputFormat: 'webp' })[0].toString('ascii', 8, 12), 'WEBP');
+});
+
+// ============================================================================
+// recolor
+// ============================================================================
+
+test('recolor - swaps each foreground color for its replacement', async (t) => {
+  // Red at 0.6 over white, and red at 0.6 and blue at 0.2 over white
+  const options = {
+    input: { data: Buffer.from([255, 102, 102, 255, 204, 51, 102, 255]), width: 2, height: 1 },
+    backgroundColor: '#ffffff',
+    foregroundColors: ['#ff0000', '#0000ff'],
+    strictMode: true,
+    trim: false,
+  };
+  const { data } = decodePng(await recolor(options, ['#00ff00', '#0000ff']));
+
+  t.deepEqual(Array.from(data), [0, 255, 0, 153, 0, 191, 64, 204]);
+  t.deepEqual(Array.from(recolorSync({ ...options, outputFormat: 'raw' }, ['#00ff00', '#0000ff'])), Array.from(data));
+  await t.throwsAsync(recolor(options, ['#00ff00']), { message: /one replacement color per foreground color/ });
+  t.throws(() => recolorSync(options, ['#00ff00', 'nope']), { message: /Invalid replacement color/ });
+});
+
+test('recolorSync - encodes the output in the output format', (t) => {
+  const options = {
+    input: readFileSync(PIXEL_2X2_PATH),
+    backgroundColor: '#ffffff',
+    foregroundColors: ['#ff0000'],
+    strictMode: true,
+    trim: true,
+  };
+
+  t.is(recolorSync({ ...options, outputFormat: 'webp' }, ['#0000ff']).toString('ascii', 8, 12), 'WEBP');
+  t.deepEqual(Array.from(recolorSync({ ...options, outputFormat: 'raw' }, ['#0000ff'])), [0, 0, 255, 255]);
+});
+
+// ============================================================================
+// processImageWeightMaps
+// ============================================================================
+
+test('processImageWeightMaps - returns each foreground color weight as a grayscale map', async (t) => {
+  // Red at 0.6 and blue at 0.2 over white
+  const input = { data: Buffer.from([255, 102, 102, 255, 204, 204, 255, 255]), width: 2, height: 1 };
+  const maps = await processImageWeightMaps({
+    input,
+    backgroundColor: '#ffffff',
+    foregroundColors: ['#ff0000', '#0000ff'],
+    strictMode: true,
+    trim: false,
+  });
+
+  t.is(maps.length, 2);
+  const [red, blue] = maps.map(decodePng);
+  t.deepEqual({ width: red.width, height: red.height, channels: red.channels }, { width: 2, height: 1, channels: 1 });
+  t.deepEqual(Array.from(red.data), [153, 0]);
+  t.deepEqual(Array.from(blue.data), [0, 51]);
+});
+
+test('processImageWeightMaps - throws without foreground colors', async (t) => {
+  await t.throwsAsync(processImageWeightMaps({ input: readFileSync(INPUT_PATH), strictMode: false, trim: false }));
+  t.throws(() => processImageWeightMapsSync({ input: readFileSync(INPUT_PATH), strictMode: false, trim: false }));
+});
+
+test('processImageWeightMaps - encodes the maps in the output format', async (t) => {
+  const options = { input: readFileSync(PIXEL_2X2_PATH), foregroundColors: ['#ff0000'], strictMode: true, trim: true };
+
+  t.deepEqual([...(await processImageWeightMaps({ ...options, outputFormat: 'raw' }))[0]], [255]);
+  t.is(processImageWeightMapsSync({ ...options, outputFormat: 'webp' })[0].toString('ascii', 8, 12), 'WEBP');
+  t.is(processImageWeightMapsSync({ ...options, outputFormat: 'tiff' })[0].toString('ascii', 0, 2), 'II');
+});
+
+// ============================================================================
+// fitSubject
+// ============================================================================
+
+test('fitSubject - outputs exactly the target size with the margin left empty', async (t) => {
+  const output = await fitSubject({ input: await readFile(INPUT_PATH), strictMode: false, trim: false }, 64, 48, 0.1);
+  const { width, height, data } = decodePng(output);
+
+  t.deepEqual({ width, height }, { width: 64, height: 48 });
+  for (let y = 0; y < height; y++) {
+    for (let x = 0; x < width; x++) {
+      if (x < 6 || x >= 58 || y < 4 || y >= 44) t.is(data[(y * width + x) * 4 + 3], 0);
+    }
+  }
+});
+
+test('fitSubjectSync - scales up and centers small subjects', (t) => {
+  const output = fitSubjectSync(
+    { input: readFileSync(PIXEL_2X2_PATH), backgroundColor: '#ffffff', strictMode: false, trim: false },
+    4,
+    4,
+    0.25,
+  );
+  const { data } = decodePng(output);
+
+  t.deepEqual(Array.from(data.subarray(0, 4)), [0, 0, 0, 0]);
+  t.deepEqual(Array.from(data.subarray((1 * 4 + 1) * 4, (1 * 4 + 1) * 4 + 4)), [255, 0, 0, 255]);
+  t.deepEqual(Array.from(data.subarray((2 * 4 + 2) * 4, (2 * 4 + 2) * 4 + 4)), [255, 0, 0, 255]);
+});
+
+test('fitSubject - throws on invalid margin ratio', async (t) => {
+  const options = { input: readFileSync(PIXEL_2X2_PATH), strictMode: false, trim: false };
+
+  await t.throwsAsync(fitSubject(options, 4, 4, 0.5));
+  t.throws(() => fitSubjectSync(options, 4, 4, 0.5));
+});
+
+test('fitSubject - encodes the canvas in the output format', async (t) => {
+  const options = { input: readFileSync(PIXEL_2X2_PATH), backgroundColor: '#ffffff', strictMode: false, trim: false };
+
+  t.is((await fitSubject({ ...options, outputFormat: 'raw' }, 4, 4, 0.25)).length, 4 * 4 * 4);
+  t.true(fitSubjectSync({ ...options, pngCompression: 'none' }, 64, 64).length > fitSubjectSync(options, 64, 64).length);
+  t.is(fitSubjectSync({ ...options, outputFormat: 'webp' }, 4, 4).toString('ascii', 8, 12), 'WEBP');
+});
+
+// ============================================================================
+// exportFavicon
+// ============================================================================
+
+test('exportFavicon - bundles the default icon sizes', async (t) => {
+  const ico = await exportFavicon({ input: readFileSync(SPLIT_4X2_PATH), strictMode: false, trim: true });
+  // ICONDIR: reserved, type (1 = icon), entry count; each 16-byte entry starts with the width (0 = 256)
+  const widths = Array.from({ length: ico.readUInt16LE(4) }, (_, i) => ico[6 + i * 16]);
+
+  t.is(ico.readUInt16LE(2), 1);
+  t.deepEqual(widths, [16, 32, 48, 0]);
+});
+
+test('exportFavicon - throws on invalid sizes', async (t) => {
+  const options = { input: readFileSync(SPLIT_4X2_PATH), strictMode: false, trim: true };
+  await t.throwsAsync(exportFavicon(options, []));
+  t.throws(() => exportFaviconSync(options, [16, 512]));
+});
+
+test('exportFaviconSync - ignores the output format', (t) => {
+  const ico = exportFaviconSync({ input: readFileSync(SPLIT_4X2_PATH), strictMode: false, trim: true, outputFormat: 'webp' }, [16]);
+
+  t.is(ico.readUInt16LE(2), 1);
+  t.is(ico.readUInt16LE(4), 1);
+});
+
+// ============================================================================
+// processWithReference
+// ============================================================================
+
+test('processWithReference - keeps only what differs from the reference', async (t) => {
+  const output = await processWithReference(readFileSync(REFERENCE_SUBJECT_PATH), readFileSync(REFERENCE_BACKGROUND_PATH));
+  const { data } = decodePng(output);
+  const pixel = (x: number, y: number) => Array.from(data.subarray((y * 4 + x) * 4, (y * 4 + x) * 4 + 4));
+
+  t.deepEqual(pixel(0, 0), [0, 0, 0, 0]);
+  t.deepEqual(pixel(1, 0), [255, 0, 0, 255]);
+  t.deepEqual(pixel(2, 0), [255, 0, 0, 255]);
+  // Small differences from the reference are treated as noise
+  t.deepEqual(pixel(3, 1), [0, 0, 0, 0]);
+});
+
+test('processWithReferenceSync - trims to the subject', (t) => {
+  const output = processWithReferenceSync(readFileSync(REFERENCE_SUBJECT_PATH), readFileSync(REFERENCE_BACKGROUND_PATH), {
+    trim: true,
+  });
+
+  t.deepEqual(readPngSize(output), { width: 2, height: 1 });
+});
+
+test('processWithReferenceSync - keeps noise above a lower threshold', (t) => {
+  const output = processWithReferenceSync(readFileSync(REFERENCE_SUBJECT_PATH), readFileSync(REFERENCE_BACKGROUND_PATH), {
+    threshold: 0,
+  });
+  const { data } = decodePng(output);
+
+  t.true(data[(1 * 4 + 3) * 4 + 3] > 0);
+});
+
+test('processWithReference - throws on mismatched sizes', async (t) => {
+  await t.throwsAsync(processWithReference(readFileSync(REFERENCE_SUBJECT_PATH), readFileSync(PIXEL_2X2_PATH)));
+  t.throws(() => processWithReferenceSync(readFileSync(REFERENCE_SUBJECT_PATH), readFileSync(PIXEL_2X2_PATH)));
+});
+
+test('processWithReferenceSync - throws on invalid opaque distance', (t) => {
+  t.throws(() =>
+    processWithReferenceSync(readFileSync(REFERENCE_SUBJECT_PATH), readFileSync(REFERENCE_BACKGROUND_PATH), {
+      opaqueDistance: 0,
+    }),
+  );
+});
+
+test('processWithReferenceSync - decodes CMYK JPEGs and accepts raw references', (t) => {
+  // White paper on the left, 100% magenta and yellow on the right, against plain white
+  const reference = { data: Buffer.alloc(16 * 8 * 4, 255), width: 16, height: 8 };
+  const { data } = decodePng(processWithReferenceSync(readFileSync(CMYK_16X8_PATH), reference));
+
+  t.is(data[3], 0);
+  t.deepEqual([...data.subarray(60, 64)], [255, 0, 0, 255]);
+});
+
+test('processWithReference - encodes the output in the output format', async (t) => {
+  const subject = readFileSync(REFERENCE_SUBJECT_PATH);
+  const background = readFileSync(REFERENCE_BACKGROUND_PATH);
+  const raw = await processWithReference(subject, background, { trim: true, outputFormat: 'raw' });
+
+  t.deepEqual([...raw], [255, 0, 0, 255, 255, 0, 0, 255]);
+  t.is(processWithReferenceSync(subject, background, { outputFormat: 'webp' }).toString('ascii', 8, 12), 'WEBP');
+  t.throws(() => processWithReferenceSync(subject, background, { outputFormat: 'bmp' }), {
+    message: /Invalid output format/,
+  });
+});
+
+// ============================================================================
+// subjectAverageColor
+// ============================================================================
+
+test('subjectAverageColor - averages only the remaining subject', async (t) => {
+  const color = await subjectAverageColor({
+    input: readFileSync(SPLIT_4X2_PATH),
+    backgroundLuminanceBias: 'darkest',
+    strictMode: false,
+    trim: false,
+  });
+
+  t.deepEqual(color, { r: 255, g: 255, b: 255 });
+});
+
+test('subjectAverageColorSync - weights colors by alpha', (t) => {
+  const color = subjectAverageColorSync({
+    input: readFileSync(INPUT_PATH),
+    strictMode: false,
+    trim: false,
+  });
+  const { data } = decodePng(processImageSync({ input: readFileSync(INPUT_PATH), strictMode: false, trim: false }));
+  const sums = [0, 0, 0];
+  let totalAlpha = 0;
+  for (let i = 0; i < data.length; i += 4) {
+    for (let c = 0; c < 3; c++) sums[c] += data[i + c] * data[i + 3];
+    totalAlpha += data[i + 3];
+  }
+
+  t.deepEqual(color, {
+    r: Math.round(sums[0] / totalAlpha),
+    g: Math.round(sums[1] / totalAlpha),
+    b: Math.round(sums[2] / totalAlpha),
+  });
+});
+
+test('subjectAverageColor - returns null when nothing is left', async (t) => {
+  const options = { input: readFileSync(PIXEL_NOISY_PATH), strictMode: false, trim: false };
+
+  t.is(await subjectAverageColor(options), null);
+  t.is(subjectAverageColorSync(options), null);
 });
 
 // ============================================================================
@@ -163,6 +2027,203 @@ test('detectBackgroundColor - detects background from image', async (t) => {
   t.true(bgColor.b >= 0 && bgColor.b <= 255);
 });
 
+test('detectBackgroundColor - luminance bias prefers the lightest or darkest common color', (t) => {
+  const input = readFileSync(SPLIT_4X2_PATH);
+
+  t.deepEqual(detectBackgroundColor(input), { r: 0, g: 0, b: 0 });
+  t.deepEqual(detectBackgroundColor(input, 'lightest'), { r: 255, g: 255, b: 255 });
+  t.deepEqual(detectBackgroundColor(input, 'darkest'), { r: 0, g: 0, b: 0 });
+});
+
+test('detectBackgroundColor - samples the pixels the strategy asks for', (t) => {
+  // 20x20 white with a 2 pixel black frame
+  const data = Buffer.alloc(20 * 20 * 4, 255);
+  for (let y = 0; y < 20; y++) {
+    for (let x = 0; x < 20; x++) {
+      if (x < 2 || y < 2 || x > 17 || y > 17) data.writeUInt32BE(0x000000ff, (y * 20 + x) * 4);
+    }
+  }
+  const input = { data, width: 20, height: 20 };
+
+  t.deepEqual(detectBackgroundColor(input), { r: 0, g: 0, b: 0 });
+  t.deepEqual(detectBackgroundColor(input, undefined, { strategy: 'corners' }), { r: 0, g: 0, b: 0 });
+  t.deepEqual(detectBackgroundColor(input, undefined, { strategy: 'border' }), { r: 255, g: 255, b: 255 });
+  t.deepEqual(detectBackgroundColor(input, undefined, { strategy: 'dominant', edgeSampleInterval: 1 }), { r: 255, g: 255, b: 255 });
+  t.throws(() => detectBackgroundColor(input, undefined, { strategy: 'ring' }), { message: /Invalid detection strategy/ });
+  t.throws(() => detectBackgroundColor(input, undefined, { strategy: 'border', borderDepth: 0 }), { message: /at least 1/ });
+});
+
+test('detectBackgroundColor - skips the excluded regions', (t) => {
+  // 10x3 white with a red watermark over the left 8 columns
+  const data = Buffer.alloc(10 * 3 * 4, 255);
+  for (let y = 0; y < 3; y++) {
+    for (let x = 0; x < 8; x++) data.writeUInt32BE(0xff0000ff, (y * 10 + x) * 4);
+  }
+  const input = { data, width: 10, height: 3 };
+
+  t.deepEqual(detectBackgroundColor(input, undefined, { edgeSampleInterval: 1 }), { r: 255, g: 0, b: 0 });
+  t.deepEqual(
+    detectBackgroundColor(input, undefined, { edgeSampleInterval: 1, excludeRegions: [{ x: 0, y: 0, width: 8, height: 3 }] }),
+    { r: 255, g: 255, b: 255 },
+  );
+});
+
+test('detectBackgroundColor - ignores the transparent palette index of indexed images', (t) => {
+  t.deepEqual(detectBackgroundColor(readFileSync(INDEXED_TRANSPARENT_PATH)), { r: 255, g: 255, b: 255 });
+});
+
+test('detectBackgroundColor - samples the edges as densely as asked', (t) => {
+  // 21x3 white with red columns at x = 0, 10 and 20, where the default interval samples
+  const data = Buffer.alloc(21 * 3 * 4, 255);
+  for (let y = 0; y < 3; y++) {
+    for (const x of [0, 10, 20]) data.writeUInt32BE(0xff0000ff, (y * 21 + x) * 4);
+  }
+  const input = { data, width: 21, height: 3 };
+
+  t.deepEqual(detectBackgroundColor(input), { r: 255, g: 0, b: 0 });
+  t.deepEqual(detectBackgroundColor(input, undefined, { edgeSampleInterval: 1 }), { r: 255, g: 255, b: 255 });
+  t.deepEqual(
+    decodePng(processImageSync({ input, strictMode: false, trim: false, backgroundDetection: { edgeSampleInterval: 1 } })).data.subarray(4, 8),
+    Buffer.from([0, 0, 0, 0]),
+  );
+  t.throws(() => detectBackgroundColor(input, undefined, { edgeSampleInterval: 0 }), { message: /at least 1/ });
+});
+
+test('detectBackgroundColor - throws on invalid luminance bias', (t) => {
+  t.throws(() => detectBackgroundColor(readFileSync(SPLIT_4X2_PATH), 'brightest'));
+});
+
+test('detectBackgroundColors - ranks the edge colors with their share of the samples', (t) => {
+  // Black on the left, white on the right: the corners and edges favor black 5 to 3
+  const candidates = detectBackgroundColors(readFileSync(SPLIT_4X2_PATH));
+
+  t.deepEqual(candidates, [
+    { color: { r: 0, g: 0, b: 0 }, samples: 5, confidence: 0.625 },
+    { color: { r: 255, g: 255, b: 255 }, samples: 3, confidence: 0.375 },
+  ]);
+  t.deepEqual(detectBackgroundColors(readFileSync(SPLIT_4X2_PATH), 1), candidates.slice(0, 1));
+  t.deepEqual(detectBackgroundColors(readFileSync(INPUT_PATH), 1)[0].color, detectBackgroundColor(readFileSync(INPUT_PATH)));
+});
+
+test('detectBackgroundColor - median and trimmed mean estimators see through noise', (t) => {
+  // 16x16 noisy tan around (200, 180, 157)
+  const data = Buffer.alloc(16 * 16 * 4, 255);
+  for (let i = 0; i < 256; i++) {
+    data[i * 4] = 200 + ((i * 37) % 11) - 5;
+    data[i * 4 + 1] = 180 + ((i * 13) % 9) - 4;
+    data[i * 4 + 2] = 157;
+  }
+  const input = { data, width: 16, height: 16 };
+
+  t.deepEqual(detectBackgroundColor(input, undefined, { edgeSampleInterval: 1, estimator: 'median' }), { r: 200, g: 180, b: 157 });
+  t.deepEqual(detectBackgroundColor(input, undefined, { edgeSampleInterval: 1, estimator: 'trimmed_mean' }), { r: 200, g: 180, b: 157 });
+  t.throws(() => detectBackgroundColor(input, undefined, { estimator: 'mean' }), { message: /Invalid background estimator/ });
+});
+
+test('detectBackgroundColorDebug - returns the edge samples and their histogram', (t) => {
+  const debug = detectBackgroundColorDebug(readFileSync(SPLIT_4X2_PATH));
+
+  t.deepEqual(debug.color, detectBackgroundColor(readFileSync(SPLIT_4X2_PATH)));
+  t.is(debug.samples.length, 8);
+  t.deepEqual(debug.histogram, detectBackgroundColors(readFileSync(SPLIT_4X2_PATH)));
+  for (const { x, y } of debug.samples) {
+    t.true(x < 4 && y < 2);
+  }
+});
+
+test('detectBackgroundUniformity - reports the share of edge samples matching the background', (t) => {
+  t.deepEqual(detectBackgroundUniformity(readFileSync(SPLIT_4X2_PATH)), {
+    color: { r: 0, g: 0, b: 0 },
+    uniformity: 0.625,
+    matchingSamples: 5,
+    samples: 8,
+  });
+  t.is(detectBackgroundUniformity(readFileSync(SPLIT_4X2_PATH), 'lightest').uniformity, 0.375);
+  t.is(detectBackgroundUniformity(readFileSync(PIXEL_1X1_PATH)).uniformity, 1);
+});
+
+test('detectChromaKey - picks the key color of an unevenly lit green screen', async (t) => {
+  // 32x32 noisy green with a red square
+  const isSubject = (x: number, y: number) => x >= 10 && x < 22 && y >= 10 && y < 22;
+  const data = Buffer.alloc(32 * 32 * 4, 255);
+  for (let y = 0; y < 32; y++) {
+    for (let x = 0; x < 32; x++) {
+      const i = (y * 32 + x) * 4;
+      if (isSubject(x, y)) {
+        data.writeUInt32BE(0xdc2020ff, i);
+      } else {
+        data[i] = 20 + ((x * 3 + y * 5) % 15);
+        data[i + 1] = 150 + ((x * 7 + y * 13) % 41);
+        data[i + 2] = 30 + ((x * 11 + y) % 17);
+      }
+    }
+  }
+  const input = { data, width: 32, height: 32 };
+
+  const key = detectChromaKey(input);
+  t.true(key.chromaKey);
+  t.deepEqual(key.color, { r: 25, g: 167, b: 38 });
+  t.is(key.coverage, 1);
+  t.true(key.hue! > 100 && key.hue! < 140);
+  t.false(detectChromaKey(readFileSync(SPLIT_4X2_PATH)).chromaKey);
+
+  const output = await processImageRaw({ input, strictMode: false, trim: false, chromaKey: true });
+  t.is(output.data[(16 * 32 + 16) * 4], 255);
+});
+
+test('detectBackgroundColorRgba - reports the alpha of a translucent background', (t) => {
+  t.deepEqual(detectBackgroundColorRgba(readFileSync(PIXEL_TRANSLUCENT_PATH)), { r: 128, g: 0, b: 0, a: 128 });
+  t.deepEqual(detectBackgroundColor(readFileSync(PIXEL_TRANSLUCENT_PATH)), { r: 64, g: 0, b: 0 });
+});
+
+test('detectBackgroundColorRgba - reports opaque backgrounds with full alpha', (t) => {
+  t.deepEqual(detectBackgroundColorRgba(readFileSync(PIXEL_2X2_PATH)), { r: 255, g: 255, b: 255, a: 255 });
+});
+
+test('detectBackgroundColorRgba - decodes CMYK JPEGs', (t) => {
+  t.deepEqual(detectBackgroundColorRgba(readFileSync(CMYK_16X8_PATH), 'lightest'), { r: 255, g: 255, b: 255, a: 255 });
+  t.deepEqual(detectBackgroundColor(readFileSync(CMYK_16X8_PATH), 'lightest'), { r: 255, g: 255, b: 255 });
+});
+
+// ============================================================================
+// assessRemovability
+// ============================================================================
+
+test('assessRemovability - scores a clean background highly', async (t) => {
+  const score = assessRemovability(await readFile(INPUT_PATH));
+
+  t.true(score > 0.9 && score <= 1);
+});
+
+test('assessRemovability - scores an inconsistent background lower', async (t) => {
+  t.true(assessRemovability(readFileSync(SPLIT_4X2_PATH)) < assessRemovability(await readFile(INPUT_PATH)));
+});
+
+test('assessRemovability - scores an image without a subject as 0', (t) => {
+  t.is(assessRemovability(readFileSync(PIXEL_1X1_PATH)), 0);
+});
+
+test('assessRemovability - decodes CMYK JPEGs', (t) => {
+  t.true(assessRemovability(readFileSync(CMYK_16X8_PATH)) > 0);
+});
+
+// ============================================================================
+// detectSolidColor
+// ============================================================================
+
+test('detectSolidColor - returns the color of a single-color image', (t) => {
+  t.deepEqual(detectSolidColor(readFileSync(PIXEL_1X1_PATH)), { r: 255, g: 0, b: 0, a: 255 });
+});
+
+test('detectSolidColor - returns null for images with several colors', (t) => {
+  t.is(detectSolidColor(readFileSync(PIXEL_2X2_PATH)), null);
+});
+
+test('detectSolidColor - decodes CMYK JPEGs', (t) => {
+  // Decoded as inverted CMYK, both halves would be black
+  t.is(detectSolidColor(readFileSync(CMYK_16X8_PATH)), null);
+});
+
 // ============================================================================
 // parseColor
 // ============================================================================
@@ -197,12 +2258,30 @@ test('parseColor - parses black', (t) => {
   t.deepEqual(color, { r: 0, g: 0, b: 0 });
 });
 
+test('parseColor - parses CSS color functions and names', (t) => {
+  t.deepEqual(parseColor('rgb(255, 128, 0)'), { r: 255, g: 128, b: 0 });
+  t.deepEqual(parseColor('rgba(255 128 0 / 100%)'), { r: 255, g: 128, b: 0 });
+  t.deepEqual(parseColor('rgb(100%, 0%, 50%)'), { r: 255, g: 0, b: 128 });
+  t.deepEqual(parseColor('hsl(120, 100%, 25%)'), { r: 0, g: 128, b: 0 });
+  t.deepEqual(parseColor('hsla(240deg 100% 50% / 1)'), { r: 0, g: 0, b: 255 });
+  t.deepEqual(parseColor('White'), { r: 255, g: 255, b: 255 });
+  t.deepEqual(parseColor('rebeccapurple'), { r: 102, g: 51, b: 153 });
+  t.throws(() => parseColor('rgba(255, 0, 0, 0.5)'), { message: /opaque/ });
+  t.throws(() => parseColor('rgb(255, 0)'));
+});
+
 test('parseColor - throws on invalid color', (t) => {
   t.throws(() => parseColor('invalid'));
 });
 
 test('parseColor - throws on wrong length', (t) => {
-  t.throws(() => parseColor('#ff00'));
+  t.throws(() => parseColor('#ff000'));
+});
+
+test('parseColor - parses hex colors with alpha when opaque', (t) => {
+  t.deepEqual(parseColor('#ff0000ff'), { r: 255, g: 0, b: 0 });
+  t.deepEqual(parseColor('#0f0f'), { r: 0, g: 255, b: 0 });
+  t.throws(() => parseColor('#ff000080'), { message: /opaque/ });
 });
 
 // ============================================================================
@@ -258,6 +2337,60 @@ test('colorToNormalized and normalizedToColor - roundtrip', (t) => {
   t.deepEqual(denormalized, original);
 });
 
+// ============================================================================
+// srgbToLinear / linearToSrgb
+// ============================================================================
+
+test('srgbToLinear - decodes a single value and a list', (t) => {
+  t.true(Math.abs((srgbToLinear(0.5) as number) - 0.214) < 0.001);
+  t.deepEqual(srgbToLinear([0, 1]), [0, 1]);
+});
+
+test('srgbToLinear and linearToSrgb - roundtrip', (t) => {
+  const values = [0.02, 0.2, 0.5, 0.9];
+  const roundtrip = linearToSrgb(srgbToLinear(values)) as number[];
+  roundtrip.forEach((value, i) => t.true(Math.abs(value - values[i]) < 1e-9));
+});
+
+// ============================================================================
+// Color space conversions
+// ============================================================================
+
+test('rgbToHsl and rgbToHsv - convert red', (t) => {
+  t.deepEqual(rgbToHsl({ r: 255, g: 0, b: 0 }), { h: 0, s: 1, l: 0.5 });
+  t.deepEqual(rgbToHsv({ r: 255, g: 0, b: 0 }), { h: 0, s: 1, v: 1 });
+});
+
+test('rgbToLab and rgbToOklab - convert red', (t) => {
+  const lab = rgbToLab({ r: 255, g: 0, b: 0 });
+  t.true(Math.abs(lab.l - 53.24) < 0.01 && Math.abs(lab.a - 80.09) < 0.01 && Math.abs(lab.b - 67.2) < 0.01);
+  const oklab = rgbToOklab({ r: 255, g: 0, b: 0 });
+  t.true(Math.abs(oklab.l - 0.628) < 0.001 && Math.abs(oklab.a - 0.2249) < 0.001 && Math.abs(oklab.b - 0.1258) < 0.001);
+});
+
+test('color space conversions - roundtrip', (t) => {
+  const color = { r: 12, g: 200, b: 99 };
+  t.deepEqual(hslToRgb(rgbToHsl(color)), color);
+  t.deepEqual(hsvToRgb(rgbToHsv(color)), color);
+  t.deepEqual(labToRgb(rgbToLab(color)), color);
+  t.deepEqual(oklabToRgb(rgbToOklab(color)), color);
+});
+
+// ============================================================================
+// colorDistance
+// ============================================================================
+
+test('colorDistance - measures RGB, CIE76 and CIEDE2000 distances', (t) => {
+  t.true(Math.abs(colorDistance('#000000', '#ffffff') - Math.sqrt(3)) < 1e-9);
+  t.true(Math.abs(colorDistance([0, 0, 0], { r: 255, g: 255, b: 255 }, 'cie76') - 100) < 0.001);
+  t.true(Math.abs(colorDistance('#202020', '#282828', 'deltaE2000') - 2.52) < 0.01);
+  t.is(colorDistance('red', '#ff0000', 'deltaE2000'), 0);
+});
+
+test('colorDistance - throws on an unknown metric', (t) => {
+  t.throws(() => colorDistance('red', 'blue', 'lab'), { message: /Metric must be one of/ });
+});
+
 // ============================================================================
 // trimImage
 // ============================================================================
@@ -275,6 +2408,110 @@ test('trimImage - trims image to content', async (t) => {
   t.true(trimmed.length > 0);
 });
 
+test('trimImage - accepts PNG encoder settings', (t) => {
+  const processed = processImageSync({ input: readFileSync(INPUT_PATH), strictMode: false, trim: false });
+  const trimmed = trimImage(processed);
+  const fast = trimImage(processed, { compression: 'fastest', filter: 'sub' });
+
+  t.deepEqual(decodePng(fast).data, decodePng(trimmed).data);
+  t.is(trimImage(processed, { interlaced: true })[28], 1);
+  t.throws(() => trimImage(processed, { filter: 'median' }));
+});
+
+test('trimImage - writes PNG for TIFF inputs unless another output format is given', (t) => {
+  const options = { input: readFileSync(TWO_PAGE_TIFF_PATH), strictMode: false, trim: false, outputFormat: 'tiff' };
+  const tiff = processImageSync(options);
+  const tiff16 = processImageSync({ ...options, outputBitDepth: 16 });
+
+  t.is(trimImage(tiff, { interlaced: true })[28], 1);
+  t.deepEqual([...trimImage(tiff, null, 'raw')], [...decodePng(trimImage(tiff)).data]);
+  t.is(trimImage(tiff, null, 'webp').toString('ascii', 8, 12), 'WEBP');
+  // 16-bit inputs keep their precision in TIFF output only: one more byte per channel of the 4x4 square
+  t.is(trimImage(tiff16, null, 'tiff').length - trimImage(tiff, null, 'tiff').length, 4 * 4 * 4);
+  t.is(trimImage(tiff16)[24], 8);
+  t.throws(() => trimImage(tiff, null, 'bmp'), { message: /Invalid output format/ });
+});
+
+// ============================================================================
+// quantize
+// ============================================================================
+
+function countVisibleColors(png: Buffer) {
+  const { channels, data } = decodePng(png);
+  const colors = new Set();
+  for (let i = 0; i < data.length; i += channels) {
+    if (data[i + 3] > 0) colors.add(data.subarray(i, i + channels).join(','));
+  }
+  return colors.size;
+}
+
+test('quantize - limits the number of visible colors', async (t) => {
+  const processed = await processImage({
+    input: await readFile(INPUT_PATH),
+    strictMode: false,
+    trim: false,
+  });
+
+  for (const dither of [false, true]) {
+    const quantized = await quantize(processed, 8, dither);
+    t.deepEqual(readPngSize(quantized), readPngSize(processed));
+    t.true(countVisibleColors(quantized) <= 8);
+  }
+});
+
+test('quantizeSync - keeps exact colors when the palette is large enough', (t) => {
+  const input = readFileSync(PIXEL_2X2_PATH);
+  const quantized = quantizeSync(input, 2, true);
+
+  t.deepEqual(decodePng(quantized).data, decodePng(trimImage(input)).data);
+});
+
+test('quantize - throws on invalid color count', async (t) => {
+  await t.throwsAsync(quantize(readFileSync(PIXEL_1X1_PATH), 0, false));
+  t.throws(() => quantizeSync(readFileSync(PIXEL_1X1_PATH), 257, false));
+});
+
+test('quantizeSync - decodes CMYK JPEGs', (t) => {
+  const { data } = decodePng(quantizeSync(readFileSync(CMYK_16X8_PATH), 2, false));
+
+  t.deepEqual([...data.subarray(0, 4)], [255, 255, 255, 255]);
+  t.deepEqual([...data.subarray(60, 64)], [255, 0, 0, 255]);
+});
+
+test('quantize - encodes the output in the output format', async (t) => {
+  const input = readFileSync(PIXEL_2X2_PATH);
+
+  t.deepEqual([...(await quantize(input, 2, false, 'raw'))], [...decodePng(trimImage(input)).data]);
+  t.is(quantizeSync(input, 2, false, 'webp').toString('ascii', 8, 12), 'WEBP');
+  t.throws(() => quantizeSync(input, 2, false, 'bmp'), { message: /Invalid output format/ });
+});
+
+// ============================================================================
+// extractPalette
+// ============================================================================
+
+test('extractPalette - returns the dominant colors with their pixel shares', (t) => {
+  const data = Buffer.alloc(16 * 4);
+  for (let i = 0; i < 16; i++) {
+    data.set([...(i < 10 ? [250, 250, 250] : i < 14 ? [200, 10, 10] : [10, 10, 200]), 255], i * 4);
+  }
+  data.set([0, 0, 0, 0], 0);
+  const image = { data, width: 4, height: 4 };
+
+  t.deepEqual(extractPalette(image, 3), [
+    { color: { r: 250, g: 250, b: 250 }, pixels: 9, share: 9 / 15 },
+    { color: { r: 200, g: 10, b: 10 }, pixels: 4, share: 4 / 15 },
+    { color: { r: 10, g: 10, b: 200 }, pixels: 2, share: 2 / 15 },
+  ]);
+  t.deepEqual(extractPalette(image, 8), extractPalette(image, 3));
+  t.deepEqual(extractPalette(image, 1), [{ color: { r: 205, g: 154, b: 179 }, pixels: 15, share: 1 }]);
+});
+
+test('extractPalette - throws on invalid color count', (t) => {
+  t.throws(() => extractPalette(readFileSync(PIXEL_1X1_PATH), 0));
+  t.throws(() => extractPalette(readFileSync(PIXEL_1X1_PATH), 257));
+});
+
 // ============================================================================
 // unmixColor
 // ============================================================================
@@ -301,6 +2538,16 @@ test('unmixColor - unmixes blended color', (t) => {
   t.true(Math.abs(result.alpha - 0.5) < 0.05);
 });
 
+test('unmixColor - reports the reconstruction error', (t) => {
+  const red = [{ r: 255, g: 0, b: 0 }];
+  const black = { r: 0, g: 0, b: 0 };
+
+  t.true(unmixColor({ r: 128, g: 0, b: 0 }, red, black).reconstructionError < 1e-9);
+  t.true(Math.abs(unmixColor({ r: 0, g: 0, b: 255 }, red, black).reconstructionError - 1) < 1e-9);
+  const mixed = unmixColor({ r: 0, g: 0, b: 255 }, [...red, { r: 0, g: 255, b: 0 }], black);
+  t.true(Math.abs(mixed.reconstructionError - 1) < 1e-9);
+});
+
 test('unmixColor - unmixes with multiple foreground colors', (t) => {
   const result = unmixColor(
     { r: 128, g: 128, b: 0 },
@@ -315,6 +2562,101 @@ test('unmixColor - unmixes with multiple foreground colors', (t) => {
   t.true(result.alpha > 0);
 });
 
+test('unmixColor - handles foreground colors collinear with the background', (t) => {
+  const result = unmixColor(
+    { r: 64, g: 0, b: 0 },
+    [
+      { r: 255, g: 0, b: 0 },
+      { r: 128, g: 0, b: 0 },
+    ],
+    { r: 0, g: 0, b: 0 },
+  );
+
+  t.true(result.weights.length === 2);
+  t.true(result.weights.every((w) => Number.isFinite(w) && w >= 0));
+  t.true(result.alpha > 0);
+  t.deepEqual(result.droppedColors, []);
+});
+
+test('unmixColor - reports foreground colors dropped as linearly dependent', (t) => {
+  const result = unmixColor(
+    { r: 64, g: 0, b: 0 },
+    [
+      { r: 255, g: 0, b: 0 },
+      { r: 128, g: 0, b: 0 },
+      { r: 0, g: 0, b: 255 },
+    ],
+    { r: 0, g: 0, b: 0 },
+    false,
+  );
+
+  t.deepEqual(result.droppedColors, [1]);
+  t.is(result.weights[1], 0);
+  t.true(result.weights[0] > 0);
+});
+
+test('unmixColor - finds opaque mixes of more than two foreground colors', (t) => {
+  const result = unmixColor(
+    { r: 102, g: 77, b: 77 },
+    [
+      { r: 255, g: 0, b: 0 },
+      { r: 0, g: 255, b: 0 },
+      { r: 0, g: 0, b: 255 },
+      { r: 255, g: 255, b: 255 },
+    ],
+    { r: 0, g: 0, b: 0 },
+  );
+
+  t.true(result.weights.every((w) => w >= 0));
+  t.true(Math.abs(result.alpha - 1) < 0.001);
+  t.true(Math.abs(result.weights.reduce((sum, w) => sum + w, 0) - 1) < 0.001);
+});
+
+test('unmixColor - returns the plain least-squares solution without opacity optimization', (t) => {
+  const foregrounds = [
+    { r: 255, g: 0, b: 0 },
+    { r: 0, g: 255, b: 0 },
+    { r: 0, g: 0, b: 255 },
+    { r: 255, g: 255, b: 255 },
+  ];
+  const observed = { r: 102, g: 77, b: 77 };
+  const black = { r: 0, g: 0, b: 0 };
+  const plain = unmixColor(observed, foregrounds, black, false);
+
+  t.true(Math.abs(plain.alpha - 0.502) < 0.001);
+  t.true(plain.reconstructionError < 1e-9);
+  t.deepEqual(unmixColor(observed, foregrounds, black, true), unmixColor(observed, foregrounds, black));
+});
+
+test('unmixColors - matches unmixColor for each observed color', (t) => {
+  const foregrounds = [
+    { r: 255, g: 0, b: 0 },
+    { r: 0, g: 0, b: 255 },
+  ];
+  const background = { r: 255, g: 255, b: 255 };
+  const observed = [
+    { r: 255, g: 128, b: 128 },
+    { r: 128, g: 0, b: 128 },
+    { r: 40, g: 200, b: 90 },
+  ];
+  const channels = new Float64Array(observed.flatMap(({ r, g, b }) => [r, g, b]));
+
+  const results = [unmixColors(observed, foregrounds, background), unmixColors(channels, foregrounds, background)];
+  for (const result of results) {
+    observed.forEach((color, i) => {
+      const single = unmixColor(color, foregrounds, background);
+      t.deepEqual([...result.weights.subarray(i * 2, i * 2 + 2)], single.weights);
+      t.is(result.alpha[i], single.alpha);
+      t.is(result.reconstructionError[i], single.reconstructionError);
+    });
+  }
+  t.throws(() => unmixColors(new Float64Array([1, 2]), foregrounds, background), { message: /triples/ });
+  t.throws(() => unmixColors(new Float64Array([1, 2, 256]), foregrounds, background), { message: /between 0 and 255/ });
+  t.throws(() => unmixColors(new Float64Array([-1, 2, 3]), foregrounds, background), { message: /between 0 and 255/ });
+  t.throws(() => unmixColors(new Float64Array([1, NaN, 3]), foregrounds, background), { message: /between 0 and 255/ });
+  t.throws(() => unmixColors(new Float64Array([1, 2, Infinity]), foregrounds, background), { message: /between 0 and 255/ });
+});
+
 // ============================================================================
 // computeUnmixResultColor
 // ============================================================================
@@ -349,6 +2691,47 @@ test('computeUnmixResultColor - respects alpha', (t) => {
   t.is(rgba.a, 128);
 });
 
+test('computeUnmixResultColor - clamps out-of-range colors by default', (t) => {
+  const colors = [
+    { r: 255, g: 0, b: 0 },
+    { r: 0, g: 0, b: 255 },
+  ];
+  const rgba = computeUnmixResultColor([1, -0.5], 1, colors);
+
+  t.deepEqual(rgba, { r: 255, g: 0, b: 0, a: 255 });
+});
+
+test('computeUnmixResultColor - scale gamut mapping keeps channels in proportion', (t) => {
+  const colors = [
+    { r: 255, g: 0, b: 0 },
+    { r: 0, g: 0, b: 255 },
+  ];
+  const rgba = computeUnmixResultColor([1, -0.5], 1, colors, 'scale');
+
+  t.true(rgba.r < 255);
+  t.true(rgba.g > 0);
+  t.true(rgba.r > rgba.g);
+  t.is(rgba.b, 0);
+});
+
+test('computeUnmixResultColor - scale leaves in-range colors unchanged', (t) => {
+  const rgba = computeUnmixResultColor(
+    [0.5, 0.5],
+    1,
+    [
+      { r: 255, g: 0, b: 0 },
+      { r: 0, g: 255, b: 0 },
+    ],
+    'scale',
+  );
+
+  t.deepEqual(rgba, { r: 128, g: 128, b: 0, a: 255 });
+});
+
+test('computeUnmixResultColor - throws on invalid gamut mapping', (t) => {
+  t.throws(() => computeUnmixResultColor([1], 1, [{ r: 255, g: 0, b: 0 }], 'wrap'));
+});
+
 // ============================================================================
 // compositeOverBackground
 // ============================================================================
@@ -392,6 +2775,70 @@ test('getDefaultThreshold - returns default value', (t) => {
   t.is(threshold, 0.05);
 });
 
+// ============================================================================
+// Tiny images
+// ============================================================================
+
+test('processImageSync - 1x1 image matching detected background becomes transparent', (t) => {
+  const output = processImageSync({
+    input: readFileSync(PIXEL_1X1_PATH),
+    strictMode: false,
+    trim: false,
+  });
+
+  t.deepEqual(readPngSize(output), { width: 1, height: 1 });
+  t.is(readFirstPixel(output)[3], 0);
+});
+
+test('processImageSync - 1x1 image with different background keeps the pixel', (t) => {
+  const output = processImageSync({
+    input: readFileSync(PIXEL_1X1_PATH),
+    backgroundColor: '#ffffff',
+    strictMode: false,
+    trim: true,
+  });
+
+  t.deepEqual(readPngSize(output), { width: 1, height: 1 });
+  t.deepEqual(readFirstPixel(output), [255, 0, 0, 255]);
+});
+
+test('processImageSync - 1x1 image in strict mode with adaptive background', (t) => {
+  const output = processImageSync({
+    input: readFileSync(PIXEL_1X1_PATH),
+    foregroundColors: ['auto'],
+    strictMode: true,
+    trim: true,
+    adaptiveBackground: true,
+  });
+
+  t.deepEqual(readPngSize(output), { width: 1, height: 1 });
+});
+
+test('processImageSync - 2x2 image trims to the single foreground pixel', (t) => {
+  const input = readFileSync(PIXEL_2X2_PATH);
+  t.deepEqual(detectBackgroundColor(input), { r: 255, g: 255, b: 255 });
+
+  const output = processImageSync({
+    input,
+    strictMode: false,
+    trim: true,
+  });
+
+  t.deepEqual(readPngSize(output), { width: 1, height: 1 });
+  t.deepEqual(readFirstPixel(output), [255, 0, 0, 255]);
+});
+
+test('processImageSync - outline surrounds the remaining content', (t) => {
+  const output = processImageSync({
+    input: readFileSync(PIXEL_2X2_PATH),
+    strictMode: false,
+    trim: true,
+    outline: { color: '#000000', width: 1 },
+  });
+
+  t.deepEqual(readPngSize(output), { width: 3, height: 3 });
+});
+
 // ============================================================================
 // Error handling
 // ============================================================================
@@ -440,6 +2887,44 @@ test('processImage - throws on invalid foreground color', async (t) => {
   });
 });
 
+test('processImage - throws when adaptive background is combined with background color', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  await t.throwsAsync(async () => {
+    await processImage({
+      input: inputBuffer,
+      backgroundColor: '#ffffff',
+      strictMode: false,
+      trim: false,
+      adaptiveBackground: true,
+    });
+  });
+});
+
+test('processImage - throws on invalid deduction weighting', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  await t.throwsAsync(async () => {
+    await processImage({
+      input: inputBuffer,
+      foregroundColors: ['auto'],
+      strictMode: false,
+      trim: false,
+      deductionWeighting: 'cubic',
+    });
+  });
+});
+
+test('processImage - throws on invalid outline opacity', async (t) => {
+  const inputBuffer = await readFile(INPUT_PATH);
+  await t.throwsAsync(async () => {
+    await processImage({
+      input: inputBuffer,
+      strictMode: false,
+      trim: false,
+      outline: { color: '#000000', width: 2, opacity: 1.5 },
+    });
+  });
+});
+
 test('detectBackgroundColor - throws on invalid image data', (t) => {
   t.throws(() => {
     detectBackgroundColor(Buffer.from('not an image'));
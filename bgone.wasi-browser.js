@@ -56,14 +56,62 @@ const {
   },
 })
 export default __napiModule.exports
+export const assessRemovability = __napiModule.exports.assessRemovability
+export const colorDistance = __napiModule.exports.colorDistance
 export const colorToNormalized = __napiModule.exports.colorToNormalized
 export const compositeOverBackground = __napiModule.exports.compositeOverBackground
 export const computeUnmixResultColor = __napiModule.exports.computeUnmixResultColor
 export const detectBackgroundColor = __napiModule.exports.detectBackgroundColor
+export const detectBackgroundColorDebug = __napiModule.exports.detectBackgroundColorDebug
+export const detectBackgroundColorRgba = __napiModule.exports.detectBackgroundColorRgba
+export const detectBackgroundColors = __napiModule.exports.detectBackgroundColors
+export const detectBackgroundUniformity = __napiModule.exports.detectBackgroundUniformity
+export const detectChromaKey = __napiModule.exports.detectChromaKey
+export const detectSolidColor = __napiModule.exports.detectSolidColor
+export const exportFavicon = __napiModule.exports.exportFavicon
+export const exportFaviconSync = __napiModule.exports.exportFaviconSync
+export const extractPalette = __napiModule.exports.extractPalette
+export const fitSubject = __napiModule.exports.fitSubject
+export const fitSubjectSync = __napiModule.exports.fitSubjectSync
 export const getDefaultThreshold = __napiModule.exports.getDefaultThreshold
+export const hslToRgb = __napiModule.exports.hslToRgb
+export const hsvToRgb = __napiModule.exports.hsvToRgb
+export const labToRgb = __napiModule.exports.labToRgb
+export const linearToSrgb = __napiModule.exports.linearToSrgb
 export const normalizedToColor = __napiModule.exports.normalizedToColor
+export const oklabToRgb = __napiModule.exports.oklabToRgb
 export const parseColor = __napiModule.exports.parseColor
 export const processImage = __napiModule.exports.processImage
+export const processImageEx = __napiModule.exports.processImageEx
+export const processImageFromConfig = __napiModule.exports.processImageFromConfig
+export const processImageLayers = __napiModule.exports.processImageLayers
+export const processImageLayersSync = __napiModule.exports.processImageLayersSync
+export const processImageRaw = __napiModule.exports.processImageRaw
+export const processImageStream = __napiModule.exports.processImageStream
 export const processImageSync = __napiModule.exports.processImageSync
+export const processImageTiles = __napiModule.exports.processImageTiles
+export const processImageToDataUrl = __napiModule.exports.processImageToDataUrl
+export const processImageToDataUrlSync = __napiModule.exports.processImageToDataUrlSync
+export const processImageWeightMaps = __napiModule.exports.processImageWeightMaps
+export const processImageWeightMapsSync = __napiModule.exports.processImageWeightMapsSync
+export const processImageWithMask = __napiModule.exports.processImageWithMask
+export const processWithReference = __napiModule.exports.processWithReference
+export const processWithReferenceSync = __napiModule.exports.processWithReferenceSync
+export const quantize = __napiModule.exports.quantize
+export const quantizeSync = __napiModule.exports.quantizeSync
+export const recolor = __napiModule.exports.recolor
+export const recolorSync = __napiModule.exports.recolorSync
+export const rgbToHsl = __napiModule.exports.rgbToHsl
+export const rgbToHsv = __napiModule.exports.rgbToHsv
+export const rgbToLab = __napiModule.exports.rgbToLab
+export const rgbToOklab = __napiModule.exports.rgbToOklab
+export const separateLayers = __napiModule.exports.separateLayers
+export const separateLayersSync = __napiModule.exports.separateLayersSync
+export const splitColorAndMatte = __napiModule.exports.splitColorAndMatte
+export const splitColorAndMatteSync = __napiModule.exports.splitColorAndMatteSync
+export const srgbToLinear = __napiModule.exports.srgbToLinear
+export const subjectAverageColor = __napiModule.exports.subjectAverageColor
+export const subjectAverageColorSync = __napiModule.exports.subjectAverageColorSync
 export const trimImage = __napiModule.exports.trimImage
 export const unmixColor = __napiModule.exports.unmixColor
+export const unmixColors = __napiModule.exports.unmixColors
@@ -108,14 +108,62 @@ const { instance: __napiInstance, module: __wasiModule, napiModule: __napiModule
   },
 })
 module.exports = __napiModule.exports
+module.exports.assessRemovability = __napiModule.exports.assessRemovability
+module.exports.colorDistance = __napiModule.exports.colorDistance
 module.exports.colorToNormalized = __napiModule.exports.colorToNormalized
 module.exports.compositeOverBackground = __napiModule.exports.compositeOverBackground
 module.exports.computeUnmixResultColor = __napiModule.exports.computeUnmixResultColor
 module.exports.detectBackgroundColor = __napiModule.exports.detectBackgroundColor
+module.exports.detectBackgroundColorDebug = __napiModule.exports.detectBackgroundColorDebug
+module.exports.detectBackgroundColorRgba = __napiModule.exports.detectBackgroundColorRgba
+module.exports.detectBackgroundColors = __napiModule.exports.detectBackgroundColors
+module.exports.detectBackgroundUniformity = __napiModule.exports.detectBackgroundUniformity
+module.exports.detectChromaKey = __napiModule.exports.detectChromaKey
+module.exports.detectSolidColor = __napiModule.exports.detectSolidColor
+module.exports.exportFavicon = __napiModule.exports.exportFavicon
+module.exports.exportFaviconSync = __napiModule.exports.exportFaviconSync
+module.exports.extractPalette = __napiModule.exports.extractPalette
+module.exports.fitSubject = __napiModule.exports.fitSubject
+module.exports.fitSubjectSync = __napiModule.exports.fitSubjectSync
 module.exports.getDefaultThreshold = __napiModule.exports.getDefaultThreshold
+module.exports.hslToRgb = __napiModule.exports.hslToRgb
+module.exports.hsvToRgb = __napiModule.exports.hsvToRgb
+module.exports.labToRgb = __napiModule.exports.labToRgb
+module.exports.linearToSrgb = __napiModule.exports.linearToSrgb
 module.exports.normalizedToColor = __napiModule.exports.normalizedToColor
+module.exports.oklabToRgb = __napiModule.exports.oklabToRgb
 module.exports.parseColor = __napiModule.exports.parseColor
 module.exports.processImage = __napiModule.exports.processImage
+module.exports.processImageEx = __napiModule.exports.processImageEx
+module.exports.processImageFromConfig = __napiModule.exports.processImageFromConfig
+module.exports.processImageLayers = __napiModule.exports.processImageLayers
+module.exports.processImageLayersSync = __napiModule.exports.processImageLayersSync
+module.exports.processImageRaw = __napiModule.exports.processImageRaw
+module.exports.processImageStream = __napiModule.exports.processImageStream
 module.exports.processImageSync = __napiModule.exports.processImageSync
+module.exports.processImageTiles = __napiModule.exports.processImageTiles
+module.exports.processImageToDataUrl = __napiModule.exports.processImageToDataUrl
+module.exports.processImageToDataUrlSync = __napiModule.exports.processImageToDataUrlSync
+module.exports.processImageWeightMaps = __napiModule.exports.processImageWeightMaps
+module.exports.processImageWeightMapsSync = __napiModule.exports.processImageWeightMapsSync
+module.exports.processImageWithMask = __napiModule.exports.processImageWithMask
+module.exports.processWithReference = __napiModule.exports.processWithReference
+module.exports.processWithReferenceSync = __napiModule.exports.processWithReferenceSync
+module.exports.quantize = __napiModule.exports.quantize
+module.exports.quantizeSync = __napiModule.exports.quantizeSync
+module.exports.recolor = __napiModule.exports.recolor
+module.exports.recolorSync = __napiModule.exports.recolorSync
+module.exports.rgbToHsl = __napiModule.exports.rgbToHsl
+module.exports.rgbToHsv = __napiModule.exports.rgbToHsv
+module.exports.rgbToLab = __napiModule.exports.rgbToLab
+module.exports.rgbToOklab = __napiModule.exports.rgbToOklab
+module.exports.separateLayers = __napiModule.exports.separateLayers
+module.exports.separateLayersSync = __napiModule.exports.separateLayersSync
+module.exports.splitColorAndMatte = __napiModule.exports.splitColorAndMatte
+module.exports.splitColorAndMatteSync = __napiModule.exports.splitColorAndMatteSync
+module.exports.srgbToLinear = __napiModule.exports.srgbToLinear
+module.exports.subjectAverageColor = __napiModule.exports.subjectAverageColor
+module.exports.subjectAverageColorSync = __napiModule.exports.subjectAverageColorSync
 module.exports.trimImage = __napiModule.exports.trimImage
 module.exports.unmixColor = __napiModule.exports.unmixColor
+module.exports.unmixColors = __napiModule.exports.unmixColors
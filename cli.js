@@ -17,6 +17,7 @@ program
   .option('-f, --fg <colors...>', 'Foreground colors (hex or "auto" for deduction)')
   .option('-s, --strict', 'Strict mode - only use specified foreground colors', false)
   .option('-t, --threshold <value>', 'Color closeness threshold (0.0-1.0)', parseFloat)
+  .option('--threshold-units <units>', 'Units of --threshold: "normalized" (0.0-1.0) or "8bit" (0-255)')
   .option('--trim', 'Trim output to content bounding box', false)
   .option('--detect', 'Only detect and print background color, do not process')
   .action((input, output, options) => {
@@ -55,7 +56,7 @@ program
     }
 
     if (options.threshold !== undefined) {
-      console.log(`  Threshold: ${options.threshold}`);
+      console.log(`  Threshold: ${options.threshold}${options.thresholdUnits ? ` (${options.thresholdUnits})` : ''}`);
     }
 
     if (options.trim) {
@@ -69,6 +70,7 @@ program
         foregroundColors: options.fg,
         strictMode: options.strict,
         threshold: options.threshold,
+        thresholdUnits: options.thresholdUnits,
         trim: options.trim,
       });
 
@@ -1,5 +1,119 @@
 /* auto-generated by NAPI-RS */
 /* eslint-disable */
+/**
+ * Score how suitable an image is for automatic background removal
+ *
+ * A fast pre-check that detects the background and measures how consistently it lines
+ * the edges and how much of the image it covers from the edges inwards, without
+ * processing the pixels. Images with a clean, uniform background score close to 1.0;
+ * route low-scoring images to manual handling.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ *
+ * # Returns
+ * The suitability score (0.0-1.0)
+ */
+export declare function assessRemovability(input: Uint8Array | ArrayBuffer | RawImage | string): number
+
+export interface BackgroundCandidate {
+  /** The edge color */
+  color: RgbColor
+  /** How many edge samples have this color */
+  samples: number
+  /** The share of all edge samples that have this color (0.0-1.0) */
+  confidence: number
+}
+
+export interface BackgroundDetectionDebug {
+  /** The detected background color */
+  color: RgbColor
+  /** Every edge sample, in sampling order */
+  samples: Array<EdgeSample>
+  /** The sampled colors with their counts, most common first */
+  histogram: Array<BackgroundCandidate>
+}
+
+export interface BackgroundDetectionOptions {
+  /**
+   * Sample every N pixels along the edges (default: 10). Lower values sample more densely,
+   * which helps small images and busy edges at a small cost in speed.
+   */
+  edgeSampleInterval?: number
+  /**
+   * Which pixels are sampled: "corners" (the four corners only), "edges" (default, the
+   * corners and along the edges), "border" (a ring `border_depth` pixels deep, for frames and
+   * title bars) or "dominant" (across the whole image)
+   */
+  strategy?: string
+  /** How many pixels deep the ring sampled by the "border" strategy is (default: 5) */
+  borderDepth?: number
+  /** Rectangles to leave out of the samples, such as a watermark or logo stamped in a corner */
+  excludeRegions?: Array<RegionSpec>
+  /**
+   * How the background color is estimated from the samples: "mode" (default, the most common
+   * sample), "median" (per channel) or "trimmed_mean" (per channel, over the middle half).
+   * The latter two suit noisy camera images where no two samples are identical. The luminance
+   * bias only applies to "mode".
+   */
+  estimator?: string
+}
+
+export interface BackgroundUniformity {
+  /** The detected background color */
+  color: RgbColor
+  /**
+   * The share of the edge samples within the threshold of `color` (0.0-1.0). Low values
+   * mean the edges are not uniform and the detected color is likely wrong.
+   */
+  uniformity: number
+  /** How many edge samples are within the threshold of `color` */
+  matchingSamples: number
+  /** How many edge samples were taken */
+  samples: number
+}
+
+export interface ChromaKeyDetection {
+  /** The key color, or the detected background color if the background is not a chroma key */
+  color: RgbColor
+  /** Whether the background is a saturated green or blue screen */
+  chromaKey: boolean
+  /** The key hue in degrees (0.0-360.0), if the background is a chroma key */
+  hue?: number
+  /**
+   * The share of the edge samples with the key hue (0.0-1.0), if the background is a chroma
+   * key
+   */
+  coverage?: number
+}
+
+/**
+ * Measure how different two colors are
+ *
+ * # Arguments
+ * * `a` - The first color, as a CSS color string, an RGB object or an `[r, g, b]` array
+ * * `b` - The second color
+ * * `metric` - "rgb" (default) for the normalized RGB distance (0.0-1.73) the closeness
+ *   threshold uses, "cie76" for the CIELAB distance, or "deltaE2000" for the CIEDE2000
+ *   difference (about 1.0 is a just noticeable difference; the "deltaE2000" threshold space
+ *   divides it by 100)
+ *
+ * # Returns
+ * The distance between the colors
+ */
+export declare function colorDistance(a: string | RgbColor | Array<number>, b: string | RgbColor | Array<number>, metric?: string | undefined | null): number
+
+export interface ColorMatteResult {
+  /**
+   * The color channels as an opaque image (PNG unless `output_format` says otherwise), with
+   * straight (unassociated) alpha
+   */
+  color: Buffer
+  /** The alpha matte as a grayscale image, in the same format and bit depth as the color */
+  matte: Buffer
+}
+
 /**
  * Convert an RGB color (0-255) to a normalized RGB color (0.0-1.0)
  *
@@ -33,22 +147,236 @@ export declare function compositeOverBackground(pixel: RgbaColor, background: Rg
  * * `weights` - The weights for each foreground color
  * * `alpha` - The alpha value
  * * `foreground_colors` - The foreground colors
+ * * `gamut_mapping` - How an out-of-range color is brought back into gamut: "clamp" (default) or
+ *   "scale"
  *
  * # Returns
  * The computed RGBA color
  */
-export declare function computeUnmixResultColor(weights: Array<number>, alpha: number, foregroundColors: Array<RgbColor>): RgbaColor
+export declare function computeUnmixResultColor(weights: Array<number>, alpha: number, foregroundColors: Array<RgbColor>, gamutMapping?: string | undefined | null): RgbaColor
 
 /**
  * Detect the background color of an image by sampling its edges
  *
  * # Arguments
- * * `input` - The input image buffer
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `luminance_bias` - Which common edge color to prefer: "none" (default), "lightest" or
+ *   "darkest"
+ * * `detection` - How the edges are sampled
  *
  * # Returns
  * The detected background color
  */
-export declare function detectBackgroundColor(input: Buffer): RgbColor
+export declare function detectBackgroundColor(input: Uint8Array | ArrayBuffer | RawImage | string, luminanceBias?: string | undefined | null, detection?: BackgroundDetectionOptions | undefined | null): RgbColor
+
+/**
+ * Detect the background color of an image, along with the samples the choice was based on
+ *
+ * For tooling that shows why a background was picked, e.g. by drawing the sampled pixels
+ * over the image and charting the histogram.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `luminance_bias` - Which common edge color to prefer: "none" (default), "lightest" or
+ *   "darkest"
+ * * `detection` - How the edges are sampled
+ *
+ * # Returns
+ * The detected background color, every edge sample and the histogram of sampled colors
+ */
+export declare function detectBackgroundColorDebug(input: Uint8Array | ArrayBuffer | RawImage | string, luminanceBias?: string | undefined | null, detection?: BackgroundDetectionOptions | undefined | null): BackgroundDetectionDebug
+
+/**
+ * Detect the background color of an image, including its alpha, by sampling its edges
+ *
+ * Votes on the full RGBA values of the edge samples instead of compositing translucent
+ * pixels over black, so a semi-transparent background is reported with its real alpha.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `luminance_bias` - Which common edge color to prefer: "none" (default), "lightest" or
+ *   "darkest"
+ * * `detection` - How the edges are sampled
+ *
+ * # Returns
+ * The detected background color and alpha
+ */
+export declare function detectBackgroundColorRgba(input: Uint8Array | ArrayBuffer | RawImage | string, luminanceBias?: string | undefined | null, detection?: BackgroundDetectionOptions | undefined | null): RgbaColor
+
+/**
+ * List the most common edge colors of an image as background candidates
+ *
+ * Useful when the edges aren't uniform, to let users pick the background among the
+ * candidates instead of relying on the single most common color.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `count` - How many candidates to return at most (default: 5)
+ * * `detection` - How the edges are sampled
+ *
+ * # Returns
+ * The candidate colors, most common first, with their sample counts and share of the samples
+ */
+export declare function detectBackgroundColors(input: Uint8Array | ArrayBuffer | RawImage | string, count?: number | undefined | null, detection?: BackgroundDetectionOptions | undefined | null): Array<BackgroundCandidate>
+
+/**
+ * Detect the background color of an image and measure how uniformly it lines the edges
+ *
+ * Use this to flag images where auto-detection is likely wrong (busy or mixed edges)
+ * before processing them, instead of silently getting a poor result.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `luminance_bias` - Which common edge color to prefer: "none" (default), "lightest" or
+ *   "darkest"
+ * * `detection` - How the edges are sampled
+ * * `threshold` - The normalized RGB distance within which an edge sample matches (default: 0.05)
+ *
+ * # Returns
+ * The detected background color with the share and number of matching edge samples
+ */
+export declare function detectBackgroundUniformity(input: Uint8Array | ArrayBuffer | RawImage | string, luminanceBias?: string | undefined | null, detection?: BackgroundDetectionOptions | undefined | null, threshold?: number | undefined | null): BackgroundUniformity
+
+/**
+ * Detect whether the background of an image is a green or blue screen, and its key color
+ *
+ * The key color is picked from the hue of the edge samples rather than their most common
+ * color, since lit screens are rarely uniform. When the edges are not mostly a saturated
+ * green or blue, the usual detected background color is returned instead.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `detection` - How the edges are sampled
+ *
+ * # Returns
+ * The key or background color, flagged as a chroma key or not
+ */
+export declare function detectChromaKey(input: Uint8Array | ArrayBuffer | RawImage | string, detection?: BackgroundDetectionOptions | undefined | null): ChromaKeyDetection
+
+/**
+ * Check whether an image consists of a single solid color
+ *
+ * Processing such an image with an auto-detected background makes it fully transparent,
+ * since its only color is the background. Use this to catch blank placeholders up front.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ *
+ * # Returns
+ * The image's only color, or null if it has more than one
+ */
+export declare function detectSolidColor(input: Uint8Array | ArrayBuffer | RawImage | string): RgbaColor | null
+
+export interface EdgeSample {
+  /** The x coordinate of the sampled pixel */
+  x: number
+  /** The y coordinate of the sampled pixel */
+  y: number
+  /** The sampled color, composited over black if translucent */
+  color: RgbColor
+}
+
+/**
+ * Remove the background asynchronously and bundle the subject at several icon sizes into
+ * one ICO file
+ *
+ * The processed image is trimmed to its content, then scaled to fit and centered on a
+ * transparent square canvas for each size. Each icon is stored as PNG inside the ICO, so
+ * `output_format` doesn't apply.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ * * `sizes` - The icon widths and heights in pixels, 1-256 each (default: 16, 32, 48, 256)
+ *
+ * # Returns
+ * A promise that resolves to the icon file buffer (ICO format), with its icons ordered from
+ * smallest to largest
+ */
+export declare function exportFavicon(options: ProcessImageOptions, sizes?: Array<number> | undefined | null): Promise<Buffer>
+
+/**
+ * Remove the background synchronously and bundle the subject at several icon sizes into one
+ * ICO file
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ * * `sizes` - The icon widths and heights in pixels, 1-256 each (default: 16, 32, 48, 256)
+ *
+ * # Returns
+ * The icon file buffer (ICO format), with its icons ordered from smallest to largest
+ */
+export declare function exportFaviconSync(options: ProcessImageOptions, sizes?: Array<number> | undefined | null): Buffer
+
+/**
+ * Extract the dominant colors of an image, to help choose foreground colors
+ *
+ * Splits the image's colors into at most `max_colors` groups by median cut and returns
+ * the average color of each group. Fully transparent pixels are ignored.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `max_colors` - The maximum number of colors in the palette (1-256)
+ *
+ * # Returns
+ * The palette colors, most common first, with their pixel counts and share of the pixels
+ */
+export declare function extractPalette(input: Uint8Array | ArrayBuffer | RawImage | string, maxColors: number): Array<PaletteColor>
+
+/**
+ * Remove the background asynchronously and center the subject on a transparent canvas of a
+ * fixed size
+ *
+ * The processed image is trimmed to its content, scaled up or down to fit inside the
+ * target size minus the margin, and centered. Useful for normalizing product photos
+ * to uniform thumbnails.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ * * `target_width` - The output width in pixels
+ * * `target_height` - The output height in pixels
+ * * `margin_ratio` - The margin on every side as a fraction of the output size
+ *   (0.0-0.5, default: 0.0)
+ *
+ * # Returns
+ * A promise that resolves to the output image buffer of exactly the target size (PNG unless
+ * `output_format` says otherwise)
+ */
+export declare function fitSubject(options: ProcessImageOptions, targetWidth: number, targetHeight: number, marginRatio?: number | undefined | null): Promise<Buffer>
+
+/**
+ * Remove the background synchronously and center the subject on a transparent canvas of a
+ * fixed size
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ * * `target_width` - The output width in pixels
+ * * `target_height` - The output height in pixels
+ * * `margin_ratio` - The margin on every side as a fraction of the output size
+ *   (0.0-0.5, default: 0.0)
+ *
+ * # Returns
+ * The output image buffer of exactly the target size (PNG unless `output_format` says otherwise)
+ */
+export declare function fitSubjectSync(options: ProcessImageOptions, targetWidth: number, targetHeight: number, marginRatio?: number | undefined | null): Buffer
+
+export interface FrameMargins {
+  /** The height of the top side of the frame in pixels */
+  top: number
+  /** The width of the right side of the frame in pixels */
+  right: number
+  /** The height of the bottom side of the frame in pixels */
+  bottom: number
+  /** The width of the left side of the frame in pixels */
+  left: number
+}
 
 /**
  * Get the default threshold for color closeness
@@ -58,6 +386,85 @@ export declare function detectBackgroundColor(input: Buffer): RgbColor
  */
 export declare function getDefaultThreshold(): number
 
+export interface HslColor {
+  /** The hue in degrees (0.0-360.0) */
+  h: number
+  /** The saturation (0.0-1.0) */
+  s: number
+  /** The lightness (0.0-1.0) */
+  l: number
+}
+
+/**
+ * Convert an HSL color to RGB (0-255)
+ *
+ * # Arguments
+ * * `color` - The hue in degrees and the saturation and lightness (0.0-1.0)
+ *
+ * # Returns
+ * The RGB color
+ */
+export declare function hslToRgb(color: HslColor): RgbColor
+
+export interface HsvColor {
+  /** The hue in degrees (0.0-360.0) */
+  h: number
+  /** The saturation (0.0-1.0) */
+  s: number
+  /** The value (0.0-1.0) */
+  v: number
+}
+
+/**
+ * Convert an HSV color to RGB (0-255)
+ *
+ * # Arguments
+ * * `color` - The hue in degrees and the saturation and value (0.0-1.0)
+ *
+ * # Returns
+ * The RGB color
+ */
+export declare function hsvToRgb(color: HsvColor): RgbColor
+
+export interface ImageWithMask {
+  /** The processed image buffer, encoded like the output of `process_image` */
+  image: Buffer
+  /** The alpha of the processed image as a grayscale PNG, at the output bit depth */
+  mask: Buffer
+}
+
+export interface LabColor {
+  /** The lightness: 0.0-100.0 in CIELAB, 0.0-1.0 in OKLab */
+  l: number
+  /** The green-red axis */
+  a: number
+  /** The blue-yellow axis */
+  b: number
+}
+
+/**
+ * Convert a CIELAB color under the D65 white point to sRGB (0-255)
+ *
+ * # Arguments
+ * * `color` - The CIELAB color
+ *
+ * # Returns
+ * The RGB color, with colors outside the sRGB gamut clamped into it
+ */
+export declare function labToRgb(color: LabColor): RgbColor
+
+/**
+ * Encode linear light values (0.0-1.0) with the sRGB transfer function, the inverse of
+ * `srgb_to_linear`
+ *
+ * # Arguments
+ * * `value` - A single channel value, or any number of them (e.g. the channels of a color)
+ *
+ * # Returns
+ * The sRGB-encoded value, or values in the same order
+ */
+export declare function linearToSrgb(value: number | Array<number>): number | Array<number>
+
 export interface NormalizedRgbColor {
   r: number
   g: number
@@ -76,17 +483,105 @@ export interface NormalizedRgbColor {
 export declare function normalizedToColor(color: NormalizedRgbColor): RgbColor
 
 /**
- * Parse a hex color string into an RGB color
+ * Convert an OKLab color to sRGB (0-255)
  *
- * Supports formats: "#ff0000", "ff0000", "#f00", "f00"
+ * # Arguments
+ * * `color` - The OKLab color
+ *
+ * # Returns
+ * The RGB color, with colors outside the sRGB gamut clamped into it
+ */
+export declare function oklabToRgb(color: LabColor): RgbColor
+
+export interface OutlineSpec {
+  /** The stroke color as a CSS color string (hex, rgb(), hsl() or a name) */
+  color: string
+  /** The stroke width in pixels */
+  width: number
+  /** The stroke opacity (0.0-1.0, default: 1.0) */
+  opacity?: number
+}
+
+export interface PaletteColor {
+  /** The average color of the pixels in this palette entry */
+  color: RgbColor
+  /** How many pixels belong to this palette entry */
+  pixels: number
+  /** The share of all non-transparent pixels that belong to this palette entry (0.0-1.0) */
+  share: number
+}
+
+/**
+ * Parse a CSS color string into an RGB color
+ *
+ * Supports formats: "#ff0000", "ff0000", "#f00", "f00", "rgb(255, 0, 0)", "rgba(255 0 0 / 1)",
+ * "hsl(0, 100%, 50%)", "hsla(0deg 100% 50% / 1)" and named colors such as "red"
  *
  * # Arguments
- * * `hex` - The hex color string
+ * * `color` - The color string
  *
  * # Returns
  * The parsed RGB color
  */
-export declare function parseColor(hex: string): RgbColor
+export declare function parseColor(color: string): RgbColor
+
+export interface PngEncodeOptions {
+  /** How hard to compress: "none", "fastest", "fast", "balanced" (default) or "smallest" */
+  compression?: string
+  /** The row filter: "none", "sub", "up", "avg", "paeth" or "adaptive" (default) */
+  filter?: string
+  /** Whether to write Adam7 interlaced rows (default: false) */
+  interlaced?: boolean
+  /**
+   * Whether to carry the DPI (`pHYs`), gamma (`gAMA`) and text chunks of a PNG input over to
+   * the output (default: true)
+   */
+  preserveMetadata?: boolean
+}
+
+export interface ProcessedImage {
+  /** The processed image buffer, in `format` */
+  data: Buffer
+  /** The image width in pixels, after trimming and outlining */
+  width: number
+  /** The image height in pixels, after trimming and outlining */
+  height: number
+  /**
+   * The format of `data`: "png" (also for indexed PNG), "apng", "webp", "avif", "tiff",
+   * "jpeg", "gif" or "raw"
+   */
+  format: string
+  /** Whether `trim` cut off any transparent rows or columns */
+  trimmed: boolean
+  /** The margins `strip_frame` cut off the input, if a frame was found */
+  frame?: FrameMargins
+  /**
+   * Whether the input is a single solid color that was detected as the background, which
+   * leaves the output fully transparent
+   */
+  solidColor: boolean
+  /**
+   * The share of edge samples matching the detected background color (0.0 to 1.0), if a
+   * single background color was auto-detected; below 0.5 the detection is likely wrong
+   */
+  backgroundUniformity?: number
+}
+
+export interface ProcessedTile {
+  /** The x coordinate of the tile's top-left corner in the unprocessed image */
+  x: number
+  /** The y coordinate of the tile's top-left corner in the unprocessed image */
+  y: number
+  /** The tile width in pixels */
+  width: number
+  /** The tile height in pixels */
+  height: number
+  /**
+   * The processed tile, encoded like `process_image` output (PNG unless `output_format` says
+   * otherwise)
+   */
+  data: Buffer
+}
 
 /**
  * Process an image asynchronously to remove its background
@@ -98,25 +593,429 @@ export declare function parseColor(hex: string): RgbColor
  * * `options` - The options for the image processing
  *
  * # Returns
- * A promise that resolves to the processed image buffer (PNG format)
+ * A promise that resolves to the processed image buffer (PNG unless `output_format` says
+ * otherwise)
  */
 export declare function processImage(options: ProcessImageOptions): Promise<Buffer>
 
+/**
+ * Process an image asynchronously and return the output along with its size and format
+ *
+ * Saves decoding the output just to learn its dimensions after `trim`.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to the processed image buffer with its width, height and format
+ */
+export declare function processImageEx(options: ProcessImageOptions): Promise<ProcessedImage>
+
+/**
+ * Process an image asynchronously using options stored as a JSON preset
+ *
+ * The preset uses the same fields as `ProcessImageOptions` (camelCase, without `input`);
+ * omitted fields take their default values and unknown fields are rejected.
+ *
+ * # Arguments
+ * * `input` - The input image buffer
+ * * `config_json` - The processing options as a JSON string
+ *
+ * # Returns
+ * A promise that resolves to the processed image buffer (PNG unless `output_format` says
+ * otherwise)
+ */
+export declare function processImageFromConfig(input: Uint8Array | ArrayBuffer, configJson: string): Promise<Buffer>
+
+/**
+ * Split an image asynchronously into one transparent layer per foreground color
+ *
+ * Each layer is filled with its foreground color and uses that color's unmix weight
+ * as alpha, so a pixel's contributions are spread across the layers. Adding up the
+ * premultiplied layers (color times alpha) plus the background times one minus their
+ * total alpha reconstructs the input. Requires at least one foreground color (or "auto").
+ * With `trim` enabled all layers are cropped to the same bounding box; `outline` is ignored.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to one image buffer (PNG unless `output_format` says otherwise)
+ * per foreground color, in the order they were specified
+ */
+export declare function processImageLayers(options: ProcessImageOptions): Promise<Array<Buffer>>
+
+/**
+ * Split an image synchronously into one transparent layer per foreground color
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * One image buffer (PNG unless `output_format` says otherwise) per foreground color, in the
+ * order they were specified
+ */
+export declare function processImageLayersSync(options: ProcessImageOptions): Array<Buffer>
+
+/**
+ * Process an image asynchronously and return the unencoded RGBA result
+ *
+ * Skips output encoding entirely, for piping the result straight into another image
+ * library. `output_format` and the encoder settings are ignored.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to the raw RGBA bytes with the image width and height
+ */
+export declare function processImageRaw(options: ProcessImageOptions): Promise<RawImage>
+
 export interface ProcessImageOptions {
-  /** The input image buffer */
-  input: Buffer
-  /** The foreground colors to match, if any. Use "auto" to deduce unknown colors. */
-  foregroundColors?: Array<string>
-  /** The background color to remove. If not specified, it will be auto-detected. */
-  backgroundColor?: string
+  /**
+   * The encoded input image (a Buffer, any typed array or DataView, or an ArrayBuffer), a
+   * base64 `data:` URL, or raw RGBA pixels with their dimensions
+   */
+  input: Uint8Array | ArrayBuffer | RawImage | string
+  /**
+   * The foreground colors to match, if any. Use "auto" to deduce unknown colors.
+   * Append "@tolerance" (e.g. "ff0000@0.05") to treat pixels within that normalized RGB
+   * distance of the color as fully that color in strict mode. A color with alpha (e.g.
+   * "#ff0000f3") has a tolerance of one minus its alpha. Colors may also be given as RGB
+   * objects or `[r, g, b]` arrays.
+   */
+  foregroundColors?: Array<string | RgbColor | Array<number>>
+  /**
+   * The background color to remove, or several (e.g. `["#ffffff", "#fefefe"]` or the two
+   * tones of a split backdrop) to unmix each pixel against the nearest one. If not specified,
+   * it will be auto-detected. Append "~tolerance" (e.g. "ffffff~0.04" or "ffffff~4%") to
+   * treat pixels within that normalized RGB distance of the color as pure background. A color
+   * with alpha (e.g. "#fffffff5") has a tolerance of one minus its alpha. Colors may also be
+   * given as RGB objects or `[r, g, b]` arrays.
+   */
+  backgroundColor?: string | RgbColor | Array<number> | Array<string | RgbColor | Array<number>>
+  /**
+   * Which edge color to prefer when auto-detecting the background: "none" (default, the most
+   * common one), "lightest" or "darkest". Only colors with at least half the votes of the most
+   * common one are considered.
+   */
+  backgroundLuminanceBias?: string
+  /** How the background is sampled when it is auto-detected or estimated per pixel */
+  backgroundDetection?: BackgroundDetectionOptions
+  /**
+   * Whether to look for a green or blue screen when auto-detecting the background (default:
+   * false). The key color is picked from the hue of the edges rather than their most common
+   * color, which is more reliable on unevenly lit screens. Falls back to the usual detection
+   * when the edges are not a chroma key.
+   */
+  chromaKey?: boolean
   /** Whether to use strict mode. Restricts unmixing to only the specified foreground colors. */
   strictMode: boolean
-  /** The threshold for color closeness (0.0-1.0, default: 0.05) */
+  /** The threshold for color closeness (0.0-1.0, default: 0.05), in `threshold_units` */
   threshold?: number
+  /**
+   * The units `threshold` is given in: "normalized" (default, a fraction of the full range) or
+   * "8bit" (channel levels out of 255, so 12 is the same as 12 / 255). 8-bit units only apply
+   * to the "rgb" threshold space.
+   */
+  thresholdUnits?: string
+  /**
+   * How the closeness threshold and color tolerances are measured: "rgb" (default, normalized
+   * RGB distance) or "deltaE2000" (the perceptual CIEDE2000 difference divided by 100, so 0.05
+   * is a difference of 5). RGB distance is lenient with dark colors and strict with light ones.
+   */
+  thresholdSpace?: string
+  /**
+   * How far (normalized RGB distance, default: 0.01) an opaque mix of foreground colors may
+   * miss the observed color and still be preferred over a closer, translucent one. Raise it
+   * for noisy or JPEG inputs; higher values trade color accuracy for cleaner, more opaque edges.
+   */
+  reconstructionTolerance?: number
+  /**
+   * The most foreground colors a single pixel is unmixed into (at least 1, default: all of
+   * them). Every combination of that many colors is searched for the best mix, e.g. 3 for
+   * flat designs whose colors meet at corners but never blend four at once. Depths that give
+   * more than 256 combinations of the foreground colors are rejected.
+   */
+  unmixSearchDepth?: number
+  /**
+   * Pixels whose channels all differ from the background by less than this (0-255) are made
+   * fully transparent in non-strict mode (default: exact matches only). Zeroes out slightly
+   * noisy backgrounds without loosening `threshold`.
+   */
+  backgroundEpsilon?: number
+  /**
+   * How unmixed colors that fall outside the displayable range are brought back into it:
+   * "clamp" (default) clamps each channel, "scale" desaturates the color towards its gray
+   * until it fits, which keeps the hue of bright, saturated pixels.
+   */
+  gamutMapping?: string
+  /**
+   * The color space the closeness test and unmixing happen in: "srgb" (the default for 8 and
+   * 16-bit inputs) or "oklab", which estimates alpha much better on anti-aliased edges between
+   * perceptually distant colors. The closeness threshold is then measured in OKLab.
+   */
+  unmixSpace?: string
+  /**
+   * Whether to composite and unmix in linear light, converting back to sRGB for the output
+   * (default: false, or true for floating point inputs). Blending in gamma-encoded sRGB leaves
+   * dark fringes on soft edges; closeness is then measured between linear colors. Cannot be
+   * combined with `unmix_space`.
+   */
+  linearLight?: boolean
+  /**
+   * How the foreground was blended onto the background: "normal" (default, alpha-over),
+   * "add" or "screen" for glows, lens flares and particles rendered additively, or "multiply"
+   * for ink on paper (sketches, signatures, stamps), where alpha is the ink density. Additive
+   * content is unmixed into colors that reproduce the input composited normally over black,
+   * multiplied content into colors that reproduce it over white, or blended back with the
+   * same mode. Cannot be combined with the "oklab" unmix space.
+   */
+  blendMode?: string
   /** Whether to trim the output image to the bounding box of non-transparent pixels */
   trim: boolean
+  /**
+   * Whether to record where the trimmed image sat in the original in a PNG `oFFs` chunk
+   * (in pixels), so the placement travels with the file. Only has an effect with `trim`.
+   */
+  embedCropOffset?: boolean
+  /**
+   * The format of the output image: "png" (default), "indexed" (palette PNG with `tRNS`
+   * transparency), "webp" (lossless, keeps alpha exact), "avif" (lossy, with alpha), "tiff",
+   * "jpeg" (flattened over `jpeg_matte_color`), "apng" (animated PNG), "gif" (transparency on or
+   * off per pixel) or "raw" (unencoded RGBA bytes, row by row). The crop offset is only embedded
+   * in PNG output. Animated GIF, APNG and WebP inputs are processed frame by frame and written in
+   * their own format (or as "apng", "webp" or "gif"), keeping frame delays and looping; other
+   * formats only process the first frame. PNG output of grayscale inputs is written as grayscale
+   * with alpha as long as every output pixel is gray.
+   */
+  outputFormat?: string
+  /**
+   * The bits per channel of the output image: 8 (default) or 16. 16-bit output keeps the full
+   * precision of 16-bit inputs and is supported for "png", "tiff" and "raw" (little-endian
+   * samples); it cannot be combined with `outline`.
+   */
+  outputBitDepth?: number
+  /**
+   * How hard PNG output is compressed: "none", "fastest", "fast", "balanced" (default) or
+   * "smallest". Faster levels make batch jobs much quicker at the cost of larger files.
+   */
+  pngCompression?: string
+  /** The row filter of PNG output: "none", "sub", "up", "avg", "paeth" or "adaptive" (default) */
+  pngFilter?: string
+  /** Whether PNG output is written with Adam7 interlacing (default: false) */
+  pngInterlaced?: boolean
+  /**
+   * The maximum number of palette entries of indexed output (1-256, default: 256), counting
+   * the one shared by fully transparent pixels
+   */
+  paletteColors?: number
+  /** Whether to apply Floyd-Steinberg dithering when quantizing indexed output (default: false) */
+  paletteDither?: boolean
+  /**
+   * Whether WebP output keeps every pixel exact (default: true). Lossy output compresses the
+   * colors for smaller files, but always keeps alpha exact so edges stay clean.
+   */
+  webpLossless?: boolean
+  /** The quality of lossy WebP output, from 1 (smallest) to 100 (best, default: 75) */
+  webpQuality?: number
+  /**
+   * The quality of AVIF output, color and alpha alike, from 1 (smallest) to 100 (best,
+   * default: 80)
+   */
+  avifQuality?: number
+  /** The AVIF encoder speed, from 1 (slowest, smallest files) to 10 (fastest, default: 4) */
+  avifSpeed?: number
+  /** The quality of JPEG output, from 1 (smallest) to 100 (best, default: 90) */
+  jpegQuality?: number
+  /** The color JPEG output is flattened over, since JPEG has no transparency (default: "#ffffff") */
+  jpegMatteColor?: string
+  /**
+   * Whether to smooth the 8x8 block artifacts of JPEG inputs before removal. Keeps the
+   * blocky ringing around the subject from turning into a ragged, semi-transparent fringe.
+   */
+  deblockEdges?: boolean
+  /**
+   * Whether to white balance the input before removal (default: false): each channel is
+   * scaled so the background detected at the edges becomes white, by at most 2x. Lets
+   * off-white photographed backgrounds be removed without loosening the thresholds;
+   * foreground colors are matched against the corrected image.
+   */
+  autoWhiteBalance?: boolean
+  /**
+   * Whether to detect a uniform solid frame around the input (e.g. the white border of a
+   * scanned photo) and cut it off before detecting the background and processing (default:
+   * false). Frames otherwise dominate the edge samples. The output is the size of the
+   * inside of the frame; `process_image_ex` reports the stripped margins.
+   */
+  stripFrame?: boolean
+  /**
+   * Whether to estimate the background per pixel from the nearest edge samples instead of
+   * using a single color. Follows vignetting and uneven lighting, but every pixel visits all
+   * edge samples, which makes processing noticeably slower on large images.
+   * Cannot be combined with `background_color` or another background model.
+   */
+  adaptiveBackground?: boolean
+  /**
+   * Whether to fit a smooth (bilinear) gradient to the edge samples and remove it instead of
+   * a single color. Suits studio sweeps and soft lighting falloff, at almost no extra cost.
+   * Cannot be combined with `background_color` or another background model.
+   */
+  gradientBackground?: boolean
+  /**
+   * A shot of the empty scene (same camera, same lighting), in any form `input` accepts and
+   * of the same size. Each pixel is unmixed against its counterpart in the plate, which
+   * removes textured or uneven backdrops that no single color describes.
+   * Cannot be combined with `background_color` or another background model.
+   */
+  backgroundPlate?: Uint8Array | ArrayBuffer | RawImage | string
+  /**
+   * Whether the background is a two-color checkerboard, like the transparency grid of image
+   * editors in screenshots. The tile colors, size and alignment are found along the edges.
+   * Cannot be combined with `background_color` or another background model.
+   */
+  checkerboardBackground?: boolean
+  /**
+   * Whether to estimate the background per tile of `background_tile_size` pixels, from the
+   * pixels of each tile close to the detected background, and blend smoothly between tiles.
+   * Follows vignetting and uneven lighting across the whole image, not just along the edges.
+   * Cannot be combined with `background_color` or another background model.
+   */
+  tiledBackground?: boolean
+  /** The tile width and height of `tiled_background`, in pixels (default: 64) */
+  backgroundTileSize?: number
+  /**
+   * Whether to only remove background that is connected to the image border, found by flood
+   * filling pixels within `threshold` of the background. Enclosed areas of the background
+   * color (a white shirt on white, the eyes of a logo) are left untouched.
+   */
+  connectedRemoval?: boolean
+  /**
+   * Whether to keep the holes the subject fully encloses opaque, restoring their original
+   * pixels after removal (the counters of letters, the whites of eyes). Unlike
+   * `connected_removal`, this looks at the result, so it also catches holes whose color
+   * drifted from the background. Not supported by tiled processing.
+   */
+  protectHoles?: boolean
+  /**
+   * Whether to keep soft shadows cast on the background as semi-transparent black (default:
+   * false), such as the contact shadow under a product, instead of removing them or leaving
+   * gray smudges. Pixels that are a darker version of the background within `threshold`
+   * count as shadow.
+   */
+  preserveShadows?: boolean
+  /**
+   * How much each distinct color counts when deducing "auto" foreground colors:
+   * "linear", "sqrt" (default), "log" or "uniform". Flatter schemes favor small, detailed
+   * subjects.
+   */
+  deductionWeighting?: string
+  /**
+   * How many of the most frequent image colors to derive "auto" foreground candidates from
+   * (default: 100). Raise it for images with many similar shades, lower it for speed.
+   */
+  deductionSampleColors?: number
+  /**
+   * Stop deducing "auto" colors after evaluating this many color combinations and use the best
+   * found so far. Bounds latency at the cost of possibly less accurate colors.
+   */
+  deductionMaxEvaluations?: number
+  /** Stop deducing "auto" colors after this many milliseconds and use the best found so far */
+  deductionTimeBudgetMs?: number
+  /**
+   * Whether to use the dominant non-background color in the image interior as the first
+   * foreground color, before deducing any "auto" colors. Works well for single-subject images.
+   */
+  autoPrimaryForeground?: boolean
+  /**
+   * How alpha is chosen in non-strict mode without foreground colors: "min_alpha" (default,
+   * the most transparent exact result) or "distance" (alpha grows with the distance from the
+   * background, for a softer matte on photographic subjects)
+   */
+  alphaModel?: string
+  /**
+   * The distance from the background (0.0-1.7, normalized RGB) at which the "distance" alpha
+   * model becomes fully opaque (default: 0.5)
+   */
+  alphaOpaqueDistance?: number
+  /**
+   * How to interpret the color channels of translucent input pixels: "straight" (default)
+   * or "premultiplied". Use "premultiplied" for data exported from canvases to avoid dark edges.
+   */
+  inputAlphaMode?: string
+  /**
+   * How the alpha input pixels already have is treated: "composite" (default) composites
+   * translucent pixels over the background and unmixes them again, "ignore" unmixes their color
+   * as if opaque, and "min" or "multiply" combine the computed alpha with the original, so
+   * transparent pixels stay transparent
+   */
+  existingAlpha?: string
+  /**
+   * Whether to rotate and flip the input as its EXIF orientation says (default: true), so phone
+   * photos come out the way they are displayed
+   */
+  autoOrient?: boolean
+  /**
+   * Whether to convert inputs with an embedded ICC profile (e.g. Display P3) to sRGB before
+   * processing (default: true), so hex colors and thresholds mean the same for every input.
+   * Inputs kept in their own color space have the given background and foreground colors
+   * mapped from sRGB into it instead.
+   */
+  convertToSrgb?: boolean
+  /**
+   * How floating point inputs such as OpenEXR renders are brought into range: "clamp"
+   * (default) clips highlights, "reinhard" compresses them. Their colors are unmixed in linear
+   * light either way.
+   */
+  hdrToneMapping?: string
+  /**
+   * The width SVG inputs are rasterized at, in pixels. Without `svg_height` the aspect ratio
+   * is kept; with it, the SVG is fitted inside both.
+   */
+  svgWidth?: number
+  /** The height SVG inputs are rasterized at, in pixels */
+  svgHeight?: number
+  /**
+   * The resolution SVG inputs are rasterized at when no `svg_width` or `svg_height` is given
+   * (default: 96, one pixel per CSS pixel)
+   */
+  svgDpi?: number
+  /** Whether to tag PNG output as sRGB with an `sRGB` chunk (default: false) */
+  tagSrgb?: boolean
+  /**
+   * Whether to carry the DPI (`pHYs`), gamma (`gAMA`) and text chunks of a PNG input over to
+   * PNG output (default: true)
+   */
+  preserveMetadata?: boolean
+  /**
+   * An outline to draw around the remaining content, sticker-style. The canvas grows by the
+   * stroke width on every side so the outline is never clipped.
+   */
+  outline?: OutlineSpec
 }
 
+/**
+ * Process an image asynchronously, passing the PNG output to `on_chunk` as it is encoded
+ *
+ * The rows are filtered and compressed one at a time, so the encoded image is never held in
+ * memory as a whole; the chunks arrive in order and can be pushed into a Node stream. Only
+ * PNG output (including "indexed" and 16-bit) can be streamed, and animated inputs are
+ * written as their first frame. As with tiles, encoding waits for a slow callback once a few
+ * chunks are queued for it.
+ *
+ * After the last chunk, `on_chunk` is called once more with an empty buffer, which marks the
+ * end of the output. The promise can resolve before the queued chunks have all reached
+ * `on_chunk`, so end a stream on the empty chunk rather than on the promise.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ * * `on_chunk` - Called with each chunk of the encoded PNG, then with an empty buffer
+ *
+ * # Returns
+ * A promise that resolves once encoding is done, or rejects if it fails
+ */
+export declare function processImageStream(options: ProcessImageOptions, onChunk: ((err: Error | null, arg: Buffer) => any)): Promise<void>
+
 /**
  * Process an image synchronously to remove its background
  *
@@ -127,10 +1026,244 @@ export interface ProcessImageOptions {
  * * `options` - The options for the image processing
  *
  * # Returns
- * The processed image buffer (PNG format)
+ * The processed image buffer (PNG unless `output_format` says otherwise)
  */
 export declare function processImageSync(options: ProcessImageOptions): Buffer
 
+/**
+ * Process an image asynchronously, reporting each tile as soon as it is done
+ *
+ * The image is split into square tiles that are processed in parallel. Every finished
+ * tile is encoded like `process_image` output and passed to `on_tile` with its position, in
+ * completion order rather than raster order, so large images can be rendered progressively.
+ * A few tiles are queued for a slow callback; beyond that, processing waits for it to catch
+ * up, so tiles never pile up in memory.
+ *
+ * The whole image is only put together if `full_image` is set. Tiles cover the image before
+ * `outline` and `trim` are applied; those are reflected in the full image only.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ * * `on_tile` - Called with each processed tile
+ * * `tile_size` - The tile width and height in pixels (default: 256)
+ * * `full_image` - Whether to also put the tiles together into the full image (default: false)
+ *
+ * # Returns
+ * A promise that resolves once all tiles are done, to the full processed image buffer (PNG
+ * unless `output_format` says otherwise) with `full_image`, or to null without it
+ */
+export declare function processImageTiles(options: ProcessImageOptions, onTile: ((err: Error | null, arg: ProcessedTile) => any), tileSize?: number | undefined | null, fullImage?: boolean | undefined | null): Promise<Buffer | null>
+
+/**
+ * Process an image asynchronously and return the result as a base64 data URL
+ *
+ * The MIME type follows the encoded output, e.g. `data:image/webp;base64,...` for WebP output.
+ * Raw output has no image format and is rejected.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to the processed image as a `data:` URL
+ */
+export declare function processImageToDataUrl(options: ProcessImageOptions): Promise<string>
+
+/**
+ * Process an image synchronously and return the result as a base64 data URL
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * The processed image as a `data:` URL
+ */
+export declare function processImageToDataUrlSync(options: ProcessImageOptions): string
+
+/**
+ * Compute one grayscale weight map per foreground color asynchronously
+ *
+ * Each map holds that color's unmix weight at every pixel (0 = none, 255 = all of it),
+ * the same weights `processImageLayers` uses as alpha, for recoloring or compositing
+ * downstream. Requires at least one foreground color (or "auto"). With `trim` enabled all
+ * maps are cropped to the same bounding box; `outline` is ignored.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to one grayscale image buffer (PNG unless `output_format` says
+ * otherwise) per foreground color, in the order they were specified
+ */
+export declare function processImageWeightMaps(options: ProcessImageOptions): Promise<Array<Buffer>>
+
+/**
+ * Compute one grayscale weight map per foreground color synchronously
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * One grayscale image buffer (PNG unless `output_format` says otherwise) per foreground
+ * color, in the order they were specified
+ */
+export declare function processImageWeightMapsSync(options: ProcessImageOptions): Array<Buffer>
+
+/**
+ * Process an image asynchronously and return the output together with its alpha mask
+ *
+ * Both come from a single pass over the pixels. Animated inputs are processed as their first
+ * frame.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to the processed image and its mask as a grayscale PNG
+ */
+export declare function processImageWithMask(options: ProcessImageOptions): Promise<ImageWithMask>
+
+/**
+ * Remove the background asynchronously by comparing an image with a shot of the empty
+ * background
+ *
+ * For controlled captures (fixed camera, same lighting), each pixel is compared with the
+ * same pixel of the reference: alpha grows with the difference and the subject color is
+ * recovered by inverting the alpha blend. This gives a much cleaner matte than color-based
+ * removal, even when the subject shares colors with the background.
+ *
+ * # Arguments
+ * * `subject` - The image with the subject, as a buffer, a base64 `data:` URL, or raw RGBA pixels
+ * * `background_reference` - The image of the background alone, of the same size and in any of the
+ *   same forms
+ * * `options` - The threshold, opaque distance, trimming and output format options
+ *
+ * # Returns
+ * A promise that resolves to the output image buffer (PNG unless `output_format` says
+ * otherwise)
+ */
+export declare function processWithReference(subject: Uint8Array | ArrayBuffer | RawImage | string, backgroundReference: Uint8Array | ArrayBuffer | RawImage | string, options?: ReferenceOptions | undefined | null): Promise<Buffer>
+
+/**
+ * Remove the background synchronously by comparing an image with a shot of the empty
+ * background
+ *
+ * # Arguments
+ * * `subject` - The image with the subject, as a buffer, a base64 `data:` URL, or raw RGBA pixels
+ * * `background_reference` - The image of the background alone, of the same size and in any of the
+ *   same forms
+ * * `options` - The threshold, opaque distance, trimming and output format options
+ *
+ * # Returns
+ * The output image buffer (PNG unless `output_format` says otherwise)
+ */
+export declare function processWithReferenceSync(subject: Uint8Array | ArrayBuffer | RawImage | string, backgroundReference: Uint8Array | ArrayBuffer | RawImage | string, options?: ReferenceOptions | undefined | null): Buffer
+
+/**
+ * Reduce an image asynchronously to a palette of at most `max_colors` colors, preserving
+ * alpha
+ *
+ * Fully transparent pixels stay transparent and are not counted towards the palette.
+ * Useful for pixel-art style output or before exporting to palette-based formats.
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `max_colors` - The maximum number of colors in the palette (1-256)
+ * * `dither` - Whether to apply Floyd-Steinberg dithering
+ * * `output_format` - The output format: "png" (default), "indexed", "webp", "avif", "tiff",
+ *   "jpeg", "apng", "gif" or "raw", each with its default settings
+ *
+ * # Returns
+ * A promise that resolves to the quantized image buffer, in the output format
+ */
+export declare function quantize(input: Uint8Array | ArrayBuffer | RawImage | string, maxColors: number, dither: boolean, outputFormat?: string | undefined | null): Promise<Buffer>
+
+/**
+ * Reduce an image synchronously to a palette of at most `max_colors` colors, preserving
+ * alpha
+ *
+ * # Arguments
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `max_colors` - The maximum number of colors in the palette (1-256)
+ * * `dither` - Whether to apply Floyd-Steinberg dithering
+ * * `output_format` - The output format: "png" (default), "indexed", "webp", "avif", "tiff",
+ *   "jpeg", "apng", "gif" or "raw", each with its default settings
+ *
+ * # Returns
+ * The quantized image buffer, in the output format
+ */
+export declare function quantizeSync(input: Uint8Array | ArrayBuffer | RawImage | string, maxColors: number, dither: boolean, outputFormat?: string | undefined | null): Buffer
+
+export interface RawImage {
+  /** The unencoded RGBA bytes, row by row */
+  data: Buffer
+  /** The image width in pixels */
+  width: number
+  /** The image height in pixels */
+  height: number
+}
+
+/**
+ * Recolor an image asynchronously by unmixing it against its foreground colors and remixing
+ * the weights with replacement colors
+ *
+ * Every pixel keeps the share each foreground color has in it, so anti-aliased edges and
+ * translucency carry over, but each color is swapped for its replacement (for example a red
+ * and white logo turned blue and white). The background is removed as with
+ * `processImageLayers`. Requires at least one foreground color (or "auto").
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ * * `replacement_colors` - One CSS color per foreground color, in the same order
+ *
+ * # Returns
+ * A promise that resolves to the recolored image buffer (PNG unless `output_format` says
+ * otherwise)
+ */
+export declare function recolor(options: ProcessImageOptions, replacementColors: Array<string>): Promise<Buffer>
+
+/**
+ * Recolor an image synchronously by unmixing it against its foreground colors and remixing
+ * the weights with replacement colors
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ * * `replacement_colors` - One CSS color per foreground color, in the same order
+ *
+ * # Returns
+ * The recolored image buffer (PNG unless `output_format` says otherwise)
+ */
+export declare function recolorSync(options: ProcessImageOptions, replacementColors: Array<string>): Buffer
+
+export interface ReferenceOptions {
+  /**
+   * Differences from the reference below this (normalized RGB distance, default: 0.05) are
+   * treated as sensor noise and removed
+   */
+  threshold?: number
+  /** The difference from the reference at which pixels become fully opaque (default: 0.5) */
+  opaqueDistance?: number
+  /** Whether to trim the output image to the bounding box of non-transparent pixels */
+  trim?: boolean
+  /**
+   * The output format: "png" (default), "indexed", "webp", "avif", "tiff", "jpeg", "apng",
+   * "gif" or "raw", each with its default settings
+   */
+  outputFormat?: string
+}
+
+export interface RegionSpec {
+  /** The x coordinate of the region's top-left corner */
+  x: number
+  /** The y coordinate of the region's top-left corner */
+  y: number
+  /** The region width in pixels */
+  width: number
+  /** The region height in pixels */
+  height: number
+}
+
 export interface RgbaColor {
   r: number
   g: number
@@ -144,16 +1277,170 @@ export interface RgbColor {
   b: number
 }
 
+/**
+ * Convert an RGB color (0-255) to HSL
+ *
+ * # Arguments
+ * * `color` - The RGB color
+ *
+ * # Returns
+ * The hue in degrees and the saturation and lightness (0.0-1.0); grays have a hue of 0
+ */
+export declare function rgbToHsl(color: RgbColor): HslColor
+
+/**
+ * Convert an RGB color (0-255) to HSV
+ *
+ * # Arguments
+ * * `color` - The RGB color
+ *
+ * # Returns
+ * The hue in degrees and the saturation and value (0.0-1.0); grays have a hue of 0
+ */
+export declare function rgbToHsv(color: RgbColor): HsvColor
+
+/**
+ * Convert an sRGB color (0-255) to CIELAB under the D65 white point, as used by the
+ * "deltaE2000" threshold space
+ *
+ * # Arguments
+ * * `color` - The RGB color
+ *
+ * # Returns
+ * The CIELAB color
+ */
+export declare function rgbToLab(color: RgbColor): LabColor
+
+/**
+ * Convert an sRGB color (0-255) to OKLab, as used by the "oklab" unmix space
+ *
+ * # Arguments
+ * * `color` - The RGB color
+ *
+ * # Returns
+ * The OKLab color
+ */
+export declare function rgbToOklab(color: RgbColor): LabColor
+
+/**
+ * Separate an image asynchronously into one solid color layer per foreground color, stacked
+ * in order
+ *
+ * Unlike `processImageLayers`, whose layers add up to the input, these layers reproduce it
+ * when composited normally over the background in the order the foreground colors were
+ * specified (first at the bottom), as in an image editor. Each layer is filled with its
+ * foreground color. Requires at least one foreground color (or "auto"). With `trim` enabled
+ * all layers are cropped to the same bounding box; `outline` is ignored.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to one image buffer (PNG unless `output_format` says otherwise)
+ * per foreground color, from the bottom layer to the top one
+ */
+export declare function separateLayers(options: ProcessImageOptions): Promise<Array<Buffer>>
+
+/**
+ * Separate an image synchronously into one solid color layer per foreground color, stacked
+ * in order
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * One image buffer (PNG unless `output_format` says otherwise) per foreground color, from the
+ * bottom layer to the top one
+ */
+export declare function separateLayersSync(options: ProcessImageOptions): Array<Buffer>
+
+/**
+ * Process an image asynchronously and return its color and alpha matte as separate images
+ *
+ * Both images come from a single processing pass, so they are pixel-aligned and
+ * cropped identically when `trim` is enabled. The color is straight (not premultiplied):
+ * recombining the color channels with the matte as alpha reproduces the `processImage` output.
+ * Fully transparent pixels have black color. Both are encoded in the output format at the
+ * output bit depth; PNG and TIFF mattes have a single gray channel and raw mattes one sample
+ * per pixel.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to the opaque color image and the grayscale matte (PNG unless
+ * `output_format` says otherwise)
+ */
+export declare function splitColorAndMatte(options: ProcessImageOptions): Promise<ColorMatteResult>
+
+/**
+ * Process an image synchronously and return its color and alpha matte as separate images
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * The opaque color image and the grayscale matte (PNG unless `output_format` says otherwise)
+ */
+export declare function splitColorAndMatteSync(options: ProcessImageOptions): ColorMatteResult
+
+/**
+ * Decode sRGB-encoded values (0.0-1.0) to linear light, with the transfer function used for
+ * linear light processing
+ *
+ * # Arguments
+ * * `value` - A single channel value, or any number of them (e.g. the channels of a color)
+ *
+ * # Returns
+ * The linear light value, or values in the same order
+ */
+export declare function srgbToLinear(value: number | Array<number>): number | Array<number>
+
+/**
+ * Compute the average color of the subject asynchronously after background removal
+ *
+ * Each output pixel's color is weighted by its alpha, so the removed background doesn't
+ * contribute and edge pixels count only as much as they are opaque. Useful as a
+ * representative swatch for thumbnails, tags, or UI theming.
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * A promise that resolves to the alpha-weighted average color, or null if nothing of the
+ * image is left
+ */
+export declare function subjectAverageColor(options: ProcessImageOptions): Promise<RgbColor | null>
+
+/**
+ * Compute the average color of the subject synchronously after background removal
+ *
+ * # Arguments
+ * * `options` - The options for the image processing
+ *
+ * # Returns
+ * The alpha-weighted average color, or null if nothing of the image is left
+ */
+export declare function subjectAverageColorSync(options: ProcessImageOptions): RgbColor | null
+
 /**
  * Trim the image to the bounding box of non-transparent pixels
  *
+ * The output is PNG unless `output_format` says otherwise, whatever the input format. TIFF
+ * output keeps the full precision of 16-bit and floating point inputs; only the first page
+ * of a multi-page TIFF is read.
+ *
  * # Arguments
- * * `input` - The input image buffer
+ * * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+ *   dimensions
+ * * `png_options` - The PNG encoder settings (default: balanced compression, adaptive filter)
+ * * `output_format` - The output format: "png" (default), "indexed", "webp", "avif", "tiff",
+ *   "jpeg", "apng", "gif" or "raw", each with its default settings
  *
  * # Returns
- * The trimmed image buffer (PNG format)
+ * The trimmed image buffer, in the output format
  */
-export declare function trimImage(input: Buffer): Buffer
+export declare function trimImage(input: Uint8Array | ArrayBuffer | RawImage | string, pngOptions?: PngEncodeOptions | undefined | null, outputFormat?: string | undefined | null): Buffer
 
 /**
  * Unmix an observed color into foreground color components
@@ -165,15 +1452,56 @@ export declare function trimImage(input: Buffer): Buffer
  * * `observed` - The observed color
  * * `foreground_colors` - The foreground colors to match
  * * `background` - The background color
+ * * `optimize_opacity` - Prefer the most opaque mix of the foreground colors (default: true);
+ *   false returns the plain least-squares solution that color deduction uses
  *
  * # Returns
- * The unmix result containing weights for each foreground color and overall alpha
+ * The unmix result containing weights for each foreground color, overall alpha, the
+ * reconstruction error and the foreground colors dropped as linearly dependent
  */
-export declare function unmixColor(observed: RgbColor, foregroundColors: Array<RgbColor>, background: RgbColor): UnmixResultJs
+export declare function unmixColor(observed: RgbColor, foregroundColors: Array<RgbColor>, background: RgbColor, optimizeOpacity?: boolean | undefined | null): UnmixResultJs
+
+/**
+ * Unmix many observed colors into foreground color components in one call
+ *
+ * Works like `unmix_color` on each observed color, in parallel, which is much faster than
+ * calling it once per color.
+ *
+ * # Arguments
+ * * `observed` - The observed colors, as RGB objects or as a flat array of r, g, b channels
+ *   (0-255, fractions allowed)
+ * * `foreground_colors` - The foreground colors to match
+ * * `background` - The background color
+ *
+ * # Returns
+ * Flat arrays holding the weights, alpha and reconstruction error of each observed color, in
+ * order
+ */
+export declare function unmixColors(observed: Float64Array | RgbColor[], foregroundColors: Array<RgbColor>, background: RgbColor): UnmixColorsResult
+
+export interface UnmixColorsResult {
+  /** The weights for each foreground color, `foregroundColors.length` per observed color */
+  weights: Float64Array
+  /** The alpha value of each observed color */
+  alpha: Float64Array
+  /** The reconstruction error of each observed color (normalized RGB distance) */
+  reconstructionError: Float64Array
+}
 
 export interface UnmixResultJs {
   /** The weights for each foreground color */
   weights: Array<number>
   /** The alpha value */
   alpha: number
+  /**
+   * How far (normalized RGB distance) the color reconstructed from the weights is from the
+   * observed color; a large error means the foreground colors don't explain it
+   */
+  reconstructionError: number
+  /**
+   * The indices of the foreground colors the least-squares solution (`optimize_opacity`
+   * false) dropped because their difference from the background is linearly dependent on
+   * earlier colors; their weights are 0
+   */
+  droppedColors: Array<number>
 }
@@ -557,14 +557,62 @@ if (!nativeBinding) {
 }
 
 module.exports = nativeBinding
+module.exports.assessRemovability = nativeBinding.assessRemovability
+module.exports.colorDistance = nativeBinding.colorDistance
 module.exports.colorToNormalized = nativeBinding.colorToNormalized
 module.exports.compositeOverBackground = nativeBinding.compositeOverBackground
 module.exports.computeUnmixResultColor = nativeBinding.computeUnmixResultColor
 module.exports.detectBackgroundColor = nativeBinding.detectBackgroundColor
+module.exports.detectBackgroundColorDebug = nativeBinding.detectBackgroundColorDebug
+module.exports.detectBackgroundColorRgba = nativeBinding.detectBackgroundColorRgba
+module.exports.detectBackgroundColors = nativeBinding.detectBackgroundColors
+module.exports.detectBackgroundUniformity = nativeBinding.detectBackgroundUniformity
+module.exports.detectChromaKey = nativeBinding.detectChromaKey
+module.exports.detectSolidColor = nativeBinding.detectSolidColor
+module.exports.exportFavicon = nativeBinding.exportFavicon
+module.exports.exportFaviconSync = nativeBinding.exportFaviconSync
+module.exports.extractPalette = nativeBinding.extractPalette
+module.exports.fitSubject = nativeBinding.fitSubject
+module.exports.fitSubjectSync = nativeBinding.fitSubjectSync
 module.exports.getDefaultThreshold = nativeBinding.getDefaultThreshold
+module.exports.hslToRgb = nativeBinding.hslToRgb
+module.exports.hsvToRgb = nativeBinding.hsvToRgb
+module.exports.labToRgb = nativeBinding.labToRgb
+module.exports.linearToSrgb = nativeBinding.linearToSrgb
 module.exports.normalizedToColor = nativeBinding.normalizedToColor
+module.exports.oklabToRgb = nativeBinding.oklabToRgb
 module.exports.parseColor = nativeBinding.parseColor
 module.exports.processImage = nativeBinding.processImage
+module.exports.processImageEx = nativeBinding.processImageEx
+module.exports.processImageFromConfig = nativeBinding.processImageFromConfig
+module.exports.processImageLayers = nativeBinding.processImageLayers
+module.exports.processImageLayersSync = nativeBinding.processImageLayersSync
+module.exports.processImageRaw = nativeBinding.processImageRaw
+module.exports.processImageStream = nativeBinding.processImageStream
 module.exports.processImageSync = nativeBinding.processImageSync
+module.exports.processImageTiles = nativeBinding.processImageTiles
+module.exports.processImageToDataUrl = nativeBinding.processImageToDataUrl
+module.exports.processImageToDataUrlSync = nativeBinding.processImageToDataUrlSync
+module.exports.processImageWeightMaps = nativeBinding.processImageWeightMaps
+module.exports.processImageWeightMapsSync = nativeBinding.processImageWeightMapsSync
+module.exports.processImageWithMask = nativeBinding.processImageWithMask
+module.exports.processWithReference = nativeBinding.processWithReference
+module.exports.processWithReferenceSync = nativeBinding.processWithReferenceSync
+module.exports.quantize = nativeBinding.quantize
+module.exports.quantizeSync = nativeBinding.quantizeSync
+module.exports.recolor = nativeBinding.recolor
+module.exports.recolorSync = nativeBinding.recolorSync
+module.exports.rgbToHsl = nativeBinding.rgbToHsl
+module.exports.rgbToHsv = nativeBinding.rgbToHsv
+module.exports.rgbToLab = nativeBinding.rgbToLab
+module.exports.rgbToOklab = nativeBinding.rgbToOklab
+module.exports.separateLayers = nativeBinding.separateLayers
+module.exports.separateLayersSync = nativeBinding.separateLayersSync
+module.exports.splitColorAndMatte = nativeBinding.splitColorAndMatte
+module.exports.splitColorAndMatteSync = nativeBinding.splitColorAndMatteSync
+module.exports.srgbToLinear = nativeBinding.srgbToLinear
+module.exports.subjectAverageColor = nativeBinding.subjectAverageColor
+module.exports.subjectAverageColorSync = nativeBinding.subjectAverageColorSync
 module.exports.trimImage = nativeBinding.trimImage
 module.exports.unmixColor = nativeBinding.unmixColor
+module.exports.unmixColors = nativeBinding.unmixColors
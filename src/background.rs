@@ -1,19 +1,174 @@
 // based on https://github.com/benface/bgone/blob/b362931f37252301f0f8dec183b2072f415b9b5f/src/background.rs
 
-use crate::color::Color;
-use image::DynamicImage;
-use std::collections::HashMap;
+use crate::color::{denormalize_color, normalize_color, rgb_to_hsv, Color, NormalizedColor};
+use anyhow::Result;
+use image::{DynamicImage, RgbImage, Rgba, RgbaImage};
+use nalgebra::{DMatrix, DVector};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Exponent applied to the distance in inverse-distance weighting
+const IDW_POWER: i32 = 2;
+
+/// Fraction of the winning vote count a color needs to be considered by a luminance bias
+const MIN_BIAS_VOTE_SHARE: f64 = 0.5;
+
+/// Minimum saturation (0.0-1.0) of an edge sample to count towards a chroma key
+const MIN_CHROMA_KEY_SATURATION: f64 = 0.3;
+
+/// Minimum value (0.0-1.0) of an edge sample to count towards a chroma key
+const MIN_CHROMA_KEY_VALUE: f64 = 0.15;
+
+/// How far (in degrees) a sample's hue may be from the key hue to belong to the key
+const CHROMA_KEY_HUE_TOLERANCE: f64 = 25.0;
+
+/// Share of the edge samples that must belong to the key for the background to be a chroma key
+const MIN_CHROMA_KEY_COVERAGE: f64 = 0.5;
+
+/// The hue ranges (in degrees) of green and blue screens
+const CHROMA_KEY_HUE_RANGES: [(f64, f64); 2] = [(70.0, 170.0), (190.0, 260.0)];
+
+/// Largest share of the image's width or height a side of a solid frame may take up
+const MAX_FRAME_SHARE: f64 = 0.25;
+
+/// Share of the first line inside a solid frame that must differ from the frame color
+const MIN_FRAME_CONTRAST: f64 = 0.5;
+
+/// Which edge color to prefer when several are similarly common
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum LuminanceBias {
+  /// Pick the most common edge color
+  #[default]
+  None,
+  /// Prefer the lightest of the common edge colors, as in product photography
+  Lightest,
+  /// Prefer the darkest of the common edge colors
+  Darkest,
+}
+
+/// Parse a background luminance bias
+/// Supports: "none", "lightest", "darkest"
+pub fn parse_luminance_bias(spec: &str) -> Result<LuminanceBias> {
+  match spec {
+    "none" => Ok(LuminanceBias::None),
+    "lightest" => Ok(LuminanceBias::Lightest),
+    "darkest" => Ok(LuminanceBias::Darkest),
+    _ => anyhow::bail!(
+      "Luminance bias must be one of \"none\", \"lightest\" or \"darkest\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// How the background color is estimated from the edge samples
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackgroundEstimator {
+  /// The most common sample, exact to the last bit
+  #[default]
+  Mode,
+  /// The per-channel median of the samples, for noisy camera images where no two samples
+  /// are identical
+  Median,
+  /// The per-channel mean of the middle half of the samples, which is steadier than the
+  /// median on grainy backgrounds while still ignoring the subject where it touches the edges
+  TrimmedMean,
+}
+
+/// Parse a background estimator
+/// Supports: "mode", "median", "trimmed_mean"
+pub fn parse_background_estimator(spec: &str) -> Result<BackgroundEstimator> {
+  match spec {
+    "mode" => Ok(BackgroundEstimator::Mode),
+    "median" => Ok(BackgroundEstimator::Median),
+    "trimmed_mean" => Ok(BackgroundEstimator::TrimmedMean),
+    _ => anyhow::bail!(
+      "Background estimator must be one of \"mode\", \"median\" or \"trimmed_mean\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// Which pixels are sampled to detect the background
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum DetectionStrategy {
+  /// Only the four corners, for subjects that touch the edges
+  Corners,
+  /// The corners and every N pixels along the edges
+  #[default]
+  Edges,
+  /// Every N pixels along each of the outermost `depth` rings of pixels, for images with a
+  /// frame or title bar of a different color
+  Border { depth: u32 },
+  /// Every N pixels in both directions across the whole image, for backgrounds that are
+  /// hidden at the edges but cover most of the image
+  Dominant,
+}
+
+/// Parse a background detection strategy
+/// Supports: "corners", "edges", "border", "dominant"
+pub fn parse_detection_strategy(spec: &str, border_depth: u32) -> Result<DetectionStrategy> {
+  match spec {
+    "corners" => Ok(DetectionStrategy::Corners),
+    "edges" => Ok(DetectionStrategy::Edges),
+    "border" => {
+      if border_depth == 0 {
+        anyhow::bail!("Border depth must be at least 1");
+      }
+      Ok(DetectionStrategy::Border {
+        depth: border_depth,
+      })
+    }
+    "dominant" => Ok(DetectionStrategy::Dominant),
+    _ => anyhow::bail!(
+      "Detection strategy must be one of \"corners\", \"edges\", \"border\" or \
+       \"dominant\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// A rectangle of pixels
+#[derive(Clone, Copy)]
+pub struct Region {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl Region {
+  /// Whether the pixel lies inside the rectangle
+  pub fn contains(&self, x: u32, y: u32) -> bool {
+    x >= self.x && y >= self.y && (x - self.x) < self.width && (y - self.y) < self.height
+  }
+}
 
 /// Configuration for background detection
 pub struct BackgroundDetectionConfig {
+  /// Which pixels are sampled
+  pub strategy: DetectionStrategy,
   /// Sample every N pixels on edges
   pub edge_sample_interval: u32,
+  /// How the background color is estimated from the samples
+  pub estimator: BackgroundEstimator,
+  /// Which of the common edge colors to prefer, with the mode estimator
+  pub luminance_bias: LuminanceBias,
+  /// Leave fully transparent pixels out of the edge samples instead of compositing them
+  /// over black, e.g. the transparent palette index of an indexed image
+  pub skip_transparent_pixels: bool,
+  /// Regions left out of the samples, e.g. a watermark stamped in a corner
+  pub excluded_regions: Vec<Region>,
 }
 
 impl Default for BackgroundDetectionConfig {
   fn default() -> Self {
     Self {
+      strategy: DetectionStrategy::Edges,
       edge_sample_interval: 10,
+      estimator: BackgroundEstimator::Mode,
+      luminance_bias: LuminanceBias::None,
+      skip_transparent_pixels: false,
+      excluded_regions: Vec::new(),
     }
   }
 }
@@ -36,60 +191,1038 @@ pub fn detect_background_color(img: &DynamicImage) -> Color {
 /// * `config` - Configuration for background detection
 ///
 /// # Returns
-/// The most common RGB color found at image edges and corners
+/// The most common RGB color found at image edges and corners, or black for an empty image.
+/// With a luminance bias, the lightest or darkest color among those with at least half the
+/// votes of the most common one. With another estimator, its estimate over the samples.
 pub fn detect_background_color_with_config(
   img: &DynamicImage,
   config: &BackgroundDetectionConfig,
 ) -> Color {
   let rgba = img.to_rgba8();
+  let colors = sample_edge_colors(&rgba, config)
+    .into_iter()
+    .map(|(_, _, color)| color);
+
+  estimate(colors, config, luminance).unwrap_or([0, 0, 0])
+}
+
+/// Rank the colors found at image edges and corners by how often they were sampled
+///
+/// # Arguments
+/// * `img` - The image to analyze
+/// * `config` - Configuration for background detection (the luminance bias is ignored)
+/// * `count` - How many colors to return at most
+///
+/// # Returns
+/// Up to `count` colors with their sample counts, most common first (ties broken by color),
+/// along with the total number of edge samples
+pub fn rank_background_colors(
+  img: &DynamicImage,
+  config: &BackgroundDetectionConfig,
+  count: usize,
+) -> (Vec<(Color, u32)>, u32) {
+  let rgba = img.to_rgba8();
+  let samples = sample_edge_colors(&rgba, config);
+
+  let mut counts: HashMap<Color, u32> = HashMap::new();
+  for (_, _, color) in &samples {
+    *counts.entry(*color).or_insert(0) += 1;
+  }
+
+  let mut ranked: Vec<(Color, u32)> = counts.into_iter().collect();
+  ranked.sort_by(|(a_color, a_count), (b_color, b_count)| {
+    b_count.cmp(a_count).then(a_color.cmp(b_color))
+  });
+  ranked.truncate(count);
+  (ranked, samples.len() as u32)
+}
+
+/// Detect the background color including its alpha by sampling image edges and corners
+///
+/// Unlike `detect_background_color_with_config`, translucent edge pixels are not composited
+/// over black: the votes are cast on the full RGBA values, so a semi-transparent background
+/// is reported with its own color and alpha.
+///
+/// # Arguments
+/// * `img` - The image to analyze
+/// * `config` - Configuration for background detection
+///
+/// # Returns
+/// The most common RGBA value found at image edges and corners (with the same luminance
+/// bias and estimator rules), or transparent black for an empty image
+pub fn detect_background_rgba_with_config(
+  img: &DynamicImage,
+  config: &BackgroundDetectionConfig,
+) -> Rgba<u8> {
+  let rgba = img.to_rgba8();
+  let pixels = sample_edge_points(&rgba, config)
+    .into_iter()
+    .map(|(x, y)| rgba.get_pixel(x, y).0);
+
+  estimate(pixels, config, |[r, g, b, _]: [u8; 4]| luminance([r, g, b]))
+    .map(Rgba)
+    .unwrap_or(Rgba([0, 0, 0, 0]))
+}
+
+/// Count how many edge samples are within `threshold` (normalized RGB distance) of a
+/// background color
+///
+/// A low share means the edges are not uniform and the detected background is likely wrong.
+///
+/// # Arguments
+/// * `img` - The image to analyze
+/// * `config` - Configuration for background detection (the luminance bias is ignored)
+/// * `background` - The background color to compare the samples with
+/// * `threshold` - The maximum normalized RGB distance to the background color
+///
+/// # Returns
+/// The number of matching samples and the total number of samples
+pub fn background_uniformity(
+  img: &DynamicImage,
+  config: &BackgroundDetectionConfig,
+  background: Color,
+  threshold: f64,
+) -> (u32, u32) {
+  let (matching, total) =
+    count_matching_edge_samples(&img.to_rgba8(), config, background, threshold);
+  (matching as u32, total as u32)
+}
+
+/// Count the edge samples within `threshold` of the background, along with all samples
+fn count_matching_edge_samples(
+  rgba: &RgbaImage,
+  config: &BackgroundDetectionConfig,
+  background: Color,
+  threshold: f64,
+) -> (usize, usize) {
+  let background = normalize_color(background);
+  let samples = sample_edge_colors(rgba, config);
+  let matching = samples
+    .iter()
+    .filter(|(_, _, color)| {
+      let color = normalize_color(*color);
+      let distance = (0..3)
+        .map(|i| (color[i] - background[i]).powi(2))
+        .sum::<f64>()
+        .sqrt();
+      distance <= threshold
+    })
+    .count();
+  (matching, samples.len())
+}
+
+/// A green or blue screen background found along the edges
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChromaKey {
+  /// The key color, the per-channel median of the samples with the key hue
+  pub color: Color,
+  /// The key hue in degrees (0.0-360.0)
+  pub hue: f64,
+  /// The share of the edge samples with the key hue (0.0-1.0)
+  pub coverage: f64,
+}
+
+/// Detect a saturated keying background (a green or blue screen) along the edges
+///
+/// Lit screens are rarely uniform enough for the most common edge color to be meaningful, so
+/// the key is found from hue statistics instead: the edge samples vote for a hue, and the key
+/// color is the median of the saturated samples close to the winning hue.
+///
+/// # Arguments
+/// * `img` - The image to analyze
+/// * `config` - Configuration for background detection (the luminance bias is ignored)
+///
+/// # Returns
+/// The key, or `None` if the edges are not mostly a saturated green or blue
+pub fn detect_chroma_key(
+  img: &DynamicImage,
+  config: &BackgroundDetectionConfig,
+) -> Option<ChromaKey> {
+  let samples = sample_edge_colors(&img.to_rgba8(), config);
+  let saturated: Vec<(Color, f64)> = samples
+    .iter()
+    .filter_map(|&(_, _, color)| {
+      let [hue, saturation, value] = rgb_to_hsv(normalize_color(color));
+      (saturation >= MIN_CHROMA_KEY_SATURATION && value >= MIN_CHROMA_KEY_VALUE)
+        .then_some((color, hue))
+    })
+    .collect();
+
+  // Vote on 10 degree hue bins, each bin also counting its two neighbours
+  let mut bins = [0u32; 36];
+  for &(_, hue) in &saturated {
+    bins[(hue / 10.0) as usize % 36] += 1;
+  }
+  let peak = (0..36).max_by_key(|&i| bins[(i + 35) % 36] + bins[i] + bins[(i + 1) % 36])?;
+
+  // Refine the hue to the circular mean of the samples around the peak
+  let peak_hue = peak as f64 * 10.0 + 5.0;
+  let (sin, cos) = saturated
+    .iter()
+    .filter(|&&(_, hue)| hue_distance(hue, peak_hue) <= 15.0)
+    .fold((0.0, 0.0), |(sin, cos), &(_, hue)| {
+      (sin + hue.to_radians().sin(), cos + hue.to_radians().cos())
+    });
+  let hue = sin.atan2(cos).to_degrees().rem_euclid(360.0);
+  if !CHROMA_KEY_HUE_RANGES
+    .iter()
+    .any(|&(low, high)| (low..=high).contains(&hue))
+  {
+    return None;
+  }
+
+  let key: Vec<Color> = saturated
+    .iter()
+    .filter(|&&(_, sample_hue)| hue_distance(sample_hue, hue) <= CHROMA_KEY_HUE_TOLERANCE)
+    .map(|&(color, _)| color)
+    .collect();
+  let coverage = key.len() as f64 / samples.len() as f64;
+  if coverage < MIN_CHROMA_KEY_COVERAGE {
+    return None;
+  }
+
+  let color = [0, 1, 2].map(|i| {
+    let mut channel: Vec<u8> = key.iter().map(|color| color[i]).collect();
+    channel.sort_unstable();
+    channel[channel.len() / 2]
+  });
+  Some(ChromaKey {
+    color,
+    hue,
+    coverage,
+  })
+}
+
+/// The distance between two hues in degrees, around the color wheel (0.0-180.0)
+fn hue_distance(a: f64, b: f64) -> f64 {
+  let distance = (a - b).rem_euclid(360.0);
+  distance.min(360.0 - distance)
+}
+
+/// Estimate the background from the samples with the configured estimator
+fn estimate<const N: usize>(
+  samples: impl Iterator<Item = [u8; N]>,
+  config: &BackgroundDetectionConfig,
+  luminance: impl Fn([u8; N]) -> f64,
+) -> Option<[u8; N]> {
+  let trimmed_mean = match config.estimator {
+    BackgroundEstimator::Mode => return vote(samples, config.luminance_bias, luminance),
+    BackgroundEstimator::Median => false,
+    BackgroundEstimator::TrimmedMean => true,
+  };
+
+  let samples: Vec<[u8; N]> = samples.collect();
+  if samples.is_empty() {
+    return None;
+  }
+  Some(std::array::from_fn(|i| {
+    let mut channel: Vec<u8> = samples.iter().map(|sample| sample[i]).collect();
+    channel.sort_unstable();
+    if trimmed_mean {
+      // Drop the lowest and highest quarter
+      let cut = channel.len() / 4;
+      let kept = &channel[cut..channel.len() - cut];
+      (kept.iter().map(|&c| c as f64).sum::<f64>() / kept.len() as f64).round() as u8
+    } else {
+      channel[channel.len() / 2]
+    }
+  }))
+}
+
+/// Pick the most common sample, or the extreme-luminance one among the common samples
+fn vote<T: Copy + Eq + Hash>(
+  samples: impl Iterator<Item = T>,
+  luminance_bias: LuminanceBias,
+  luminance: impl Fn(T) -> f64,
+) -> Option<T> {
+  let mut counts: HashMap<T, u32> = HashMap::new();
+
+  // Count occurrences
+  for sample in samples {
+    *counts.entry(sample).or_insert(0) += 1;
+  }
+
+  let max_count = counts.values().copied().max().unwrap_or(0);
+  let candidates = counts
+    .into_iter()
+    .filter(|(_, count)| *count as f64 >= max_count as f64 * MIN_BIAS_VOTE_SHARE);
+
+  let chosen = match luminance_bias {
+    LuminanceBias::None => candidates.max_by_key(|(_, count)| *count),
+    LuminanceBias::Lightest => {
+      candidates.max_by(|(a, _), (b, _)| luminance(*a).total_cmp(&luminance(*b)))
+    }
+    LuminanceBias::Darkest => {
+      candidates.min_by(|(a, _), (b, _)| luminance(*a).total_cmp(&luminance(*b)))
+    }
+  };
+
+  chosen.map(|(sample, _)| sample)
+}
+
+/// Relative luminance of an sRGB color using Rec. 709 weights (0.0-255.0)
+fn luminance(color: Color) -> f64 {
+  0.2126 * color[0] as f64 + 0.7152 * color[1] as f64 + 0.0722 * color[2] as f64
+}
+
+/// The width of each side of a frame around an image, in pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Margins {
+  pub top: u32,
+  pub right: u32,
+  pub bottom: u32,
+  pub left: u32,
+}
+
+/// Detect a uniform solid frame around an image, such as the white border of a scanned photo
+///
+/// Every side must be at least one pixel wide and at most a quarter of the image, and at
+/// least half of the first line inside each side must differ from the frame color. That
+/// keeps a plain background running up to the subject from being taken for a frame.
+///
+/// # Arguments
+/// * `rgba` - The image to analyze
+/// * `threshold` - The maximum normalized RGB distance of a frame pixel to the frame color
+///
+/// # Returns
+/// The width of each side of the frame, or `None` if there is no frame
+pub fn detect_solid_frame(rgba: &RgbaImage, threshold: f64) -> Option<Margins> {
   let (width, height) = rgba.dimensions();
+  if width < 3 || height < 3 {
+    return None;
+  }
+  let frame_color = normalize_color(composite_over_black(rgba.get_pixel(0, 0)));
+  let is_frame = |x: u32, y: u32| {
+    let color = normalize_color(composite_over_black(rgba.get_pixel(x, y)));
+    (0..3)
+      .map(|i| (color[i] - frame_color[i]).powi(2))
+      .sum::<f64>()
+      .sqrt()
+      <= threshold
+  };
+
+  // Count the lines from each side inwards that are entirely the frame color, up to the cap
+  let max_rows = (height as f64 * MAX_FRAME_SHARE) as u32;
+  let max_columns = (width as f64 * MAX_FRAME_SHARE) as u32;
+  let is_row_frame = |&y: &u32| (0..width).all(|x| is_frame(x, y));
+  let is_column_frame = |&x: &u32| (0..height).all(|y| is_frame(x, y));
+  let margins = Margins {
+    top: (0..max_rows).take_while(is_row_frame).count() as u32,
+    right: (0..max_columns)
+      .map(|i| width - 1 - i)
+      .take_while(is_column_frame)
+      .count() as u32,
+    bottom: (0..max_rows)
+      .map(|i| height - 1 - i)
+      .take_while(is_row_frame)
+      .count() as u32,
+    left: (0..max_columns).take_while(is_column_frame).count() as u32,
+  };
+  if [margins.top, margins.right, margins.bottom, margins.left].contains(&0)
+    || margins.top >= max_rows
+    || margins.bottom >= max_rows
+    || margins.left >= max_columns
+    || margins.right >= max_columns
+  {
+    return None;
+  }
+
+  // The first line inside each side must mostly differ from the frame
+  let (x0, x1) = (margins.left, width - margins.right);
+  let (y0, y1) = (margins.top, height - margins.bottom);
+  let contrast = |pixels: &mut dyn Iterator<Item = (u32, u32)>| {
+    let (differing, total) = pixels.fold((0, 0), |(differing, total), (x, y)| {
+      (differing + !is_frame(x, y) as u32, total + 1)
+    });
+    differing as f64 >= total as f64 * MIN_FRAME_CONTRAST
+  };
+  let inner_lines_differ = contrast(&mut (x0..x1).map(|x| (x, y0)))
+    && contrast(&mut (x0..x1).map(|x| (x, y1 - 1)))
+    && contrast(&mut (y0..y1).map(|y| (x0, y)))
+    && contrast(&mut (y0..y1).map(|y| (x1 - 1, y)));
+  inner_lines_differ.then_some(margins)
+}
+
+/// Check whether every pixel of an image has the same RGBA value
+///
+/// Such an image (e.g. a blank placeholder) has nothing to separate: its only color is
+/// detected as the background, so processing it yields a fully transparent image.
+///
+/// # Returns
+/// The single pixel value, or `None` if the image has more than one color or no pixels
+pub fn detect_solid_color(rgba: &RgbaImage) -> Option<Rgba<u8>> {
+  let mut pixels = rgba.pixels();
+  let first = *pixels.next()?;
+  pixels.all(|pixel| *pixel == first).then_some(first)
+}
+
+/// Sample the colors at the image corners and along its edges
+///
+/// For translucent pixels, the color is composited over black to get the effective color.
+///
+/// # Returns
+/// The sampled `(x, y, color)` tuples
+pub fn sample_edge_colors(
+  rgba: &RgbaImage,
+  config: &BackgroundDetectionConfig,
+) -> Vec<(u32, u32, Color)> {
+  sample_edge_points(rgba, config)
+    .into_iter()
+    .map(|(x, y)| (x, y, composite_over_black(rgba.get_pixel(x, y))))
+    .collect()
+}
+
+/// Pick the sample positions at the image corners and along its edges, or wherever
+/// `config.strategy` asks for
+///
+/// Fully transparent pixels are skipped if `config.skip_transparent_pixels` is set, and so are
+/// pixels in `config.excluded_regions`.
+///
+/// # Returns
+/// The sampled `(x, y)` positions
+fn sample_edge_points(rgba: &RgbaImage, config: &BackgroundDetectionConfig) -> Vec<(u32, u32)> {
+  let (width, height) = rgba.dimensions();
+
+  // Empty images have no edges to sample
+  if width == 0 || height == 0 {
+    return Vec::new();
+  }
+
+  // Guard against a zero interval, which would never advance
+  let interval = config.edge_sample_interval.max(1) as usize;
 
-  let mut color_counts: HashMap<Color, u32> = HashMap::new();
   let mut sample_points = Vec::new();
 
-  // Add corners
-  sample_points.extend(&[
-    (0, 0),
-    (width - 1, 0),
-    (0, height - 1),
-    (width - 1, height - 1),
-  ]);
-
-  // Add edge samples
-  for x in (0..width).step_by(config.edge_sample_interval as usize) {
-    sample_points.push((x, 0));
-    sample_points.push((x, height - 1));
-  }
-
-  for y in (0..height).step_by(config.edge_sample_interval as usize) {
-    sample_points.push((0, y));
-    sample_points.push((width - 1, y));
-  }
-
-  // Count color occurrences
-  // For translucent pixels, composite over black to get the effective color
-  for &(x, y) in &sample_points {
-    let pixel = rgba.get_pixel(x, y);
-    let alpha = pixel[3] as f64 / 255.0;
-
-    // Composite over black background for translucent pixels
-    let color = if alpha < 1.0 {
-      [
-        (pixel[0] as f64 * alpha).round() as u8,
-        (pixel[1] as f64 * alpha).round() as u8,
-        (pixel[2] as f64 * alpha).round() as u8,
-      ]
-    } else {
-      [pixel[0], pixel[1], pixel[2]]
+  // Rings of pixels inset from the edges; a single ring for the corners and edges strategies
+  let depth = match config.strategy {
+    DetectionStrategy::Dominant => 0,
+    DetectionStrategy::Corners | DetectionStrategy::Edges => 1,
+    DetectionStrategy::Border { depth } => depth.min(width.min(height).div_ceil(2)),
+  };
+  for inset in 0..depth {
+    let (left, right) = (inset, width - 1 - inset);
+    let (top, bottom) = (inset, height - 1 - inset);
+
+    // Add corners (these coincide on 1-pixel wide or tall rings, which is harmless)
+    sample_points.extend(&[(left, top), (right, top), (left, bottom), (right, bottom)]);
+    if config.strategy == DetectionStrategy::Corners {
+      break;
+    }
+
+    // Add edge samples
+    for x in (left..=right).step_by(interval) {
+      sample_points.push((x, top));
+      sample_points.push((x, bottom));
+    }
+
+    for y in (top..=bottom).step_by(interval) {
+      sample_points.push((left, y));
+      sample_points.push((right, y));
+    }
+  }
+
+  if config.strategy == DetectionStrategy::Dominant {
+    for y in (0..height).step_by(interval) {
+      for x in (0..width).step_by(interval) {
+        sample_points.push((x, y));
+      }
+    }
+  }
+
+  sample_points
+    .into_iter()
+    .filter(|&(x, y)| !(config.skip_transparent_pixels && rgba.get_pixel(x, y)[3] == 0))
+    .filter(|&(x, y)| {
+      !config
+        .excluded_regions
+        .iter()
+        .any(|region| region.contains(x, y))
+    })
+    .collect()
+}
+
+/// Composite a translucent pixel over black to get its effective color
+fn composite_over_black(pixel: &Rgba<u8>) -> Color {
+  let alpha = pixel[3] as f64 / 255.0;
+
+  if alpha < 1.0 {
+    [
+      (pixel[0] as f64 * alpha).round() as u8,
+      (pixel[1] as f64 * alpha).round() as u8,
+      (pixel[2] as f64 * alpha).round() as u8,
+    ]
+  } else {
+    [pixel[0], pixel[1], pixel[2]]
+  }
+}
+
+/// A spatially varying background estimated from edge samples
+///
+/// Instead of a single global color, the background at each pixel is interpolated
+/// from the edge samples using inverse-distance weighting, so gradual vignetting and
+/// uneven lighting towards the corners are followed rather than left behind as a halo.
+///
+/// Every lookup visits all edge samples, so processing an image with this model costs
+/// `O(width * height * samples)` instead of `O(width * height)`. With the default sample
+/// interval of 10 that is roughly `0.4 * (width + height)` extra color blends per pixel.
+pub struct LocalBackgroundModel {
+  /// Sampled positions and their normalized colors
+  samples: Vec<(f64, f64, NormalizedColor)>,
+}
+
+impl LocalBackgroundModel {
+  /// Build a local background model from the edge samples of an image
+  ///
+  /// # Arguments
+  /// * `img` - The image to analyze
+  /// * `config` - Configuration for background detection
+  pub fn from_image(img: &DynamicImage, config: &BackgroundDetectionConfig) -> Self {
+    let rgba = img.to_rgba8();
+    let samples = sample_edge_colors(&rgba, config)
+      .into_iter()
+      .map(|(x, y, color)| (x as f64, y as f64, normalize_color(color)))
+      .collect();
+
+    Self { samples }
+  }
+
+  /// Estimate the background color at the given pixel
+  ///
+  /// Samples closer to the pixel contribute more, falling off with the square of
+  /// the distance. A pixel that coincides with a sample takes its color exactly.
+  pub fn background_at(&self, x: u32, y: u32) -> Color {
+    let (px, py) = (x as f64, y as f64);
+    let mut sum = [0.0; 3];
+    let mut total_weight = 0.0;
+
+    for &(sx, sy, color) in &self.samples {
+      let dist_sq = (sx - px).powi(2) + (sy - py).powi(2);
+      if dist_sq == 0.0 {
+        return denormalize_color(color);
+      }
+
+      let weight = 1.0 / dist_sq.sqrt().powi(IDW_POWER);
+      sum[0] += color[0] * weight;
+      sum[1] += color[1] * weight;
+      sum[2] += color[2] * weight;
+      total_weight += weight;
+    }
+
+    if total_weight == 0.0 {
+      return [0, 0, 0];
+    }
+
+    denormalize_color([
+      sum[0] / total_weight,
+      sum[1] / total_weight,
+      sum[2] / total_weight,
+    ])
+  }
+}
+
+/// A smooth background gradient fitted to the edge samples
+///
+/// Each channel is modeled as a bilinear surface `a + b*u + c*v + d*u*v` over the normalized
+/// image coordinates, fitted by least squares. This follows linear studio sweeps and corner
+/// falloff without the per-pixel cost of `LocalBackgroundModel`: every lookup is a handful of
+/// multiplications. Edge samples far from the first fit (e.g. where the subject touches the
+/// edge) are dropped and the surface is fitted again.
+pub struct GradientBackgroundModel {
+  /// The surface coefficients `[a, b, c, d]` of each channel, in normalized color units
+  coefficients: [[f64; 4]; 3],
+  /// The scale from pixel coordinates to the normalized `u` and `v` coordinates
+  scale: (f64, f64),
+}
+
+impl GradientBackgroundModel {
+  /// Fit a gradient background model to the edge samples of an image
+  ///
+  /// # Arguments
+  /// * `img` - The image to analyze
+  /// * `config` - Configuration for background detection
+  pub fn from_image(img: &DynamicImage, config: &BackgroundDetectionConfig) -> Self {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let scale = (
+      1.0 / (width.max(2) - 1) as f64,
+      1.0 / (height.max(2) - 1) as f64,
+    );
+    let samples: Vec<(f64, f64, NormalizedColor)> = sample_edge_colors(&rgba, config)
+      .into_iter()
+      .map(|(x, y, color)| {
+        (
+          x as f64 * scale.0,
+          y as f64 * scale.1,
+          normalize_color(color),
+        )
+      })
+      .collect();
+
+    let mut model = Self {
+      coefficients: fit_bilinear(&samples),
+      scale,
+    };
+
+    let inliers: Vec<_> = samples
+      .iter()
+      .filter(|(u, v, color)| {
+        let fitted = model.color_at(*u, *v);
+        let distance = (0..3)
+          .map(|i| (color[i] - fitted[i]).powi(2))
+          .sum::<f64>()
+          .sqrt();
+        distance <= GRADIENT_OUTLIER_DISTANCE
+      })
+      .copied()
+      .collect();
+    if inliers.len() >= GRADIENT_COEFFICIENTS && inliers.len() < samples.len() {
+      model.coefficients = fit_bilinear(&inliers);
+    }
+
+    model
+  }
+
+  /// The background color at the given pixel
+  pub fn background_at(&self, x: u32, y: u32) -> Color {
+    denormalize_color(self.color_at(x as f64 * self.scale.0, y as f64 * self.scale.1))
+  }
+
+  /// The fitted color at the given normalized coordinates
+  fn color_at(&self, u: f64, v: f64) -> NormalizedColor {
+    let terms = [1.0, u, v, u * v];
+    self
+      .coefficients
+      .map(|c| (0..GRADIENT_COEFFICIENTS).map(|i| c[i] * terms[i]).sum())
+  }
+}
+
+/// Number of coefficients of the bilinear gradient surface
+const GRADIENT_COEFFICIENTS: usize = 4;
+
+/// Normalized RGB distance from the first gradient fit beyond which an edge sample is an outlier
+const GRADIENT_OUTLIER_DISTANCE: f64 = 0.1;
+
+/// Fit a bilinear surface per channel to `(u, v, color)` samples by least squares
+///
+/// Degenerate sample layouts (a single row or column of pixels) fall back to the minimum-norm
+/// solution, which leaves the unconstrained terms at zero.
+fn fit_bilinear(samples: &[(f64, f64, NormalizedColor)]) -> [[f64; 4]; 3] {
+  if samples.is_empty() {
+    return [[0.0; 4]; 3];
+  }
+
+  let design = DMatrix::from_fn(samples.len(), GRADIENT_COEFFICIENTS, |row, col| {
+    let (u, v, _) = samples[row];
+    [1.0, u, v, u * v][col]
+  });
+  let svd = design.svd(true, true);
+
+  [0, 1, 2].map(|channel| {
+    let target = DVector::from_iterator(samples.len(), samples.iter().map(|s| s.2[channel]));
+    svd
+      .solve(&target, 1e-9)
+      .map(|solution| [solution[0], solution[1], solution[2], solution[3]])
+      .unwrap_or([0.0; 4])
+  })
+}
+
+/// A two-color checkerboard, like the transparency grid image editors draw
+///
+/// The two tile colors are the two most common edge colors. The tile size is the most common
+/// length of the alternating runs along the edges, and the horizontal and vertical phases come
+/// from where the runs switch along the top and bottom rows and left and right columns.
+pub struct CheckerboardBackgroundModel {
+  /// The color of the tiles with an even and an odd tile index sum
+  colors: [Color; 2],
+  /// The tile width and height in pixels
+  tile_size: u32,
+  /// How far the tile grid is shifted left and up, in pixels
+  offset: (u32, u32),
+}
+
+impl CheckerboardBackgroundModel {
+  /// Find a checkerboard along the edges of an image
+  ///
+  /// # Arguments
+  /// * `img` - The image to analyze
+  /// * `threshold` - The maximum normalized RGB distance of an edge pixel to a tile color
+  ///
+  /// # Returns
+  /// The checkerboard, or `None` if the edges don't alternate between two colors
+  pub fn from_image(img: &DynamicImage, threshold: f64) -> Option<Self> {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let config = BackgroundDetectionConfig {
+      edge_sample_interval: 1,
+      ..Default::default()
+    };
+    let (ranked, _) = rank_background_colors(img, &config, 2);
+    let [(first, _), (second, _)] = ranked[..] else {
+      return None;
+    };
+    let colors = [first, second];
+    let tiles = colors.map(normalize_color);
+
+    // Which tile color each pixel matches, if any
+    let classify = |x: u32, y: u32| {
+      let color = normalize_color(composite_over_black(rgba.get_pixel(x, y)));
+      (0..2)
+        .map(|k| {
+          let distance = (0..3)
+            .map(|i| (color[i] - tiles[k][i]).powi(2))
+            .sum::<f64>()
+            .sqrt();
+          (k, distance)
+        })
+        .filter(|&(_, distance)| distance <= threshold)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(k, _)| k)
     };
+    let rows: Vec<Vec<Option<usize>>> = [0, height - 1]
+      .iter()
+      .map(|&y| (0..width).map(|x| classify(x, y)).collect())
+      .collect();
+    let columns: Vec<Vec<Option<usize>>> = [0, width - 1]
+      .iter()
+      .map(|&x| (0..height).map(|y| classify(x, y)).collect())
+      .collect();
 
-    *color_counts.entry(color).or_insert(0) += 1;
+    // Runs that start and end at a switch between the tile colors are whole tiles
+    let row_switches: Vec<Vec<u32>> = rows.iter().map(|line| color_switches(line)).collect();
+    let column_switches: Vec<Vec<u32>> = columns.iter().map(|line| color_switches(line)).collect();
+    let run_lengths = rows
+      .iter()
+      .zip(&row_switches)
+      .chain(columns.iter().zip(&column_switches))
+      .flat_map(|(line, switches)| {
+        switches.windows(2).filter_map(|pair| {
+          let run = &line[pair[0] as usize..pair[1] as usize];
+          run
+            .iter()
+            .all(|&k| k.is_some() && k == run[0])
+            .then(|| pair[1] - pair[0])
+        })
+      });
+    let tile_size = most_common(run_lengths)?;
+
+    let phase = |switches: &[Vec<u32>]| {
+      most_common(switches.iter().flatten().map(|&switch| switch % tile_size))
+        .map_or(0, |phase| (tile_size - phase) % tile_size)
+    };
+    let mut model = Self {
+      colors,
+      tile_size,
+      offset: (phase(&row_switches), phase(&column_switches)),
+    };
+
+    // Swap the colors if the edges mostly disagree with the predicted parity
+    let edge_points = rows
+      .iter()
+      .zip([0, height - 1])
+      .flat_map(|(line, y)| line.iter().enumerate().map(move |(x, &k)| (x as u32, y, k)))
+      .chain(
+        columns
+          .iter()
+          .zip([0, width - 1])
+          .flat_map(|(line, x)| line.iter().enumerate().map(move |(y, &k)| (x, y as u32, k))),
+      );
+    let (agree, disagree) = edge_points.fold((0, 0), |(agree, disagree), (x, y, k)| match k {
+      Some(k) if k == model.tile_index(x, y) => (agree + 1, disagree),
+      Some(_) => (agree, disagree + 1),
+      None => (agree, disagree),
+    });
+    if disagree > agree {
+      model.colors.swap(0, 1);
+    }
+
+    Some(model)
+  }
+
+  /// The background color at the given pixel
+  pub fn background_at(&self, x: u32, y: u32) -> Color {
+    self.colors[self.tile_index(x, y)]
+  }
+
+  /// Whether the pixel lies on an even (0) or odd (1) tile
+  fn tile_index(&self, x: u32, y: u32) -> usize {
+    (((x + self.offset.0) / self.tile_size + (y + self.offset.1) / self.tile_size) % 2) as usize
   }
+}
 
-  // Find most common color
-  color_counts
+/// The positions along a line of pixels where one tile color gives way to the other
+fn color_switches(line: &[Option<usize>]) -> Vec<u32> {
+  (1..line.len())
+    .filter(|&i| matches!((line[i - 1], line[i]), (Some(a), Some(b)) if a != b))
+    .map(|i| i as u32)
+    .collect()
+}
+
+/// The most common value, the smallest one on ties
+fn most_common(values: impl Iterator<Item = u32>) -> Option<u32> {
+  let mut counts: HashMap<u32, u32> = HashMap::new();
+  for value in values {
+    *counts.entry(value).or_insert(0) += 1;
+  }
+  counts
     .into_iter()
-    .max_by_key(|(_, count)| *count)
-    .map(|(color, _)| color)
-    .unwrap_or([0, 0, 0])
+    .max_by(|(a_value, a_count), (b_value, b_count)| {
+      a_count.cmp(b_count).then(b_value.cmp(a_value))
+    })
+    .map(|(value, _)| value)
+}
+
+/// Normalized RGB distance from the expected background within which a pixel counts towards
+/// the background of its tile
+const TILE_BACKGROUND_DISTANCE: f64 = 0.15;
+
+/// Share of a tile's pixels that must be background for the tile to get its own estimate
+const MIN_TILE_BACKGROUND_SHARE: f64 = 0.25;
+
+/// A background estimated per tile and blended bilinearly between the tile centers
+///
+/// Tiles are estimated ring by ring from the border inwards. Each tile expects the average of
+/// its already estimated neighbours (the global background for the first tiles) and takes the
+/// per-channel median of its pixels close to that, which keeps the subject out of the estimate
+/// while following a background that drifts across the image. Tiles the subject mostly covers
+/// keep the expected color.
+pub struct TiledBackgroundModel {
+  /// The background of each tile, row by row
+  tiles: Vec<NormalizedColor>,
+  /// The number of tile columns and rows
+  grid: (u32, u32),
+  /// The tile width and height in pixels
+  tile_size: u32,
+}
+
+impl TiledBackgroundModel {
+  /// Estimate the background of every tile of an image
+  ///
+  /// # Arguments
+  /// * `img` - The image to analyze
+  /// * `background` - The global background color
+  /// * `tile_size` - The tile width and height in pixels (at least 1)
+  pub fn from_image(img: &DynamicImage, background: Color, tile_size: u32) -> Self {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let grid = (width.div_ceil(tile_size), height.div_ceil(tile_size));
+    let background = normalize_color(background);
+
+    // Visit the tiles from the border inwards
+    let ring = |(tx, ty): (u32, u32)| tx.min(ty).min(grid.0 - 1 - tx).min(grid.1 - 1 - ty);
+    let mut order: Vec<(u32, u32)> = (0..grid.1)
+      .flat_map(|ty| (0..grid.0).map(move |tx| (tx, ty)))
+      .collect();
+    order.sort_by_key(|&tile| ring(tile));
+
+    let mut tiles: Vec<Option<NormalizedColor>> = vec![None; (grid.0 * grid.1) as usize];
+    for (tx, ty) in order {
+      let neighbours: Vec<NormalizedColor> = (ty.saturating_sub(1)..=(ty + 1).min(grid.1 - 1))
+        .flat_map(|ny| (tx.saturating_sub(1)..=(tx + 1).min(grid.0 - 1)).map(move |nx| (nx, ny)))
+        .filter_map(|(nx, ny)| tiles[(ny * grid.0 + nx) as usize])
+        .collect();
+      let expected = if neighbours.is_empty() {
+        background
+      } else {
+        [0, 1, 2].map(|c| neighbours.iter().map(|n| n[c]).sum::<f64>() / neighbours.len() as f64)
+      };
+
+      let (x0, y0) = (tx * tile_size, ty * tile_size);
+      let (x1, y1) = ((x0 + tile_size).min(width), (y0 + tile_size).min(height));
+      let mut channels: [Vec<f64>; 3] = Default::default();
+      for y in y0..y1 {
+        for x in x0..x1 {
+          let color = normalize_color(composite_over_black(rgba.get_pixel(x, y)));
+          let distance = (0..3)
+            .map(|i| (color[i] - expected[i]).powi(2))
+            .sum::<f64>()
+            .sqrt();
+          if distance <= TILE_BACKGROUND_DISTANCE {
+            for (values, value) in channels.iter_mut().zip(color) {
+              values.push(value);
+            }
+          }
+        }
+      }
+
+      let pixels = ((x1 - x0) * (y1 - y0)) as f64;
+      let estimate = if channels[0].len() as f64 >= pixels * MIN_TILE_BACKGROUND_SHARE {
+        channels.map(|mut values| {
+          values.sort_by(f64::total_cmp);
+          values[values.len() / 2]
+        })
+      } else {
+        expected
+      };
+      tiles[(ty * grid.0 + tx) as usize] = Some(estimate);
+    }
+
+    Self {
+      tiles: tiles.into_iter().flatten().collect(),
+      grid,
+      tile_size,
+    }
+  }
+
+  /// The background color at the given pixel, blended between the four nearest tile centers
+  pub fn background_at(&self, x: u32, y: u32) -> Color {
+    let axis = |position: u32, tiles: u32| {
+      let t =
+        ((position as f64 + 0.5) / self.tile_size as f64 - 0.5).clamp(0.0, (tiles - 1) as f64);
+      let first = t.floor() as u32;
+      (first, (first + 1).min(tiles - 1), t - first as f64)
+    };
+    let (x0, x1, fx) = axis(x, self.grid.0);
+    let (y0, y1, fy) = axis(y, self.grid.1);
+    let tile = |tx: u32, ty: u32| self.tiles[(ty * self.grid.0 + tx) as usize];
+    let (a, b, c, d) = (tile(x0, y0), tile(x1, y0), tile(x0, y1), tile(x1, y1));
+
+    denormalize_color([0, 1, 2].map(|i| {
+      let top = a[i] + (b[i] - a[i]) * fx;
+      let bottom = c[i] + (d[i] - c[i]) * fx;
+      top + (bottom - top) * fy
+    }))
+  }
+}
+
+/// A background color that varies across the image
+pub enum SpatialBackground {
+  /// Interpolated between the nearest edge samples
+  Local(LocalBackgroundModel),
+  /// A smooth gradient fitted to all edge samples
+  Gradient(GradientBackgroundModel),
+  /// A clean shot of the empty scene, pixel for pixel
+  Plate(RgbImage),
+  /// An alternating two-color grid of square tiles
+  Checkerboard(CheckerboardBackgroundModel),
+  /// Estimated per tile across the whole image
+  Tiled(TiledBackgroundModel),
+}
+
+impl SpatialBackground {
+  /// The background color at the given pixel
+  pub fn background_at(&self, x: u32, y: u32) -> Color {
+    match self {
+      SpatialBackground::Local(model) => model.background_at(x, y),
+      SpatialBackground::Gradient(model) => model.background_at(x, y),
+      SpatialBackground::Plate(plate) => plate.get_pixel(x, y).0,
+      SpatialBackground::Checkerboard(model) => model.background_at(x, y),
+      SpatialBackground::Tiled(model) => model.background_at(x, y),
+    }
+  }
+}
+
+/// Background share of the image at which the removability score stops increasing
+const TARGET_BACKGROUND_FRACTION: f64 = 0.25;
+
+/// Estimate how well an image lends itself to automatic background removal
+///
+/// Combines two cheap measures, without any per-pixel unmixing:
+/// * the background confidence: the share of edge samples within `threshold` of the
+///   detected background color
+/// * the share of the image covered by background that is connected to the edges,
+///   found by flood-filling (4-connected) from every border pixel close to the background.
+///   Coverage up to a quarter of the image raises the score; an image that is entirely
+///   background has no subject and scores 0.
+///
+/// # Arguments
+/// * `img` - The image to analyze
+/// * `config` - Configuration for background detection
+/// * `threshold` - The maximum normalized RGB distance to the background color
+///
+/// # Returns
+/// A suitability score from 0.0 (unsuitable) to 1.0 (clean, uniform background)
+pub fn assess_removability(
+  img: &DynamicImage,
+  config: &BackgroundDetectionConfig,
+  threshold: f64,
+) -> f64 {
+  let rgba = img.to_rgba8();
+  let (width, height) = rgba.dimensions();
+  let background = detect_background_color_with_config(img, config);
+  let (matching, total) = count_matching_edge_samples(&rgba, config, background, threshold);
+  if total == 0 {
+    return 0.0;
+  }
+  let confidence = matching as f64 / total as f64;
+
+  let background = normalize_color(background);
+  let is_background = |pixel: &Rgba<u8>| {
+    let color = normalize_color(composite_over_black(pixel));
+    let distance = (0..3)
+      .map(|i| (color[i] - background[i]).powi(2))
+      .sum::<f64>()
+      .sqrt();
+    distance <= threshold
+  };
+
+  let filled = flood_fill_from_border(width, height, |x, y| is_background(rgba.get_pixel(x, y)));
+  let connected = filled.iter().filter(|&&filled| filled).count();
+
+  let connected_fraction = connected as f64 / (width as f64 * height as f64);
+  if connected_fraction >= 1.0 {
+    return 0.0;
+  }
+
+  confidence * (connected_fraction / TARGET_BACKGROUND_FRACTION).min(1.0)
+}
+
+/// Flood fill (4-connected) the pixels matching `is_background` from every matching border
+/// pixel, returning whether each pixel (in row-major order) was reached
+pub fn flood_fill_from_border(
+  width: u32,
+  height: u32,
+  is_background: impl Fn(u32, u32) -> bool,
+) -> Vec<bool> {
+  let mut visited = vec![false; (width * height) as usize];
+  let mut queue = VecDeque::new();
+  let border = (0..width)
+    .flat_map(|x| [(x, 0), (x, height - 1)])
+    .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]));
+  for (x, y) in border {
+    let index = (y * width + x) as usize;
+    if !visited[index] && is_background(x, y) {
+      visited[index] = true;
+      queue.push_back((x, y));
+    }
+  }
+
+  while let Some((x, y)) = queue.pop_front() {
+    let neighbours = [
+      (x.wrapping_sub(1), y),
+      (x + 1, y),
+      (x, y.wrapping_sub(1)),
+      (x, y + 1),
+    ];
+    for (nx, ny) in neighbours {
+      if nx >= width || ny >= height {
+        continue;
+      }
+      let index = (ny * width + nx) as usize;
+      if !visited[index] && is_background(nx, ny) {
+        visited[index] = true;
+        queue.push_back((nx, ny));
+      }
+    }
+  }
+
+  visited
+}
+
+/// The pixels background removal may touch when only background connected to the border is
+/// removed: the flood-filled background, plus the pixels next to it (8-connected) so the
+/// anti-aliased edge between it and the subject is still unmixed
+pub fn border_connected_region(
+  width: u32,
+  height: u32,
+  is_background: impl Fn(u32, u32) -> bool,
+) -> Vec<bool> {
+  let filled = flood_fill_from_border(width, height, is_background);
+  (0..height)
+    .flat_map(|y| (0..width).map(move |x| (x, y)))
+    .map(|(x, y)| {
+      (y.saturating_sub(1)..=(y + 1).min(height - 1)).any(|ny| {
+        (x.saturating_sub(1)..=(x + 1).min(width - 1)).any(|nx| filled[(ny * width + nx) as usize])
+      })
+    })
+    .collect()
 }
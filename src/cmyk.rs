@@ -0,0 +1,147 @@
+use anyhow::Result;
+use image::RgbImage;
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+use zune_core::bytestream::ZCursor;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+use zune_jpeg::JpegDecoder;
+
+/// The JPEG APP14 marker, which Adobe applications use to flag inverted CMYK data
+const APP14: u8 = 0xee;
+
+/// What the headers of a JPEG tell about its color components
+struct JpegLayout {
+  /// The number of color components of the frame
+  components: u8,
+  /// Whether an Adobe APP14 segment is present, meaning the CMYK data is stored inverted
+  adobe: bool,
+}
+
+/// Read the frame and Adobe segments of a JPEG, up to its first scan
+fn jpeg_layout(data: &[u8]) -> Option<JpegLayout> {
+  if !data.starts_with(&[0xff, 0xd8]) {
+    return None;
+  }
+
+  let mut adobe = false;
+  let mut pos = 2;
+  while pos + 4 <= data.len() {
+    if data[pos] != 0xff {
+      return None;
+    }
+    let marker = data[pos + 1];
+    // Fill bytes may pad markers
+    if marker == 0xff {
+      pos += 1;
+      continue;
+    }
+    let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+    let segment = data.get(pos + 4..pos + 2 + length)?;
+
+    match marker {
+      APP14 => adobe |= segment.starts_with(b"Adobe"),
+      // SOF0-SOF15, except DHT (0xc4), JPG (0xc8) and DAC (0xcc)
+      0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+        return segment
+          .get(5)
+          .map(|&components| JpegLayout { components, adobe });
+      }
+      // Start of scan, with no frame header before it
+      0xda => return None,
+      _ => {}
+    }
+    pos += 2 + length;
+  }
+  None
+}
+
+/// Whether the bytes are a JPEG with four color components, CMYK or YCCK
+pub fn is_cmyk_jpeg(data: &[u8]) -> bool {
+  jpeg_layout(data).is_some_and(|layout| layout.components == 4)
+}
+
+/// Decode a CMYK or YCCK JPEG to RGB
+///
+/// Data is read as inverted when an Adobe APP14 segment is present, as Adobe applications
+/// write it, and as plain ink amounts otherwise. A CMYK ICC profile converts the inks to
+/// sRGB; without one, each channel is the share of white light its ink and the black ink
+/// let through.
+pub fn decode_cmyk_jpeg(data: &[u8], icc_profile: Option<&[u8]>) -> Result<RgbImage> {
+  let layout = jpeg_layout(data).ok_or_else(|| anyhow::anyhow!("Not a CMYK JPEG"))?;
+  let decode_error = |e: zune_jpeg::errors::DecodeErrors| anyhow::anyhow!("{:?}", e);
+
+  let options = DecoderOptions::default()
+    .set_strict_mode(false)
+    .set_max_width(usize::MAX)
+    .set_max_height(usize::MAX);
+  let mut decoder = JpegDecoder::new_with_options(ZCursor::new(data), options);
+  decoder.decode_headers().map_err(decode_error)?;
+  let colorspace = decoder.input_colorspace().expect("headers were decoded");
+  if !matches!(colorspace, ColorSpace::CMYK | ColorSpace::YCCK) {
+    anyhow::bail!("Not a CMYK JPEG");
+  }
+  let (width, height) = decoder.dimensions().expect("headers were decoded");
+
+  // Keep the four stored components as they are, and convert them ourselves
+  decoder.set_options(options.jpeg_set_out_colorspace(colorspace));
+  let mut samples = decoder.decode().map_err(decode_error)?;
+
+  for pixel in samples.chunks_exact_mut(4) {
+    // YCCK encodes the complements of the stored CMY components as YCbCr
+    if colorspace == ColorSpace::YCCK {
+      let cmy = ycc_to_rgb([pixel[0], pixel[1], pixel[2]]).map(|c| 255 - c);
+      pixel[..3].copy_from_slice(&cmy);
+    }
+    if layout.adobe {
+      pixel.iter_mut().for_each(|ink| *ink = 255 - *ink);
+    }
+  }
+
+  let rgb = match icc_profile.and_then(cmyk_profile) {
+    Some(profile) => {
+      let transform = profile
+        .create_transform_8bit(
+          Layout::Rgba,
+          &ColorProfile::new_srgb(),
+          Layout::Rgb,
+          TransformOptions::default(),
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to convert to sRGB: {}", e))?;
+      let mut rgb = vec![0; samples.len() / 4 * 3];
+      transform
+        .transform(&samples, &mut rgb)
+        .map_err(|e| anyhow::anyhow!("Failed to convert to sRGB: {}", e))?;
+      rgb
+    }
+    None => samples
+      .chunks_exact(4)
+      .flat_map(|pixel| {
+        let white = 255 - pixel[3] as u32;
+        [0, 1, 2].map(|i| (((255 - pixel[i] as u32) * white + 127) / 255) as u8)
+      })
+      .collect(),
+  };
+
+  Ok(
+    RgbImage::from_raw(width as u32, height as u32, rgb)
+      .expect("one pixel was converted per decoded pixel"),
+  )
+}
+
+/// Parse an ICC profile, if it describes CMYK data
+fn cmyk_profile(icc_profile: &[u8]) -> Option<ColorProfile> {
+  ColorProfile::new_from_slice(icc_profile)
+    .ok()
+    .filter(|profile| profile.color_space == DataColorSpace::Cmyk)
+}
+
+/// Convert JFIF YCbCr components to the components they encode
+fn ycc_to_rgb([y, cb, cr]: [u8; 3]) -> [u8; 3] {
+  let (y, cb, cr) = (y as f64, cb as f64 - 128.0, cr as f64 - 128.0);
+  [
+    y + 1.402 * cr,
+    y - 0.344136 * cb - 0.714136 * cr,
+    y + 1.772 * cb,
+  ]
+  .map(|c| c.round().clamp(0.0, 255.0) as u8)
+}
@@ -1,16 +1,117 @@
 // based on https://github.com/benface/bgone/blob/b362931f37252301f0f8dec183b2072f415b9b5f/src/color.rs
 
 use anyhow::{Context, Result};
+use image::DynamicImage;
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
 
 /// Multiplier to expand hex color shorthand (e.g., F -> FF)
 const HEX_SHORTHAND_MULTIPLIER: u8 = 17;
 
+/// Offset of the OKLab a and b axes in the OKLab working space, which keeps sRGB colors
+/// (a in [-0.24, 0.28], b in [-0.32, 0.2]) within [0, 1]
+const OKLAB_AXIS_OFFSET: f64 = 0.5;
+
 /// RGB color represented as [R, G, B] with values 0-255
 pub type Color = [u8; 3];
 
 /// Normalized RGB color with values 0.0-1.0
 pub type NormalizedColor = [f64; 3];
 
+/// Normalized RGBA color with values 0.0-1.0
+pub type NormalizedRgba = [f64; 4];
+
+/// How scene-linear floating point inputs (e.g. OpenEXR renders) are brought into the [0, 1]
+/// working range
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToneMapping {
+  /// Clamp each channel, clipping highlights brighter than 1.0
+  #[default]
+  Clamp,
+  /// Compress the luminance with extended Reinhard, mapping the brightest pixel to 1.0 and
+  /// leaving images already in range untouched
+  Reinhard,
+}
+
+/// How the distance between two colors is measured for closeness thresholds and tolerances
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThresholdSpace {
+  /// Euclidean distance between normalized RGB colors
+  #[default]
+  Rgb,
+  /// The CIEDE2000 perceptual difference, divided by 100 to keep thresholds in [0, 1] (a
+  /// threshold of 0.05 is a difference of 5)
+  DeltaE2000,
+}
+
+impl ThresholdSpace {
+  /// The distance between two normalized sRGB colors
+  pub fn distance(self, a: NormalizedColor, b: NormalizedColor) -> f64 {
+    match self {
+      ThresholdSpace::Rgb => (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f64>().sqrt(),
+      ThresholdSpace::DeltaE2000 => delta_e_2000(srgb_to_lab(a), srgb_to_lab(b)) / 100.0,
+    }
+  }
+}
+
+/// The units a closeness threshold is given in
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThresholdUnits {
+  /// A fraction of the full channel range (0.0-1.0)
+  #[default]
+  Normalized,
+  /// 8-bit channel levels (0-255), so 12 is a distance of 12 levels
+  EightBit,
+}
+
+impl ThresholdUnits {
+  /// Convert a threshold in these units to a normalized one
+  pub fn normalize(self, threshold: f64) -> f64 {
+    match self {
+      ThresholdUnits::Normalized => threshold,
+      ThresholdUnits::EightBit => threshold / 255.0,
+    }
+  }
+}
+
+/// The color space pixels are unmixed in
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum WorkingSpace {
+  /// Gamma-encoded sRGB, as stored in the image
+  #[default]
+  Srgb,
+  /// Linear light sRGB
+  LinearLight,
+  /// OKLab, with the a and b axes offset by 0.5 so sRGB colors stay within [0, 1]
+  Oklab,
+}
+
+impl WorkingSpace {
+  /// Convert a normalized sRGB color to this space
+  pub fn from_srgb(self, color: NormalizedColor) -> NormalizedColor {
+    match self {
+      WorkingSpace::Srgb => color,
+      WorkingSpace::LinearLight => color.map(srgb_to_linear),
+      WorkingSpace::Oklab => {
+        let [l, a, b] = srgb_to_oklab(color);
+        [l, a + OKLAB_AXIS_OFFSET, b + OKLAB_AXIS_OFFSET]
+      }
+    }
+  }
+
+  /// Convert a color in this space back to normalized sRGB, clamping it into gamut
+  pub fn to_srgb(self, color: NormalizedColor) -> NormalizedColor {
+    match self {
+      WorkingSpace::Srgb => color,
+      WorkingSpace::LinearLight => color.map(|c| linear_to_srgb(c.clamp(0.0, 1.0))),
+      WorkingSpace::Oklab => {
+        let [l, a, b] = color;
+        oklab_to_linear_srgb([l, a - OKLAB_AXIS_OFFSET, b - OKLAB_AXIS_OFFSET])
+          .map(|c| linear_to_srgb(c.clamp(0.0, 1.0)))
+      }
+    }
+  }
+}
+
 /// A foreground color specification - either known or unknown
 pub enum ForegroundColorSpec {
   /// A known color specified by the user
@@ -19,43 +120,268 @@ pub enum ForegroundColorSpec {
   Unknown,
 }
 
-/// Parse a hex color string into RGB
-/// Supports: "#ff0000", "ff0000", "#f00", "f00"
-pub fn parse_hex_color(hex: &str) -> Result<Color> {
+/// Parse a hex color string into RGB and an alpha (0.0-1.0)
+/// Supports: "#ff0000", "ff0000", "#f00", "f00", and with alpha "#ff000080", "#f008"
+pub fn parse_hex_color_with_alpha(hex: &str) -> Result<(Color, f64)> {
   let hex = hex.trim_start_matches('#');
+  if !hex.is_ascii() {
+    anyhow::bail!("Invalid hex color: {}", hex);
+  }
 
-  let (r, g, b) = match hex.len() {
-    3 => {
-      // Expand shorthand: "f00" -> "ff0000"
-      let r = u8::from_str_radix(&hex[0..1], 16).context("Invalid red component")?;
-      let g = u8::from_str_radix(&hex[1..2], 16).context("Invalid green component")?;
-      let b = u8::from_str_radix(&hex[2..3], 16).context("Invalid blue component")?;
-      (
-        r * HEX_SHORTHAND_MULTIPLIER,
-        g * HEX_SHORTHAND_MULTIPLIER,
-        b * HEX_SHORTHAND_MULTIPLIER,
-      )
-    }
-    6 => {
-      // Full hex color
-      let r = u8::from_str_radix(&hex[0..2], 16).context("Invalid red component")?;
-      let g = u8::from_str_radix(&hex[2..4], 16).context("Invalid green component")?;
-      let b = u8::from_str_radix(&hex[4..6], 16).context("Invalid blue component")?;
-      (r, g, b)
-    }
-    _ => anyhow::bail!("Hex color must be 3 or 6 characters long (got: {})", hex),
+  // Each component is one digit in shorthand ("f00" -> "ff0000") or two in full
+  let digits = match hex.len() {
+    3 | 4 => 1,
+    6 | 8 => 2,
+    _ => anyhow::bail!(
+      "Hex color must be 3, 4, 6 or 8 characters long (got: {})",
+      hex
+    ),
+  };
+  let component = |i: usize, name: &str| {
+    let value = u8::from_str_radix(&hex[i * digits..(i + 1) * digits], 16)
+      .with_context(|| format!("Invalid {} component", name))?;
+    Ok::<_, anyhow::Error>(if digits == 1 {
+      value * HEX_SHORTHAND_MULTIPLIER
+    } else {
+      value
+    })
+  };
+
+  let color = [
+    component(0, "red")?,
+    component(1, "green")?,
+    component(2, "blue")?,
+  ];
+  let alpha = if hex.len().is_multiple_of(4) {
+    component(3, "alpha")? as f64 / 255.0
+  } else {
+    1.0
   };
+  Ok((color, alpha))
+}
 
-  Ok([r, g, b])
+/// Parse a CSS color string into RGB
+/// Supports: hex colors (see `parse_hex_color_with_alpha`), "rgb(255, 0, 0)",
+/// "rgba(255 0 0 / 100%)", "hsl(0, 100%, 50%)", "hsla(0deg 100% 50% / 1)" and named colors
+/// ("white", "rebeccapurple")
+///
+/// The color must be opaque: colors with an alpha below 1 are rejected.
+pub fn parse_css_color(spec: &str) -> Result<Color> {
+  let (color, alpha) = parse_css_color_with_alpha(spec)?;
+  if alpha < 1.0 {
+    anyhow::bail!("Color must be opaque (got alpha: {})", alpha);
+  }
+  Ok(color)
+}
+
+/// Parse a CSS color string into RGB and an alpha (0.0-1.0)
+/// Supports the same formats as `parse_css_color`, along with "transparent"
+pub fn parse_css_color_with_alpha(spec: &str) -> Result<(Color, f64)> {
+  let spec = spec.trim();
+  let lower = spec.to_ascii_lowercase();
+
+  if let Some((function, arguments)) = lower
+    .strip_suffix(')')
+    .and_then(|rest| rest.split_once('('))
+  {
+    let arguments: Vec<&str> = arguments
+      .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+      .filter(|argument| !argument.is_empty())
+      .collect();
+    let (channels, alpha) = match arguments[..] {
+      [a, b, c] => ([a, b, c], 1.0),
+      [a, b, c, alpha] => ([a, b, c], parse_css_alpha(alpha)?),
+      _ => anyhow::bail!("{}() takes 3 or 4 arguments (got: {})", function, spec),
+    };
+    let color = match function.trim() {
+      "rgb" | "rgba" => {
+        let [r, g, b] = channels;
+        [
+          parse_css_channel(r)?,
+          parse_css_channel(g)?,
+          parse_css_channel(b)?,
+        ]
+      }
+      "hsl" | "hsla" => {
+        let [h, s, l] = channels;
+        let hue = h
+          .strip_suffix("deg")
+          .unwrap_or(h)
+          .parse::<f64>()
+          .with_context(|| format!("Invalid hue: {}", h))?;
+        denormalize_color(hsl_to_rgb([
+          hue,
+          parse_css_percentage(s)?,
+          parse_css_percentage(l)?,
+        ]))
+      }
+      _ => anyhow::bail!("Unsupported color function: {}()", function.trim()),
+    };
+    return Ok((color, alpha));
+  }
+
+  if lower == "transparent" {
+    return Ok(([0, 0, 0], 0.0));
+  }
+  if let Ok(i) = NAMED_COLORS.binary_search_by_key(&lower.as_str(), |&(name, _)| name) {
+    return Ok((NAMED_COLORS[i].1, 1.0));
+  }
+  parse_hex_color_with_alpha(spec)
+}
+
+/// Parse an rgb() channel: a number from 0 to 255, or a percentage
+fn parse_css_channel(spec: &str) -> Result<u8> {
+  let value = match spec.strip_suffix('%') {
+    Some(percent) => percent
+      .parse::<f64>()
+      .map(|percent| percent / 100.0 * 255.0),
+    None => spec.parse::<f64>(),
+  }
+  .with_context(|| format!("Invalid color channel: {}", spec))?;
+  Ok(value.round().clamp(0.0, 255.0) as u8)
+}
+
+/// Parse an hsl() saturation or lightness percentage into 0.0-1.0
+fn parse_css_percentage(spec: &str) -> Result<f64> {
+  let percent = spec
+    .strip_suffix('%')
+    .unwrap_or(spec)
+    .parse::<f64>()
+    .with_context(|| format!("Invalid percentage: {}", spec))?;
+  Ok((percent / 100.0).clamp(0.0, 1.0))
+}
+
+/// Parse a CSS alpha: a number from 0.0 to 1.0, or a percentage
+fn parse_css_alpha(spec: &str) -> Result<f64> {
+  let alpha = match spec.strip_suffix('%') {
+    Some(percent) => percent.parse::<f64>().map(|percent| percent / 100.0),
+    None => spec.parse::<f64>(),
+  }
+  .with_context(|| format!("Invalid alpha: {}", spec))?;
+  Ok(alpha.clamp(0.0, 1.0))
 }
 
 /// Parse a foreground color specification
-/// Can be either a hex color or "auto" for unknown
+/// Can be either a CSS color or "auto" for unknown, optionally with a tolerance (see
+/// `parse_foreground_spec_with_tolerance`)
 pub fn parse_foreground_spec(spec: &str) -> Result<ForegroundColorSpec> {
-  if spec == "auto" {
-    Ok(ForegroundColorSpec::Unknown)
-  } else {
-    parse_hex_color(spec).map(ForegroundColorSpec::Known)
+  parse_foreground_spec_with_tolerance(spec).map(|(spec, _)| spec)
+}
+
+/// Parse a foreground color specification with an optional tolerance radius
+/// Supports: "ff0000", "red", "rgb(255, 0, 0)", "auto", "ff0000@0.05", "auto@0.05", "ff0000f3"
+///
+/// The tolerance is a normalized RGB distance (0.0-1.0): in strict mode, pixels within it
+/// of the color are treated as fully that color instead of being unmixed. A color with an
+/// alpha below 1 (e.g. "#ff0000f3") has a tolerance of one minus its alpha.
+pub fn parse_foreground_spec_with_tolerance(
+  spec: &str,
+) -> Result<(ForegroundColorSpec, Option<f64>)> {
+  let (color, tolerance) = match spec.split_once('@') {
+    Some((color, tolerance)) => (color, Some(tolerance)),
+    None => (spec, None),
+  };
+
+  if color == "auto" {
+    let tolerance = tolerance.map(parse_tolerance).transpose()?;
+    return Ok((ForegroundColorSpec::Unknown, tolerance));
+  }
+  let (color, tolerance) = parse_color_with_tolerance(color, tolerance)?;
+  Ok((ForegroundColorSpec::Known(color), tolerance))
+}
+
+/// Parse a background color specification with an optional tolerance radius
+/// Supports: "ffffff", "white", "rgb(255 255 255)", "ffffff~0.04", "ffffff~4%", "fffffff5"
+///
+/// The tolerance is a normalized RGB distance (0.0-1.0, or a percentage of it): pixels within
+/// it of the color are pure background and become fully transparent instead of being unmixed.
+/// A color with an alpha below 1 (e.g. "#fffffff5") has a tolerance of one minus its alpha.
+pub fn parse_background_spec(spec: &str) -> Result<(Color, Option<f64>)> {
+  match spec.split_once('~') {
+    Some((color, tolerance)) => parse_color_with_tolerance(color, Some(tolerance)),
+    None => parse_color_with_tolerance(spec, None),
+  }
+}
+
+/// Parse a color and its tolerance, given either as a suffix or by the color's alpha
+fn parse_color_with_tolerance(
+  color: &str,
+  tolerance: Option<&str>,
+) -> Result<(Color, Option<f64>)> {
+  let (color, alpha) = parse_css_color_with_alpha(color)?;
+  let tolerance = match tolerance {
+    Some(_) if alpha < 1.0 => {
+      anyhow::bail!("A color with alpha cannot also have a tolerance suffix")
+    }
+    Some(tolerance) => Some(parse_tolerance(tolerance)?),
+    None if alpha < 1.0 => Some(1.0 - alpha),
+    None => None,
+  };
+  Ok((color, tolerance))
+}
+
+/// Parse a tolerance radius: a normalized RGB distance, or a percentage of it
+fn parse_tolerance(spec: &str) -> Result<f64> {
+  let tolerance: f64 = match spec.strip_suffix('%') {
+    Some(percent) => percent.parse::<f64>().map(|percent| percent / 100.0),
+    None => spec.parse(),
+  }
+  .with_context(|| format!("Invalid tolerance: {}", spec))?;
+  if !(0.0..=1.0).contains(&tolerance) {
+    anyhow::bail!("Tolerance must be between 0.0 and 1.0 (got: {})", tolerance);
+  }
+  Ok(tolerance)
+}
+
+/// Parse a tone mapping
+/// Supports: "clamp", "reinhard"
+pub fn parse_tone_mapping(spec: &str) -> Result<ToneMapping> {
+  match spec {
+    "clamp" => Ok(ToneMapping::Clamp),
+    "reinhard" => Ok(ToneMapping::Reinhard),
+    _ => anyhow::bail!(
+      "Tone mapping must be one of \"clamp\" or \"reinhard\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// Parse a threshold space
+/// Supports: "rgb", "deltaE2000"
+pub fn parse_threshold_space(spec: &str) -> Result<ThresholdSpace> {
+  match spec {
+    "rgb" => Ok(ThresholdSpace::Rgb),
+    "deltaE2000" => Ok(ThresholdSpace::DeltaE2000),
+    _ => anyhow::bail!(
+      "Threshold space must be one of \"rgb\" or \"deltaE2000\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// Parse the units of a closeness threshold
+/// Supports: "normalized", "8bit"
+pub fn parse_threshold_units(spec: &str) -> Result<ThresholdUnits> {
+  match spec {
+    "normalized" => Ok(ThresholdUnits::Normalized),
+    "8bit" => Ok(ThresholdUnits::EightBit),
+    _ => anyhow::bail!(
+      "Threshold units must be one of \"normalized\" or \"8bit\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// Parse an unmixing color space
+/// Supports: "srgb", "oklab"
+pub fn parse_unmix_space(spec: &str) -> Result<WorkingSpace> {
+  match spec {
+    "srgb" => Ok(WorkingSpace::Srgb),
+    "oklab" => Ok(WorkingSpace::Oklab),
+    _ => anyhow::bail!(
+      "Unmix space must be one of \"srgb\" or \"oklab\" (got: {})",
+      spec
+    ),
   }
 }
 
@@ -76,3 +402,522 @@ pub fn denormalize_color(color: NormalizedColor) -> Color {
     (color[2] * 255.0).round().clamp(0.0, 255.0) as u8,
   ]
 }
+
+/// Convert a NormalizedRgba to 8 bits per channel
+pub fn denormalize_rgba(color: NormalizedRgba) -> [u8; 4] {
+  color.map(|c| (c * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+/// Convert a NormalizedRgba to 16 bits per channel
+pub fn denormalize_rgba16(color: NormalizedRgba) -> [u16; 4] {
+  color.map(|c| (c * 65535.0).round().clamp(0.0, 65535.0) as u16)
+}
+
+/// Convert an image from the color space of its embedded ICC profile to sRGB
+///
+/// Images deeper than 8 bits per channel are converted at 16 bits. Profiles that aren't RGB
+/// (e.g. gray) leave the image unchanged.
+pub fn convert_to_srgb(img: DynamicImage, icc_profile: &[u8]) -> Result<DynamicImage> {
+  let source = ColorProfile::new_from_slice(icc_profile)
+    .map_err(|e| anyhow::anyhow!("Invalid ICC profile: {}", e))?;
+  if source.color_space != DataColorSpace::Rgb {
+    return Ok(img);
+  }
+  let srgb = ColorProfile::new_srgb();
+  let transform_error = |e: moxcms::CmsError| anyhow::anyhow!("Failed to convert to sRGB: {}", e);
+
+  let color = img.color();
+  if color.bytes_per_pixel() > color.channel_count() {
+    let mut rgba = img.to_rgba16();
+    let transform = source
+      .create_transform_16bit(
+        Layout::Rgba,
+        &srgb,
+        Layout::Rgba,
+        TransformOptions::default(),
+      )
+      .map_err(transform_error)?;
+    let src = rgba.as_raw().clone();
+    transform
+      .transform(&src, &mut rgba)
+      .map_err(transform_error)?;
+    Ok(DynamicImage::ImageRgba16(rgba))
+  } else {
+    let mut rgba = img.to_rgba8();
+    let transform = source
+      .create_transform_8bit(
+        Layout::Rgba,
+        &srgb,
+        Layout::Rgba,
+        TransformOptions::default(),
+      )
+      .map_err(transform_error)?;
+    let src = rgba.as_raw().clone();
+    transform
+      .transform(&src, &mut rgba)
+      .map_err(transform_error)?;
+    Ok(DynamicImage::ImageRgba8(rgba))
+  }
+}
+
+/// Convert an sRGB color into the color space of an ICC profile, the inverse of
+/// `convert_to_srgb`
+///
+/// Profiles that aren't RGB leave the color unchanged.
+pub fn convert_from_srgb(color: Color, icc_profile: &[u8]) -> Result<Color> {
+  let target = ColorProfile::new_from_slice(icc_profile)
+    .map_err(|e| anyhow::anyhow!("Invalid ICC profile: {}", e))?;
+  if target.color_space != DataColorSpace::Rgb {
+    return Ok(color);
+  }
+  let transform_error = |e: moxcms::CmsError| anyhow::anyhow!("Failed to convert from sRGB: {}", e);
+
+  let transform = ColorProfile::new_srgb()
+    .create_transform_8bit(
+      Layout::Rgb,
+      &target,
+      Layout::Rgb,
+      TransformOptions::default(),
+    )
+    .map_err(transform_error)?;
+  let mut converted = [0; 3];
+  transform
+    .transform(&color, &mut converted)
+    .map_err(transform_error)?;
+  Ok(converted)
+}
+
+/// Convert a normalized RGB color to hue (0.0-360.0 degrees), saturation and value (0.0-1.0)
+///
+/// Grays have a hue of 0.0.
+pub fn rgb_to_hsv(color: NormalizedColor) -> [f64; 3] {
+  let [r, g, b] = color;
+  let max = r.max(g).max(b);
+  let min = r.min(g).min(b);
+  let chroma = max - min;
+
+  let hue = if chroma == 0.0 {
+    0.0
+  } else if max == r {
+    60.0 * ((g - b) / chroma).rem_euclid(6.0)
+  } else if max == g {
+    60.0 * ((b - r) / chroma + 2.0)
+  } else {
+    60.0 * ((r - g) / chroma + 4.0)
+  };
+  let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+  [hue, saturation, max]
+}
+
+/// Convert hue (degrees), saturation and value (0.0-1.0) to a normalized RGB color
+pub fn hsv_to_rgb(hsv: [f64; 3]) -> NormalizedColor {
+  let [hue, saturation, value] = hsv;
+  hue_to_rgb(hue, value * saturation, value)
+}
+
+/// Convert a normalized RGB color to hue (0.0-360.0 degrees), saturation and lightness
+/// (0.0-1.0)
+///
+/// Grays have a hue and saturation of 0.0.
+pub fn rgb_to_hsl(color: NormalizedColor) -> [f64; 3] {
+  let [hue, _, max] = rgb_to_hsv(color);
+  let min = color[0].min(color[1]).min(color[2]);
+  let lightness = (max + min) / 2.0;
+  let saturation = if max == min {
+    0.0
+  } else {
+    (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+  };
+  [hue, saturation, lightness]
+}
+
+/// Convert hue (degrees), saturation and lightness (0.0-1.0) to a normalized RGB color
+pub fn hsl_to_rgb(hsl: [f64; 3]) -> NormalizedColor {
+  let [hue, saturation, lightness] = hsl;
+  let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+  hue_to_rgb(hue, chroma, lightness + chroma / 2.0)
+}
+
+/// The RGB color of a hue with the given chroma and largest channel value
+fn hue_to_rgb(hue: f64, chroma: f64, max: f64) -> NormalizedColor {
+  let hue = hue.rem_euclid(360.0) / 60.0;
+  let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+  let (r, g, b) = match hue as u32 {
+    0 => (chroma, x, 0.0),
+    1 => (x, chroma, 0.0),
+    2 => (0.0, chroma, x),
+    3 => (0.0, x, chroma),
+    4 => (x, 0.0, chroma),
+    _ => (chroma, 0.0, x),
+  };
+  let m = max - chroma;
+  [r + m, g + m, b + m]
+}
+
+/// Decode an sRGB-encoded channel value (0.0-1.0) to linear light
+pub fn srgb_to_linear(c: f64) -> f64 {
+  if c <= 0.04045 {
+    c / 12.92
+  } else {
+    ((c + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+/// Encode a linear light channel value (0.0-1.0) with the sRGB transfer function
+pub fn linear_to_srgb(c: f64) -> f64 {
+  if c <= 0.0031308 {
+    c * 12.92
+  } else {
+    1.055 * c.powf(1.0 / 2.4) - 0.055
+  }
+}
+
+/// Convert a normalized sRGB color to CIELAB under the D65 white point
+pub fn srgb_to_lab(color: NormalizedColor) -> [f64; 3] {
+  let [r, g, b] = color.map(srgb_to_linear);
+  let xyz = [
+    (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047,
+    0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+    (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883,
+  ];
+  let [fx, fy, fz] = xyz.map(|t| {
+    if t > 216.0 / 24389.0 {
+      t.cbrt()
+    } else {
+      (24389.0 / 27.0 * t + 16.0) / 116.0
+    }
+  });
+  [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Convert a CIELAB color under the D65 white point to normalized sRGB, which may fall outside
+/// [0, 1]
+pub fn lab_to_srgb(lab: [f64; 3]) -> NormalizedColor {
+  let [l, a, b] = lab;
+  let fy = (l + 16.0) / 116.0;
+  let [x, y, z] = [fy + a / 500.0, fy, fy - b / 200.0].map(|f| {
+    if f.powi(3) > 216.0 / 24389.0 {
+      f.powi(3)
+    } else {
+      (116.0 * f - 16.0) * 27.0 / 24389.0
+    }
+  });
+  let (x, z) = (x * 0.95047, z * 1.08883);
+  [
+    3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+    -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+    0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+  ]
+  .map(linear_to_srgb)
+}
+
+/// Convert a normalized sRGB color to OKLab
+pub fn srgb_to_oklab(color: NormalizedColor) -> [f64; 3] {
+  linear_srgb_to_oklab(color.map(srgb_to_linear))
+}
+
+/// Convert an OKLab color to normalized sRGB, which may fall outside [0, 1]
+pub fn oklab_to_srgb(lab: [f64; 3]) -> NormalizedColor {
+  oklab_to_linear_srgb(lab).map(linear_to_srgb)
+}
+
+/// Convert a linear light sRGB color to OKLab
+pub fn linear_srgb_to_oklab(color: NormalizedColor) -> [f64; 3] {
+  let [r, g, b] = color;
+  let lms = [
+    0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b,
+    0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b,
+    0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b,
+  ];
+  let [l, m, s] = lms.map(f64::cbrt);
+  [
+    0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+    1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+    0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+  ]
+}
+
+/// Convert an OKLab color to linear light sRGB, which may fall outside [0, 1]
+pub fn oklab_to_linear_srgb(lab: [f64; 3]) -> NormalizedColor {
+  let [l, a, b] = lab;
+  let [l, m, s] = [
+    l + 0.3963377774 * a + 0.2158037573 * b,
+    l - 0.1055613458 * a - 0.0638541728 * b,
+    l - 0.0894841775 * a - 1.2914855480 * b,
+  ]
+  .map(|c| c * c * c);
+  [
+    4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+    -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+    -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+  ]
+}
+
+/// The CIE76 color difference between two CIELAB colors: their Euclidean distance
+pub fn delta_e_76(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+  (0..3)
+    .map(|i| (lab1[i] - lab2[i]).powi(2))
+    .sum::<f64>()
+    .sqrt()
+}
+
+/// The CIEDE2000 color difference between two CIELAB colors
+pub fn delta_e_2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+  let [l1, a1, b1] = lab1;
+  let [l2, a2, b2] = lab2;
+
+  // Stretch the a* axis of near-neutral colors
+  let c_mean = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+  let c_mean7 = c_mean.powi(7);
+  let g = 0.5 * (1.0 - (c_mean7 / (c_mean7 + 25f64.powi(7))).sqrt());
+  let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+  let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+  let hue = |a: f64, b: f64| {
+    if a == 0.0 && b == 0.0 {
+      0.0
+    } else {
+      b.atan2(a).to_degrees().rem_euclid(360.0)
+    }
+  };
+  let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+
+  let delta_l = l2 - l1;
+  let delta_c = c2 - c1;
+  let delta_h = if c1 * c2 == 0.0 {
+    0.0
+  } else if (h2 - h1).abs() <= 180.0 {
+    h2 - h1
+  } else if h2 <= h1 {
+    h2 - h1 + 360.0
+  } else {
+    h2 - h1 - 360.0
+  };
+  let delta_big_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+  let l_mean = (l1 + l2) / 2.0;
+  let c_mean = (c1 + c2) / 2.0;
+  let h_mean = if c1 * c2 == 0.0 {
+    h1 + h2
+  } else if (h1 - h2).abs() <= 180.0 {
+    (h1 + h2) / 2.0
+  } else if h1 + h2 < 360.0 {
+    (h1 + h2 + 360.0) / 2.0
+  } else {
+    (h1 + h2 - 360.0) / 2.0
+  };
+
+  let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+    + 0.24 * (2.0 * h_mean).to_radians().cos()
+    + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+    - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+  let l_offset = (l_mean - 50.0).powi(2);
+  let s_l = 1.0 + 0.015 * l_offset / (20.0 + l_offset).sqrt();
+  let s_c = 1.0 + 0.045 * c_mean;
+  let s_h = 1.0 + 0.015 * c_mean * t;
+  let c_mean7 = c_mean.powi(7);
+  let r_t = -2.0
+    * (c_mean7 / (c_mean7 + 25f64.powi(7))).sqrt()
+    * (60.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp())
+      .to_radians()
+      .sin();
+
+  let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_big_h / s_h);
+  (l * l + c * c + h * h + r_t * c * h).sqrt()
+}
+
+/// Bring a scene-linear floating point image into the [0, 1] range and encode it as sRGB
+///
+/// The result stays at 32-bit float, so no precision is lost before unmixing. NaNs and
+/// negative values become 0.0.
+pub fn tone_map_to_srgb(img: DynamicImage, mapping: ToneMapping) -> DynamicImage {
+  let mut rgba = img.into_rgba32f();
+  let luminance = |p: &image::Rgba<f32>| {
+    (0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64).max(0.0)
+  };
+  // Extended Reinhard maps this luminance to 1.0; 1.0 keeps in-range images unchanged
+  let white = rgba
+    .pixels()
+    .map(luminance)
+    .filter(|l| l.is_finite())
+    .fold(1.0, f64::max);
+
+  for pixel in rgba.pixels_mut() {
+    let scale = match mapping {
+      ToneMapping::Clamp => 1.0,
+      ToneMapping::Reinhard => {
+        let l = luminance(pixel);
+        if l > 0.0 && l.is_finite() {
+          (1.0 + l / (white * white)) / (1.0 + l)
+        } else {
+          1.0
+        }
+      }
+    };
+    for c in &mut pixel.0[..3] {
+      let linear = (*c as f64 * scale).clamp(0.0, 1.0);
+      *c = if linear.is_nan() {
+        0.0
+      } else {
+        linear_to_srgb(linear) as f32
+      };
+    }
+    pixel[3] = if pixel[3].is_nan() {
+      0.0
+    } else {
+      pixel[3].clamp(0.0, 1.0)
+    };
+  }
+  DynamicImage::ImageRgba32F(rgba)
+}
+
+/// The CSS named colors, sorted by name
+const NAMED_COLORS: [(&str, Color); 148] = [
+  ("aliceblue", [240, 248, 255]),
+  ("antiquewhite", [250, 235, 215]),
+  ("aqua", [0, 255, 255]),
+  ("aquamarine", [127, 255, 212]),
+  ("azure", [240, 255, 255]),
+  ("beige", [245, 245, 220]),
+  ("bisque", [255, 228, 196]),
+  ("black", [0, 0, 0]),
+  ("blanchedalmond", [255, 235, 205]),
+  ("blue", [0, 0, 255]),
+  ("blueviolet", [138, 43, 226]),
+  ("brown", [165, 42, 42]),
+  ("burlywood", [222, 184, 135]),
+  ("cadetblue", [95, 158, 160]),
+  ("chartreuse", [127, 255, 0]),
+  ("chocolate", [210, 105, 30]),
+  ("coral", [255, 127, 80]),
+  ("cornflowerblue", [100, 149, 237]),
+  ("cornsilk", [255, 248, 220]),
+  ("crimson", [220, 20, 60]),
+  ("cyan", [0, 255, 255]),
+  ("darkblue", [0, 0, 139]),
+  ("darkcyan", [0, 139, 139]),
+  ("darkgoldenrod", [184, 134, 11]),
+  ("darkgray", [169, 169, 169]),
+  ("darkgreen", [0, 100, 0]),
+  ("darkgrey", [169, 169, 169]),
+  ("darkkhaki", [189, 183, 107]),
+  ("darkmagenta", [139, 0, 139]),
+  ("darkolivegreen", [85, 107, 47]),
+  ("darkorange", [255, 140, 0]),
+  ("darkorchid", [153, 50, 204]),
+  ("darkred", [139, 0, 0]),
+  ("darksalmon", [233, 150, 122]),
+  ("darkseagreen", [143, 188, 143]),
+  ("darkslateblue", [72, 61, 139]),
+  ("darkslategray", [47, 79, 79]),
+  ("darkslategrey", [47, 79, 79]),
+  ("darkturquoise", [0, 206, 209]),
+  ("darkviolet", [148, 0, 211]),
+  ("deeppink", [255, 20, 147]),
+  ("deepskyblue", [0, 191, 255]),
+  ("dimgray", [105, 105, 105]),
+  ("dimgrey", [105, 105, 105]),
+  ("dodgerblue", [30, 144, 255]),
+  ("firebrick", [178, 34, 34]),
+  ("floralwhite", [255, 250, 240]),
+  ("forestgreen", [34, 139, 34]),
+  ("fuchsia", [255, 0, 255]),
+  ("gainsboro", [220, 220, 220]),
+  ("ghostwhite", [248, 248, 255]),
+  ("gold", [255, 215, 0]),
+  ("goldenrod", [218, 165, 32]),
+  ("gray", [128, 128, 128]),
+  ("green", [0, 128, 0]),
+  ("greenyellow", [173, 255, 47]),
+  ("grey", [128, 128, 128]),
+  ("honeydew", [240, 255, 240]),
+  ("hotpink", [255, 105, 180]),
+  ("indianred", [205, 92, 92]),
+  ("indigo", [75, 0, 130]),
+  ("ivory", [255, 255, 240]),
+  ("khaki", [240, 230, 140]),
+  ("lavender", [230, 230, 250]),
+  ("lavenderblush", [255, 240, 245]),
+  ("lawngreen", [124, 252, 0]),
+  ("lemonchiffon", [255, 250, 205]),
+  ("lightblue", [173, 216, 230]),
+  ("lightcoral", [240, 128, 128]),
+  ("lightcyan", [224, 255, 255]),
+  ("lightgoldenrodyellow", [250, 250, 210]),
+  ("lightgray", [211, 211, 211]),
+  ("lightgreen", [144, 238, 144]),
+  ("lightgrey", [211, 211, 211]),
+  ("lightpink", [255, 182, 193]),
+  ("lightsalmon", [255, 160, 122]),
+  ("lightseagreen", [32, 178, 170]),
+  ("lightskyblue", [135, 206, 250]),
+  ("lightslategray", [119, 136, 153]),
+  ("lightslategrey", [119, 136, 153]),
+  ("lightsteelblue", [176, 196, 222]),
+  ("lightyellow", [255, 255, 224]),
+  ("lime", [0, 255, 0]),
+  ("limegreen", [50, 205, 50]),
+  ("linen", [250, 240, 230]),
+  ("magenta", [255, 0, 255]),
+  ("maroon", [128, 0, 0]),
+  ("mediumaquamarine", [102, 205, 170]),
+  ("mediumblue", [0, 0, 205]),
+  ("mediumorchid", [186, 85, 211]),
+  ("mediumpurple", [147, 112, 219]),
+  ("mediumseagreen", [60, 179, 113]),
+  ("mediumslateblue", [123, 104, 238]),
+  ("mediumspringgreen", [0, 250, 154]),
+  ("mediumturquoise", [72, 209, 204]),
+  ("mediumvioletred", [199, 21, 133]),
+  ("midnightblue", [25, 25, 112]),
+  ("mintcream", [245, 255, 250]),
+  ("mistyrose", [255, 228, 225]),
+  ("moccasin", [255, 228, 181]),
+  ("navajowhite", [255, 222, 173]),
+  ("navy", [0, 0, 128]),
+  ("oldlace", [253, 245, 230]),
+  ("olive", [128, 128, 0]),
+  ("olivedrab", [107, 142, 35]),
+  ("orange", [255, 165, 0]),
+  ("orangered", [255, 69, 0]),
+  ("orchid", [218, 112, 214]),
+  ("palegoldenrod", [238, 232, 170]),
+  ("palegreen", [152, 251, 152]),
+  ("paleturquoise", [175, 238, 238]),
+  ("palevioletred", [219, 112, 147]),
+  ("papayawhip", [255, 239, 213]),
+  ("peachpuff", [255, 218, 185]),
+  ("peru", [205, 133, 63]),
+  ("pink", [255, 192, 203]),
+  ("plum", [221, 160, 221]),
+  ("powderblue", [176, 224, 230]),
+  ("purple", [128, 0, 128]),
+  ("rebeccapurple", [102, 51, 153]),
+  ("red", [255, 0, 0]),
+  ("rosybrown", [188, 143, 143]),
+  ("royalblue", [65, 105, 225]),
+  ("saddlebrown", [139, 69, 19]),
+  ("salmon", [250, 128, 114]),
+  ("sandybrown", [244, 164, 96]),
+  ("seagreen", [46, 139, 87]),
+  ("seashell", [255, 245, 238]),
+  ("sienna", [160, 82, 45]),
+  ("silver", [192, 192, 192]),
+  ("skyblue", [135, 206, 235]),
+  ("slateblue", [106, 90, 205]),
+  ("slategray", [112, 128, 144]),
+  ("slategrey", [112, 128, 144]),
+  ("snow", [255, 250, 250]),
+  ("springgreen", [0, 255, 127]),
+  ("steelblue", [70, 130, 180]),
+  ("tan", [210, 180, 140]),
+  ("teal", [0, 128, 128]),
+  ("thistle", [216, 191, 216]),
+  ("tomato", [255, 99, 71]),
+  ("turquoise", [64, 224, 208]),
+  ("violet", [238, 130, 238]),
+  ("wheat", [245, 222, 179]),
+  ("white", [255, 255, 255]),
+  ("whitesmoke", [245, 245, 245]),
+  ("yellow", [255, 255, 0]),
+  ("yellowgreen", [154, 205, 50]),
+];
@@ -0,0 +1,79 @@
+use anyhow::{bail, Context, Result};
+
+/// The base64 alphabet, indexed by 6-bit value
+const BASE64_ALPHABET: &[u8; 64] =
+  b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Decode the image bytes of a `data:image/...;base64,` URL
+pub fn parse_data_url(url: &str) -> Result<Vec<u8>> {
+  let (header, data) = url
+    .strip_prefix("data:")
+    .and_then(|rest| rest.split_once(','))
+    .context("Data URLs must look like \"data:image/png;base64,...\"")?;
+  let Some(media_type) = header.strip_suffix(";base64") else {
+    bail!("Only base64 data URLs are supported");
+  };
+  if !media_type.is_empty() && !media_type.starts_with("image/") {
+    bail!("Data URL must hold an image (got: {})", media_type);
+  }
+  decode_base64(data)
+}
+
+/// Build a base64 data URL for an encoded image, with the MIME type of its format
+pub fn to_data_url(encoded: &[u8]) -> Result<String> {
+  let format = image::guess_format(encoded).context("Unknown image format")?;
+  Ok(format!(
+    "data:{};base64,{}",
+    format.to_mime_type(),
+    encode_base64(encoded)
+  ))
+}
+
+/// Decode base64 with or without padding, skipping whitespace and accepting the URL-safe
+/// alphabet
+fn decode_base64(data: &str) -> Result<Vec<u8>> {
+  let mut bytes = Vec::with_capacity(data.len() / 4 * 3);
+  let mut buffer = 0u32;
+  let mut bits = 0;
+  for c in data.trim_end().trim_end_matches('=').bytes() {
+    let value = match c {
+      b'A'..=b'Z' => c - b'A',
+      b'a'..=b'z' => c - b'a' + 26,
+      b'0'..=b'9' => c - b'0' + 52,
+      b'+' | b'-' => 62,
+      b'/' | b'_' => 63,
+      c if c.is_ascii_whitespace() => continue,
+      c => bail!("Invalid base64 character: {:?}", c as char),
+    };
+    buffer = buffer << 6 | value as u32;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      bytes.push((buffer >> bits) as u8);
+    }
+  }
+  // A single leftover character holds fewer than 8 bits
+  if bits >= 6 {
+    bail!("Truncated base64 data");
+  }
+  Ok(bytes)
+}
+
+/// Encode bytes as padded base64
+fn encode_base64(bytes: &[u8]) -> String {
+  let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+  for chunk in bytes.chunks(3) {
+    let group = chunk
+      .iter()
+      .enumerate()
+      .fold(0u32, |group, (i, &b)| group | (b as u32) << (16 - 8 * i));
+    for i in 0..4 {
+      if i <= chunk.len() {
+        encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+      } else {
+        encoded.push('=');
+      }
+    }
+  }
+  encoded
+}
@@ -1,15 +1,117 @@
 // based on https://github.com/benface/bgone/blob/b362931f37252301f0f8dec183b2072f415b9b5f/src/deduce.rs
 
 use crate::color::{normalize_color, Color, ForegroundColorSpec, NormalizedColor};
-use crate::unmix::{compute_result_color, unmix_colors_internal};
+use crate::unmix::{compute_result_color, unmix_colors_internal, DEFAULT_RECONSTRUCTION_TOLERANCE};
 use anyhow::Result;
-use image::DynamicImage;
+use image::{DynamicImage, Rgba};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 const MAX_CANDIDATES_2_UNKNOWNS: usize = 30;
 const MAX_CANDIDATES_3_UNKNOWNS_ALL: usize = 25;
 const MAX_CANDIDATES_3_UNKNOWNS_SELECTED: usize = 20;
 
+/// How much each distinct observed color counts towards the deduction error
+#[derive(Clone, Copy, Default)]
+pub enum DeductionWeighting {
+  /// Weight by pixel count, favoring the most frequent colors
+  Linear,
+  /// Weight by the square root of the pixel count
+  #[default]
+  Sqrt,
+  /// Weight by the logarithm of the pixel count, favoring rare colors more than `Sqrt`
+  Log,
+  /// Every distinct color counts the same, regardless of how often it occurs
+  Uniform,
+}
+
+impl DeductionWeighting {
+  fn weight(self, count: usize) -> f64 {
+    match self {
+      DeductionWeighting::Linear => count as f64,
+      DeductionWeighting::Sqrt => (count as f64).sqrt(),
+      DeductionWeighting::Log => (count as f64).ln_1p(),
+      DeductionWeighting::Uniform => 1.0,
+    }
+  }
+}
+
+/// Configuration for foreground color deduction
+pub struct DeductionConfig {
+  /// How much each distinct observed color counts towards the deduction error
+  pub weighting: DeductionWeighting,
+  /// Number of most frequent observed colors to derive candidate foreground colors from
+  pub max_observed_colors: usize,
+  /// Stop deriving candidates once this many distinct ones per requested candidate were found.
+  /// `None` always scans all `max_observed_colors` colors.
+  pub candidate_pool_factor: Option<usize>,
+  /// Leave fully transparent pixels out of the observed colors, e.g. the transparent
+  /// palette index of an indexed image, whose RGB values are arbitrary
+  pub skip_transparent_pixels: bool,
+  /// Stop searching color combinations after this many evaluations and keep the best so far
+  pub max_evaluations: Option<usize>,
+  /// Stop searching color combinations once this much time has passed and keep the best so far
+  pub time_budget: Option<Duration>,
+}
+
+impl Default for DeductionConfig {
+  fn default() -> Self {
+    Self {
+      weighting: DeductionWeighting::default(),
+      max_observed_colors: 100,
+      candidate_pool_factor: Some(3),
+      skip_transparent_pixels: false,
+      max_evaluations: None,
+      time_budget: None,
+    }
+  }
+}
+
+/// Tracks how much of the deduction budget has been used
+struct SearchBudget<'a> {
+  config: &'a DeductionConfig,
+  start: Instant,
+  evaluations: usize,
+}
+
+impl<'a> SearchBudget<'a> {
+  fn new(config: &'a DeductionConfig) -> Self {
+    Self {
+      config,
+      start: Instant::now(),
+      evaluations: 0,
+    }
+  }
+
+  /// Record an evaluation and check whether the search should stop
+  fn spend(&mut self) -> bool {
+    self.evaluations += 1;
+    self
+      .config
+      .max_evaluations
+      .is_some_and(|max| self.evaluations >= max)
+      || self
+        .config
+        .time_budget
+        .is_some_and(|budget| self.start.elapsed() >= budget)
+  }
+}
+
+/// Parse a deduction weighting scheme
+/// Supports: "linear", "sqrt", "log", "uniform"
+pub fn parse_deduction_weighting(spec: &str) -> Result<DeductionWeighting> {
+  match spec {
+    "linear" => Ok(DeductionWeighting::Linear),
+    "sqrt" => Ok(DeductionWeighting::Sqrt),
+    "log" => Ok(DeductionWeighting::Log),
+    "uniform" => Ok(DeductionWeighting::Uniform),
+    _ => anyhow::bail!(
+      "Deduction weighting must be one of \"linear\", \"sqrt\", \"log\" or \"uniform\" (got: {})",
+      spec
+    ),
+  }
+}
+
 fn color_distance(c1: NormalizedColor, c2: NormalizedColor) -> f64 {
   (0..3).map(|i| (c1[i] - c2[i]).powi(2)).sum::<f64>().sqrt()
 }
@@ -19,11 +121,20 @@ fn find_candidate_foreground_colors(
   background: Color,
   num_candidates: usize,
   threshold: f64,
+  config: &DeductionConfig,
 ) -> Vec<Color> {
   let bg_norm = normalize_color(background);
-  let mut candidates = Vec::new();
+  let mut unique_candidates: Vec<Color> = Vec::new();
+  let pool_limit = config
+    .candidate_pool_factor
+    .map(|factor| num_candidates.saturating_mul(factor).max(1));
+
+  for &(observed, _) in observed_colors.iter().take(config.max_observed_colors) {
+    // Enough distinct candidates to choose from; the rest would mostly be duplicates
+    if pool_limit.is_some_and(|limit| unique_candidates.len() >= limit) {
+      break;
+    }
 
-  for &(observed, _) in observed_colors.iter().take(100) {
     let obs_norm = normalize_color(observed);
 
     if color_distance(obs_norm, bg_norm) < 0.01 {
@@ -63,25 +174,16 @@ fn find_candidate_foreground_colors(
           .sum::<f64>()
           .sqrt();
 
-        if error < 5.0 {
-          candidates.push(fg_u8);
-        }
-      }
-    }
-  }
+        // Deduplicate as we go so the early stop counts distinct candidates
+        let is_duplicate = unique_candidates.iter().any(|existing| {
+          color_distance(normalize_color(fg_u8), normalize_color(*existing)) < threshold
+        });
 
-  let mut unique_candidates = Vec::new();
-  for candidate in candidates {
-    let mut is_duplicate = false;
-    for existing in &unique_candidates {
-      if color_distance(normalize_color(candidate), normalize_color(*existing)) < threshold {
-        is_duplicate = true;
-        break;
+        if error < 5.0 && !is_duplicate {
+          unique_candidates.push(fg_u8);
+        }
       }
     }
-    if !is_duplicate {
-      unique_candidates.push(candidate);
-    }
   }
 
   if unique_candidates.len() > num_candidates {
@@ -134,14 +236,21 @@ fn evaluate_color_set(
   foreground_colors: &[NormalizedColor],
   pixels: &[(Color, usize)],
   background: NormalizedColor,
+  weighting: DeductionWeighting,
 ) -> f64 {
   let mut total_error = 0.0;
   let mut total_weight = 0.0;
 
   for &(observed, count) in pixels {
-    let weight = (count as f64).sqrt();
-
-    let unmix_result = unmix_colors_internal(observed, foreground_colors, background, false);
+    let weight = weighting.weight(count);
+
+    let unmix_result = unmix_colors_internal(
+      observed,
+      foreground_colors,
+      background,
+      false,
+      DEFAULT_RECONSTRUCTION_TOLERANCE,
+    );
     let (result_color, alpha) = compute_result_color(&unmix_result, foreground_colors);
 
     let reconstructed = [
@@ -173,12 +282,162 @@ fn evaluate_color_set(
   reconstruction_error + color_quality_penalty
 }
 
+/// Count the occurrences of each distinct RGB color, most frequent first
+fn color_histogram<'a>(pixels: impl Iterator<Item = &'a Rgba<u8>>) -> Vec<(Color, usize)> {
+  let mut color_counts = HashMap::new();
+
+  for pixel in pixels {
+    let color = [pixel[0], pixel[1], pixel[2]];
+    *color_counts.entry(color).or_insert(0) += 1;
+  }
+
+  let mut histogram: Vec<(Color, usize)> = color_counts.into_iter().collect();
+  histogram.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+  histogram
+}
+
+/// Find the dominant foreground color in the interior of an image
+///
+/// Samples the central half of the image (inset by a quarter on each side), where a
+/// single subject usually sits, and returns its most common color that is not within
+/// `threshold` of the background.
+///
+/// # Returns
+/// The dominant interior color, or `None` if the interior is all background
+pub fn detect_primary_foreground_color(
+  image: &DynamicImage,
+  background_color: Color,
+  threshold: f64,
+) -> Option<Color> {
+  let rgba = image.to_rgba8();
+  let (width, height) = rgba.dimensions();
+
+  // Keep at least one pixel on tiny images
+  let (x_start, y_start) = (width / 4, height / 4);
+  let x_end = (width - width / 4).max(x_start + 1).min(width);
+  let y_end = (height - height / 4).max(y_start + 1).min(height);
+
+  let interior = (y_start..y_end).flat_map(|y| (x_start..x_end).map(move |x| (x, y)));
+  let histogram = color_histogram(interior.map(|(x, y)| rgba.get_pixel(x, y)));
+
+  let bg_norm = normalize_color(background_color);
+  histogram
+    .into_iter()
+    .map(|(color, _)| color)
+    .find(|&color| color_distance(normalize_color(color), bg_norm) >= threshold)
+}
+
+/// Extract the dominant colors of an image by median cut over its color histogram
+///
+/// Fully transparent pixels are ignored. Starting from a single box holding every distinct
+/// color, the box with the widest channel range is repeatedly split at the pixel-weighted
+/// median of that channel, until there are `max_colors` boxes or no box can be split.
+///
+/// # Returns
+/// Each box's pixel-weighted average color with its pixel count, most common first, and
+/// the total number of counted pixels
+pub fn extract_palette(image: &DynamicImage, max_colors: usize) -> (Vec<(Color, usize)>, usize) {
+  let rgba = image.to_rgba8();
+  let histogram = color_histogram(rgba.pixels().filter(|pixel| pixel[3] > 0));
+  let total = histogram.iter().map(|&(_, count)| count).sum();
+  if histogram.is_empty() || max_colors == 0 {
+    return (Vec::new(), total);
+  }
+
+  let mut boxes = vec![histogram];
+  while boxes.len() < max_colors {
+    let Some((index, channel, _)) = boxes
+      .iter()
+      .enumerate()
+      .filter(|(_, colors)| colors.len() > 1)
+      .map(|(i, colors)| {
+        let (channel, range) = widest_channel(colors);
+        (i, channel, range)
+      })
+      .max_by_key(|&(_, _, range)| range)
+    else {
+      break;
+    };
+
+    let mut colors = boxes.swap_remove(index);
+    colors.sort_unstable_by_key(|&(color, _)| (color[channel], color));
+    let half = colors.iter().map(|&(_, count)| count).sum::<usize>() / 2;
+
+    // Split after the color holding the median pixel, keeping both halves non-empty
+    let mut seen = 0;
+    let mut split = colors.len() - 1;
+    for (i, &(_, count)) in colors.iter().enumerate() {
+      seen += count;
+      if seen > half {
+        split = i.min(colors.len() - 2) + 1;
+        break;
+      }
+    }
+
+    let upper = colors.split_off(split);
+    boxes.push(colors);
+    boxes.push(upper);
+  }
+
+  let mut palette: Vec<(Color, usize)> = boxes
+    .iter()
+    .map(|colors| {
+      let count: usize = colors.iter().map(|&(_, count)| count).sum();
+      let mut sums = [0usize; 3];
+      for &(color, n) in colors {
+        for (sum, channel) in sums.iter_mut().zip(color) {
+          *sum += channel as usize * n;
+        }
+      }
+      (
+        sums.map(|sum| ((sum as f64 / count as f64).round()) as u8),
+        count,
+      )
+    })
+    .collect();
+  palette.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+  (palette, total)
+}
+
+/// Find the channel with the widest range of values among some colors
+fn widest_channel(colors: &[(Color, usize)]) -> (usize, u8) {
+  (0..3)
+    .map(|channel| {
+      let (min, max) = colors
+        .iter()
+        .fold((u8::MAX, u8::MIN), |(min, max), &(color, _)| {
+          (min.min(color[channel]), max.max(color[channel]))
+        });
+      (channel, max - min)
+    })
+    .max_by_key(|&(_, range)| range)
+    .unwrap_or((0, 0))
+}
+
 /// Deduce unknown foreground colors from an image
 pub fn deduce_unknown_colors(
   image: &DynamicImage,
   specs: &[ForegroundColorSpec],
   background_color: Color,
   threshold: f64,
+) -> Result<Vec<Color>> {
+  deduce_unknown_colors_with_config(
+    image,
+    specs,
+    background_color,
+    threshold,
+    &DeductionConfig::default(),
+  )
+}
+
+/// Deduce unknown foreground colors from an image with custom configuration
+pub fn deduce_unknown_colors_with_config(
+  image: &DynamicImage,
+  specs: &[ForegroundColorSpec],
+  background_color: Color,
+  threshold: f64,
+  config: &DeductionConfig,
 ) -> Result<Vec<Color>> {
   let mut known_colors = Vec::new();
   let mut unknown_indices = Vec::new();
@@ -207,19 +466,20 @@ pub fn deduce_unknown_colors(
   }
 
   let rgba = image.to_rgba8();
-  let mut color_counts = HashMap::new();
-
-  for pixel in rgba.pixels() {
-    let color = [pixel[0], pixel[1], pixel[2]];
-    *color_counts.entry(color).or_insert(0) += 1;
-  }
-
-  let mut pixels: Vec<(Color, usize)> = color_counts.into_iter().collect();
-  pixels.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+  let pixels = color_histogram(
+    rgba
+      .pixels()
+      .filter(|pixel| !(config.skip_transparent_pixels && pixel[3] == 0)),
+  );
 
   let unknown_count = unknown_indices.len();
-  let candidates =
-    find_candidate_foreground_colors(&pixels, background_color, unknown_count * 10, threshold);
+  let candidates = find_candidate_foreground_colors(
+    &pixels,
+    background_color,
+    unknown_count * 10,
+    threshold,
+    config,
+  );
 
   let mut all_candidates = candidates;
 
@@ -250,6 +510,8 @@ pub fn deduce_unknown_colors(
 
   let mut best_colors = vec![];
   let mut best_error = f64::MAX;
+  // At least one combination is always evaluated, so there is a best-so-far to return
+  let mut budget = SearchBudget::new(config);
 
   if unknown_count == 1 {
     for candidate in &all_candidates {
@@ -268,14 +530,17 @@ pub fn deduce_unknown_colors(
         }
       }
 
-      let error = evaluate_color_set(&test_fg, &pixels, background_norm);
+      let error = evaluate_color_set(&test_fg, &pixels, background_norm, config.weighting);
       if error < best_error {
         best_error = error;
         best_colors = vec![*candidate];
       }
+      if budget.spend() {
+        break;
+      }
     }
   } else if unknown_count == 2 && all_candidates.len() <= MAX_CANDIDATES_2_UNKNOWNS {
-    for (i, c1) in all_candidates.iter().enumerate() {
+    'search: for (i, c1) in all_candidates.iter().enumerate() {
       for c2 in all_candidates.iter().skip(i + 1) {
         let mut test_fg = vec![[0.0; 3]; specs.len()];
         let mut known_idx = 0;
@@ -295,11 +560,14 @@ pub fn deduce_unknown_colors(
           }
         }
 
-        let error = evaluate_color_set(&test_fg, &pixels, background_norm);
+        let error = evaluate_color_set(&test_fg, &pixels, background_norm, config.weighting);
         if error < best_error {
           best_error = error;
           best_colors = test_unknown.to_vec();
         }
+        if budget.spend() {
+          break 'search;
+        }
       }
     }
   } else if unknown_count == 3 {
@@ -309,7 +577,7 @@ pub fn deduce_unknown_colors(
       select_most_different_colors(&all_candidates, MAX_CANDIDATES_3_UNKNOWNS_SELECTED)
     };
 
-    for (i, c1) in candidates_to_try.iter().enumerate() {
+    'search: for (i, c1) in candidates_to_try.iter().enumerate() {
       for (j, c2) in candidates_to_try.iter().enumerate().skip(i + 1) {
         for c3 in candidates_to_try.iter().skip(j + 1) {
           let mut test_fg = vec![[0.0; 3]; specs.len()];
@@ -330,11 +598,14 @@ pub fn deduce_unknown_colors(
             }
           }
 
-          let error = evaluate_color_set(&test_fg, &pixels, background_norm);
+          let error = evaluate_color_set(&test_fg, &pixels, background_norm, config.weighting);
           if error < best_error {
             best_error = error;
             best_colors = test_unknown.to_vec();
           }
+          if budget.spend() {
+            break 'search;
+          }
         }
       }
     }
@@ -0,0 +1,1584 @@
+use crate::color::{parse_css_color, Color};
+use crate::process::quantize_rgba;
+use flate2::write::{DeflateEncoder, ZlibEncoder};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::tiff::TiffEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{
+  ExtendedColorType, Frame, GrayImage, ImageBuffer, ImageEncoder, Luma, Rgb, RgbImage, Rgba,
+  RgbaImage,
+};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+
+/// An RGBA image with 16 bits per channel
+pub type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+/// A grayscale image with 16 bits per sample
+pub type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// How hard the PNG encoder compresses the image data
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum PngCompression {
+  /// Stored without compression
+  None,
+  /// The fastest compression, for batch jobs where encoding time dominates
+  Fastest,
+  Fast,
+  #[default]
+  Balanced,
+  /// The smallest files, at a much higher encoding time
+  Smallest,
+}
+
+/// Settings for indexed (palette) PNG output
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PaletteSettings {
+  /// The maximum number of palette entries (1-256), including the one for transparent pixels
+  pub max_colors: u16,
+  /// Whether to apply Floyd-Steinberg dithering when quantizing
+  pub dither: bool,
+}
+
+/// Parse the palette settings of indexed PNG output
+///
+/// # Arguments
+/// * `max_colors` - The maximum number of palette entries, from 1 to 256 (default: 256)
+/// * `dither` - Whether to apply Floyd-Steinberg dithering (default: false)
+pub fn parse_palette_settings(
+  max_colors: Option<u32>,
+  dither: Option<bool>,
+) -> anyhow::Result<PaletteSettings> {
+  let max_colors = max_colors.unwrap_or(256);
+  if !(1..=256).contains(&max_colors) {
+    anyhow::bail!(
+      "Palette colors must be between 1 and 256 (got: {})",
+      max_colors
+    );
+  }
+  Ok(PaletteSettings {
+    max_colors: max_colors as u16,
+    dither: dither.unwrap_or(false),
+  })
+}
+
+/// Settings for PNG output
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PngSettings {
+  pub compression: PngCompression,
+  /// The row filter, or `Adaptive` to pick one per row
+  pub filter: png::Filter,
+  /// Whether to write the rows in Adam7 interlaced order
+  pub interlaced: bool,
+  /// Whether to write an indexed PNG with a palette of quantized colors, instead of RGBA
+  pub palette: Option<PaletteSettings>,
+  /// Whether to write an `sRGB` chunk marking the colors as sRGB
+  pub srgb: bool,
+  /// Whether to write images whose pixels are all gray as grayscale with alpha, instead of
+  /// RGBA. Ignored for indexed output.
+  pub grayscale: bool,
+}
+
+impl Default for PngSettings {
+  fn default() -> Self {
+    PngSettings {
+      compression: PngCompression::default(),
+      filter: png::Filter::Adaptive,
+      interlaced: false,
+      palette: None,
+      srgb: false,
+      grayscale: false,
+    }
+  }
+}
+
+/// Parse the PNG settings
+///
+/// # Arguments
+/// * `compression` - "none", "fastest", "fast", "balanced" (default) or "smallest"
+/// * `filter` - "none", "sub", "up", "avg", "paeth" or "adaptive" (default)
+/// * `interlaced` - Whether to write Adam7 interlaced rows (default: false)
+pub fn parse_png_settings(
+  compression: Option<&str>,
+  filter: Option<&str>,
+  interlaced: Option<bool>,
+) -> anyhow::Result<PngSettings> {
+  let compression = match compression.unwrap_or("balanced") {
+    "none" => PngCompression::None,
+    "fastest" => PngCompression::Fastest,
+    "fast" => PngCompression::Fast,
+    "balanced" => PngCompression::Balanced,
+    "smallest" => PngCompression::Smallest,
+    other => anyhow::bail!(
+      "PNG compression must be one of \"none\", \"fastest\", \"fast\", \"balanced\" or \
+       \"smallest\" (got: {})",
+      other
+    ),
+  };
+  let filter = match filter.unwrap_or("adaptive") {
+    "none" => png::Filter::NoFilter,
+    "sub" => png::Filter::Sub,
+    "up" => png::Filter::Up,
+    "avg" => png::Filter::Avg,
+    "paeth" => png::Filter::Paeth,
+    "adaptive" => png::Filter::Adaptive,
+    other => anyhow::bail!(
+      "PNG filter must be one of \"none\", \"sub\", \"up\", \"avg\", \"paeth\" or \
+       \"adaptive\" (got: {})",
+      other
+    ),
+  };
+  Ok(PngSettings {
+    compression,
+    filter,
+    interlaced: interlaced.unwrap_or(false),
+    palette: None,
+    srgb: false,
+    grayscale: false,
+  })
+}
+
+/// Default quality (1-100) of WebP output that isn't lossless
+pub const DEFAULT_WEBP_QUALITY: u8 = 75;
+
+/// Default quality (1-100) of AVIF output
+pub const DEFAULT_AVIF_QUALITY: u8 = 80;
+
+/// Default encoder speed (1-10) of AVIF output
+pub const DEFAULT_AVIF_SPEED: u8 = 4;
+
+/// Default quality (1-100) of JPEG output
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Settings for WebP output
+///
+/// Lossy output compresses the color channels with libwebp's VP8 encoder, while the alpha
+/// channel is always compressed losslessly, since lossy alpha ruins the edges of a cutout.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct WebPSettings {
+  /// Whether every pixel is kept exact
+  pub lossless: bool,
+  /// The quality of lossy output, from 1 (smallest) to 100 (best)
+  pub quality: u8,
+}
+
+impl Default for WebPSettings {
+  fn default() -> Self {
+    WebPSettings {
+      lossless: true,
+      quality: DEFAULT_WEBP_QUALITY,
+    }
+  }
+}
+
+/// Parse the WebP settings
+///
+/// # Arguments
+/// * `lossless` - Whether every pixel is kept exact (default: true)
+/// * `quality` - The quality of lossy output, from 1 to 100 (default: 75)
+pub fn parse_webp_settings(
+  lossless: Option<bool>,
+  quality: Option<u32>,
+) -> anyhow::Result<WebPSettings> {
+  let quality = quality.unwrap_or(DEFAULT_WEBP_QUALITY as u32);
+  if !(1..=100).contains(&quality) {
+    anyhow::bail!("WebP quality must be between 1 and 100 (got: {})", quality);
+  }
+  Ok(WebPSettings {
+    lossless: lossless.unwrap_or(true),
+    quality: quality as u8,
+  })
+}
+
+/// Encode an RGBA image as lossy WebP with a losslessly compressed alpha channel
+fn encode_lossy_webp(img: &RgbaImage, quality: u8) -> anyhow::Result<Vec<u8>> {
+  let mut config =
+    webp::WebPConfig::new().map_err(|_| anyhow::anyhow!("Failed to set up the WebP encoder"))?;
+  config.lossless = 0;
+  config.quality = quality as f32;
+  config.alpha_compression = 1;
+  config.alpha_quality = 100;
+
+  let encoded = webp::Encoder::from_rgba(img.as_raw(), img.width(), img.height())
+    .encode_advanced(&config)
+    .map_err(|e| anyhow::anyhow!("Failed to encode WebP: {:?}", e))?;
+  Ok(encoded.to_vec())
+}
+
+/// Settings for AVIF output
+///
+/// AVIF is always lossy; the alpha channel is encoded at the same quality as the color.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AvifSettings {
+  /// The quality of the output, from 1 (smallest) to 100 (best)
+  pub quality: u8,
+  /// The encoder speed, from 1 (slowest, smallest files) to 10 (fastest)
+  pub speed: u8,
+}
+
+impl Default for AvifSettings {
+  fn default() -> Self {
+    AvifSettings {
+      quality: DEFAULT_AVIF_QUALITY,
+      speed: DEFAULT_AVIF_SPEED,
+    }
+  }
+}
+
+/// Parse the AVIF settings
+///
+/// # Arguments
+/// * `quality` - The quality of the output, from 1 to 100 (default: 80)
+/// * `speed` - The encoder speed, from 1 to 10 (default: 4)
+pub fn parse_avif_settings(
+  quality: Option<u32>,
+  speed: Option<u32>,
+) -> anyhow::Result<AvifSettings> {
+  let quality = quality.unwrap_or(DEFAULT_AVIF_QUALITY as u32);
+  if !(1..=100).contains(&quality) {
+    anyhow::bail!("AVIF quality must be between 1 and 100 (got: {})", quality);
+  }
+  let speed = speed.unwrap_or(DEFAULT_AVIF_SPEED as u32);
+  if !(1..=10).contains(&speed) {
+    anyhow::bail!("AVIF speed must be between 1 and 10 (got: {})", speed);
+  }
+  Ok(AvifSettings {
+    quality: quality as u8,
+    speed: speed as u8,
+  })
+}
+
+/// Settings for JPEG output
+///
+/// JPEG has no alpha channel, so the result is flattened over a matte color first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct JpegSettings {
+  /// The quality of the output, from 1 (smallest) to 100 (best)
+  pub quality: u8,
+  /// The color transparent areas are filled with
+  pub matte: Color,
+}
+
+impl Default for JpegSettings {
+  fn default() -> Self {
+    JpegSettings {
+      quality: DEFAULT_JPEG_QUALITY,
+      matte: [255, 255, 255],
+    }
+  }
+}
+
+/// Parse the JPEG settings
+///
+/// # Arguments
+/// * `quality` - The quality of the output, from 1 to 100 (default: 90)
+/// * `matte` - The hex color to flatten transparent areas over (default: "#ffffff")
+pub fn parse_jpeg_settings(
+  quality: Option<u32>,
+  matte: Option<&str>,
+) -> anyhow::Result<JpegSettings> {
+  let quality = quality.unwrap_or(DEFAULT_JPEG_QUALITY as u32);
+  if !(1..=100).contains(&quality) {
+    anyhow::bail!("JPEG quality must be between 1 and 100 (got: {})", quality);
+  }
+  let matte = match matte {
+    Some(color) => parse_css_color(color)?,
+    None => JpegSettings::default().matte,
+  };
+  Ok(JpegSettings {
+    quality: quality as u8,
+    matte,
+  })
+}
+
+/// The container format of processed output images
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+  Png(PngSettings),
+  /// WebP with an alpha channel, lossless unless its settings say otherwise
+  WebP(WebPSettings),
+  /// AVIF with an alpha channel
+  Avif(AvifSettings),
+  /// Lossless TIFF with an alpha channel
+  Tiff,
+  /// JPEG, flattened over the matte color of its settings
+  Jpeg(JpegSettings),
+  /// Animated PNG, with every frame in full RGBA. Never interlaced or indexed.
+  Apng(PngSettings),
+  /// GIF, animated for animated inputs. Transparency is on or off per pixel, so pixels below
+  /// half opacity become transparent and the rest opaque.
+  Gif,
+  /// Unencoded RGBA bytes, row by row, without any header
+  Raw,
+}
+
+impl Default for OutputFormat {
+  fn default() -> Self {
+    OutputFormat::Png(PngSettings::default())
+  }
+}
+
+impl OutputFormat {
+  /// The name of the format, as accepted by `parse_output_format`; indexed PNG is "png"
+  pub fn name(self) -> &'static str {
+    match self {
+      OutputFormat::Png(_) => "png",
+      OutputFormat::WebP(_) => "webp",
+      OutputFormat::Avif(_) => "avif",
+      OutputFormat::Tiff => "tiff",
+      OutputFormat::Jpeg(_) => "jpeg",
+      OutputFormat::Apng(_) => "apng",
+      OutputFormat::Gif => "gif",
+      OutputFormat::Raw => "raw",
+    }
+  }
+}
+
+/// Parse an output format
+/// Supports: "png" (with `png_settings`), "indexed" (with `png_settings` and
+/// `palette_settings`), "webp" (with `webp_settings`), "avif" (with `avif_settings`), "tiff",
+/// "jpeg" (with `jpeg_settings`), "apng" (with `png_settings`), "gif", "raw"
+pub fn parse_output_format(
+  spec: &str,
+  png_settings: PngSettings,
+  palette_settings: PaletteSettings,
+  webp_settings: WebPSettings,
+  avif_settings: AvifSettings,
+  jpeg_settings: JpegSettings,
+) -> anyhow::Result<OutputFormat> {
+  match spec {
+    "png" => Ok(OutputFormat::Png(png_settings)),
+    "indexed" => Ok(OutputFormat::Png(PngSettings {
+      palette: Some(palette_settings),
+      ..png_settings
+    })),
+    "webp" => Ok(OutputFormat::WebP(webp_settings)),
+    "avif" => Ok(OutputFormat::Avif(avif_settings)),
+    "tiff" => Ok(OutputFormat::Tiff),
+    "jpeg" => Ok(OutputFormat::Jpeg(jpeg_settings)),
+    "apng" => Ok(OutputFormat::Apng(PngSettings {
+      interlaced: false,
+      palette: None,
+      ..png_settings
+    })),
+    "gif" => Ok(OutputFormat::Gif),
+    "raw" => Ok(OutputFormat::Raw),
+    _ => anyhow::bail!(
+      "Output format must be one of \"png\", \"indexed\", \"webp\", \"avif\", \"tiff\", \
+       \"jpeg\", \"apng\", \"gif\" or \"raw\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// Encode a 16-bit RGBA image in the given output format
+///
+/// Only PNG, TIFF and raw output keep the 16 bits; raw samples are little-endian.
+pub fn encode_rgba16(img: &Rgba16Image, format: OutputFormat) -> anyhow::Result<Vec<u8>> {
+  match format {
+    OutputFormat::Png(settings) => encode_png_rgba16(img, settings, &PngMetadata::default()),
+    OutputFormat::Tiff => {
+      let mut buffer = Cursor::new(Vec::new());
+      let bytes: Vec<u8> = img.as_raw().iter().flat_map(|v| v.to_ne_bytes()).collect();
+      TiffEncoder::new(&mut buffer).write_image(
+        &bytes,
+        img.width(),
+        img.height(),
+        ExtendedColorType::Rgba16,
+      )?;
+      Ok(buffer.into_inner())
+    }
+    OutputFormat::Raw => Ok(img.as_raw().iter().flat_map(|v| v.to_le_bytes()).collect()),
+    OutputFormat::WebP(_)
+    | OutputFormat::Avif(_)
+    | OutputFormat::Jpeg(_)
+    | OutputFormat::Apng(_)
+    | OutputFormat::Gif => {
+      anyhow::bail!("WebP, AVIF, JPEG, APNG and GIF output are limited to 8 bits per channel")
+    }
+  }
+}
+
+/// Encode an RGBA image in the given output format
+pub fn encode_rgba(img: &RgbaImage, format: OutputFormat) -> anyhow::Result<Vec<u8>> {
+  let mut buffer = Cursor::new(Vec::new());
+  match format {
+    OutputFormat::Png(settings) => return encode_png_rgba(img, settings, &PngMetadata::default()),
+    OutputFormat::WebP(settings) if !settings.lossless => {
+      return encode_lossy_webp(img, settings.quality)
+    }
+    OutputFormat::WebP(_) => WebPEncoder::new_lossless(&mut buffer).encode(
+      img.as_raw(),
+      img.width(),
+      img.height(),
+      ExtendedColorType::Rgba8,
+    )?,
+    OutputFormat::Avif(settings) => {
+      AvifEncoder::new_with_speed_quality(&mut buffer, settings.speed, settings.quality)
+        .write_image(
+          img.as_raw(),
+          img.width(),
+          img.height(),
+          ExtendedColorType::Rgba8,
+        )?
+    }
+    OutputFormat::Tiff => TiffEncoder::new(&mut buffer).write_image(
+      img.as_raw(),
+      img.width(),
+      img.height(),
+      ExtendedColorType::Rgba8,
+    )?,
+    OutputFormat::Jpeg(settings) => {
+      let [mr, mg, mb] = settings.matte.map(|c| c as f64);
+      let flattened = RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = img.get_pixel(x, y).0.map(|c| c as f64);
+        let alpha = a / 255.0;
+        let blend = |fg: f64, matte: f64| (fg * alpha + matte * (1.0 - alpha)).round() as u8;
+        Rgb([blend(r, mr), blend(g, mg), blend(b, mb)])
+      });
+      JpegEncoder::new_with_quality(&mut buffer, settings.quality).write_image(
+        flattened.as_raw(),
+        flattened.width(),
+        flattened.height(),
+        ExtendedColorType::Rgb8,
+      )?
+    }
+    OutputFormat::Apng(settings) => return encode_apng(&[Frame::new(img.clone())], 0, settings),
+    OutputFormat::Gif => return encode_gif([Frame::new(img.clone())], 1),
+    OutputFormat::Raw => buffer.get_mut().extend_from_slice(img.as_raw()),
+  }
+
+  Ok(buffer.into_inner())
+}
+
+/// Encode a grayscale image, such as a weight map, in the given output format
+///
+/// PNG (unless indexed) and TIFF store a single gray channel and raw output is one byte per
+/// pixel; the other formats get the gray as opaque color. PNG output carries `metadata`.
+pub fn encode_gray(
+  img: &GrayImage,
+  format: OutputFormat,
+  metadata: &PngMetadata,
+) -> anyhow::Result<Vec<u8>> {
+  match format {
+    OutputFormat::Png(settings) if settings.palette.is_none() => {
+      let row = |x0: u32, dx: u32, y: u32| -> Vec<u8> {
+        (x0..img.width())
+          .step_by(dx as usize)
+          .map(|x| img.get_pixel(x, y)[0])
+          .collect()
+      };
+      encode_png_gray(
+        img.dimensions(),
+        png::BitDepth::Eight,
+        settings,
+        metadata,
+        row,
+      )
+    }
+    OutputFormat::Tiff => {
+      let mut buffer = Cursor::new(Vec::new());
+      TiffEncoder::new(&mut buffer).write_image(
+        img.as_raw(),
+        img.width(),
+        img.height(),
+        ExtendedColorType::L8,
+      )?;
+      Ok(buffer.into_inner())
+    }
+    OutputFormat::Raw => Ok(img.as_raw().clone()),
+    _ => {
+      let rgba = RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let [l] = img.get_pixel(x, y).0;
+        Rgba([l, l, l, 255])
+      });
+      match format {
+        OutputFormat::Png(settings) => encode_png_rgba(&rgba, settings, metadata),
+        _ => encode_rgba(&rgba, format),
+      }
+    }
+  }
+}
+
+/// Encode a 16-bit grayscale image, such as an alpha matte, in the given output format
+///
+/// Only PNG (unless indexed), TIFF and raw output keep the 16 bits; raw samples are
+/// little-endian. PNG output carries `metadata`.
+pub fn encode_gray16(
+  img: &Gray16Image,
+  format: OutputFormat,
+  metadata: &PngMetadata,
+) -> anyhow::Result<Vec<u8>> {
+  match format {
+    OutputFormat::Png(settings) => {
+      if settings.palette.is_some() {
+        anyhow::bail!("Indexed output is limited to 8 bits per channel");
+      }
+      // PNG stores 16-bit samples big-endian
+      let row = |x0: u32, dx: u32, y: u32| -> Vec<u8> {
+        (x0..img.width())
+          .step_by(dx as usize)
+          .flat_map(|x| img.get_pixel(x, y)[0].to_be_bytes())
+          .collect()
+      };
+      encode_png_gray(
+        img.dimensions(),
+        png::BitDepth::Sixteen,
+        settings,
+        metadata,
+        row,
+      )
+    }
+    OutputFormat::Tiff => {
+      let mut buffer = Cursor::new(Vec::new());
+      let bytes: Vec<u8> = img.as_raw().iter().flat_map(|v| v.to_ne_bytes()).collect();
+      TiffEncoder::new(&mut buffer).write_image(
+        &bytes,
+        img.width(),
+        img.height(),
+        ExtendedColorType::L16,
+      )?;
+      Ok(buffer.into_inner())
+    }
+    OutputFormat::Raw => Ok(img.as_raw().iter().flat_map(|v| v.to_le_bytes()).collect()),
+    OutputFormat::WebP(_)
+    | OutputFormat::Avif(_)
+    | OutputFormat::Jpeg(_)
+    | OutputFormat::Apng(_)
+    | OutputFormat::Gif => {
+      anyhow::bail!("WebP, AVIF, JPEG, APNG and GIF output are limited to 8 bits per channel")
+    }
+  }
+}
+
+/// Encode a single-channel grayscale PNG whose rows `row(x0, dx, y)` packs (see `write_png`)
+fn encode_png_gray(
+  (width, height): (u32, u32),
+  bit_depth: png::BitDepth,
+  settings: PngSettings,
+  metadata: &PngMetadata,
+  row: impl Fn(u32, u32, u32) -> Vec<u8> + Sync,
+) -> anyhow::Result<Vec<u8>> {
+  let mut info = png::Info::with_size(width, height);
+  info.interlaced = settings.interlaced;
+  info.color_type = png::ColorType::Grayscale;
+  info.bit_depth = bit_depth;
+  let filter_distance = if bit_depth == png::BitDepth::Sixteen {
+    2
+  } else {
+    1
+  };
+
+  let mut buffer = Vec::new();
+  write_png(&mut buffer, info, settings, metadata, row, filter_distance)?;
+  Ok(buffer)
+}
+
+/// Encode RGBA frames as a GIF, played `plays` times (0 = forever)
+///
+/// Every frame replaces the previous one entirely, so frames must cover the whole canvas.
+pub fn encode_gif(frames: impl IntoIterator<Item = Frame>, plays: u32) -> anyhow::Result<Vec<u8>> {
+  let mut buffer = Vec::new();
+  {
+    let mut encoder = GifEncoder::new(&mut buffer);
+    // The GIF loop count is the number of repetitions after the first play
+    match plays {
+      0 => encoder.set_repeat(Repeat::Infinite)?,
+      1 => {}
+      n => encoder.set_repeat(Repeat::Finite((n - 1).min(u16::MAX as u32) as u16))?,
+    }
+    encoder.encode_frames(frames.into_iter().map(|frame| {
+      let (left, top, delay) = (frame.left(), frame.top(), frame.delay());
+      let mut img = frame.into_buffer();
+      for pixel in img.pixels_mut() {
+        pixel[3] = if pixel[3] < 128 { 0 } else { 255 };
+      }
+      Frame::from_parts(img, left, top, delay)
+    }))?;
+  }
+  Ok(buffer)
+}
+
+/// Encode RGBA images as the entries of a single ICO file, each stored as PNG
+///
+/// Every image must be between 1x1 and 256x256 pixels.
+pub fn encode_ico(images: &[RgbaImage]) -> anyhow::Result<Vec<u8>> {
+  let entries = images
+    .iter()
+    .map(|img| {
+      IcoFrame::as_png(
+        img.as_raw(),
+        img.width(),
+        img.height(),
+        ExtendedColorType::Rgba8,
+      )
+    })
+    .collect::<image::ImageResult<Vec<_>>>()?;
+
+  let mut buffer = Vec::new();
+  IcoEncoder::new(&mut buffer).encode_images(&entries)?;
+  Ok(buffer)
+}
+
+/// Encode RGBA frames as an animated WebP, played `plays` times (0 = forever)
+///
+/// Each frame is encoded like a still WebP image with `settings` and wrapped in an `ANMF`
+/// chunk. Every frame replaces the previous one entirely, so frames must cover the whole canvas.
+pub fn encode_animated_webp(
+  frames: &[Frame],
+  plays: u32,
+  settings: WebPSettings,
+) -> anyhow::Result<Vec<u8>> {
+  let (width, height) = frames
+    .first()
+    .map_or((1, 1), |frame| frame.buffer().dimensions());
+
+  // VP8X: animation and alpha flags, then the canvas size minus one (24 bits each)
+  let mut vp8x = vec![0x12, 0, 0, 0];
+  vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+  vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+  // ANIM: a transparent background color and the loop count
+  let mut anim = vec![0, 0, 0, 0];
+  anim.extend_from_slice(&(plays.min(u16::MAX as u32) as u16).to_le_bytes());
+
+  let mut body = b"WEBP".to_vec();
+  write_riff_chunk(&mut body, b"VP8X", &vp8x);
+  write_riff_chunk(&mut body, b"ANIM", &anim);
+  for frame in frames {
+    let still = encode_rgba(frame.buffer(), OutputFormat::WebP(settings))?;
+    let (numer, denom) = frame.delay().numer_denom_ms();
+    let duration_ms = (numer as f64 / denom as f64).round().min(0xff_ffff as f64) as u32;
+
+    // ANMF: the frame offset (halved) and size minus one, its duration (24 bits each), then
+    // the flags (no blending, no disposal) followed by the frame's own image chunks
+    let mut anmf = vec![0; 6];
+    anmf.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    anmf.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    anmf.extend_from_slice(&duration_ms.to_le_bytes()[..3]);
+    anmf.push(0b10);
+    // Lossy stills carry their own VP8X chunk ahead of the ALPH and VP8 chunks, which
+    // doesn't belong in a frame
+    let chunks = &still[12..];
+    anmf.extend_from_slice(
+      chunks
+        .strip_prefix(b"VP8X")
+        .map_or(chunks, |rest| &rest[14..]),
+    );
+    write_riff_chunk(&mut body, b"ANMF", &anmf);
+  }
+
+  let mut riff = Vec::with_capacity(body.len() + 8);
+  write_riff_chunk(&mut riff, b"RIFF", &body);
+  Ok(riff)
+}
+
+/// Append a RIFF chunk, padded to an even length
+fn write_riff_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+  out.extend_from_slice(fourcc);
+  out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+  out.extend_from_slice(data);
+  if data.len() % 2 == 1 {
+    out.push(0);
+  }
+}
+
+/// Encode RGBA frames as an animated PNG, played `plays` times (0 = forever)
+///
+/// Every frame replaces the previous one entirely, so frames must cover the whole canvas.
+pub fn encode_apng(frames: &[Frame], plays: u32, settings: PngSettings) -> anyhow::Result<Vec<u8>> {
+  let (width, height) = frames
+    .first()
+    .map_or((1, 1), |frame| frame.buffer().dimensions());
+  let mut info = png::Info::with_size(width, height);
+  info.color_type = png::ColorType::Rgba;
+  info.bit_depth = png::BitDepth::Eight;
+
+  let mut buffer = Vec::new();
+  let mut encoder = png_encoder(&mut buffer, info, settings)?;
+  encoder.set_animated(frames.len() as u32, plays)?;
+  let mut writer = encoder.write_header()?;
+  for frame in frames {
+    let (numer, denom) = frame.delay().numer_denom_ms();
+    let delay_ms = (numer as f64 / denom as f64).round().min(u16::MAX as f64) as u16;
+    writer.set_frame_delay(delay_ms, 1000)?;
+    writer.set_dispose_op(png::DisposeOp::None)?;
+    writer.set_blend_op(png::BlendOp::Source)?;
+    writer.write_image_data(frame.buffer().as_raw())?;
+  }
+  writer.finish()?;
+
+  Ok(buffer)
+}
+
+/// The largest `IDAT` chunk written for progressive PNG images, in bytes
+const IDAT_CHUNK_SIZE: usize = 1 << 16;
+
+/// The approximate size of the filtered rows compressed together when encoding PNG images in
+/// parallel (with the `parallel-png` feature)
+#[cfg(not(test))]
+const PARALLEL_STRIPE_BYTES: usize = 1 << 18;
+
+/// Small stripes in tests, so small images span many stripes and batches
+#[cfg(test)]
+const PARALLEL_STRIPE_BYTES: usize = 1 << 10;
+
+/// The modulus of the Adler-32 checksum that ends a zlib stream
+const ADLER_MODULUS: u32 = 65521;
+
+/// Ancillary chunks written alongside the pixels of a PNG image
+#[derive(Clone, Default)]
+pub struct PngMetadata {
+  /// The position in pixels, written as an `oFFs` chunk
+  pub offset: Option<(u32, u32)>,
+  /// The physical pixel size (`pHYs`), which carries the DPI
+  pub pixel_dims: Option<png::PixelDimensions>,
+  /// The image gamma (`gAMA`)
+  pub gamma: Option<png::ScaledFloat>,
+  /// Raw `tEXt`, `zTXt` and `iTXt` chunks, in their original order
+  pub text: Vec<(png::chunk::ChunkType, Vec<u8>)>,
+}
+
+impl PngMetadata {
+  /// Read the `pHYs`, `gAMA` and text chunks of a PNG image
+  ///
+  /// Other formats and malformed chunks yield no metadata rather than an error, since the
+  /// image itself is decoded separately.
+  pub fn read(input: &[u8]) -> Self {
+    let mut metadata = Self::default();
+    let Some(mut rest) = input.strip_prefix(b"\x89PNG\r\n\x1a\n") else {
+      return metadata;
+    };
+    // Each chunk is its length, type, data and CRC
+    while rest.len() >= 12 {
+      let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+      let Some(data) = rest.get(8..8 + len) else {
+        break;
+      };
+      let chunk_type = png::chunk::ChunkType([rest[4], rest[5], rest[6], rest[7]]);
+      match &chunk_type.0 {
+        b"pHYs" if len == 9 => {
+          metadata.pixel_dims = png::Unit::from_u8(data[8]).map(|unit| png::PixelDimensions {
+            xppu: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            yppu: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            unit,
+          });
+        }
+        b"gAMA" if len == 4 => {
+          let gamma = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+          metadata.gamma = Some(png::ScaledFloat::from_scaled(gamma));
+        }
+        b"tEXt" | b"zTXt" | b"iTXt" => metadata.text.push((chunk_type, data.to_vec())),
+        b"IEND" => break,
+        _ => {}
+      }
+      rest = rest.get(12 + len..).unwrap_or_default();
+    }
+    metadata
+  }
+}
+
+/// Encode an RGBA image as PNG with the given ancillary chunks
+pub fn encode_png_rgba(
+  img: &RgbaImage,
+  settings: PngSettings,
+  metadata: &PngMetadata,
+) -> anyhow::Result<Vec<u8>> {
+  let mut buffer = Vec::new();
+  write_png_rgba(&mut buffer, img, settings, metadata)?;
+  Ok(buffer)
+}
+
+/// Encode a 16-bit RGBA image as PNG with the given ancillary chunks
+pub fn encode_png_rgba16(
+  img: &Rgba16Image,
+  settings: PngSettings,
+  metadata: &PngMetadata,
+) -> anyhow::Result<Vec<u8>> {
+  let mut buffer = Vec::new();
+  write_png_rgba16(&mut buffer, img, settings, metadata)?;
+  Ok(buffer)
+}
+
+/// Write an RGBA image as PNG with the given ancillary chunks, row by row
+///
+/// With palette settings the image is quantized and written as an indexed PNG, at the
+/// smallest bit depth that holds its palette.
+pub fn write_png_rgba<W: Write>(
+  w: W,
+  img: &RgbaImage,
+  settings: PngSettings,
+  metadata: &PngMetadata,
+) -> anyhow::Result<()> {
+  let (width, height) = img.dimensions();
+  let mut info = png::Info::with_size(width, height);
+  info.interlaced = settings.interlaced;
+
+  let indexed = settings
+    .palette
+    .map(|palette_settings| IndexedImage::quantize(img, palette_settings));
+  let gray = indexed.is_none() && settings.grayscale && img.pixels().all(is_gray);
+  match &indexed {
+    Some(indexed) => {
+      info.color_type = png::ColorType::Indexed;
+      info.bit_depth = indexed.bit_depth;
+      info.palette = Some(
+        indexed
+          .palette
+          .iter()
+          .flat_map(|c| [c[0], c[1], c[2]])
+          .collect(),
+      );
+      let opaque_tail = indexed
+        .palette
+        .iter()
+        .rev()
+        .take_while(|c| c[3] == 255)
+        .count();
+      let alphas = &indexed.palette[..indexed.palette.len() - opaque_tail];
+      if !alphas.is_empty() {
+        info.trns = Some(alphas.iter().map(|c| c[3]).collect());
+      }
+    }
+    None => {
+      info.color_type = if gray {
+        png::ColorType::GrayscaleAlpha
+      } else {
+        png::ColorType::Rgba
+      };
+      info.bit_depth = png::BitDepth::Eight;
+    }
+  }
+
+  // Packs the pixels `x0, x0 + dx, ...` of row `y` the way PNG stores them
+  let row = |x0: u32, dx: u32, y: u32| -> Vec<u8> {
+    let xs = (x0..width).step_by(dx as usize);
+    match &indexed {
+      Some(indexed) => indexed.packed_row(xs, y),
+      None if gray => xs
+        .flat_map(|x| {
+          let [l, _, _, a] = img.get_pixel(x, y).0;
+          [l, a]
+        })
+        .collect(),
+      None => xs.flat_map(|x| img.get_pixel(x, y).0).collect(),
+    }
+  };
+  // The byte distance to the corresponding byte of the previous pixel, for filtering
+  let filter_distance = match (&indexed, gray) {
+    (Some(_), _) => 1,
+    (None, true) => 2,
+    (None, false) => 4,
+  };
+
+  write_png(w, info, settings, metadata, row, filter_distance)
+}
+
+/// Write a 16-bit RGBA image as PNG with the given ancillary chunks, row by row
+pub fn write_png_rgba16<W: Write>(
+  w: W,
+  img: &Rgba16Image,
+  settings: PngSettings,
+  metadata: &PngMetadata,
+) -> anyhow::Result<()> {
+  if settings.palette.is_some() {
+    anyhow::bail!("Indexed output is limited to 8 bits per channel");
+  }
+
+  let (width, height) = img.dimensions();
+  let gray = settings.grayscale && img.pixels().all(is_gray);
+  let mut info = png::Info::with_size(width, height);
+  info.interlaced = settings.interlaced;
+  info.color_type = if gray {
+    png::ColorType::GrayscaleAlpha
+  } else {
+    png::ColorType::Rgba
+  };
+  info.bit_depth = png::BitDepth::Sixteen;
+
+  // PNG stores 16-bit samples big-endian
+  let row = |x0: u32, dx: u32, y: u32| -> Vec<u8> {
+    let pixels = (x0..width)
+      .step_by(dx as usize)
+      .map(|x| img.get_pixel(x, y).0);
+    if gray {
+      pixels
+        .flat_map(|[l, _, _, a]| [l, a])
+        .flat_map(u16::to_be_bytes)
+        .collect()
+    } else {
+      pixels.flatten().flat_map(u16::to_be_bytes).collect()
+    }
+  };
+
+  write_png(w, info, settings, metadata, row, if gray { 4 } else { 8 })
+}
+
+/// Write a PNG image with the given header and ancillary chunks
+///
+/// `row(x0, dx, y)` packs the pixels `x0, x0 + dx, ...` of row `y`, and `filter_distance`
+/// is the byte distance to the corresponding byte of the previous pixel. Progressive images
+/// are compressed as their rows are packed, so only the compressed stream is buffered.
+fn write_png<W: Write>(
+  w: W,
+  mut info: png::Info,
+  settings: PngSettings,
+  metadata: &PngMetadata,
+  row: impl Fn(u32, u32, u32) -> Vec<u8> + Sync,
+  filter_distance: usize,
+) -> anyhow::Result<()> {
+  let (width, height) = (info.width, info.height);
+  // Set on the header so the encoder places them before the palette and image data
+  info.pixel_dims = metadata.pixel_dims;
+  info.source_gamma = metadata.gamma;
+
+  let mut writer = png_encoder(w, info, settings)?.write_header()?;
+
+  for (chunk_type, data) in &metadata.text {
+    writer.write_chunk(*chunk_type, data)?;
+  }
+  if let Some((x, y)) = metadata.offset {
+    // oFFs: signed 32-bit x and y positions followed by the unit (0 = pixels)
+    let mut chunk = Vec::with_capacity(9);
+    chunk.extend_from_slice(&(x as i32).to_be_bytes());
+    chunk.extend_from_slice(&(y as i32).to_be_bytes());
+    chunk.push(0);
+    writer.write_chunk(png::chunk::ChunkType(*b"oFFs"), &chunk)?;
+  }
+
+  if settings.interlaced {
+    // The PNG encoder only writes progressive rows, so the Adam7 passes are filtered and
+    // compressed here and written as a single IDAT chunk
+    let level = zlib_level(settings.compression);
+    let mut zlib = ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+    zlib.write_all(&adam7_filtered_rows(
+      width,
+      height,
+      row,
+      filter_distance,
+      settings.filter,
+    ))?;
+    writer.write_chunk(png::chunk::IDAT, &zlib.finish()?)?;
+  } else if cfg!(feature = "parallel-png") && height > stripe_rows(&row) {
+    write_parallel_idat(&mut writer, height, row, filter_distance, settings)?;
+  } else {
+    let mut stream = writer.stream_writer_with_size(IDAT_CHUNK_SIZE)?;
+    for y in 0..height {
+      stream.write_all(&row(0, 1, y))?;
+    }
+    stream.finish()?;
+  }
+  writer.finish()?;
+
+  Ok(())
+}
+
+/// Create a PNG encoder with the compression, filter and color tagging of the given settings
+fn png_encoder<'a, W: Write>(
+  w: W,
+  mut info: png::Info<'a>,
+  settings: PngSettings,
+) -> anyhow::Result<png::Encoder<'a, W>> {
+  if settings.srgb {
+    info.srgb = Some(png::SrgbRenderingIntent::Perceptual);
+  }
+  let mut encoder = png::Encoder::with_info(w, info)?;
+  encoder.set_compression(match settings.compression {
+    PngCompression::None => png::Compression::NoCompression,
+    PngCompression::Fastest => png::Compression::Fastest,
+    PngCompression::Fast => png::Compression::Fast,
+    PngCompression::Balanced => png::Compression::Balanced,
+    PngCompression::Smallest => png::Compression::High,
+  });
+  encoder.set_filter(settings.filter);
+  Ok(encoder)
+}
+
+/// An image quantized to palette indices
+struct IndexedImage {
+  width: u32,
+  /// The palette colors, translucent ones first so the `tRNS` chunk stays short
+  palette: Vec<[u8; 4]>,
+  /// The palette index of every pixel, row by row
+  indices: Vec<u8>,
+  bit_depth: png::BitDepth,
+}
+
+impl IndexedImage {
+  /// Quantize an image to at most `max_colors` palette entries
+  ///
+  /// Fully transparent pixels share a single entry, which counts towards the limit.
+  fn quantize(img: &RgbaImage, settings: PaletteSettings) -> Self {
+    let has_transparency = img.pixels().any(|pixel| pixel[3] == 0);
+    let max_visible = if has_transparency {
+      settings.max_colors.saturating_sub(1).max(1)
+    } else {
+      settings.max_colors
+    };
+    let quantized = quantize_rgba(img, max_visible as usize, settings.dither);
+
+    let mut palette: Vec<[u8; 4]> = quantized.pixels().map(|pixel| pixel.0).collect();
+    palette.sort_unstable_by_key(|color| (color[3], *color));
+    palette.dedup();
+    let lookup: HashMap<[u8; 4], u8> = palette
+      .iter()
+      .enumerate()
+      .map(|(index, color)| (*color, index as u8))
+      .collect();
+
+    let bit_depth = match palette.len() {
+      0..=2 => png::BitDepth::One,
+      3..=4 => png::BitDepth::Two,
+      5..=16 => png::BitDepth::Four,
+      _ => png::BitDepth::Eight,
+    };
+
+    IndexedImage {
+      width: img.width(),
+      indices: quantized.pixels().map(|pixel| lookup[&pixel.0]).collect(),
+      palette,
+      bit_depth,
+    }
+  }
+
+  /// Pack the indices of the given pixels of row `y` at the bit depth, most significant
+  /// bits first
+  fn packed_row(&self, xs: impl Iterator<Item = u32>, y: u32) -> Vec<u8> {
+    let bits = self.bit_depth as usize;
+    let per_byte = 8 / bits;
+    let mut packed = Vec::new();
+    for (i, x) in xs.enumerate() {
+      if i % per_byte == 0 {
+        packed.push(0);
+      }
+      let index = self.indices[(y * self.width + x) as usize];
+      let shift = 8 - bits * (i % per_byte + 1);
+      *packed.last_mut().unwrap() |= index << shift;
+    }
+    packed
+  }
+}
+
+/// The x/y start and step of the seven Adam7 passes
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [
+  (0, 0, 8, 8),
+  (4, 0, 8, 8),
+  (0, 4, 4, 8),
+  (2, 0, 4, 4),
+  (0, 2, 2, 4),
+  (1, 0, 2, 2),
+  (0, 1, 1, 2),
+];
+
+/// Check whether a pixel has equal red, green and blue channels
+fn is_gray<T: PartialEq>(pixel: &Rgba<T>) -> bool {
+  pixel[0] == pixel[1] && pixel[1] == pixel[2]
+}
+
+/// The zlib compression level for a PNG compression setting
+fn zlib_level(compression: PngCompression) -> u32 {
+  match compression {
+    PngCompression::None => 0,
+    PngCompression::Fastest => 1,
+    PngCompression::Fast => 3,
+    PngCompression::Balanced => 6,
+    PngCompression::Smallest => 9,
+  }
+}
+
+/// The number of rows in each stripe of a parallel-encoded PNG, at least one
+fn stripe_rows(row: &impl Fn(u32, u32, u32) -> Vec<u8>) -> u32 {
+  (PARALLEL_STRIPE_BYTES / row(0, 1, 0).len().max(1)).max(1) as u32
+}
+
+/// Filter and compress the rows of a progressive image in parallel stripes, written as
+/// `IDAT` chunks
+///
+/// Each stripe is deflated on its own and ends on a byte boundary (a sync flush), so the
+/// stripes concatenate into a single zlib stream; only the last one is finished. Stripes are
+/// compressed a batch at a time and written in order, so memory stays bounded. Matches lost
+/// across stripe boundaries make the output slightly larger than with serial encoding.
+fn write_parallel_idat<W: Write>(
+  writer: &mut png::Writer<W>,
+  height: u32,
+  row: impl Fn(u32, u32, u32) -> Vec<u8> + Sync,
+  distance: usize,
+  settings: PngSettings,
+) -> anyhow::Result<()> {
+  let level = zlib_level(settings.compression);
+  let rows_per_stripe = stripe_rows(&row);
+  let stripes: Vec<u32> = (0..height).step_by(rows_per_stripe as usize).collect();
+  let batch_size = rayon::current_num_threads() * 2;
+
+  // zlib header: deflate with a 32K window, the compression level, and a check value
+  let flags = match level {
+    0 | 1 => 0x01,
+    2..=5 => 0x5e,
+    6 => 0x9c,
+    _ => 0xda,
+  };
+  writer.write_chunk(png::chunk::IDAT, &[0x78, flags])?;
+
+  let mut checksum = (1, 0);
+  for batch in stripes.chunks(batch_size) {
+    let compressed = batch
+      .par_iter()
+      .map(|&y0| {
+        let y1 = (y0 + rows_per_stripe).min(height);
+        let mut above = if y0 > 0 {
+          row(0, 1, y0 - 1)
+        } else {
+          Vec::new()
+        };
+        let mut data = Vec::new();
+        for y in y0..y1 {
+          let current = row(0, 1, y);
+          let (kind, filtered) = filter_scanline(&current, &above, distance, settings.filter);
+          data.push(kind);
+          data.extend_from_slice(&filtered);
+          above = current;
+        }
+
+        let mut deflate = DeflateEncoder::new(Vec::new(), flate2::Compression::new(level));
+        deflate.write_all(&data)?;
+        let compressed = if y1 == height {
+          deflate.finish()?
+        } else {
+          deflate.flush()?;
+          std::mem::take(deflate.get_mut())
+        };
+        Ok((compressed, adler32(&data), data.len()))
+      })
+      .collect::<std::io::Result<Vec<_>>>()?;
+
+    for (stripe, stripe_checksum, len) in compressed {
+      checksum = combine_adler32(checksum, stripe_checksum, len);
+      writer.write_chunk(png::chunk::IDAT, &stripe)?;
+    }
+  }
+
+  let (a, b) = checksum;
+  writer.write_chunk(png::chunk::IDAT, &(b << 16 | a).to_be_bytes())?;
+  Ok(())
+}
+
+/// The two running sums of the Adler-32 checksum of `data`
+fn adler32(data: &[u8]) -> (u32, u32) {
+  let (mut a, mut b) = (1u32, 0u32);
+  // The largest run of bytes before the sums may overflow 32 bits
+  for chunk in data.chunks(5552) {
+    for &byte in chunk {
+      a += byte as u32;
+      b += a;
+    }
+    a %= ADLER_MODULUS;
+    b %= ADLER_MODULUS;
+  }
+  (a, b)
+}
+
+/// The Adler-32 sums of two concatenated runs of bytes, given the sums of each and the length
+/// of the second
+fn combine_adler32(first: (u32, u32), second: (u32, u32), second_len: usize) -> (u32, u32) {
+  let modulus = ADLER_MODULUS as u64;
+  let (a1, b1) = (first.0 as u64, first.1 as u64);
+  let (a2, b2) = (second.0 as u64, second.1 as u64);
+  let len = second_len as u64 % modulus;
+  (
+    ((a1 + a2 + modulus - 1) % modulus) as u32,
+    ((b1 + b2 + len * (a1 + modulus - 1)) % modulus) as u32,
+  )
+}
+
+/// Filter a row with the given PNG filter, returning the filter type and the filtered bytes
+///
+/// The adaptive filter picks the type with the smallest sum of absolute (signed) residuals.
+fn filter_scanline(
+  row: &[u8],
+  above: &[u8],
+  distance: usize,
+  filter: png::Filter,
+) -> (u8, Vec<u8>) {
+  match filter {
+    png::Filter::NoFilter => (0, filter_row(0, row, above, distance)),
+    png::Filter::Sub => (1, filter_row(1, row, above, distance)),
+    png::Filter::Up => (2, filter_row(2, row, above, distance)),
+    png::Filter::Avg => (3, filter_row(3, row, above, distance)),
+    png::Filter::Paeth => (4, filter_row(4, row, above, distance)),
+    _ => (0..5)
+      .map(|kind| (kind, filter_row(kind, row, above, distance)))
+      .min_by_key(|(_, filtered)| {
+        filtered
+          .iter()
+          .map(|&v| (v as i8).unsigned_abs() as u32)
+          .sum::<u32>()
+      })
+      .unwrap(),
+  }
+}
+
+/// Split an image into its Adam7 passes and filter each row, prefixed with its filter type
+///
+/// `row(x0, dx, y)` packs the pixels `x0, x0 + dx, ...` of row `y`.
+fn adam7_filtered_rows(
+  width: u32,
+  height: u32,
+  row: impl Fn(u32, u32, u32) -> Vec<u8>,
+  distance: usize,
+  filter: png::Filter,
+) -> Vec<u8> {
+  let mut data = Vec::new();
+  for (x0, y0, dx, dy) in ADAM7_PASSES {
+    if x0 >= width || y0 >= height {
+      continue;
+    }
+
+    let mut previous: Option<Vec<u8>> = None;
+    for y in (y0..height).step_by(dy as usize) {
+      let row = row(x0, dx, y);
+      let above = previous.as_deref().unwrap_or(&[]);
+
+      let (kind, filtered) = filter_scanline(&row, above, distance, filter);
+      data.push(kind);
+      data.extend_from_slice(&filtered);
+      previous = Some(row);
+    }
+  }
+  data
+}
+
+/// Apply a PNG filter type (0-4) to a row, given the unfiltered row above it (empty for the
+/// first row of a pass) and the byte distance to the previous pixel
+fn filter_row(kind: u8, row: &[u8], above: &[u8], distance: usize) -> Vec<u8> {
+  let zeros;
+  let above = if above.is_empty() {
+    zeros = vec![0; row.len()];
+    &zeros
+  } else {
+    above
+  };
+  let distance = distance.min(row.len());
+  // The first pixel has no left neighbor, so `a` and `c` are zero there
+  let (head, tail) = row.split_at(distance);
+  let (above_head, above_tail) = above.split_at(distance);
+  let lefts = row.iter();
+  let upper_lefts = above.iter();
+
+  let mut filtered = Vec::with_capacity(row.len());
+  match kind {
+    0 => filtered.extend_from_slice(row),
+    1 => {
+      filtered.extend_from_slice(head);
+      filtered.extend(tail.iter().zip(lefts).map(|(&x, &a)| x.wrapping_sub(a)));
+    }
+    2 => filtered.extend(row.iter().zip(above).map(|(&x, &b)| x.wrapping_sub(b))),
+    3 => {
+      filtered.extend(
+        head
+          .iter()
+          .zip(above_head)
+          .map(|(&x, &b)| x.wrapping_sub(b / 2)),
+      );
+      filtered.extend(
+        tail
+          .iter()
+          .zip(above_tail)
+          .zip(lefts)
+          .map(|((&x, &b), &a)| x.wrapping_sub(((a as u16 + b as u16) / 2) as u8)),
+      );
+    }
+    _ => {
+      filtered.extend(
+        head
+          .iter()
+          .zip(above_head)
+          .map(|(&x, &b)| x.wrapping_sub(b)),
+      );
+      filtered.extend(
+        tail
+          .iter()
+          .zip(above_tail)
+          .zip(lefts.zip(upper_lefts))
+          .map(|((&x, &b), (&a, &c))| x.wrapping_sub(paeth_predictor(a, b, c))),
+      );
+    }
+  }
+  filtered
+}
+
+/// The PNG Paeth predictor: whichever of left, above and upper left is closest to
+/// `left + above - upper left`
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+  let p = a as i16 + b as i16 - c as i16;
+  let (pa, pb, pc) = (
+    (p - a as i16).abs(),
+    (p - b as i16).abs(),
+    (p - c as i16).abs(),
+  );
+  if pa <= pb && pa <= pc {
+    a
+  } else if pb <= pc {
+    b
+  } else {
+    c
+  }
+}
+
+/// The serial encoder is lossless, so images written in parallel stripes must decode to the
+/// exact source pixels
+#[cfg(all(test, feature = "parallel-png"))]
+mod parallel_png_tests {
+  use super::*;
+  use std::io::Read;
+
+  const FILTERS: [png::Filter; 6] = [
+    png::Filter::NoFilter,
+    png::Filter::Sub,
+    png::Filter::Up,
+    png::Filter::Avg,
+    png::Filter::Paeth,
+    png::Filter::Adaptive,
+  ];
+
+  /// A deterministic, noisy stream of bytes
+  fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+      .map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        (state >> 24) as u8
+      })
+      .collect()
+  }
+
+  /// An RGBA image with an odd width, smooth gradients and noise, so every filter has work
+  fn test_image(height: u32) -> RgbaImage {
+    let width = 37;
+    let noise = noise((width * height) as usize);
+    RgbaImage::from_fn(width, height, |x, y| {
+      let n = noise[(y * width + x) as usize];
+      Rgba([(x * 7) as u8, (y * 3) as u8, n, (x + y) as u8 | 0x80])
+    })
+  }
+
+  /// Decode a PNG with the `png` crate, returning its header color type and bit depth and its
+  /// pixels with palettes expanded to RGBA
+  fn decode(png: &[u8]) -> (png::ColorType, png::BitDepth, Vec<u8>) {
+    let mut decoder = png::Decoder::new(Cursor::new(png));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().unwrap();
+    let (color_type, bit_depth) = (reader.info().color_type, reader.info().bit_depth);
+    let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    pixels.truncate(info.buffer_size());
+    (color_type, bit_depth, pixels)
+  }
+
+  /// Check that `png` was written by `write_parallel_idat` and that its zlib stream inflates
+  /// with a matching Adler-32 checksum
+  fn assert_parallel_stream(png: &[u8]) {
+    let mut idat = Vec::new();
+    let mut idat_lengths = Vec::new();
+    let mut rest = &png[8..];
+    while rest.len() >= 12 {
+      let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+      if &rest[4..8] == b"IDAT" {
+        idat.extend_from_slice(&rest[8..8 + len]);
+        idat_lengths.push(len);
+      }
+      rest = &rest[12 + len..];
+    }
+    // The zlib header, at least two stripes and the checksum each get their own chunk
+    assert_eq!(idat_lengths.first(), Some(&2));
+    assert_eq!(idat_lengths.last(), Some(&4));
+    assert!(idat_lengths.len() > 4);
+
+    let mut data = Vec::new();
+    flate2::read::ZlibDecoder::new(&idat[..])
+      .read_to_end(&mut data)
+      .unwrap();
+    let (a, b) = adler32(&data);
+    assert_eq!(idat[idat.len() - 4..], (b << 16 | a).to_be_bytes());
+  }
+
+  /// The heights of images with `row_bytes` per row that end on a stripe boundary, one row
+  /// past it, and that span many batches of stripes
+  fn heights(row_bytes: usize) -> [u32; 3] {
+    let rows = (PARALLEL_STRIPE_BYTES / row_bytes) as u32;
+    [rows * 3, rows * 3 + 1, rows * 40 + 7]
+  }
+
+  #[test]
+  fn combine_adler32_matches_a_single_pass() {
+    assert_eq!(adler32(b"Wikipedia"), (0x0398, 0x11e6));
+
+    let data = noise(20_000);
+    for split in [0, 1, 5551, 5552, 5553, 10_000, 19_999, 20_000] {
+      let (first, second) = data.split_at(split);
+      assert_eq!(
+        combine_adler32(adler32(first), adler32(second), second.len()),
+        adler32(&data),
+        "split at {}",
+        split
+      );
+    }
+    // Large runs of 0xff push the sums towards the modulus
+    let ones = vec![0xff; 100_000];
+    let (first, second) = ones.split_at(65_521);
+    assert_eq!(
+      combine_adler32(adler32(first), adler32(second), second.len()),
+      adler32(&ones)
+    );
+  }
+
+  #[test]
+  fn rgba_matches_the_source_with_every_filter() {
+    for filter in FILTERS {
+      for height in heights(37 * 4) {
+        let img = test_image(height);
+        let settings = PngSettings {
+          filter,
+          ..PngSettings::default()
+        };
+        let png = encode_png_rgba(&img, settings, &PngMetadata::default()).unwrap();
+
+        assert_parallel_stream(&png);
+        let (color_type, _, pixels) = decode(&png);
+        assert_eq!(color_type, png::ColorType::Rgba);
+        assert_eq!(
+          pixels,
+          *img.as_raw(),
+          "{:?} filter, {} rows",
+          filter,
+          height
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn uncompressed_output_matches_the_source() {
+    for height in heights(37 * 4) {
+      let img = test_image(height);
+      let settings = PngSettings {
+        compression: PngCompression::None,
+        ..PngSettings::default()
+      };
+      let png = encode_png_rgba(&img, settings, &PngMetadata::default()).unwrap();
+
+      assert_parallel_stream(&png);
+      assert_eq!(decode(&png).2, *img.as_raw());
+    }
+  }
+
+  #[test]
+  fn gray_matches_the_source() {
+    for filter in FILTERS {
+      // Sized for the single-channel image, so the gray and alpha one has twice the stripes
+      for height in heights(37) {
+        let img = test_image(height);
+        let gray_alpha = RgbaImage::from_fn(img.width(), height, |x, y| {
+          let [_, _, l, a] = img.get_pixel(x, y).0;
+          Rgba([l, l, l, a])
+        });
+        let settings = PngSettings {
+          filter,
+          grayscale: true,
+          ..PngSettings::default()
+        };
+        let png = encode_png_rgba(&gray_alpha, settings, &PngMetadata::default()).unwrap();
+
+        assert_parallel_stream(&png);
+        let (color_type, _, pixels) = decode(&png);
+        assert_eq!(color_type, png::ColorType::GrayscaleAlpha);
+        let expected: Vec<u8> = gray_alpha.pixels().flat_map(|p| [p[0], p[3]]).collect();
+        assert_eq!(pixels, expected, "{:?} filter, {} rows", filter, height);
+
+        let gray = GrayImage::from_fn(img.width(), height, |x, y| Luma([img.get_pixel(x, y)[2]]));
+        let png = encode_gray(&gray, OutputFormat::Png(settings), &PngMetadata::default()).unwrap();
+        assert_parallel_stream(&png);
+        assert_eq!(decode(&png).2, *gray.as_raw());
+      }
+    }
+  }
+
+  #[test]
+  fn indexed_matches_the_source() {
+    // Four colors, one of them transparent, fit a 2-bit palette exactly
+    let colors = [
+      [0, 0, 0, 0],
+      [255, 0, 0, 255],
+      [0, 128, 255, 200],
+      [20, 20, 20, 255],
+    ];
+    for filter in FILTERS {
+      for height in heights(10) {
+        let noise = noise((37 * height) as usize);
+        let img = RgbaImage::from_fn(37, height, |x, y| {
+          Rgba(colors[(noise[(y * 37 + x) as usize] % 4) as usize])
+        });
+        let settings = PngSettings {
+          filter,
+          palette: Some(PaletteSettings {
+            max_colors: 16,
+            dither: false,
+          }),
+          ..PngSettings::default()
+        };
+        let png = encode_png_rgba(&img, settings, &PngMetadata::default()).unwrap();
+
+        assert_parallel_stream(&png);
+        let (color_type, bit_depth, pixels) = decode(&png);
+        assert_eq!(
+          (color_type, bit_depth),
+          (png::ColorType::Indexed, png::BitDepth::Two)
+        );
+        assert_eq!(
+          pixels,
+          *img.as_raw(),
+          "{:?} filter, {} rows",
+          filter,
+          height
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn sixteen_bit_matches_the_source() {
+    for filter in FILTERS {
+      // Sized for the single-channel image, so the RGBA one has four times the stripes
+      for height in heights(37 * 2) {
+        let noise = noise((37 * height * 2) as usize);
+        let img = Rgba16Image::from_fn(37, height, |x, y| {
+          let i = ((y * 37 + x) * 2) as usize;
+          let n = u16::from_be_bytes([noise[i], noise[i + 1]]);
+          Rgba([(x * 1000) as u16, (y * 300) as u16, n, n | 0x8000])
+        });
+        let settings = PngSettings {
+          filter,
+          ..PngSettings::default()
+        };
+        let png = encode_png_rgba16(&img, settings, &PngMetadata::default()).unwrap();
+
+        assert_parallel_stream(&png);
+        let (_, bit_depth, pixels) = decode(&png);
+        assert_eq!(bit_depth, png::BitDepth::Sixteen);
+        let expected: Vec<u8> = img.as_raw().iter().flat_map(|v| v.to_be_bytes()).collect();
+        assert_eq!(pixels, expected, "{:?} filter, {} rows", filter, height);
+
+        let gray = Gray16Image::from_fn(37, height, |x, y| Luma([img.get_pixel(x, y)[2]]));
+        let png =
+          encode_gray16(&gray, OutputFormat::Png(settings), &PngMetadata::default()).unwrap();
+        assert_parallel_stream(&png);
+        let expected: Vec<u8> = gray.as_raw().iter().flat_map(|v| v.to_be_bytes()).collect();
+        assert_eq!(decode(&png).2, expected);
+      }
+    }
+  }
+}
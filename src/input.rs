@@ -0,0 +1,146 @@
+use napi::bindgen_prelude::{FromNapiValue, ToNapiValue, TypeName, Uint8Array, ValidateNapiValue};
+use napi::{sys, Error, Result, Status, ValueType};
+use std::ops::Deref;
+use std::ptr;
+
+/// Encoded image bytes given as a `Buffer`, any other typed array or `DataView`, or an
+/// `ArrayBuffer`
+///
+/// The bytes are viewed in place, without copying them out of JavaScript memory.
+pub struct ImageBytes(Uint8Array);
+
+impl ImageBytes {
+  /// The bytes of an `ArrayBuffer` or an `ArrayBuffer` view: the buffer, offset and length
+  unsafe fn byte_range(
+    env: sys::napi_env,
+    value: sys::napi_value,
+  ) -> Result<(sys::napi_value, usize, usize)> {
+    let mut is = false;
+    check(unsafe { sys::napi_is_arraybuffer(env, value, &mut is) })?;
+    if is {
+      let mut length = 0;
+      check(unsafe { sys::napi_get_arraybuffer_info(env, value, ptr::null_mut(), &mut length) })?;
+      return Ok((value, 0, length));
+    }
+
+    let mut buffer = ptr::null_mut();
+    let mut offset = 0;
+    check(unsafe { sys::napi_is_typedarray(env, value, &mut is) })?;
+    if is {
+      let mut kind = 0;
+      let mut length = 0;
+      check(unsafe {
+        sys::napi_get_typedarray_info(
+          env,
+          value,
+          &mut kind,
+          &mut length,
+          ptr::null_mut(),
+          &mut buffer,
+          &mut offset,
+        )
+      })?;
+      return Ok((buffer, offset, length * element_size(kind)));
+    }
+
+    check(unsafe { sys::napi_is_dataview(env, value, &mut is) })?;
+    if is {
+      let mut length = 0;
+      check(unsafe {
+        sys::napi_get_dataview_info(
+          env,
+          value,
+          &mut length,
+          ptr::null_mut(),
+          &mut buffer,
+          &mut offset,
+        )
+      })?;
+      return Ok((buffer, offset, length));
+    }
+
+    Err(Error::new(
+      Status::InvalidArg,
+      "Expected a Buffer, Uint8Array or ArrayBuffer".to_string(),
+    ))
+  }
+}
+
+/// The size in bytes of one element of a typed array type
+fn element_size(kind: sys::napi_typedarray_type) -> usize {
+  use sys::TypedarrayType as T;
+  match kind {
+    T::int8_array | T::uint8_array | T::uint8_clamped_array => 1,
+    T::int16_array | T::uint16_array => 2,
+    T::int32_array | T::uint32_array | T::float32_array => 4,
+    // float64, bigint64 and biguint64
+    _ => 8,
+  }
+}
+
+/// Turn a failed N-API call into an error
+fn check(status: sys::napi_status) -> Result<()> {
+  if status == sys::Status::napi_ok {
+    Ok(())
+  } else {
+    Err(Error::new(
+      Status::InvalidArg,
+      "Failed to read input bytes".to_string(),
+    ))
+  }
+}
+
+impl Deref for ImageBytes {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl TypeName for ImageBytes {
+  fn type_name() -> &'static str {
+    "Uint8Array | ArrayBuffer"
+  }
+
+  fn value_type() -> ValueType {
+    ValueType::Object
+  }
+}
+
+impl ValidateNapiValue for ImageBytes {
+  unsafe fn validate(env: sys::napi_env, napi_val: sys::napi_value) -> Result<sys::napi_value> {
+    unsafe { Self::byte_range(env, napi_val) }.map(|_| ptr::null_mut())
+  }
+}
+
+impl FromNapiValue for ImageBytes {
+  unsafe fn from_napi_value(env: sys::napi_env, napi_val: sys::napi_value) -> Result<Self> {
+    // View the bytes as a Uint8Array, whatever kind of view (or buffer) was passed in
+    let (buffer, offset, length) = unsafe { Self::byte_range(env, napi_val) }?;
+    let mut view = ptr::null_mut();
+    check(unsafe {
+      sys::napi_create_typedarray(
+        env,
+        sys::TypedarrayType::uint8_array,
+        length,
+        buffer,
+        offset,
+        &mut view,
+      )
+    })?;
+    unsafe { Uint8Array::from_napi_value(env, view) }.map(Self)
+  }
+}
+
+impl ToNapiValue for ImageBytes {
+  unsafe fn to_napi_value(env: sys::napi_env, val: Self) -> Result<sys::napi_value> {
+    unsafe { Uint8Array::to_napi_value(env, val.0) }
+  }
+}
+
+impl From<Vec<u8>> for ImageBytes {
+  fn from(bytes: Vec<u8>) -> Self {
+    Self(bytes.into())
+  }
+}
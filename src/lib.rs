@@ -1,35 +1,184 @@
 #![deny(clippy::all)]
 
 pub mod background;
+pub mod cmyk;
 pub mod color;
+pub mod data_url;
 pub mod deduce;
+pub mod encode;
+pub mod input;
 pub mod process;
+pub mod svg;
 pub mod unmix;
 
-use crate::background::detect_background_color as detect_bg;
+use crate::background::{
+  assess_removability as assess_background_removability, background_uniformity,
+  border_connected_region, detect_background_color_with_config, detect_background_rgba_with_config,
+  detect_chroma_key as detect_background_chroma_key, detect_solid_color as detect_solid,
+  detect_solid_frame, parse_background_estimator, parse_detection_strategy, parse_luminance_bias,
+  rank_background_colors, sample_edge_colors, BackgroundDetectionConfig,
+  CheckerboardBackgroundModel, GradientBackgroundModel, LocalBackgroundModel, Margins, Region,
+  SpatialBackground, TiledBackgroundModel,
+};
+use crate::cmyk::{decode_cmyk_jpeg, is_cmyk_jpeg};
 use crate::color::{
-  denormalize_color, normalize_color, parse_foreground_spec, parse_hex_color, Color,
-  ForegroundColorSpec, NormalizedColor,
+  convert_from_srgb, convert_to_srgb, delta_e_2000, delta_e_76, denormalize_color,
+  denormalize_rgba, denormalize_rgba16, linear_to_srgb as linear_channel_to_srgb, normalize_color,
+  parse_background_spec, parse_css_color, parse_foreground_spec_with_tolerance,
+  parse_threshold_space, parse_threshold_units, parse_tone_mapping, parse_unmix_space, srgb_to_lab,
+  srgb_to_linear as srgb_channel_to_linear, tone_map_to_srgb, Color, ForegroundColorSpec,
+  NormalizedColor, NormalizedRgba, ThresholdSpace, ThresholdUnits, ToneMapping, WorkingSpace,
+};
+use crate::data_url::{parse_data_url, to_data_url};
+use crate::deduce::{
+  deduce_unknown_colors_with_config, detect_primary_foreground_color,
+  extract_palette as extract_dominant_colors, parse_deduction_weighting, DeductionConfig,
 };
-use crate::deduce::deduce_unknown_colors;
+use crate::encode::{
+  encode_animated_webp, encode_apng, encode_gif, encode_gray, encode_gray16, encode_ico,
+  encode_png_rgba, encode_png_rgba16, encode_rgba, encode_rgba16, parse_avif_settings,
+  parse_jpeg_settings, parse_output_format, parse_palette_settings, parse_png_settings,
+  parse_webp_settings, write_png_rgba, write_png_rgba16, AvifSettings, Gray16Image, JpegSettings,
+  OutputFormat, PngMetadata, PngSettings, Rgba16Image, WebPSettings,
+};
+use crate::input::ImageBytes;
 use crate::process::{
-  composite_pixel_over_background, process_pixel_non_strict_no_fg,
-  process_pixel_non_strict_with_fg, trim_to_content,
+  alpha_weighted_average_color, apply_channel_gains, apply_outline,
+  composite_normalized_over_background, composite_pixel_over_background,
+  composite_pixel_over_background_with_mode, content_bounds, deblock_jpeg_blocks,
+  fill_enclosed_holes, find_minimum_alpha_for_color, fit_to_canvas, parse_alpha_mode,
+  parse_alpha_model, parse_blend_mode, parse_existing_alpha, process_normalized_non_strict_no_fg,
+  process_normalized_non_strict_with_fg, process_pixel_with_reference, quantize_rgba,
+  shadow_opacity, trim_to_content, white_balance_gains, AlphaMode, AlphaModel, BlendMode,
+  ExistingAlpha, DEFAULT_ALPHA_OPAQUE_DISTANCE, DEFAULT_BACKGROUND_EPSILON,
+};
+use crate::svg::{is_svg, rasterize_svg, SvgSize};
+use crate::unmix::{
+  compute_result_color_with_gamut, dropped_foreground_indices, is_color_close_to_foreground,
+  parse_gamut_mapping, unmix_colors_internal, unmix_normalized_with_depth,
+  unmix_normalized_with_tolerance, ColorCombinations, GamutMapping, UnmixResult,
+  DEFAULT_COLOR_CLOSENESS_THRESHOLD, DEFAULT_RECONSTRUCTION_TOLERANCE,
+};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{
+  AnimationDecoder, DynamicImage, Frame, GrayImage, ImageBuffer, ImageDecoder, Luma, Pixel,
+  Primitive, Rgba, RgbaImage,
 };
-use crate::unmix::{compute_result_color, unmix_colors, DEFAULT_COLOR_CLOSENESS_THRESHOLD};
-use image::{ImageBuffer, Rgba};
 use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
 use napi_derive::napi;
 use rayon::prelude::*;
-use std::io::Cursor;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{BufWriter, Cursor, Write};
+use std::time::Duration;
+
+/// Default tile width and height for tiled processing
+const DEFAULT_TILE_SIZE: u32 = 256;
+
+/// How many bytes of streamed output are collected before they are passed to JavaScript
+const STREAM_CHUNK_SIZE: usize = 1 << 16;
+
+/// How many finished tiles or streamed chunks may wait for their JavaScript callback before
+/// processing pauses until the callback catches up
+const CALLBACK_QUEUE_SIZE: usize = 16;
+
+/// A JavaScript callback whose queue of pending calls is bounded by `CALLBACK_QUEUE_SIZE`
+type BoundedCallback<T> =
+  ThreadsafeFunction<T, Unknown<'static>, T, Status, true, false, CALLBACK_QUEUE_SIZE>;
+
+/// Default number of candidates returned by `detect_background_colors`
+const DEFAULT_BACKGROUND_CANDIDATES: u32 = 5;
+
+/// Default depth of the ring sampled by the "border" detection strategy
+const DEFAULT_BORDER_DEPTH: u32 = 5;
+
+/// Default tile width and height of the tiled background model
+const DEFAULT_BACKGROUND_TILE_SIZE: u32 = 64;
+
+/// Default icon sizes of favicon exports
+const DEFAULT_ICO_SIZES: [u32; 4] = [16, 32, 48, 256];
 
 #[napi(object)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct RgbColor {
   pub r: u8,
   pub g: u8,
   pub b: u8,
 }
 
+/// A color option: a CSS color string, an RGB object or an `[r, g, b]` array
+pub type ColorInput = Either3<String, RgbColor, Vec<u8>>;
+
+#[napi(object)]
+pub struct BackgroundCandidate {
+  /// The edge color
+  pub color: RgbColor,
+  /// How many edge samples have this color
+  pub samples: u32,
+  /// The share of all edge samples that have this color (0.0-1.0)
+  pub confidence: f64,
+}
+
+#[napi(object)]
+pub struct PaletteColor {
+  /// The average color of the pixels in this palette entry
+  pub color: RgbColor,
+  /// How many pixels belong to this palette entry
+  pub pixels: u32,
+  /// The share of all non-transparent pixels that belong to this palette entry (0.0-1.0)
+  pub share: f64,
+}
+
+#[napi(object)]
+pub struct EdgeSample {
+  /// The x coordinate of the sampled pixel
+  pub x: u32,
+  /// The y coordinate of the sampled pixel
+  pub y: u32,
+  /// The sampled color, composited over black if translucent
+  pub color: RgbColor,
+}
+
+#[napi(object)]
+pub struct BackgroundDetectionDebug {
+  /// The detected background color
+  pub color: RgbColor,
+  /// Every edge sample, in sampling order
+  pub samples: Vec<EdgeSample>,
+  /// The sampled colors with their counts, most common first
+  pub histogram: Vec<BackgroundCandidate>,
+}
+
+#[napi(object)]
+pub struct BackgroundUniformity {
+  /// The detected background color
+  pub color: RgbColor,
+  /// The share of the edge samples within the threshold of `color` (0.0-1.0). Low values
+  /// mean the edges are not uniform and the detected color is likely wrong.
+  pub uniformity: f64,
+  /// How many edge samples are within the threshold of `color`
+  pub matching_samples: u32,
+  /// How many edge samples were taken
+  pub samples: u32,
+}
+
+#[napi(object)]
+pub struct ChromaKeyDetection {
+  /// The key color, or the detected background color if the background is not a chroma key
+  pub color: RgbColor,
+  /// Whether the background is a saturated green or blue screen
+  pub chroma_key: bool,
+  /// The key hue in degrees (0.0-360.0), if the background is a chroma key
+  pub hue: Option<f64>,
+  /// The share of the edge samples with the key hue (0.0-1.0), if the background is a chroma
+  /// key
+  pub coverage: Option<f64>,
+}
+
 #[napi(object)]
 pub struct RgbaColor {
   pub r: u8,
@@ -45,28 +194,620 @@ pub struct NormalizedRgbColor {
   pub b: f64,
 }
 
+#[napi(object)]
+pub struct HslColor {
+  /// The hue in degrees (0.0-360.0)
+  pub h: f64,
+  /// The saturation (0.0-1.0)
+  pub s: f64,
+  /// The lightness (0.0-1.0)
+  pub l: f64,
+}
+
+#[napi(object)]
+pub struct HsvColor {
+  /// The hue in degrees (0.0-360.0)
+  pub h: f64,
+  /// The saturation (0.0-1.0)
+  pub s: f64,
+  /// The value (0.0-1.0)
+  pub v: f64,
+}
+
+#[napi(object)]
+pub struct LabColor {
+  /// The lightness: 0.0-100.0 in CIELAB, 0.0-1.0 in OKLab
+  pub l: f64,
+  /// The green-red axis
+  pub a: f64,
+  /// The blue-yellow axis
+  pub b: f64,
+}
+
+#[napi(object)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OutlineSpec {
+  /// The stroke color as a CSS color string (hex, rgb(), hsl() or a name)
+  pub color: String,
+  /// The stroke width in pixels
+  pub width: u32,
+  /// The stroke opacity (0.0-1.0, default: 1.0)
+  pub opacity: Option<f64>,
+}
+
+#[napi(object)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BackgroundDetectionOptions {
+  /// Sample every N pixels along the edges (default: 10). Lower values sample more densely,
+  /// which helps small images and busy edges at a small cost in speed.
+  pub edge_sample_interval: Option<u32>,
+  /// Which pixels are sampled: "corners" (the four corners only), "edges" (default, the
+  /// corners and along the edges), "border" (a ring `border_depth` pixels deep, for frames and
+  /// title bars) or "dominant" (across the whole image)
+  pub strategy: Option<String>,
+  /// How many pixels deep the ring sampled by the "border" strategy is (default: 5)
+  pub border_depth: Option<u32>,
+  /// Rectangles to leave out of the samples, such as a watermark or logo stamped in a corner
+  pub exclude_regions: Option<Vec<RegionSpec>>,
+  /// How the background color is estimated from the samples: "mode" (default, the most common
+  /// sample), "median" (per channel) or "trimmed_mean" (per channel, over the middle half).
+  /// The latter two suit noisy camera images where no two samples are identical. The luminance
+  /// bias only applies to "mode".
+  pub estimator: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RegionSpec {
+  /// The x coordinate of the region's top-left corner
+  pub x: u32,
+  /// The y coordinate of the region's top-left corner
+  pub y: u32,
+  /// The region width in pixels
+  pub width: u32,
+  /// The region height in pixels
+  pub height: u32,
+}
+
+#[napi(object)]
+pub struct ReferenceOptions {
+  /// Differences from the reference below this (normalized RGB distance, default: 0.05) are
+  /// treated as sensor noise and removed
+  pub threshold: Option<f64>,
+  /// The difference from the reference at which pixels become fully opaque (default: 0.5)
+  pub opaque_distance: Option<f64>,
+  /// Whether to trim the output image to the bounding box of non-transparent pixels
+  pub trim: Option<bool>,
+  /// The output format: "png" (default), "indexed", "webp", "avif", "tiff", "jpeg", "apng",
+  /// "gif" or "raw", each with its default settings
+  pub output_format: Option<String>,
+}
+
+#[napi(object)]
+pub struct PngEncodeOptions {
+  /// How hard to compress: "none", "fastest", "fast", "balanced" (default) or "smallest"
+  pub compression: Option<String>,
+  /// The row filter: "none", "sub", "up", "avg", "paeth" or "adaptive" (default)
+  pub filter: Option<String>,
+  /// Whether to write Adam7 interlaced rows (default: false)
+  pub interlaced: Option<bool>,
+  /// Whether to carry the DPI (`pHYs`), gamma (`gAMA`) and text chunks of a PNG input over to
+  /// the output (default: true)
+  pub preserve_metadata: Option<bool>,
+}
+
 #[napi(object)]
 pub struct ProcessImageOptions {
-  /// The input image buffer
-  pub input: Buffer,
+  /// The encoded input image (a Buffer, any typed array or DataView, or an ArrayBuffer), a
+  /// base64 `data:` URL, or raw RGBA pixels with their dimensions
+  #[napi(ts_type = "Uint8Array | ArrayBuffer | RawImage | string")]
+  pub input: ImageInput,
   /// The foreground colors to match, if any. Use "auto" to deduce unknown colors.
-  pub foreground_colors: Option<Vec<String>>,
-  /// The background color to remove. If not specified, it will be auto-detected.
-  pub background_color: Option<String>,
+  /// Append "@tolerance" (e.g. "ff0000@0.05") to treat pixels within that normalized RGB
+  /// distance of the color as fully that color in strict mode. A color with alpha (e.g.
+  /// "#ff0000f3") has a tolerance of one minus its alpha. Colors may also be given as RGB
+  /// objects or `[r, g, b]` arrays.
+  pub foreground_colors: Option<Vec<ColorInput>>,
+  /// The background color to remove, or several (e.g. `["#ffffff", "#fefefe"]` or the two
+  /// tones of a split backdrop) to unmix each pixel against the nearest one. If not specified,
+  /// it will be auto-detected. Append "~tolerance" (e.g. "ffffff~0.04" or "ffffff~4%") to
+  /// treat pixels within that normalized RGB distance of the color as pure background. A color
+  /// with alpha (e.g. "#fffffff5") has a tolerance of one minus its alpha. Colors may also be
+  /// given as RGB objects or `[r, g, b]` arrays.
+  #[napi(ts_type = "string | RgbColor | Array<number> | Array<string | RgbColor | Array<number>>")]
+  pub background_color: Option<Either<ColorInput, Vec<ColorInput>>>,
+  /// Which edge color to prefer when auto-detecting the background: "none" (default, the most
+  /// common one), "lightest" or "darkest". Only colors with at least half the votes of the most
+  /// common one are considered.
+  pub background_luminance_bias: Option<String>,
+  /// How the background is sampled when it is auto-detected or estimated per pixel
+  pub background_detection: Option<BackgroundDetectionOptions>,
+  /// Whether to look for a green or blue screen when auto-detecting the background (default:
+  /// false). The key color is picked from the hue of the edges rather than their most common
+  /// color, which is more reliable on unevenly lit screens. Falls back to the usual detection
+  /// when the edges are not a chroma key.
+  pub chroma_key: Option<bool>,
   /// Whether to use strict mode. Restricts unmixing to only the specified foreground colors.
   pub strict_mode: bool,
-  /// The threshold for color closeness (0.0-1.0, default: 0.05)
+  /// The threshold for color closeness (0.0-1.0, default: 0.05), in `threshold_units`
   pub threshold: Option<f64>,
+  /// The units `threshold` is given in: "normalized" (default, a fraction of the full range) or
+  /// "8bit" (channel levels out of 255, so 12 is the same as 12 / 255). 8-bit units only apply
+  /// to the "rgb" threshold space.
+  pub threshold_units: Option<String>,
+  /// How the closeness threshold and color tolerances are measured: "rgb" (default, normalized
+  /// RGB distance) or "deltaE2000" (the perceptual CIEDE2000 difference divided by 100, so 0.05
+  /// is a difference of 5). RGB distance is lenient with dark colors and strict with light ones.
+  pub threshold_space: Option<String>,
+  /// How far (normalized RGB distance, default: 0.01) an opaque mix of foreground colors may
+  /// miss the observed color and still be preferred over a closer, translucent one. Raise it
+  /// for noisy or JPEG inputs; higher values trade color accuracy for cleaner, more opaque edges.
+  pub reconstruction_tolerance: Option<f64>,
+  /// The most foreground colors a single pixel is unmixed into (at least 1, default: all of
+  /// them). Every combination of that many colors is searched for the best mix, e.g. 3 for
+  /// flat designs whose colors meet at corners but never blend four at once. Depths that give
+  /// more than 256 combinations of the foreground colors are rejected.
+  pub unmix_search_depth: Option<u32>,
+  /// Pixels whose channels all differ from the background by less than this (0-255) are made
+  /// fully transparent in non-strict mode (default: exact matches only). Zeroes out slightly
+  /// noisy backgrounds without loosening `threshold`.
+  pub background_epsilon: Option<f64>,
+  /// How unmixed colors that fall outside the displayable range are brought back into it:
+  /// "clamp" (default) clamps each channel, "scale" desaturates the color towards its gray
+  /// until it fits, which keeps the hue of bright, saturated pixels.
+  pub gamut_mapping: Option<String>,
+  /// The color space the closeness test and unmixing happen in: "srgb" (the default for 8 and
+  /// 16-bit inputs) or "oklab", which estimates alpha much better on anti-aliased edges between
+  /// perceptually distant colors. The closeness threshold is then measured in OKLab.
+  pub unmix_space: Option<String>,
+  /// Whether to composite and unmix in linear light, converting back to sRGB for the output
+  /// (default: false, or true for floating point inputs). Blending in gamma-encoded sRGB leaves
+  /// dark fringes on soft edges; closeness is then measured between linear colors. Cannot be
+  /// combined with `unmix_space`.
+  pub linear_light: Option<bool>,
+  /// How the foreground was blended onto the background: "normal" (default, alpha-over),
+  /// "add" or "screen" for glows, lens flares and particles rendered additively, or "multiply"
+  /// for ink on paper (sketches, signatures, stamps), where alpha is the ink density. Additive
+  /// content is unmixed into colors that reproduce the input composited normally over black,
+  /// multiplied content into colors that reproduce it over white, or blended back with the
+  /// same mode. Cannot be combined with the "oklab" unmix space.
+  pub blend_mode: Option<String>,
   /// Whether to trim the output image to the bounding box of non-transparent pixels
   pub trim: bool,
+  /// Whether to record where the trimmed image sat in the original in a PNG `oFFs` chunk
+  /// (in pixels), so the placement travels with the file. Only has an effect with `trim`.
+  pub embed_crop_offset: Option<bool>,
+  /// The format of the output image: "png" (default), "indexed" (palette PNG with `tRNS`
+  /// transparency), "webp" (lossless, keeps alpha exact), "avif" (lossy, with alpha), "tiff",
+  /// "jpeg" (flattened over `jpeg_matte_color`), "apng" (animated PNG), "gif" (transparency on or
+  /// off per pixel) or "raw" (unencoded RGBA bytes, row by row). The crop offset is only embedded
+  /// in PNG output. Animated GIF, APNG and WebP inputs are processed frame by frame and written in
+  /// their own format (or as "apng", "webp" or "gif"), keeping frame delays and looping; other
+  /// formats only process the first frame. PNG output of grayscale inputs is written as grayscale
+  /// with alpha as long as every output pixel is gray.
+  pub output_format: Option<String>,
+  /// The bits per channel of the output image: 8 (default) or 16. 16-bit output keeps the full
+  /// precision of 16-bit inputs and is supported for "png", "tiff" and "raw" (little-endian
+  /// samples); it cannot be combined with `outline`.
+  pub output_bit_depth: Option<u32>,
+  /// How hard PNG output is compressed: "none", "fastest", "fast", "balanced" (default) or
+  /// "smallest". Faster levels make batch jobs much quicker at the cost of larger files.
+  pub png_compression: Option<String>,
+  /// The row filter of PNG output: "none", "sub", "up", "avg", "paeth" or "adaptive" (default)
+  pub png_filter: Option<String>,
+  /// Whether PNG output is written with Adam7 interlacing (default: false)
+  pub png_interlaced: Option<bool>,
+  /// The maximum number of palette entries of indexed output (1-256, default: 256), counting
+  /// the one shared by fully transparent pixels
+  pub palette_colors: Option<u32>,
+  /// Whether to apply Floyd-Steinberg dithering when quantizing indexed output (default: false)
+  pub palette_dither: Option<bool>,
+  /// Whether WebP output keeps every pixel exact (default: true). Lossy output compresses the
+  /// colors for smaller files, but always keeps alpha exact so edges stay clean.
+  pub webp_lossless: Option<bool>,
+  /// The quality of lossy WebP output, from 1 (smallest) to 100 (best, default: 75)
+  pub webp_quality: Option<u32>,
+  /// The quality of AVIF output, color and alpha alike, from 1 (smallest) to 100 (best,
+  /// default: 80)
+  pub avif_quality: Option<u32>,
+  /// The AVIF encoder speed, from 1 (slowest, smallest files) to 10 (fastest, default: 4)
+  pub avif_speed: Option<u32>,
+  /// The quality of JPEG output, from 1 (smallest) to 100 (best, default: 90)
+  pub jpeg_quality: Option<u32>,
+  /// The color JPEG output is flattened over, since JPEG has no transparency (default: "#ffffff")
+  pub jpeg_matte_color: Option<String>,
+  /// Whether to smooth the 8x8 block artifacts of JPEG inputs before removal. Keeps the
+  /// blocky ringing around the subject from turning into a ragged, semi-transparent fringe.
+  pub deblock_edges: Option<bool>,
+  /// Whether to white balance the input before removal (default: false): each channel is
+  /// scaled so the background detected at the edges becomes white, by at most 2x. Lets
+  /// off-white photographed backgrounds be removed without loosening the thresholds;
+  /// foreground colors are matched against the corrected image.
+  pub auto_white_balance: Option<bool>,
+  /// Whether to detect a uniform solid frame around the input (e.g. the white border of a
+  /// scanned photo) and cut it off before detecting the background and processing (default:
+  /// false). Frames otherwise dominate the edge samples. The output is the size of the
+  /// inside of the frame; `process_image_ex` reports the stripped margins.
+  pub strip_frame: Option<bool>,
+  /// Whether to estimate the background per pixel from the nearest edge samples instead of
+  /// using a single color. Follows vignetting and uneven lighting, but every pixel visits all
+  /// edge samples, which makes processing noticeably slower on large images.
+  /// Cannot be combined with `background_color` or another background model.
+  pub adaptive_background: Option<bool>,
+  /// Whether to fit a smooth (bilinear) gradient to the edge samples and remove it instead of
+  /// a single color. Suits studio sweeps and soft lighting falloff, at almost no extra cost.
+  /// Cannot be combined with `background_color` or another background model.
+  pub gradient_background: Option<bool>,
+  /// A shot of the empty scene (same camera, same lighting), in any form `input` accepts and
+  /// of the same size. Each pixel is unmixed against its counterpart in the plate, which
+  /// removes textured or uneven backdrops that no single color describes.
+  /// Cannot be combined with `background_color` or another background model.
+  #[napi(ts_type = "Uint8Array | ArrayBuffer | RawImage | string")]
+  pub background_plate: Option<ImageInput>,
+  /// Whether the background is a two-color checkerboard, like the transparency grid of image
+  /// editors in screenshots. The tile colors, size and alignment are found along the edges.
+  /// Cannot be combined with `background_color` or another background model.
+  pub checkerboard_background: Option<bool>,
+  /// Whether to estimate the background per tile of `background_tile_size` pixels, from the
+  /// pixels of each tile close to the detected background, and blend smoothly between tiles.
+  /// Follows vignetting and uneven lighting across the whole image, not just along the edges.
+  /// Cannot be combined with `background_color` or another background model.
+  pub tiled_background: Option<bool>,
+  /// The tile width and height of `tiled_background`, in pixels (default: 64)
+  pub background_tile_size: Option<u32>,
+  /// Whether to only remove background that is connected to the image border, found by flood
+  /// filling pixels within `threshold` of the background. Enclosed areas of the background
+  /// color (a white shirt on white, the eyes of a logo) are left untouched.
+  pub connected_removal: Option<bool>,
+  /// Whether to keep the holes the subject fully encloses opaque, restoring their original
+  /// pixels after removal (the counters of letters, the whites of eyes). Unlike
+  /// `connected_removal`, this looks at the result, so it also catches holes whose color
+  /// drifted from the background. Not supported by tiled processing.
+  pub protect_holes: Option<bool>,
+  /// Whether to keep soft shadows cast on the background as semi-transparent black (default:
+  /// false), such as the contact shadow under a product, instead of removing them or leaving
+  /// gray smudges. Pixels that are a darker version of the background within `threshold`
+  /// count as shadow.
+  pub preserve_shadows: Option<bool>,
+  /// How much each distinct color counts when deducing "auto" foreground colors:
+  /// "linear", "sqrt" (default), "log" or "uniform". Flatter schemes favor small, detailed
+  /// subjects.
+  pub deduction_weighting: Option<String>,
+  /// How many of the most frequent image colors to derive "auto" foreground candidates from
+  /// (default: 100). Raise it for images with many similar shades, lower it for speed.
+  pub deduction_sample_colors: Option<u32>,
+  /// Stop deducing "auto" colors after evaluating this many color combinations and use the best
+  /// found so far. Bounds latency at the cost of possibly less accurate colors.
+  pub deduction_max_evaluations: Option<u32>,
+  /// Stop deducing "auto" colors after this many milliseconds and use the best found so far
+  pub deduction_time_budget_ms: Option<u32>,
+  /// Whether to use the dominant non-background color in the image interior as the first
+  /// foreground color, before deducing any "auto" colors. Works well for single-subject images.
+  pub auto_primary_foreground: Option<bool>,
+  /// How alpha is chosen in non-strict mode without foreground colors: "min_alpha" (default,
+  /// the most transparent exact result) or "distance" (alpha grows with the distance from the
+  /// background, for a softer matte on photographic subjects)
+  pub alpha_model: Option<String>,
+  /// The distance from the background (0.0-1.7, normalized RGB) at which the "distance" alpha
+  /// model becomes fully opaque (default: 0.5)
+  pub alpha_opaque_distance: Option<f64>,
+  /// How to interpret the color channels of translucent input pixels: "straight" (default)
+  /// or "premultiplied". Use "premultiplied" for data exported from canvases to avoid dark edges.
+  pub input_alpha_mode: Option<String>,
+  /// How the alpha input pixels already have is treated: "composite" (default) composites
+  /// translucent pixels over the background and unmixes them again, "ignore" unmixes their color
+  /// as if opaque, and "min" or "multiply" combine the computed alpha with the original, so
+  /// transparent pixels stay transparent
+  pub existing_alpha: Option<String>,
+  /// Whether to rotate and flip the input as its EXIF orientation says (default: true), so phone
+  /// photos come out the way they are displayed
+  pub auto_orient: Option<bool>,
+  /// Whether to convert inputs with an embedded ICC profile (e.g. Display P3) to sRGB before
+  /// processing (default: true), so hex colors and thresholds mean the same for every input.
+  /// Inputs kept in their own color space have the given background and foreground colors
+  /// mapped from sRGB into it instead.
+  pub convert_to_srgb: Option<bool>,
+  /// How floating point inputs such as OpenEXR renders are brought into range: "clamp"
+  /// (default) clips highlights, "reinhard" compresses them. Their colors are unmixed in linear
+  /// light either way.
+  pub hdr_tone_mapping: Option<String>,
+  /// The width SVG inputs are rasterized at, in pixels. Without `svg_height` the aspect ratio
+  /// is kept; with it, the SVG is fitted inside both.
+  pub svg_width: Option<u32>,
+  /// The height SVG inputs are rasterized at, in pixels
+  pub svg_height: Option<u32>,
+  /// The resolution SVG inputs are rasterized at when no `svg_width` or `svg_height` is given
+  /// (default: 96, one pixel per CSS pixel)
+  pub svg_dpi: Option<f64>,
+  /// Whether to tag PNG output as sRGB with an `sRGB` chunk (default: false)
+  pub tag_srgb: Option<bool>,
+  /// Whether to carry the DPI (`pHYs`), gamma (`gAMA`) and text chunks of a PNG input over to
+  /// PNG output (default: true)
+  pub preserve_metadata: Option<bool>,
+  /// An outline to draw around the remaining content, sticker-style. The canvas grows by the
+  /// stroke width on every side so the outline is never clipped.
+  pub outline: Option<OutlineSpec>,
+}
+
+/// A color as stored in presets: a CSS color string, an RGB object or an `[r, g, b]` array
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ConfigColor {
+  Spec(String),
+  Rgb(RgbColor),
+  Channels(Vec<u8>),
+}
+
+impl From<ConfigColor> for ColorInput {
+  fn from(color: ConfigColor) -> Self {
+    match color {
+      ConfigColor::Spec(spec) => Either3::A(spec),
+      ConfigColor::Rgb(rgb) => Either3::B(rgb),
+      ConfigColor::Channels(channels) => Either3::C(channels),
+    }
+  }
+}
+
+/// One background color or several, as stored in presets
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BackgroundColors {
+  One(ConfigColor),
+  Many(Vec<ConfigColor>),
+}
+
+/// Processing options without the input image, so they can be stored and versioned as presets
+///
+/// Mirrors `ProcessImageOptions` field for field, except for the images (`input` and
+/// `background_plate`); missing fields take their default values.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ProcessConfig {
+  pub foreground_colors: Option<Vec<ConfigColor>>,
+  pub background_color: Option<BackgroundColors>,
+  pub background_luminance_bias: Option<String>,
+  pub background_detection: Option<BackgroundDetectionOptions>,
+  pub chroma_key: Option<bool>,
+  pub strict_mode: bool,
+  pub threshold: Option<f64>,
+  pub threshold_units: Option<String>,
+  pub threshold_space: Option<String>,
+  pub reconstruction_tolerance: Option<f64>,
+  pub unmix_search_depth: Option<u32>,
+  pub background_epsilon: Option<f64>,
+  pub gamut_mapping: Option<String>,
+  pub unmix_space: Option<String>,
+  pub linear_light: Option<bool>,
+  pub blend_mode: Option<String>,
+  pub trim: bool,
+  pub embed_crop_offset: Option<bool>,
+  pub output_format: Option<String>,
+  pub output_bit_depth: Option<u32>,
+  pub png_compression: Option<String>,
+  pub png_filter: Option<String>,
+  pub png_interlaced: Option<bool>,
+  pub palette_colors: Option<u32>,
+  pub palette_dither: Option<bool>,
+  pub webp_lossless: Option<bool>,
+  pub webp_quality: Option<u32>,
+  pub avif_quality: Option<u32>,
+  pub avif_speed: Option<u32>,
+  pub jpeg_quality: Option<u32>,
+  pub jpeg_matte_color: Option<String>,
+  pub deblock_edges: Option<bool>,
+  pub auto_white_balance: Option<bool>,
+  pub strip_frame: Option<bool>,
+  pub adaptive_background: Option<bool>,
+  pub gradient_background: Option<bool>,
+  pub checkerboard_background: Option<bool>,
+  pub tiled_background: Option<bool>,
+  pub background_tile_size: Option<u32>,
+  pub connected_removal: Option<bool>,
+  pub protect_holes: Option<bool>,
+  pub preserve_shadows: Option<bool>,
+  pub deduction_weighting: Option<String>,
+  pub deduction_sample_colors: Option<u32>,
+  pub deduction_max_evaluations: Option<u32>,
+  pub deduction_time_budget_ms: Option<u32>,
+  pub auto_primary_foreground: Option<bool>,
+  pub alpha_model: Option<String>,
+  pub alpha_opaque_distance: Option<f64>,
+  pub input_alpha_mode: Option<String>,
+  pub existing_alpha: Option<String>,
+  pub auto_orient: Option<bool>,
+  pub convert_to_srgb: Option<bool>,
+  pub hdr_tone_mapping: Option<String>,
+  pub svg_width: Option<u32>,
+  pub svg_height: Option<u32>,
+  pub svg_dpi: Option<f64>,
+  pub tag_srgb: Option<bool>,
+  pub preserve_metadata: Option<bool>,
+  pub outline: Option<OutlineSpec>,
+}
+
+impl ProcessImageOptions {
+  /// Build processing options from an input image and a stored preset
+  pub fn from_config(input: ImageBytes, config: ProcessConfig) -> Self {
+    Self {
+      input: Either3::A(input),
+      foreground_colors: config
+        .foreground_colors
+        .map(|colors| colors.into_iter().map(ColorInput::from).collect()),
+      background_color: config.background_color.map(|colors| match colors {
+        BackgroundColors::One(color) => Either::A(color.into()),
+        BackgroundColors::Many(colors) => {
+          Either::B(colors.into_iter().map(ColorInput::from).collect())
+        }
+      }),
+      background_luminance_bias: config.background_luminance_bias,
+      background_detection: config.background_detection,
+      chroma_key: config.chroma_key,
+      strict_mode: config.strict_mode,
+      threshold: config.threshold,
+      threshold_units: config.threshold_units,
+      threshold_space: config.threshold_space,
+      reconstruction_tolerance: config.reconstruction_tolerance,
+      unmix_search_depth: config.unmix_search_depth,
+      background_epsilon: config.background_epsilon,
+      gamut_mapping: config.gamut_mapping,
+      unmix_space: config.unmix_space,
+      linear_light: config.linear_light,
+      blend_mode: config.blend_mode,
+      trim: config.trim,
+      embed_crop_offset: config.embed_crop_offset,
+      output_format: config.output_format,
+      output_bit_depth: config.output_bit_depth,
+      png_compression: config.png_compression,
+      png_filter: config.png_filter,
+      png_interlaced: config.png_interlaced,
+      palette_colors: config.palette_colors,
+      palette_dither: config.palette_dither,
+      webp_lossless: config.webp_lossless,
+      webp_quality: config.webp_quality,
+      avif_quality: config.avif_quality,
+      avif_speed: config.avif_speed,
+      jpeg_quality: config.jpeg_quality,
+      jpeg_matte_color: config.jpeg_matte_color,
+      deblock_edges: config.deblock_edges,
+      auto_white_balance: config.auto_white_balance,
+      strip_frame: config.strip_frame,
+      adaptive_background: config.adaptive_background,
+      gradient_background: config.gradient_background,
+      background_plate: None,
+      checkerboard_background: config.checkerboard_background,
+      tiled_background: config.tiled_background,
+      background_tile_size: config.background_tile_size,
+      connected_removal: config.connected_removal,
+      protect_holes: config.protect_holes,
+      preserve_shadows: config.preserve_shadows,
+      deduction_weighting: config.deduction_weighting,
+      deduction_sample_colors: config.deduction_sample_colors,
+      deduction_max_evaluations: config.deduction_max_evaluations,
+      deduction_time_budget_ms: config.deduction_time_budget_ms,
+      auto_primary_foreground: config.auto_primary_foreground,
+      alpha_model: config.alpha_model,
+      alpha_opaque_distance: config.alpha_opaque_distance,
+      input_alpha_mode: config.input_alpha_mode,
+      existing_alpha: config.existing_alpha,
+      auto_orient: config.auto_orient,
+      convert_to_srgb: config.convert_to_srgb,
+      hdr_tone_mapping: config.hdr_tone_mapping,
+      svg_width: config.svg_width,
+      svg_height: config.svg_height,
+      svg_dpi: config.svg_dpi,
+      tag_srgb: config.tag_srgb,
+      preserve_metadata: config.preserve_metadata,
+      outline: config.outline,
+    }
+  }
+}
+
+#[napi(object)]
+pub struct ProcessedImage {
+  /// The processed image buffer, in `format`
+  pub data: Buffer,
+  /// The image width in pixels, after trimming and outlining
+  pub width: u32,
+  /// The image height in pixels, after trimming and outlining
+  pub height: u32,
+  /// The format of `data`: "png" (also for indexed PNG), "apng", "webp", "avif", "tiff",
+  /// "jpeg", "gif" or "raw"
+  pub format: String,
+  /// Whether `trim` cut off any transparent rows or columns
+  pub trimmed: bool,
+  /// The margins `strip_frame` cut off the input, if a frame was found
+  pub frame: Option<FrameMargins>,
+  /// Whether the input is a single solid color that was detected as the background, which
+  /// leaves the output fully transparent
+  pub solid_color: bool,
+  /// The share of edge samples matching the detected background color (0.0 to 1.0), if a
+  /// single background color was auto-detected; below 0.5 the detection is likely wrong
+  pub background_uniformity: Option<f64>,
+}
+
+#[napi(object)]
+pub struct FrameMargins {
+  /// The height of the top side of the frame in pixels
+  pub top: u32,
+  /// The width of the right side of the frame in pixels
+  pub right: u32,
+  /// The height of the bottom side of the frame in pixels
+  pub bottom: u32,
+  /// The width of the left side of the frame in pixels
+  pub left: u32,
+}
+
+#[napi(object)]
+pub struct ColorMatteResult {
+  /// The color channels as an opaque image (PNG unless `output_format` says otherwise), with
+  /// straight (unassociated) alpha
+  pub color: Buffer,
+  /// The alpha matte as a grayscale image, in the same format and bit depth as the color
+  pub matte: Buffer,
+}
+
+#[napi(object)]
+pub struct ImageWithMask {
+  /// The processed image buffer, encoded like the output of `process_image`
+  pub image: Buffer,
+  /// The alpha of the processed image as a grayscale PNG, at the output bit depth
+  pub mask: Buffer,
+}
+
+#[napi(object)]
+pub struct ProcessedTile {
+  /// The x coordinate of the tile's top-left corner in the unprocessed image
+  pub x: u32,
+  /// The y coordinate of the tile's top-left corner in the unprocessed image
+  pub y: u32,
+  /// The tile width in pixels
+  pub width: u32,
+  /// The tile height in pixels
+  pub height: u32,
+  /// The processed tile, encoded like `process_image` output (PNG unless `output_format` says
+  /// otherwise)
+  pub data: Buffer,
+}
+
+#[napi(object)]
+pub struct RawImage {
+  /// The unencoded RGBA bytes, row by row
+  pub data: Buffer,
+  /// The image width in pixels
+  pub width: u32,
+  /// The image height in pixels
+  pub height: u32,
 }
 
+/// An input image: encoded bytes, raw RGBA pixels or a base64 data URL
+pub type ImageInput = Either3<ImageBytes, RawImage, String>;
+
 #[napi(object)]
 pub struct UnmixResultJs {
   /// The weights for each foreground color
   pub weights: Vec<f64>,
   /// The alpha value
   pub alpha: f64,
+  /// How far (normalized RGB distance) the color reconstructed from the weights is from the
+  /// observed color; a large error means the foreground colors don't explain it
+  pub reconstruction_error: f64,
+  /// The indices of the foreground colors the least-squares solution (`optimize_opacity`
+  /// false) dropped because their difference from the background is linearly dependent on
+  /// earlier colors; their weights are 0
+  pub dropped_colors: Vec<u32>,
+}
+
+#[napi(object)]
+pub struct UnmixColorsResult {
+  /// The weights for each foreground color, `foregroundColors.length` per observed color
+  pub weights: Float64Array,
+  /// The alpha value of each observed color
+  pub alpha: Float64Array,
+  /// The reconstruction error of each observed color (normalized RGB distance)
+  pub reconstruction_error: Float64Array,
 }
 
 pub struct AsyncProcessImage {
@@ -97,333 +838,4399 @@ impl Task for AsyncProcessImage {
 /// * `options` - The options for the image processing
 ///
 /// # Returns
-/// A promise that resolves to the processed image buffer (PNG format)
+/// A promise that resolves to the processed image buffer (PNG unless `output_format` says
+/// otherwise)
 pub fn process_image(options: ProcessImageOptions) -> AsyncTask<AsyncProcessImage> {
   AsyncTask::new(AsyncProcessImage { options })
 }
 
+pub struct AsyncProcessImageEx {
+  options: ProcessImageOptions,
+}
+
 #[napi]
-/// Process an image synchronously to remove its background
+impl Task for AsyncProcessImageEx {
+  type Output = ProcessedImage;
+  type JsValue = ProcessedImage;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    let output = process_image_output(&self.options)?;
+    Ok(ProcessedImage {
+      data: output.data.into(),
+      width: output.width,
+      height: output.height,
+      format: output.format.to_string(),
+      trimmed: output.crop.trimmed,
+      frame: output.crop.frame.map(|frame| FrameMargins {
+        top: frame.top,
+        right: frame.right,
+        bottom: frame.bottom,
+        left: frame.left,
+      }),
+      solid_color: output.crop.solid_color,
+      background_uniformity: output.crop.background_uniformity,
+    })
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output)
+  }
+}
+
+#[napi]
+/// Process an image asynchronously and return the output along with its size and format
 ///
-/// Supports automatic background detection, foreground color deduction using "auto",
-/// and both strict and non-strict processing modes.
+/// Saves decoding the output just to learn its dimensions after `trim`.
 ///
 /// # Arguments
 /// * `options` - The options for the image processing
 ///
 /// # Returns
-/// The processed image buffer (PNG format)
-pub fn process_image_sync(options: ProcessImageOptions) -> Result<Buffer> {
-  let result = process_image_internal(&options)?;
-  Ok(result.into())
+/// A promise that resolves to the processed image buffer with its width, height and format
+pub fn process_image_ex(options: ProcessImageOptions) -> AsyncTask<AsyncProcessImageEx> {
+  AsyncTask::new(AsyncProcessImageEx { options })
+}
+
+pub struct AsyncProcessImageWithMask {
+  options: ProcessImageOptions,
 }
 
 #[napi]
-/// Detect the background color of an image by sampling its edges
-///
-/// # Arguments
-/// * `input` - The input image buffer
-///
-/// # Returns
-/// The detected background color
-pub fn detect_background_color(input: Buffer) -> Result<RgbColor> {
-  let img = image::load_from_memory(&input)
-    .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to load image: {}", e)))?;
-  let color = detect_bg(&img);
-  Ok(RgbColor {
-    r: color[0],
-    g: color[1],
-    b: color[2],
-  })
+impl Task for AsyncProcessImageWithMask {
+  type Output = ImageWithMask;
+  type JsValue = ImageWithMask;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    process_image_with_mask_internal(&self.options)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output)
+  }
 }
 
 #[napi]
-/// Parse a hex color string into an RGB color
+/// Process an image asynchronously and return the output together with its alpha mask
 ///
-/// Supports formats: "#ff0000", "ff0000", "#f00", "f00"
+/// Both come from a single pass over the pixels. Animated inputs are processed as their first
+/// frame.
 ///
 /// # Arguments
-/// * `hex` - The hex color string
+/// * `options` - The options for the image processing
 ///
 /// # Returns
-/// The parsed RGB color
-pub fn parse_color(hex: String) -> Result<RgbColor> {
-  let color = parse_hex_color(&hex)
-    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid hex color: {}", e)))?;
-  Ok(RgbColor {
-    r: color[0],
-    g: color[1],
-    b: color[2],
-  })
+/// A promise that resolves to the processed image and its mask as a grayscale PNG
+pub fn process_image_with_mask(
+  options: ProcessImageOptions,
+) -> AsyncTask<AsyncProcessImageWithMask> {
+  AsyncTask::new(AsyncProcessImageWithMask { options })
+}
+
+pub struct AsyncProcessImageRaw {
+  options: ProcessImageOptions,
 }
 
 #[napi]
-/// Convert an RGB color (0-255) to a normalized RGB color (0.0-1.0)
+impl Task for AsyncProcessImageRaw {
+  type Output = RgbaImage;
+  type JsValue = RawImage;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    let (final_img, _) = process_image_to_rgba(&self.options)?;
+    Ok(final_img)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    let (width, height) = output.dimensions();
+    Ok(RawImage {
+      data: output.into_raw().into(),
+      width,
+      height,
+    })
+  }
+}
+
+#[napi]
+/// Process an image asynchronously and return the unencoded RGBA result
+///
+/// Skips output encoding entirely, for piping the result straight into another image
+/// library. `output_format` and the encoder settings are ignored.
 ///
 /// # Arguments
-/// * `color` - The RGB color
+/// * `options` - The options for the image processing
 ///
 /// # Returns
-/// The normalized RGB color
-pub fn color_to_normalized(color: RgbColor) -> NormalizedRgbColor {
-  let normalized = normalize_color([color.r, color.g, color.b]);
-  NormalizedRgbColor {
-    r: normalized[0],
-    g: normalized[1],
-    b: normalized[2],
+/// A promise that resolves to the raw RGBA bytes with the image width and height
+pub fn process_image_raw(options: ProcessImageOptions) -> AsyncTask<AsyncProcessImageRaw> {
+  AsyncTask::new(AsyncProcessImageRaw { options })
+}
+
+pub struct AsyncProcessImageTiles {
+  options: ProcessImageOptions,
+  tile_size: u32,
+  full_image: bool,
+  on_tile: BoundedCallback<ProcessedTile>,
+}
+
+#[napi]
+impl Task for AsyncProcessImageTiles {
+  type Output = Option<Vec<u8>>;
+  type JsValue = Option<Buffer>;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    process_image_by_tiles(
+      &self.options,
+      self.tile_size,
+      self.full_image,
+      &self.on_tile,
+    )
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.map(Buffer::from))
   }
 }
 
 #[napi]
-/// Convert a normalized RGB color (0.0-1.0) to an RGB color (0-255)
+/// Process an image asynchronously, reporting each tile as soon as it is done
+///
+/// The image is split into square tiles that are processed in parallel. Every finished
+/// tile is encoded like `process_image` output and passed to `on_tile` with its position, in
+/// completion order rather than raster order, so large images can be rendered progressively.
+/// A few tiles are queued for a slow callback; beyond that, processing waits for it to catch
+/// up, so tiles never pile up in memory.
+///
+/// The whole image is only put together if `full_image` is set. Tiles cover the image before
+/// `outline` and `trim` are applied; those are reflected in the full image only.
 ///
 /// # Arguments
-/// * `color` - The normalized RGB color
+/// * `options` - The options for the image processing
+/// * `on_tile` - Called with each processed tile
+/// * `tile_size` - The tile width and height in pixels (default: 256)
+/// * `full_image` - Whether to also put the tiles together into the full image (default: false)
 ///
 /// # Returns
-/// The RGB color
-pub fn normalized_to_color(color: NormalizedRgbColor) -> RgbColor {
-  let denormalized = denormalize_color([color.r, color.g, color.b]);
-  RgbColor {
-    r: denormalized[0],
-    g: denormalized[1],
-    b: denormalized[2],
+/// A promise that resolves once all tiles are done, to the full processed image buffer (PNG
+/// unless `output_format` says otherwise) with `full_image`, or to null without it
+pub fn process_image_tiles(
+  options: ProcessImageOptions,
+  #[napi(ts_arg_type = "((err: Error | null, arg: ProcessedTile) => any)")]
+  on_tile: BoundedCallback<ProcessedTile>,
+  tile_size: Option<u32>,
+  full_image: Option<bool>,
+) -> Result<AsyncTask<AsyncProcessImageTiles>> {
+  let tile_size = tile_size.unwrap_or(DEFAULT_TILE_SIZE);
+  if tile_size == 0 {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Tile size must be at least 1".to_string(),
+    ));
   }
+
+  Ok(AsyncTask::new(AsyncProcessImageTiles {
+    options,
+    tile_size,
+    full_image: full_image.unwrap_or(false),
+    on_tile,
+  }))
 }
 
 #[napi]
-/// Trim the image to the bounding box of non-transparent pixels
+/// Process an image synchronously to remove its background
+///
+/// Supports automatic background detection, foreground color deduction using "auto",
+/// and both strict and non-strict processing modes.
 ///
 /// # Arguments
-/// * `input` - The input image buffer
+/// * `options` - The options for the image processing
 ///
 /// # Returns
-/// The trimmed image buffer (PNG format)
-pub fn trim_image(input: Buffer) -> Result<Buffer> {
-  let img = image::load_from_memory(&input)
-    .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to load image: {}", e)))?;
-  let rgba = img.to_rgba8();
-  let trimmed = trim_to_content(&rgba);
+/// The processed image buffer (PNG unless `output_format` says otherwise)
+pub fn process_image_sync(options: ProcessImageOptions) -> Result<Buffer> {
+  let result = process_image_internal(&options)?;
+  Ok(result.into())
+}
 
-  let mut buffer = Cursor::new(Vec::new());
-  trimmed
-    .write_to(&mut buffer, image::ImageFormat::Png)
-    .map_err(|e| {
-      Error::new(
-        Status::GenericFailure,
-        format!("Failed to write output image: {}", e),
-      )
-    })?;
+pub struct AsyncProcessImageStream {
+  options: ProcessImageOptions,
+  on_chunk: BoundedCallback<Buffer>,
+}
+
+#[napi]
+impl Task for AsyncProcessImageStream {
+  type Output = ();
+  type JsValue = ();
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    process_image_to_stream(&self.options, &self.on_chunk)
+  }
 
-  Ok(buffer.into_inner().into())
+  fn resolve(&mut self, _env: Env, _output: Self::Output) -> Result<Self::JsValue> {
+    Ok(())
+  }
 }
 
 #[napi]
-/// Unmix an observed color into foreground color components
+/// Process an image asynchronously, passing the PNG output to `on_chunk` as it is encoded
 ///
-/// Given an observed color and known foreground/background colors,
-/// determines how much of each foreground color contributed to the observed color.
+/// The rows are filtered and compressed one at a time, so the encoded image is never held in
+/// memory as a whole; the chunks arrive in order and can be pushed into a Node stream. Only
+/// PNG output (including "indexed" and 16-bit) can be streamed, and animated inputs are
+/// written as their first frame. As with tiles, encoding waits for a slow callback once a few
+/// chunks are queued for it.
+///
+/// After the last chunk, `on_chunk` is called once more with an empty buffer, which marks the
+/// end of the output. The promise can resolve before the queued chunks have all reached
+/// `on_chunk`, so end a stream on the empty chunk rather than on the promise.
 ///
 /// # Arguments
-/// * `observed` - The observed color
-/// * `foreground_colors` - The foreground colors to match
-/// * `background` - The background color
+/// * `options` - The options for the image processing
+/// * `on_chunk` - Called with each chunk of the encoded PNG, then with an empty buffer
 ///
 /// # Returns
-/// The unmix result containing weights for each foreground color and overall alpha
-pub fn unmix_color(
-  observed: RgbColor,
-  foreground_colors: Vec<RgbColor>,
-  background: RgbColor,
-) -> UnmixResultJs {
-  let fg_normalized: Vec<NormalizedColor> = foreground_colors
-    .iter()
-    .map(|c| normalize_color([c.r, c.g, c.b]))
-    .collect();
-  let bg_normalized = normalize_color([background.r, background.g, background.b]);
+/// A promise that resolves once encoding is done, or rejects if it fails
+pub fn process_image_stream(
+  options: ProcessImageOptions,
+  #[napi(ts_arg_type = "((err: Error | null, arg: Buffer) => any)")] on_chunk: BoundedCallback<
+    Buffer,
+  >,
+) -> AsyncTask<AsyncProcessImageStream> {
+  AsyncTask::new(AsyncProcessImageStream { options, on_chunk })
+}
 
-  let result = unmix_colors(
-    [observed.r, observed.g, observed.b],
-    &fg_normalized,
-    bg_normalized,
-  );
+pub struct AsyncProcessImageDataUrl {
+  options: ProcessImageOptions,
+}
 
-  UnmixResultJs {
-    weights: result.weights,
-    alpha: result.alpha,
+#[napi]
+impl Task for AsyncProcessImageDataUrl {
+  type Output = String;
+  type JsValue = String;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    process_image_to_data_url_internal(&self.options)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output)
   }
 }
 
 #[napi]
-/// Compute the final color from unmix result
+/// Process an image asynchronously and return the result as a base64 data URL
+///
+/// The MIME type follows the encoded output, e.g. `data:image/webp;base64,...` for WebP output.
+/// Raw output has no image format and is rejected.
 ///
 /// # Arguments
-/// * `weights` - The weights for each foreground color
-/// * `alpha` - The alpha value
-/// * `foreground_colors` - The foreground colors
+/// * `options` - The options for the image processing
 ///
 /// # Returns
-/// The computed RGBA color
-pub fn compute_unmix_result_color(
-  weights: Vec<f64>,
-  alpha: f64,
-  foreground_colors: Vec<RgbColor>,
-) -> RgbaColor {
-  let fg_normalized: Vec<NormalizedColor> = foreground_colors
-    .iter()
-    .map(|c| normalize_color([c.r, c.g, c.b]))
+/// A promise that resolves to the processed image as a `data:` URL
+pub fn process_image_to_data_url(
+  options: ProcessImageOptions,
+) -> AsyncTask<AsyncProcessImageDataUrl> {
+  AsyncTask::new(AsyncProcessImageDataUrl { options })
+}
+
+#[napi]
+/// Process an image synchronously and return the result as a base64 data URL
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// The processed image as a `data:` URL
+pub fn process_image_to_data_url_sync(options: ProcessImageOptions) -> Result<String> {
+  process_image_to_data_url_internal(&options)
+}
+
+#[napi]
+/// Process an image asynchronously using options stored as a JSON preset
+///
+/// The preset uses the same fields as `ProcessImageOptions` (camelCase, without `input`);
+/// omitted fields take their default values and unknown fields are rejected.
+///
+/// # Arguments
+/// * `input` - The input image buffer
+/// * `config_json` - The processing options as a JSON string
+///
+/// # Returns
+/// A promise that resolves to the processed image buffer (PNG unless `output_format` says
+/// otherwise)
+pub fn process_image_from_config(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer")] input: ImageBytes,
+  config_json: String,
+) -> Result<AsyncTask<AsyncProcessImage>> {
+  let config: ProcessConfig = serde_json::from_str(&config_json)
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid config: {}", e)))?;
+  Ok(AsyncTask::new(AsyncProcessImage {
+    options: ProcessImageOptions::from_config(input, config),
+  }))
+}
+
+pub struct AsyncSplitColorAndMatte {
+  options: ProcessImageOptions,
+}
+
+#[napi]
+impl Task for AsyncSplitColorAndMatte {
+  type Output = ColorMatteResult;
+  type JsValue = ColorMatteResult;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    split_color_and_matte_internal(&self.options)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output)
+  }
+}
+
+#[napi]
+/// Process an image asynchronously and return its color and alpha matte as separate images
+///
+/// Both images come from a single processing pass, so they are pixel-aligned and
+/// cropped identically when `trim` is enabled. The color is straight (not premultiplied):
+/// recombining the color channels with the matte as alpha reproduces the `processImage` output.
+/// Fully transparent pixels have black color. Both are encoded in the output format at the
+/// output bit depth; PNG and TIFF mattes have a single gray channel and raw mattes one sample
+/// per pixel.
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// A promise that resolves to the opaque color image and the grayscale matte (PNG unless
+/// `output_format` says otherwise)
+pub fn split_color_and_matte(options: ProcessImageOptions) -> AsyncTask<AsyncSplitColorAndMatte> {
+  AsyncTask::new(AsyncSplitColorAndMatte { options })
+}
+
+#[napi]
+/// Process an image synchronously and return its color and alpha matte as separate images
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// The opaque color image and the grayscale matte (PNG unless `output_format` says otherwise)
+pub fn split_color_and_matte_sync(options: ProcessImageOptions) -> Result<ColorMatteResult> {
+  split_color_and_matte_internal(&options)
+}
+
+pub struct AsyncFitSubject {
+  options: ProcessImageOptions,
+  target_width: u32,
+  target_height: u32,
+  margin_ratio: Option<f64>,
+}
+
+#[napi]
+impl Task for AsyncFitSubject {
+  type Output = Vec<u8>;
+  type JsValue = Buffer;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    fit_subject_internal(
+      &self.options,
+      self.target_width,
+      self.target_height,
+      self.margin_ratio,
+    )
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.into())
+  }
+}
+
+#[napi]
+/// Remove the background asynchronously and center the subject on a transparent canvas of a
+/// fixed size
+///
+/// The processed image is trimmed to its content, scaled up or down to fit inside the
+/// target size minus the margin, and centered. Useful for normalizing product photos
+/// to uniform thumbnails.
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+/// * `target_width` - The output width in pixels
+/// * `target_height` - The output height in pixels
+/// * `margin_ratio` - The margin on every side as a fraction of the output size
+///   (0.0-0.5, default: 0.0)
+///
+/// # Returns
+/// A promise that resolves to the output image buffer of exactly the target size (PNG unless
+/// `output_format` says otherwise)
+pub fn fit_subject(
+  options: ProcessImageOptions,
+  target_width: u32,
+  target_height: u32,
+  margin_ratio: Option<f64>,
+) -> AsyncTask<AsyncFitSubject> {
+  AsyncTask::new(AsyncFitSubject {
+    options,
+    target_width,
+    target_height,
+    margin_ratio,
+  })
+}
+
+#[napi]
+/// Remove the background synchronously and center the subject on a transparent canvas of a
+/// fixed size
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+/// * `target_width` - The output width in pixels
+/// * `target_height` - The output height in pixels
+/// * `margin_ratio` - The margin on every side as a fraction of the output size
+///   (0.0-0.5, default: 0.0)
+///
+/// # Returns
+/// The output image buffer of exactly the target size (PNG unless `output_format` says otherwise)
+pub fn fit_subject_sync(
+  options: ProcessImageOptions,
+  target_width: u32,
+  target_height: u32,
+  margin_ratio: Option<f64>,
+) -> Result<Buffer> {
+  Ok(fit_subject_internal(&options, target_width, target_height, margin_ratio)?.into())
+}
+
+pub struct AsyncExportFavicon {
+  options: ProcessImageOptions,
+  sizes: Option<Vec<u32>>,
+}
+
+#[napi]
+impl Task for AsyncExportFavicon {
+  type Output = Vec<u8>;
+  type JsValue = Buffer;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    export_favicon_internal(&self.options, self.sizes.as_deref())
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.into())
+  }
+}
+
+#[napi]
+/// Remove the background asynchronously and bundle the subject at several icon sizes into
+/// one ICO file
+///
+/// The processed image is trimmed to its content, then scaled to fit and centered on a
+/// transparent square canvas for each size. Each icon is stored as PNG inside the ICO, so
+/// `output_format` doesn't apply.
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+/// * `sizes` - The icon widths and heights in pixels, 1-256 each (default: 16, 32, 48, 256)
+///
+/// # Returns
+/// A promise that resolves to the icon file buffer (ICO format), with its icons ordered from
+/// smallest to largest
+pub fn export_favicon(
+  options: ProcessImageOptions,
+  sizes: Option<Vec<u32>>,
+) -> AsyncTask<AsyncExportFavicon> {
+  AsyncTask::new(AsyncExportFavicon { options, sizes })
+}
+
+#[napi]
+/// Remove the background synchronously and bundle the subject at several icon sizes into one
+/// ICO file
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+/// * `sizes` - The icon widths and heights in pixels, 1-256 each (default: 16, 32, 48, 256)
+///
+/// # Returns
+/// The icon file buffer (ICO format), with its icons ordered from smallest to largest
+pub fn export_favicon_sync(
+  options: ProcessImageOptions,
+  sizes: Option<Vec<u32>>,
+) -> Result<Buffer> {
+  Ok(export_favicon_internal(&options, sizes.as_deref())?.into())
+}
+
+pub struct AsyncProcessWithReference {
+  subject: ImageInput,
+  background_reference: ImageInput,
+  options: Option<ReferenceOptions>,
+}
+
+#[napi]
+impl Task for AsyncProcessWithReference {
+  type Output = Vec<u8>;
+  type JsValue = Buffer;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    process_with_reference_internal(
+      &self.subject,
+      &self.background_reference,
+      self.options.as_ref(),
+    )
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.into())
+  }
+}
+
+#[napi]
+/// Remove the background asynchronously by comparing an image with a shot of the empty
+/// background
+///
+/// For controlled captures (fixed camera, same lighting), each pixel is compared with the
+/// same pixel of the reference: alpha grows with the difference and the subject color is
+/// recovered by inverting the alpha blend. This gives a much cleaner matte than color-based
+/// removal, even when the subject shares colors with the background.
+///
+/// # Arguments
+/// * `subject` - The image with the subject, as a buffer, a base64 `data:` URL, or raw RGBA pixels
+/// * `background_reference` - The image of the background alone, of the same size and in any of the
+///   same forms
+/// * `options` - The threshold, opaque distance, trimming and output format options
+///
+/// # Returns
+/// A promise that resolves to the output image buffer (PNG unless `output_format` says
+/// otherwise)
+pub fn process_with_reference(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] subject: ImageInput,
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")]
+  background_reference: ImageInput,
+  options: Option<ReferenceOptions>,
+) -> AsyncTask<AsyncProcessWithReference> {
+  AsyncTask::new(AsyncProcessWithReference {
+    subject,
+    background_reference,
+    options,
+  })
+}
+
+#[napi]
+/// Remove the background synchronously by comparing an image with a shot of the empty
+/// background
+///
+/// # Arguments
+/// * `subject` - The image with the subject, as a buffer, a base64 `data:` URL, or raw RGBA pixels
+/// * `background_reference` - The image of the background alone, of the same size and in any of the
+///   same forms
+/// * `options` - The threshold, opaque distance, trimming and output format options
+///
+/// # Returns
+/// The output image buffer (PNG unless `output_format` says otherwise)
+pub fn process_with_reference_sync(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] subject: ImageInput,
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")]
+  background_reference: ImageInput,
+  options: Option<ReferenceOptions>,
+) -> Result<Buffer> {
+  Ok(process_with_reference_internal(&subject, &background_reference, options.as_ref())?.into())
+}
+
+pub struct AsyncSubjectAverageColor {
+  options: ProcessImageOptions,
+}
+
+#[napi]
+impl Task for AsyncSubjectAverageColor {
+  type Output = Option<RgbColor>;
+  type JsValue = Option<RgbColor>;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    subject_average_color_internal(&self.options)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output)
+  }
+}
+
+#[napi]
+/// Compute the average color of the subject asynchronously after background removal
+///
+/// Each output pixel's color is weighted by its alpha, so the removed background doesn't
+/// contribute and edge pixels count only as much as they are opaque. Useful as a
+/// representative swatch for thumbnails, tags, or UI theming.
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// A promise that resolves to the alpha-weighted average color, or null if nothing of the
+/// image is left
+pub fn subject_average_color(options: ProcessImageOptions) -> AsyncTask<AsyncSubjectAverageColor> {
+  AsyncTask::new(AsyncSubjectAverageColor { options })
+}
+
+#[napi]
+/// Compute the average color of the subject synchronously after background removal
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// The alpha-weighted average color, or null if nothing of the image is left
+pub fn subject_average_color_sync(options: ProcessImageOptions) -> Result<Option<RgbColor>> {
+  subject_average_color_internal(&options)
+}
+
+pub struct AsyncProcessImageLayers {
+  options: ProcessImageOptions,
+}
+
+#[napi]
+impl Task for AsyncProcessImageLayers {
+  type Output = Vec<Vec<u8>>;
+  type JsValue = Vec<Buffer>;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    process_image_layers_internal(&self.options)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.into_iter().map(Buffer::from).collect())
+  }
+}
+
+#[napi]
+/// Split an image asynchronously into one transparent layer per foreground color
+///
+/// Each layer is filled with its foreground color and uses that color's unmix weight
+/// as alpha, so a pixel's contributions are spread across the layers. Adding up the
+/// premultiplied layers (color times alpha) plus the background times one minus their
+/// total alpha reconstructs the input. Requires at least one foreground color (or "auto").
+/// With `trim` enabled all layers are cropped to the same bounding box; `outline` is ignored.
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// A promise that resolves to one image buffer (PNG unless `output_format` says otherwise)
+/// per foreground color, in the order they were specified
+pub fn process_image_layers(options: ProcessImageOptions) -> AsyncTask<AsyncProcessImageLayers> {
+  AsyncTask::new(AsyncProcessImageLayers { options })
+}
+
+#[napi]
+/// Split an image synchronously into one transparent layer per foreground color
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// One image buffer (PNG unless `output_format` says otherwise) per foreground color, in the
+/// order they were specified
+pub fn process_image_layers_sync(options: ProcessImageOptions) -> Result<Vec<Buffer>> {
+  Ok(
+    process_image_layers_internal(&options)?
+      .into_iter()
+      .map(Buffer::from)
+      .collect(),
+  )
+}
+
+pub struct AsyncRecolor {
+  options: ProcessImageOptions,
+  replacement_colors: Vec<String>,
+}
+
+#[napi]
+impl Task for AsyncRecolor {
+  type Output = Vec<u8>;
+  type JsValue = Buffer;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    recolor_internal(&self.options, &self.replacement_colors)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.into())
+  }
+}
+
+#[napi]
+/// Recolor an image asynchronously by unmixing it against its foreground colors and remixing
+/// the weights with replacement colors
+///
+/// Every pixel keeps the share each foreground color has in it, so anti-aliased edges and
+/// translucency carry over, but each color is swapped for its replacement (for example a red
+/// and white logo turned blue and white). The background is removed as with
+/// `processImageLayers`. Requires at least one foreground color (or "auto").
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+/// * `replacement_colors` - One CSS color per foreground color, in the same order
+///
+/// # Returns
+/// A promise that resolves to the recolored image buffer (PNG unless `output_format` says
+/// otherwise)
+pub fn recolor(
+  options: ProcessImageOptions,
+  replacement_colors: Vec<String>,
+) -> AsyncTask<AsyncRecolor> {
+  AsyncTask::new(AsyncRecolor {
+    options,
+    replacement_colors,
+  })
+}
+
+#[napi]
+/// Recolor an image synchronously by unmixing it against its foreground colors and remixing
+/// the weights with replacement colors
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+/// * `replacement_colors` - One CSS color per foreground color, in the same order
+///
+/// # Returns
+/// The recolored image buffer (PNG unless `output_format` says otherwise)
+pub fn recolor_sync(
+  options: ProcessImageOptions,
+  replacement_colors: Vec<String>,
+) -> Result<Buffer> {
+  Ok(recolor_internal(&options, &replacement_colors)?.into())
+}
+
+pub struct AsyncSeparateLayers {
+  options: ProcessImageOptions,
+}
+
+#[napi]
+impl Task for AsyncSeparateLayers {
+  type Output = Vec<Vec<u8>>;
+  type JsValue = Vec<Buffer>;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    separate_layers_internal(&self.options)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.into_iter().map(Buffer::from).collect())
+  }
+}
+
+#[napi]
+/// Separate an image asynchronously into one solid color layer per foreground color, stacked
+/// in order
+///
+/// Unlike `processImageLayers`, whose layers add up to the input, these layers reproduce it
+/// when composited normally over the background in the order the foreground colors were
+/// specified (first at the bottom), as in an image editor. Each layer is filled with its
+/// foreground color. Requires at least one foreground color (or "auto"). With `trim` enabled
+/// all layers are cropped to the same bounding box; `outline` is ignored.
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// A promise that resolves to one image buffer (PNG unless `output_format` says otherwise)
+/// per foreground color, from the bottom layer to the top one
+pub fn separate_layers(options: ProcessImageOptions) -> AsyncTask<AsyncSeparateLayers> {
+  AsyncTask::new(AsyncSeparateLayers { options })
+}
+
+#[napi]
+/// Separate an image synchronously into one solid color layer per foreground color, stacked
+/// in order
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// One image buffer (PNG unless `output_format` says otherwise) per foreground color, from the
+/// bottom layer to the top one
+pub fn separate_layers_sync(options: ProcessImageOptions) -> Result<Vec<Buffer>> {
+  Ok(
+    separate_layers_internal(&options)?
+      .into_iter()
+      .map(Buffer::from)
+      .collect(),
+  )
+}
+
+pub struct AsyncProcessImageWeightMaps {
+  options: ProcessImageOptions,
+}
+
+#[napi]
+impl Task for AsyncProcessImageWeightMaps {
+  type Output = Vec<Vec<u8>>;
+  type JsValue = Vec<Buffer>;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    process_image_weight_maps_internal(&self.options)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.into_iter().map(Buffer::from).collect())
+  }
+}
+
+#[napi]
+/// Compute one grayscale weight map per foreground color asynchronously
+///
+/// Each map holds that color's unmix weight at every pixel (0 = none, 255 = all of it),
+/// the same weights `processImageLayers` uses as alpha, for recoloring or compositing
+/// downstream. Requires at least one foreground color (or "auto"). With `trim` enabled all
+/// maps are cropped to the same bounding box; `outline` is ignored.
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// A promise that resolves to one grayscale image buffer (PNG unless `output_format` says
+/// otherwise) per foreground color, in the order they were specified
+pub fn process_image_weight_maps(
+  options: ProcessImageOptions,
+) -> AsyncTask<AsyncProcessImageWeightMaps> {
+  AsyncTask::new(AsyncProcessImageWeightMaps { options })
+}
+
+#[napi]
+/// Compute one grayscale weight map per foreground color synchronously
+///
+/// # Arguments
+/// * `options` - The options for the image processing
+///
+/// # Returns
+/// One grayscale image buffer (PNG unless `output_format` says otherwise) per foreground
+/// color, in the order they were specified
+pub fn process_image_weight_maps_sync(options: ProcessImageOptions) -> Result<Vec<Buffer>> {
+  Ok(
+    process_image_weight_maps_internal(&options)?
+      .into_iter()
+      .map(Buffer::from)
+      .collect(),
+  )
+}
+
+#[napi]
+/// Detect the background color of an image by sampling its edges
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `luminance_bias` - Which common edge color to prefer: "none" (default), "lightest" or
+///   "darkest"
+/// * `detection` - How the edges are sampled
+///
+/// # Returns
+/// The detected background color
+pub fn detect_background_color(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  luminance_bias: Option<String>,
+  detection: Option<BackgroundDetectionOptions>,
+) -> Result<RgbColor> {
+  let img = load_input(&input, DecodeOptions::default())?;
+  let color = detect_background_color_with_config(
+    &img,
+    &background_detection_config(
+      luminance_bias.as_deref(),
+      detection.as_ref(),
+      input_has_transparent_palette_index(&input),
+    )?,
+  );
+  Ok(RgbColor {
+    r: color[0],
+    g: color[1],
+    b: color[2],
+  })
+}
+
+#[napi]
+/// Detect the background color of an image, including its alpha, by sampling its edges
+///
+/// Votes on the full RGBA values of the edge samples instead of compositing translucent
+/// pixels over black, so a semi-transparent background is reported with its real alpha.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `luminance_bias` - Which common edge color to prefer: "none" (default), "lightest" or
+///   "darkest"
+/// * `detection` - How the edges are sampled
+///
+/// # Returns
+/// The detected background color and alpha
+pub fn detect_background_color_rgba(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  luminance_bias: Option<String>,
+  detection: Option<BackgroundDetectionOptions>,
+) -> Result<RgbaColor> {
+  let img = load_input(&input, DecodeOptions::default())?;
+  let Rgba([r, g, b, a]) = detect_background_rgba_with_config(
+    &img,
+    &background_detection_config(
+      luminance_bias.as_deref(),
+      detection.as_ref(),
+      input_has_transparent_palette_index(&input),
+    )?,
+  );
+
+  Ok(RgbaColor { r, g, b, a })
+}
+
+#[napi]
+/// List the most common edge colors of an image as background candidates
+///
+/// Useful when the edges aren't uniform, to let users pick the background among the
+/// candidates instead of relying on the single most common color.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `count` - How many candidates to return at most (default: 5)
+/// * `detection` - How the edges are sampled
+///
+/// # Returns
+/// The candidate colors, most common first, with their sample counts and share of the samples
+pub fn detect_background_colors(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  count: Option<u32>,
+  detection: Option<BackgroundDetectionOptions>,
+) -> Result<Vec<BackgroundCandidate>> {
+  let img = load_input(&input, DecodeOptions::default())?;
+  let config = background_detection_config(
+    None,
+    detection.as_ref(),
+    input_has_transparent_palette_index(&input),
+  )?;
+  let (ranked, total) = rank_background_colors(
+    &img,
+    &config,
+    count.unwrap_or(DEFAULT_BACKGROUND_CANDIDATES) as usize,
+  );
+
+  Ok(
+    ranked
+      .into_iter()
+      .map(|([r, g, b], samples)| BackgroundCandidate {
+        color: RgbColor { r, g, b },
+        samples,
+        confidence: samples as f64 / total as f64,
+      })
+      .collect(),
+  )
+}
+
+#[napi]
+/// Detect the background color of an image, along with the samples the choice was based on
+///
+/// For tooling that shows why a background was picked, e.g. by drawing the sampled pixels
+/// over the image and charting the histogram.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `luminance_bias` - Which common edge color to prefer: "none" (default), "lightest" or
+///   "darkest"
+/// * `detection` - How the edges are sampled
+///
+/// # Returns
+/// The detected background color, every edge sample and the histogram of sampled colors
+pub fn detect_background_color_debug(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  luminance_bias: Option<String>,
+  detection: Option<BackgroundDetectionOptions>,
+) -> Result<BackgroundDetectionDebug> {
+  let img = load_input(&input, DecodeOptions::default())?;
+  let config = background_detection_config(
+    luminance_bias.as_deref(),
+    detection.as_ref(),
+    input_has_transparent_palette_index(&input),
+  )?;
+  let [r, g, b] = detect_background_color_with_config(&img, &config);
+  let samples = sample_edge_colors(&img.to_rgba8(), &config);
+  let (histogram, total) = rank_background_colors(&img, &config, samples.len());
+
+  Ok(BackgroundDetectionDebug {
+    color: RgbColor { r, g, b },
+    samples: samples
+      .into_iter()
+      .map(|(x, y, [r, g, b])| EdgeSample {
+        x,
+        y,
+        color: RgbColor { r, g, b },
+      })
+      .collect(),
+    histogram: histogram
+      .into_iter()
+      .map(|([r, g, b], samples)| BackgroundCandidate {
+        color: RgbColor { r, g, b },
+        samples,
+        confidence: samples as f64 / total as f64,
+      })
+      .collect(),
+  })
+}
+
+#[napi]
+/// Detect the background color of an image and measure how uniformly it lines the edges
+///
+/// Use this to flag images where auto-detection is likely wrong (busy or mixed edges)
+/// before processing them, instead of silently getting a poor result.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `luminance_bias` - Which common edge color to prefer: "none" (default), "lightest" or
+///   "darkest"
+/// * `detection` - How the edges are sampled
+/// * `threshold` - The normalized RGB distance within which an edge sample matches (default: 0.05)
+///
+/// # Returns
+/// The detected background color with the share and number of matching edge samples
+pub fn detect_background_uniformity(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  luminance_bias: Option<String>,
+  detection: Option<BackgroundDetectionOptions>,
+  threshold: Option<f64>,
+) -> Result<BackgroundUniformity> {
+  let img = load_input(&input, DecodeOptions::default())?;
+  let config = background_detection_config(
+    luminance_bias.as_deref(),
+    detection.as_ref(),
+    input_has_transparent_palette_index(&input),
+  )?;
+  let color = detect_background_color_with_config(&img, &config);
+  let (matching_samples, samples) = background_uniformity(
+    &img,
+    &config,
+    color,
+    threshold.unwrap_or(DEFAULT_COLOR_CLOSENESS_THRESHOLD),
+  );
+
+  Ok(BackgroundUniformity {
+    color: RgbColor {
+      r: color[0],
+      g: color[1],
+      b: color[2],
+    },
+    uniformity: if samples == 0 {
+      0.0
+    } else {
+      matching_samples as f64 / samples as f64
+    },
+    matching_samples,
+    samples,
+  })
+}
+
+#[napi]
+/// Detect whether the background of an image is a green or blue screen, and its key color
+///
+/// The key color is picked from the hue of the edge samples rather than their most common
+/// color, since lit screens are rarely uniform. When the edges are not mostly a saturated
+/// green or blue, the usual detected background color is returned instead.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `detection` - How the edges are sampled
+///
+/// # Returns
+/// The key or background color, flagged as a chroma key or not
+pub fn detect_chroma_key(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  detection: Option<BackgroundDetectionOptions>,
+) -> Result<ChromaKeyDetection> {
+  let img = load_input(&input, DecodeOptions::default())?;
+  let config = background_detection_config(
+    None,
+    detection.as_ref(),
+    input_has_transparent_palette_index(&input),
+  )?;
+
+  Ok(match detect_background_chroma_key(&img, &config) {
+    Some(key) => ChromaKeyDetection {
+      color: RgbColor {
+        r: key.color[0],
+        g: key.color[1],
+        b: key.color[2],
+      },
+      chroma_key: true,
+      hue: Some(key.hue),
+      coverage: Some(key.coverage),
+    },
+    None => {
+      let [r, g, b] = detect_background_color_with_config(&img, &config);
+      ChromaKeyDetection {
+        color: RgbColor { r, g, b },
+        chroma_key: false,
+        hue: None,
+        coverage: None,
+      }
+    }
+  })
+}
+
+#[napi]
+/// Score how suitable an image is for automatic background removal
+///
+/// A fast pre-check that detects the background and measures how consistently it lines
+/// the edges and how much of the image it covers from the edges inwards, without
+/// processing the pixels. Images with a clean, uniform background score close to 1.0;
+/// route low-scoring images to manual handling.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+///
+/// # Returns
+/// The suitability score (0.0-1.0)
+pub fn assess_removability(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+) -> Result<f64> {
+  let img = load_input(&input, DecodeOptions::default())?;
+  let config =
+    background_detection_config(None, None, input_has_transparent_palette_index(&input))?;
+
+  Ok(assess_background_removability(
+    &img,
+    &config,
+    DEFAULT_COLOR_CLOSENESS_THRESHOLD,
+  ))
+}
+
+#[napi]
+/// Check whether an image consists of a single solid color
+///
+/// Processing such an image with an auto-detected background makes it fully transparent,
+/// since its only color is the background. Use this to catch blank placeholders up front.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+///
+/// # Returns
+/// The image's only color, or null if it has more than one
+pub fn detect_solid_color(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+) -> Result<Option<RgbaColor>> {
+  let img = load_input(&input, DecodeOptions::default())?;
+
+  Ok(detect_solid(&img.to_rgba8()).map(|Rgba([r, g, b, a])| RgbaColor { r, g, b, a }))
+}
+
+#[napi]
+/// Parse a CSS color string into an RGB color
+///
+/// Supports formats: "#ff0000", "ff0000", "#f00", "f00", "rgb(255, 0, 0)", "rgba(255 0 0 / 1)",
+/// "hsl(0, 100%, 50%)", "hsla(0deg 100% 50% / 1)" and named colors such as "red"
+///
+/// # Arguments
+/// * `color` - The color string
+///
+/// # Returns
+/// The parsed RGB color
+pub fn parse_color(color: String) -> Result<RgbColor> {
+  let color = parse_css_color(&color)
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid color: {}", e)))?;
+  Ok(RgbColor {
+    r: color[0],
+    g: color[1],
+    b: color[2],
+  })
+}
+
+#[napi]
+/// Convert an RGB color (0-255) to a normalized RGB color (0.0-1.0)
+///
+/// # Arguments
+/// * `color` - The RGB color
+///
+/// # Returns
+/// The normalized RGB color
+pub fn color_to_normalized(color: RgbColor) -> NormalizedRgbColor {
+  let normalized = normalize_color([color.r, color.g, color.b]);
+  NormalizedRgbColor {
+    r: normalized[0],
+    g: normalized[1],
+    b: normalized[2],
+  }
+}
+
+#[napi]
+/// Convert a normalized RGB color (0.0-1.0) to an RGB color (0-255)
+///
+/// # Arguments
+/// * `color` - The normalized RGB color
+///
+/// # Returns
+/// The RGB color
+pub fn normalized_to_color(color: NormalizedRgbColor) -> RgbColor {
+  let denormalized = denormalize_color([color.r, color.g, color.b]);
+  RgbColor {
+    r: denormalized[0],
+    g: denormalized[1],
+    b: denormalized[2],
+  }
+}
+
+#[napi]
+/// Decode sRGB-encoded values (0.0-1.0) to linear light, with the transfer function used for
+/// linear light processing
+///
+/// # Arguments
+/// * `value` - A single channel value, or any number of them (e.g. the channels of a color)
+///
+/// # Returns
+/// The linear light value, or values in the same order
+pub fn srgb_to_linear(value: Either<f64, Vec<f64>>) -> Either<f64, Vec<f64>> {
+  match value {
+    Either::A(value) => Either::A(srgb_channel_to_linear(value)),
+    Either::B(values) => Either::B(values.into_iter().map(srgb_channel_to_linear).collect()),
+  }
+}
+
+#[napi]
+/// Encode linear light values (0.0-1.0) with the sRGB transfer function, the inverse of
+/// `srgb_to_linear`
+///
+/// # Arguments
+/// * `value` - A single channel value, or any number of them (e.g. the channels of a color)
+///
+/// # Returns
+/// The sRGB-encoded value, or values in the same order
+pub fn linear_to_srgb(value: Either<f64, Vec<f64>>) -> Either<f64, Vec<f64>> {
+  match value {
+    Either::A(value) => Either::A(linear_channel_to_srgb(value)),
+    Either::B(values) => Either::B(values.into_iter().map(linear_channel_to_srgb).collect()),
+  }
+}
+
+#[napi]
+/// Measure how different two colors are
+///
+/// # Arguments
+/// * `a` - The first color, as a CSS color string, an RGB object or an `[r, g, b]` array
+/// * `b` - The second color
+/// * `metric` - "rgb" (default) for the normalized RGB distance (0.0-1.73) the closeness
+///   threshold uses, "cie76" for the CIELAB distance, or "deltaE2000" for the CIEDE2000
+///   difference (about 1.0 is a just noticeable difference; the "deltaE2000" threshold space
+///   divides it by 100)
+///
+/// # Returns
+/// The distance between the colors
+pub fn color_distance(
+  #[napi(ts_arg_type = "string | RgbColor | Array<number>")] a: ColorInput,
+  #[napi(ts_arg_type = "string | RgbColor | Array<number>")] b: ColorInput,
+  metric: Option<String>,
+) -> Result<f64> {
+  let [a, b] = [a, b].map(|color| {
+    parse_color_input(
+      &color,
+      |spec| Ok((parse_css_color(spec)?, None)),
+      |color| color,
+    )
+    .map(|(color, _)| normalize_color(color))
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid color: {}", e)))
+  });
+  let (a, b) = (a?, b?);
+  match metric.as_deref().unwrap_or("rgb") {
+    "rgb" => Ok(ThresholdSpace::Rgb.distance(a, b)),
+    "cie76" => Ok(delta_e_76(srgb_to_lab(a), srgb_to_lab(b))),
+    "deltaE2000" => Ok(delta_e_2000(srgb_to_lab(a), srgb_to_lab(b))),
+    metric => Err(Error::new(
+      Status::InvalidArg,
+      format!(
+        "Metric must be one of \"rgb\", \"cie76\" or \"deltaE2000\" (got: {})",
+        metric
+      ),
+    )),
+  }
+}
+
+#[napi]
+/// Convert an RGB color (0-255) to HSL
+///
+/// # Arguments
+/// * `color` - The RGB color
+///
+/// # Returns
+/// The hue in degrees and the saturation and lightness (0.0-1.0); grays have a hue of 0
+pub fn rgb_to_hsl(color: RgbColor) -> HslColor {
+  let [h, s, l] = color::rgb_to_hsl(normalize_color([color.r, color.g, color.b]));
+  HslColor { h, s, l }
+}
+
+#[napi]
+/// Convert an HSL color to RGB (0-255)
+///
+/// # Arguments
+/// * `color` - The hue in degrees and the saturation and lightness (0.0-1.0)
+///
+/// # Returns
+/// The RGB color
+pub fn hsl_to_rgb(color: HslColor) -> RgbColor {
+  let [r, g, b] = denormalize_color(color::hsl_to_rgb([color.h, color.s, color.l]));
+  RgbColor { r, g, b }
+}
+
+#[napi]
+/// Convert an RGB color (0-255) to HSV
+///
+/// # Arguments
+/// * `color` - The RGB color
+///
+/// # Returns
+/// The hue in degrees and the saturation and value (0.0-1.0); grays have a hue of 0
+pub fn rgb_to_hsv(color: RgbColor) -> HsvColor {
+  let [h, s, v] = color::rgb_to_hsv(normalize_color([color.r, color.g, color.b]));
+  HsvColor { h, s, v }
+}
+
+#[napi]
+/// Convert an HSV color to RGB (0-255)
+///
+/// # Arguments
+/// * `color` - The hue in degrees and the saturation and value (0.0-1.0)
+///
+/// # Returns
+/// The RGB color
+pub fn hsv_to_rgb(color: HsvColor) -> RgbColor {
+  let [r, g, b] = denormalize_color(color::hsv_to_rgb([color.h, color.s, color.v]));
+  RgbColor { r, g, b }
+}
+
+#[napi]
+/// Convert an sRGB color (0-255) to CIELAB under the D65 white point, as used by the
+/// "deltaE2000" threshold space
+///
+/// # Arguments
+/// * `color` - The RGB color
+///
+/// # Returns
+/// The CIELAB color
+pub fn rgb_to_lab(color: RgbColor) -> LabColor {
+  let [l, a, b] = color::srgb_to_lab(normalize_color([color.r, color.g, color.b]));
+  LabColor { l, a, b }
+}
+
+#[napi]
+/// Convert a CIELAB color under the D65 white point to sRGB (0-255)
+///
+/// # Arguments
+/// * `color` - The CIELAB color
+///
+/// # Returns
+/// The RGB color, with colors outside the sRGB gamut clamped into it
+pub fn lab_to_rgb(color: LabColor) -> RgbColor {
+  let [r, g, b] = denormalize_color(color::lab_to_srgb([color.l, color.a, color.b]));
+  RgbColor { r, g, b }
+}
+
+#[napi]
+/// Convert an sRGB color (0-255) to OKLab, as used by the "oklab" unmix space
+///
+/// # Arguments
+/// * `color` - The RGB color
+///
+/// # Returns
+/// The OKLab color
+pub fn rgb_to_oklab(color: RgbColor) -> LabColor {
+  let [l, a, b] = color::srgb_to_oklab(normalize_color([color.r, color.g, color.b]));
+  LabColor { l, a, b }
+}
+
+#[napi]
+/// Convert an OKLab color to sRGB (0-255)
+///
+/// # Arguments
+/// * `color` - The OKLab color
+///
+/// # Returns
+/// The RGB color, with colors outside the sRGB gamut clamped into it
+pub fn oklab_to_rgb(color: LabColor) -> RgbColor {
+  let [r, g, b] = denormalize_color(color::oklab_to_srgb([color.l, color.a, color.b]));
+  RgbColor { r, g, b }
+}
+
+#[napi]
+/// Trim the image to the bounding box of non-transparent pixels
+///
+/// The output is PNG unless `output_format` says otherwise, whatever the input format. TIFF
+/// output keeps the full precision of 16-bit and floating point inputs; only the first page
+/// of a multi-page TIFF is read.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `png_options` - The PNG encoder settings (default: balanced compression, adaptive filter)
+/// * `output_format` - The output format: "png" (default), "indexed", "webp", "avif", "tiff",
+///   "jpeg", "apng", "gif" or "raw", each with its default settings
+///
+/// # Returns
+/// The trimmed image buffer, in the output format
+pub fn trim_image(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  png_options: Option<PngEncodeOptions>,
+  output_format: Option<String>,
+) -> Result<Buffer> {
+  let settings = match &png_options {
+    Some(options) => png_settings(
+      options.compression.as_deref(),
+      options.filter.as_deref(),
+      options.interlaced,
+    )?,
+    None => PngSettings::default(),
+  };
+  let preserve_metadata = png_options
+    .and_then(|options| options.preserve_metadata)
+    .unwrap_or(true);
+  let output_format = default_output_format(output_format.as_deref(), settings)?;
+
+  let img = load_input(&input, DecodeOptions::default())?;
+  let color = img.color();
+  let encoded = match output_format {
+    OutputFormat::Png(settings) => {
+      let metadata = match encoded_input(&input)? {
+        Some(buffer) if preserve_metadata => PngMetadata::read(&buffer),
+        _ => PngMetadata::default(),
+      };
+      let settings = PngSettings {
+        grayscale: !color.has_color(),
+        ..settings
+      };
+      encode_png_rgba(&trim_to_content(&img.to_rgba8()), settings, &metadata)
+    }
+    OutputFormat::Tiff if color.bytes_per_pixel() > color.channel_count() => {
+      encode_rgba16(&trim_to_content(&img.to_rgba16()), output_format)
+    }
+    _ => encode_rgba(&trim_to_content(&img.to_rgba8()), output_format),
+  }
+  .map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })?;
+  Ok(encoded.into())
+}
+
+pub struct AsyncQuantize {
+  input: ImageInput,
+  max_colors: u32,
+  dither: bool,
+  output_format: Option<String>,
+}
+
+#[napi]
+impl Task for AsyncQuantize {
+  type Output = Vec<u8>;
+  type JsValue = Buffer;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    quantize_internal(
+      &self.input,
+      self.max_colors,
+      self.dither,
+      self.output_format.as_deref(),
+    )
+  }
+
+  fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+    Ok(output.into())
+  }
+}
+
+#[napi]
+/// Reduce an image asynchronously to a palette of at most `max_colors` colors, preserving
+/// alpha
+///
+/// Fully transparent pixels stay transparent and are not counted towards the palette.
+/// Useful for pixel-art style output or before exporting to palette-based formats.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `max_colors` - The maximum number of colors in the palette (1-256)
+/// * `dither` - Whether to apply Floyd-Steinberg dithering
+/// * `output_format` - The output format: "png" (default), "indexed", "webp", "avif", "tiff",
+///   "jpeg", "apng", "gif" or "raw", each with its default settings
+///
+/// # Returns
+/// A promise that resolves to the quantized image buffer, in the output format
+pub fn quantize(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  max_colors: u32,
+  dither: bool,
+  output_format: Option<String>,
+) -> AsyncTask<AsyncQuantize> {
+  AsyncTask::new(AsyncQuantize {
+    input,
+    max_colors,
+    dither,
+    output_format,
+  })
+}
+
+#[napi]
+/// Reduce an image synchronously to a palette of at most `max_colors` colors, preserving
+/// alpha
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `max_colors` - The maximum number of colors in the palette (1-256)
+/// * `dither` - Whether to apply Floyd-Steinberg dithering
+/// * `output_format` - The output format: "png" (default), "indexed", "webp", "avif", "tiff",
+///   "jpeg", "apng", "gif" or "raw", each with its default settings
+///
+/// # Returns
+/// The quantized image buffer, in the output format
+pub fn quantize_sync(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  max_colors: u32,
+  dither: bool,
+  output_format: Option<String>,
+) -> Result<Buffer> {
+  Ok(quantize_internal(&input, max_colors, dither, output_format.as_deref())?.into())
+}
+
+#[napi]
+/// Extract the dominant colors of an image, to help choose foreground colors
+///
+/// Splits the image's colors into at most `max_colors` groups by median cut and returns
+/// the average color of each group. Fully transparent pixels are ignored.
+///
+/// # Arguments
+/// * `input` - The input image buffer, a base64 `data:` URL, or raw RGBA pixels with their
+///   dimensions
+/// * `max_colors` - The maximum number of colors in the palette (1-256)
+///
+/// # Returns
+/// The palette colors, most common first, with their pixel counts and share of the pixels
+pub fn extract_palette(
+  #[napi(ts_arg_type = "Uint8Array | ArrayBuffer | RawImage | string")] input: ImageInput,
+  max_colors: u32,
+) -> Result<Vec<PaletteColor>> {
+  if !(1..=256).contains(&max_colors) {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!("Max colors must be between 1 and 256 (got: {})", max_colors),
+    ));
+  }
+
+  let img = load_input(&input, DecodeOptions::default())?;
+  let (palette, total) = extract_dominant_colors(&img, max_colors as usize);
+
+  Ok(
+    palette
+      .into_iter()
+      .map(|([r, g, b], pixels)| PaletteColor {
+        color: RgbColor { r, g, b },
+        pixels: pixels as u32,
+        share: pixels as f64 / total as f64,
+      })
+      .collect(),
+  )
+}
+
+#[napi]
+/// Unmix an observed color into foreground color components
+///
+/// Given an observed color and known foreground/background colors,
+/// determines how much of each foreground color contributed to the observed color.
+///
+/// # Arguments
+/// * `observed` - The observed color
+/// * `foreground_colors` - The foreground colors to match
+/// * `background` - The background color
+/// * `optimize_opacity` - Prefer the most opaque mix of the foreground colors (default: true);
+///   false returns the plain least-squares solution that color deduction uses
+///
+/// # Returns
+/// The unmix result containing weights for each foreground color, overall alpha, the
+/// reconstruction error and the foreground colors dropped as linearly dependent
+pub fn unmix_color(
+  observed: RgbColor,
+  foreground_colors: Vec<RgbColor>,
+  background: RgbColor,
+  optimize_opacity: Option<bool>,
+) -> UnmixResultJs {
+  let fg_normalized: Vec<NormalizedColor> = foreground_colors
+    .iter()
+    .map(|c| normalize_color([c.r, c.g, c.b]))
+    .collect();
+  let bg_normalized = normalize_color([background.r, background.g, background.b]);
+
+  let optimize_opacity = optimize_opacity.unwrap_or(true);
+
+  let result = unmix_colors_internal(
+    [observed.r, observed.g, observed.b],
+    &fg_normalized,
+    bg_normalized,
+    optimize_opacity,
+    DEFAULT_RECONSTRUCTION_TOLERANCE,
+  );
+
+  UnmixResultJs {
+    weights: result.weights,
+    alpha: result.alpha,
+    reconstruction_error: result.reconstruction_error,
+    dropped_colors: dropped_foreground_indices(&fg_normalized, bg_normalized, optimize_opacity)
+      .into_iter()
+      .map(|i| i as u32)
+      .collect(),
+  }
+}
+
+#[napi]
+/// Unmix many observed colors into foreground color components in one call
+///
+/// Works like `unmix_color` on each observed color, in parallel, which is much faster than
+/// calling it once per color.
+///
+/// # Arguments
+/// * `observed` - The observed colors, as RGB objects or as a flat array of r, g, b channels
+///   (0-255, fractions allowed)
+/// * `foreground_colors` - The foreground colors to match
+/// * `background` - The background color
+///
+/// # Returns
+/// Flat arrays holding the weights, alpha and reconstruction error of each observed color, in
+/// order
+pub fn unmix_colors(
+  #[napi(ts_arg_type = "Float64Array | RgbColor[]")] observed: Either<Float64Array, Vec<RgbColor>>,
+  foreground_colors: Vec<RgbColor>,
+  background: RgbColor,
+) -> Result<UnmixColorsResult> {
+  let observed: Vec<NormalizedColor> = match &observed {
+    Either::A(channels) => {
+      if channels.len() % 3 != 0 {
+        return Err(Error::new(
+          Status::InvalidArg,
+          format!(
+            "Observed channels must come in r, g, b triples (got: {} values)",
+            channels.len()
+          ),
+        ));
+      }
+      if let Some(value) = channels
+        .iter()
+        .find(|value| !(0.0..=255.0).contains(*value))
+      {
+        return Err(Error::new(
+          Status::InvalidArg,
+          format!(
+            "Observed channels must be between 0 and 255 (got: {})",
+            value
+          ),
+        ));
+      }
+      channels
+        .chunks_exact(3)
+        .map(|c| [c[0] / 255.0, c[1] / 255.0, c[2] / 255.0])
+        .collect()
+    }
+    Either::B(colors) => colors
+      .iter()
+      .map(|c| normalize_color([c.r, c.g, c.b]))
+      .collect(),
+  };
+  let fg_normalized: Vec<NormalizedColor> = foreground_colors
+    .iter()
+    .map(|c| normalize_color([c.r, c.g, c.b]))
+    .collect();
+  let bg_normalized = normalize_color([background.r, background.g, background.b]);
+
+  let results: Vec<UnmixResult> = observed
+    .par_iter()
+    .map(|&color| {
+      unmix_normalized_with_tolerance(
+        color,
+        &fg_normalized,
+        bg_normalized,
+        DEFAULT_RECONSTRUCTION_TOLERANCE,
+      )
+    })
+    .collect();
+
+  Ok(UnmixColorsResult {
+    weights: Float64Array::new(
+      results
+        .iter()
+        .flat_map(|result| result.weights.iter().copied())
+        .collect(),
+    ),
+    alpha: Float64Array::new(results.iter().map(|result| result.alpha).collect()),
+    reconstruction_error: Float64Array::new(
+      results
+        .iter()
+        .map(|result| result.reconstruction_error)
+        .collect(),
+    ),
+  })
+}
+
+#[napi]
+/// Compute the final color from unmix result
+///
+/// # Arguments
+/// * `weights` - The weights for each foreground color
+/// * `alpha` - The alpha value
+/// * `foreground_colors` - The foreground colors
+/// * `gamut_mapping` - How an out-of-range color is brought back into gamut: "clamp" (default) or
+///   "scale"
+///
+/// # Returns
+/// The computed RGBA color
+pub fn compute_unmix_result_color(
+  weights: Vec<f64>,
+  alpha: f64,
+  foreground_colors: Vec<RgbColor>,
+  gamut_mapping: Option<String>,
+) -> Result<RgbaColor> {
+  let gamut_mapping = gamut_mapping
+    .as_deref()
+    .map(parse_gamut_mapping)
+    .transpose()
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid gamut mapping: {}", e)))?
+    .unwrap_or_default();
+
+  let fg_normalized: Vec<NormalizedColor> = foreground_colors
+    .iter()
+    .map(|c| normalize_color([c.r, c.g, c.b]))
+    .collect();
+
+  // The result color only depends on the weights and alpha
+  let unmix_result = UnmixResult {
+    weights,
+    alpha,
+    reconstruction_error: 0.0,
+  };
+  let (result_color, result_alpha) =
+    compute_result_color_with_gamut(&unmix_result, &fg_normalized, gamut_mapping);
+  let final_color = denormalize_color(result_color);
+
+  Ok(RgbaColor {
+    r: final_color[0],
+    g: final_color[1],
+    b: final_color[2],
+    a: (result_alpha * 255.0).round() as u8,
+  })
+}
+
+#[napi]
+/// Composite an RGBA pixel over an RGB background color
+///
+/// If the input pixel is translucent (alpha < 255), this pre-composes it over
+/// the background color to produce an opaque equivalent.
+///
+/// # Arguments
+/// * `pixel` - The RGBA pixel color
+/// * `background` - The background RGB color
+///
+/// # Returns
+/// The composited RGB color
+pub fn composite_over_background(pixel: RgbaColor, background: RgbColor) -> RgbColor {
+  let rgba_pixel = Rgba([pixel.r, pixel.g, pixel.b, pixel.a]);
+  let bg_color: Color = [background.r, background.g, background.b];
+  let result = composite_pixel_over_background(&rgba_pixel, bg_color);
+  RgbColor {
+    r: result[0],
+    g: result[1],
+    b: result[2],
+  }
+}
+
+#[napi]
+/// Get the default threshold for color closeness
+///
+/// # Returns
+/// The default threshold (0.05 = 5% of max RGB distance)
+pub fn get_default_threshold() -> f64 {
+  DEFAULT_COLOR_CLOSENESS_THRESHOLD
+}
+
+/// Forwards everything written to it to a JavaScript callback
+struct ChunkSink<'a>(&'a BoundedCallback<Buffer>);
+
+impl ChunkSink<'_> {
+  /// Pass a chunk to JavaScript, waiting while the callback's queue is full
+  fn send(&self, chunk: &[u8]) -> std::io::Result<()> {
+    match self.0.call(
+      Ok(chunk.to_vec().into()),
+      ThreadsafeFunctionCallMode::Blocking,
+    ) {
+      Status::Ok => Ok(()),
+      status => Err(std::io::Error::other(format!(
+        "Failed to pass a chunk to JavaScript: {}",
+        status
+      ))),
+    }
+  }
+}
+
+impl Write for ChunkSink<'_> {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.send(buf)?;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Process an image and write the PNG output to `on_chunk` row by row, followed by an empty
+/// chunk that marks the end
+fn process_image_to_stream(
+  options: &ProcessImageOptions,
+  on_chunk: &BoundedCallback<Buffer>,
+) -> Result<()> {
+  let OutputFormat::Png(settings) = output_format(options)? else {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Streaming is only supported for png and indexed output".to_string(),
+    ));
+  };
+
+  let mut sink = BufWriter::with_capacity(STREAM_CHUNK_SIZE, ChunkSink(on_chunk));
+  let written = if output_bit_depth(options)? == 16 {
+    let (final_img, crop) = process_image_to_rgba16(options)?;
+    let metadata = png_metadata(options, crop);
+    write_png_rgba16(&mut sink, &final_img, settings, &metadata)
+  } else {
+    let (final_img, crop) = process_image_to_rgba(options)?;
+    let metadata = png_metadata(options, crop);
+    write_png_rgba(&mut sink, &final_img, settings, &metadata)
+  };
+
+  // The promise can resolve before the queued chunks reach `on_chunk`, so an empty chunk,
+  // queued after all of them, is what reliably marks the end of the output
+  written
+    .and_then(|()| Ok(sink.flush()?))
+    .and_then(|()| Ok(ChunkSink(on_chunk).send(&[])?))
+    .map_err(|e| {
+      Error::new(
+        Status::GenericFailure,
+        format!("Failed to write output image: {}", e),
+      )
+    })
+}
+
+/// Process an image and encode the output as a base64 data URL
+fn process_image_to_data_url_internal(options: &ProcessImageOptions) -> Result<String> {
+  if matches!(output_format(options)?, OutputFormat::Raw) {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Raw output cannot be returned as a data URL".to_string(),
+    ));
+  }
+  let encoded = process_image_internal(options)?;
+  to_data_url(&encoded).map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })
+}
+
+fn process_image_internal(options: &ProcessImageOptions) -> Result<Vec<u8>> {
+  Ok(process_image_output(options)?.data)
+}
+
+/// An encoded output image along with its size and format
+struct ProcessedOutput {
+  data: Vec<u8>,
+  width: u32,
+  height: u32,
+  format: &'static str,
+  crop: Crop,
+}
+
+/// Run the full pipeline and encode the result in the output format
+fn process_image_output(options: &ProcessImageOptions) -> Result<ProcessedOutput> {
+  let output_format = output_format(options)?;
+  if output_bit_depth(options)? == 16 {
+    let (final_img, crop) = process_image_to_rgba16(options)?;
+    let (width, height) = final_img.dimensions();
+    return Ok(ProcessedOutput {
+      data: encode_output16(options, output_format, &final_img, crop)?,
+      width,
+      height,
+      format: output_format.name(),
+      crop,
+    });
+  }
+
+  // Animations stay animated unless a still output format is asked for
+  if options.output_format.is_none()
+    || matches!(
+      output_format,
+      OutputFormat::Apng(_) | OutputFormat::WebP(_) | OutputFormat::Gif
+    )
+  {
+    if let Some(animation) = load_animation(&options.input, DecodeOptions::from_options(options)?)?
+    {
+      let (frames, crop) = process_animation(options, animation.frames)?;
+      let (width, height) = frames
+        .first()
+        .map_or((0, 0), |frame| frame.buffer().dimensions());
+      let (encoded, format) = match (output_format, animation.format) {
+        (OutputFormat::Png(settings), image::ImageFormat::Png)
+        | (OutputFormat::Apng(settings), _) => (
+          encode_apng(
+            &frames,
+            animation.plays,
+            PngSettings {
+              interlaced: false,
+              palette: None,
+              ..settings
+            },
+          ),
+          "apng",
+        ),
+        (OutputFormat::WebP(settings), _) => (
+          encode_animated_webp(&frames, animation.plays, settings),
+          "webp",
+        ),
+        (OutputFormat::Png(_), image::ImageFormat::WebP) => (
+          encode_animated_webp(&frames, animation.plays, webp_settings(options)?),
+          "webp",
+        ),
+        _ => (encode_gif(frames, animation.plays), "gif"),
+      };
+      let data = encoded.map_err(|e| {
+        Error::new(
+          Status::GenericFailure,
+          format!("Failed to write output image: {}", e),
+        )
+      })?;
+      return Ok(ProcessedOutput {
+        data,
+        width,
+        height,
+        format,
+        crop,
+      });
+    }
+  }
+
+  let (final_img, crop) = process_image_to_rgba(options)?;
+  let (width, height) = final_img.dimensions();
+  Ok(ProcessedOutput {
+    data: encode_output(options, output_format, &final_img, crop)?,
+    width,
+    height,
+    format: output_format.name(),
+    crop,
+  })
+}
+
+/// Process an image and fit its content onto a canvas of the target size, encoded in the
+/// output format
+fn fit_subject_internal(
+  options: &ProcessImageOptions,
+  target_width: u32,
+  target_height: u32,
+  margin_ratio: Option<f64>,
+) -> Result<Vec<u8>> {
+  if target_width == 0 || target_height == 0 {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!(
+        "Target size must be at least 1x1 (got: {}x{})",
+        target_width, target_height
+      ),
+    ));
+  }
+
+  let margin_ratio = margin_ratio.unwrap_or(0.0);
+  if !(0.0..0.5).contains(&margin_ratio) {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!(
+        "Margin ratio must be at least 0.0 and less than 0.5 (got: {})",
+        margin_ratio
+      ),
+    ));
+  }
+
+  let output_format = output_format(options)?;
+  let (rgba, _) = process_image_to_rgba(options)?;
+  let fitted = fit_to_canvas(&rgba, target_width, target_height, margin_ratio);
+
+  // The subject is rescaled and recentered, so no offset into the input applies
+  encode_output(options, output_format, &fitted, Crop::NONE)
+}
+
+/// Process an image and compute the alpha-weighted average color of what is left
+fn subject_average_color_internal(options: &ProcessImageOptions) -> Result<Option<RgbColor>> {
+  let (rgba, _) = process_image_to_rgba(options)?;
+
+  Ok(alpha_weighted_average_color(&rgba).map(|[r, g, b]| RgbColor { r, g, b }))
+}
+
+/// Process an image and bundle its content at several icon sizes into one ICO file
+fn export_favicon_internal(
+  options: &ProcessImageOptions,
+  sizes: Option<&[u32]>,
+) -> Result<Vec<u8>> {
+  let mut sizes = sizes.unwrap_or(&DEFAULT_ICO_SIZES).to_vec();
+  if sizes.is_empty() {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "At least one icon size is required".to_string(),
+    ));
+  }
+  if let Some(size) = sizes.iter().find(|size| !(1..=256).contains(*size)) {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!("Icon sizes must be between 1 and 256 (got: {})", size),
+    ));
+  }
+  sizes.sort_unstable();
+  sizes.dedup();
+
+  let (rgba, _) = process_image_to_rgba(options)?;
+  let icons: Vec<RgbaImage> = sizes
+    .iter()
+    .map(|&size| fit_to_canvas(&rgba, size, size, 0.0))
+    .collect();
+
+  encode_ico(&icons).map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })
+}
+
+/// Remix the unmix weights of an image with replacement colors, encoded in the output format
+fn recolor_internal(
+  options: &ProcessImageOptions,
+  replacement_colors: &[String],
+) -> Result<Vec<u8>> {
+  let output_format = output_format(options)?;
+  let prepared = prepare_image(options)?;
+  if prepared.foreground_colors.is_empty() {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Recoloring requires at least one foreground color".to_string(),
+    ));
+  }
+  if replacement_colors.len() != prepared.foreground_colors.len() {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!(
+        "Expected one replacement color per foreground color ({}, got: {})",
+        prepared.foreground_colors.len(),
+        replacement_colors.len()
+      ),
+    ));
+  }
+  let replacement_colors = replacement_colors
+    .iter()
+    .map(|color| {
+      parse_css_color(color)
+        .map(|color| prepared.srgb_to_working(normalize_color(color)))
+        .map_err(|e| {
+          Error::new(
+            Status::InvalidArg,
+            format!("Invalid replacement color: {}", e),
+          )
+        })
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  let (width, height) = prepared.rgba.dimensions();
+  let pixels: Vec<u8> = unmix_foreground_weights(&prepared)
+    .into_iter()
+    .flat_map(|weights| {
+      let alpha = weights.iter().sum::<f64>().min(1.0);
+      let (color, alpha) = compute_result_color_with_gamut(
+        &UnmixResult {
+          weights,
+          alpha,
+          reconstruction_error: 0.0,
+        },
+        &replacement_colors,
+        prepared.gamut_mapping,
+      );
+      let [r, g, b] = prepared.working_to_srgb(color);
+      denormalize_rgba([r, g, b, alpha])
+    })
+    .collect();
+  let output =
+    RgbaImage::from_raw(width, height, pixels).expect("one RGBA pixel per unmixed pixel");
+
+  let (output, crop) = if options.trim {
+    let crop = Crop::from_bounds(content_bounds(&output), output.dimensions());
+    (trim_to_content(&output), crop)
+  } else {
+    (output, Crop::NONE)
+  };
+  encode_output(options, output_format, &output, crop.of_prepared(&prepared))
+}
+
+/// Split an image into one layer per foreground color, each encoded in the output format
+fn process_image_layers_internal(options: &ProcessImageOptions) -> Result<Vec<Vec<u8>>> {
+  let output_format = output_format(options)?;
+  let (layers, crop) = process_image_to_layers(options, false)?;
+  layers
+    .iter()
+    .map(|layer| encode_output(options, output_format, layer, crop))
+    .collect()
+}
+
+/// Remove the background by comparing an image with a shot of the empty background, encoding
+/// the output in the output format of the options
+fn process_with_reference_internal(
+  subject: &ImageInput,
+  background_reference: &ImageInput,
+  options: Option<&ReferenceOptions>,
+) -> Result<Vec<u8>> {
+  let output_format = default_output_format(
+    options.and_then(|o| o.output_format.as_deref()),
+    PngSettings::default(),
+  )?;
+  let subject = load_input(subject, DecodeOptions::default())?.to_rgba8();
+  let reference = load_input(background_reference, DecodeOptions::default())
+    .map_err(|e| {
+      Error::new(
+        Status::InvalidArg,
+        format!("Invalid background reference: {}", e.reason),
+      )
+    })?
+    .to_rgb8();
+
+  if subject.dimensions() != reference.dimensions() {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!(
+        "Background reference must be the same size as the image ({}x{}, got: {}x{})",
+        subject.width(),
+        subject.height(),
+        reference.width(),
+        reference.height()
+      ),
+    ));
+  }
+
+  let threshold = options
+    .and_then(|o| o.threshold)
+    .unwrap_or(DEFAULT_COLOR_CLOSENESS_THRESHOLD);
+  let opaque_distance = options
+    .and_then(|o| o.opaque_distance)
+    .unwrap_or(DEFAULT_ALPHA_OPAQUE_DISTANCE);
+  if opaque_distance.is_nan() || opaque_distance <= 0.0 {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!(
+        "Opaque distance must be greater than 0.0 (got: {})",
+        opaque_distance
+      ),
+    ));
+  }
+  let trim = options.and_then(|o| o.trim).unwrap_or(false);
+
+  let (width, height) = subject.dimensions();
+  let processed_pixels: Vec<[u8; 4]> = (0..width * height)
+    .into_par_iter()
+    .map(|i| {
+      let (x, y) = (i % width, i / width);
+      let background = reference.get_pixel(x, y).0;
+      let observed = composite_pixel_over_background(subject.get_pixel(x, y), background);
+      process_pixel_with_reference(
+        observed,
+        normalize_color(background),
+        threshold,
+        opaque_distance,
+      )
+    })
+    .collect();
+
+  let output_img = RgbaImage::from_fn(width, height, |x, y| {
+    Rgba(processed_pixels[(y * width + x) as usize])
+  });
+  let final_img = if trim {
+    trim_to_content(&output_img)
+  } else {
+    output_img
+  };
+
+  encode_rgba(&final_img, output_format).map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })
+}
+
+/// Reduce an image to a palette of at most `max_colors` colors, encoding it in the output
+/// format
+fn quantize_internal(
+  input: &ImageInput,
+  max_colors: u32,
+  dither: bool,
+  output_format: Option<&str>,
+) -> Result<Vec<u8>> {
+  if !(1..=256).contains(&max_colors) {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!("Max colors must be between 1 and 256 (got: {})", max_colors),
+    ));
+  }
+  let output_format = default_output_format(output_format, PngSettings::default())?;
+
+  let img = load_input(input, DecodeOptions::default())?;
+  let quantized = quantize_rgba(&img.to_rgba8(), max_colors as usize, dither);
+
+  encode_rgba(&quantized, output_format).map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })
+}
+
+/// Compute the weight maps of a still image, encoding each in the output format
+fn process_image_weight_maps_internal(options: &ProcessImageOptions) -> Result<Vec<Vec<u8>>> {
+  let output_format = output_format(options)?;
+  let (layers, crop) = process_image_to_layers(options, false)?;
+  layers
+    .iter()
+    .map(|layer| {
+      let map = GrayImage::from_fn(layer.width(), layer.height(), |x, y| {
+        Luma([layer.get_pixel(x, y)[3]])
+      });
+      encode_output_gray(options, output_format, &map, crop)
+    })
+    .collect()
+}
+
+/// Separate a still image into stacked layers, encoding each in the output format
+fn separate_layers_internal(options: &ProcessImageOptions) -> Result<Vec<Vec<u8>>> {
+  let output_format = output_format(options)?;
+  let (layers, crop) = process_image_to_layers(options, true)?;
+  layers
+    .iter()
+    .map(|layer| encode_output(options, output_format, layer, crop))
+    .collect()
+}
+
+/// Process a still image once, encoding its color opaque and its alpha as a grayscale image,
+/// both in the output format and at the output bit depth
+fn split_color_and_matte_internal(options: &ProcessImageOptions) -> Result<ColorMatteResult> {
+  let output_format = output_format(options)?;
+  if output_bit_depth(options)? == 16 {
+    let (rgba, crop) = process_image_to_rgba16(options)?;
+    let (width, height) = rgba.dimensions();
+    let color = Rgba16Image::from_fn(width, height, |x, y| {
+      let [r, g, b, _] = rgba.get_pixel(x, y).0;
+      Rgba([r, g, b, u16::MAX])
+    });
+    let matte = Gray16Image::from_fn(width, height, |x, y| Luma([rgba.get_pixel(x, y)[3]]));
+    return Ok(ColorMatteResult {
+      color: encode_output16(options, output_format, &color, crop)?.into(),
+      matte: encode_output_gray16(options, output_format, &matte, crop)?.into(),
+    });
+  }
+
+  let (rgba, crop) = process_image_to_rgba(options)?;
+  let (width, height) = rgba.dimensions();
+
+  let color = RgbaImage::from_fn(width, height, |x, y| {
+    let [r, g, b, _] = rgba.get_pixel(x, y).0;
+    Rgba([r, g, b, 255])
+  });
+  let matte = GrayImage::from_fn(width, height, |x, y| Luma([rgba.get_pixel(x, y)[3]]));
+
+  Ok(ColorMatteResult {
+    color: encode_output(options, output_format, &color, crop)?.into(),
+    matte: encode_output_gray(options, output_format, &matte, crop)?.into(),
+  })
+}
+
+/// Process a still image once, encoding both the output and its alpha as a grayscale PNG
+fn process_image_with_mask_internal(options: &ProcessImageOptions) -> Result<ImageWithMask> {
+  let output_format = output_format(options)?;
+  if output_bit_depth(options)? == 16 {
+    let (final_img, crop) = process_image_to_rgba16(options)?;
+    let (width, height) = final_img.dimensions();
+    let mask = ImageBuffer::from_fn(width, height, |x, y| Luma([final_img.get_pixel(x, y)[3]]));
+    return Ok(ImageWithMask {
+      mask: encode_png(&DynamicImage::ImageLuma16(mask))?.into(),
+      image: encode_output16(options, output_format, &final_img, crop)?.into(),
+    });
+  }
+
+  let (final_img, crop) = process_image_to_rgba(options)?;
+  let (width, height) = final_img.dimensions();
+  let mask = GrayImage::from_fn(width, height, |x, y| Luma([final_img.get_pixel(x, y)[3]]));
+  Ok(ImageWithMask {
+    mask: encode_png(&DynamicImage::ImageLuma8(mask))?.into(),
+    image: encode_output(options, output_format, &final_img, crop)?.into(),
+  })
+}
+
+/// An input image with its background and foreground colors resolved
+struct PreparedImage {
+  /// The decoded input image
+  rgba: RgbaImage,
+  /// The decoded input image at full precision, if it has more than 8 bits per channel
+  rgba16: Option<ImageBuffer<Rgba<u16>, Vec<u16>>>,
+  /// The global background color (detected or user-specified, the first of several)
+  background_color: Color,
+  /// The background colors each pixel is unmixed against the nearest of, if several were given
+  background_palette: Vec<Color>,
+  /// The radius around each given background color within which pixels are pure background
+  background_tolerances: Vec<Option<f64>>,
+  /// The foreground colors, with any "auto" specs deduced
+  foreground_colors: Vec<Color>,
+  /// The tolerance radius of each foreground color, if specified
+  foreground_tolerances: Vec<Option<f64>>,
+  /// The threshold for color closeness
+  color_threshold: f64,
+  /// How closeness to the threshold and tolerances is measured
+  threshold_space: ThresholdSpace,
+  /// The reconstruction error accepted for higher-opacity unmixing solutions
+  reconstruction_tolerance: f64,
+  /// The combinations of foreground colors a pixel is unmixed into, if their number is limited
+  unmix_combinations: Option<ColorCombinations>,
+  /// The per-channel difference (normalized) below which a pixel is the background itself
+  background_epsilon: f64,
+  /// How out-of-range result colors are brought back into gamut
+  gamut_mapping: GamutMapping,
+  /// The per-pixel background model, if the background varies across the image
+  local_background: Option<SpatialBackground>,
+  /// How translucent input pixels are composited over the background
+  input_alpha_mode: AlphaMode,
+  /// How the alpha input pixels already have is combined with the computed alpha
+  existing_alpha: ExistingAlpha,
+  /// How alpha is chosen in non-strict mode without foreground colors
+  alpha_model: AlphaModel,
+  /// Whether fully transparent pixels come from a transparent palette index and pass through as is
+  palette_transparency: bool,
+  /// The color space colors are unmixed in
+  working_space: WorkingSpace,
+  /// How the foreground was blended onto the background
+  blend_mode: BlendMode,
+  /// The pixels background removal may touch, if only background connected to the border is
+  /// removed; the others pass through as is
+  removable: Option<Vec<bool>>,
+  /// Whether holes enclosed by opaque content get their original pixels back after removal
+  protect_holes: bool,
+  /// The solid frame cut off the input, if any
+  solid_frame: Option<Margins>,
+  /// The per-channel gains the input was white balanced with, if any
+  channel_gains: Option<[f64; 3]>,
+  /// Whether shadows cast on the background are kept as semi-transparent black
+  preserve_shadows: bool,
+  /// Whether the input is a single solid color that was detected as the background
+  solid_color: bool,
+  /// The share of edge samples matching the detected background color, if a single color was
+  /// detected
+  background_uniformity: Option<f64>,
+}
+
+impl PreparedImage {
+  /// Swap in another image of the same size, keeping the resolved colors and settings
+  fn with_frame(self, frame: RgbaImage) -> Self {
+    let connected_removal = self.removable.is_some();
+    let frame = match self.solid_frame {
+      Some(margins) => crop_frame(&DynamicImage::ImageRgba8(frame), margins).to_rgba8(),
+      None => frame,
+    };
+    let frame = match self.channel_gains {
+      Some(gains) => apply_channel_gains(&DynamicImage::ImageRgba8(frame), gains).to_rgba8(),
+      None => frame,
+    };
+    let mut prepared = Self {
+      rgba: frame,
+      rgba16: None,
+      ..self
+    };
+    if connected_removal {
+      prepared.removable = Some(prepared.border_connected_region());
+    }
+    prepared
+  }
+
+  /// The background connected to the border, and the edge pixels around it
+  fn border_connected_region(&self) -> Vec<bool> {
+    let (width, height) = self.rgba.dimensions();
+    border_connected_region(width, height, |x, y| {
+      let background = self.background_at(x, y);
+      let bg_normalized = self.srgb_to_working(normalize_color(background));
+      let observed = self.observed_at(x, y, background);
+      self.color_distance(observed, bg_normalized) <= self.color_threshold
+    })
+  }
+
+  /// The background color to unmix against at the given pixel
+  fn background_at(&self, x: u32, y: u32) -> Color {
+    match &self.local_background {
+      Some(model) => model.background_at(x, y),
+      None if self.background_palette.len() > 1 => self.nearest_background(x, y),
+      None => self.background_color,
+    }
+  }
+
+  /// The tolerance radius of a given background color, if specified
+  fn background_tolerance(&self, background: Color) -> Option<f64> {
+    self
+      .background_palette
+      .iter()
+      .position(|&color| color == background)
+      .and_then(|i| self.background_tolerances[i])
+  }
+
+  /// The listed background color the pixel is closest to, once composited over it
+  fn nearest_background(&self, x: u32, y: u32) -> Color {
+    let pixel = self.rgba.get_pixel(x, y);
+    self
+      .background_palette
+      .iter()
+      .copied()
+      .min_by(|&a, &b| {
+        let distance = |background: Color| {
+          let observed = normalize_color(composite_pixel_over_background_with_mode(
+            pixel,
+            background,
+            self.input_alpha_mode,
+          ));
+          let background = normalize_color(background);
+          (0..3)
+            .map(|i| (observed[i] - background[i]).powi(2))
+            .sum::<f64>()
+        };
+        distance(a).total_cmp(&distance(b))
+      })
+      .unwrap_or(self.background_color)
+  }
+
+  /// Convert an sRGB color into the space colors are unmixed in
+  fn srgb_to_working(&self, color: NormalizedColor) -> NormalizedColor {
+    self.working_space.from_srgb(color)
+  }
+
+  /// Whether a non-strict pixel is unmixed in OKLab against no close foreground color, which
+  /// the minimum alpha search must do in sRGB instead
+  fn needs_srgb_minimum_alpha(
+    &self,
+    observed: NormalizedColor,
+    background: NormalizedColor,
+    fg_normalized: &[NormalizedColor],
+  ) -> bool {
+    self.working_space == WorkingSpace::Oklab
+      && (fg_normalized.is_empty()
+        || !is_color_close_to_foreground(
+          observed.into(),
+          fg_normalized,
+          background,
+          self.color_threshold,
+          |a, b| self.color_distance(a, b),
+        ))
+  }
+
+  /// The distance between two working space colors, measured in the threshold space
+  fn color_distance(&self, a: NormalizedColor, b: NormalizedColor) -> f64 {
+    match self.threshold_space {
+      ThresholdSpace::Rgb => self.threshold_space.distance(a, b),
+      space => space.distance(self.working_to_srgb(a), self.working_to_srgb(b)),
+    }
+  }
+
+  /// Convert an unmixed color back to sRGB
+  fn working_to_srgb(&self, color: NormalizedColor) -> NormalizedColor {
+    self.working_space.to_srgb(color)
+  }
+
+  /// The input pixel at the given position with its color not premultiplied by alpha
+  fn straight_pixel_at(&self, x: u32, y: u32) -> NormalizedRgba {
+    let [r, g, b, alpha] = self.pixel_normalized(x, y);
+    match self.input_alpha_mode {
+      AlphaMode::Premultiplied if alpha > 0.0 => {
+        let [r, g, b] = [r, g, b].map(|c| (c / alpha).min(1.0));
+        [r, g, b, alpha]
+      }
+      _ => [r, g, b, alpha],
+    }
+  }
+
+  /// The input pixel at the given position, at full precision
+  fn pixel_normalized(&self, x: u32, y: u32) -> NormalizedRgba {
+    match &self.rgba16 {
+      Some(rgba16) => rgba16.get_pixel(x, y).0.map(|c| c as f64 / 65535.0),
+      None => self.rgba.get_pixel(x, y).0.map(|c| c as f64 / 255.0),
+    }
+  }
+
+  /// The opaque color observed at the given pixel, composited over its background, in the
+  /// space colors are unmixed in
+  fn observed_at(&self, x: u32, y: u32, background: Color) -> NormalizedColor {
+    if self.working_space == WorkingSpace::LinearLight
+      && self.existing_alpha == ExistingAlpha::Composite
+    {
+      // Composite in linear light too, so translucent pixels blend the way light does
+      let [r, g, b, alpha] = self.straight_pixel_at(x, y);
+      let [r, g, b] = [r, g, b].map(srgb_channel_to_linear);
+      return composite_normalized_over_background(
+        [r, g, b, alpha],
+        self.srgb_to_working(normalize_color(background)),
+        AlphaMode::Straight,
+      );
+    }
+    self.srgb_to_working(self.observed_srgb_at(x, y, background))
+  }
+
+  /// The observed color of a pixel like `observed_at`, but in sRGB
+  fn observed_srgb_at(&self, x: u32, y: u32, background: Color) -> NormalizedColor {
+    if self.existing_alpha != ExistingAlpha::Composite {
+      // The pixel's own color, as if it were opaque
+      let [r, g, b, _] = self.straight_pixel_at(x, y);
+      return [r, g, b];
+    }
+
+    match &self.rgba16 {
+      Some(rgba16) => composite_normalized_over_background(
+        rgba16.get_pixel(x, y).0.map(|c| c as f64 / 65535.0),
+        normalize_color(background),
+        self.input_alpha_mode,
+      ),
+      None => normalize_color(composite_pixel_over_background_with_mode(
+        self.rgba.get_pixel(x, y),
+        background,
+        self.input_alpha_mode,
+      )),
+    }
+  }
+
+  /// Whether the pixel uses the transparent palette index of an indexed input
+  fn is_palette_transparent(&self, pixel: &Rgba<u8>) -> bool {
+    self.palette_transparency && pixel[3] == 0
+  }
+
+  /// The closest foreground color whose tolerance radius contains the observed color
+  fn foreground_within_tolerance(&self, obs_norm: NormalizedColor) -> Option<usize> {
+    self
+      .foreground_colors
+      .iter()
+      .zip(&self.foreground_tolerances)
+      .enumerate()
+      .filter_map(|(k, (&color, tolerance))| {
+        let distance = self.color_distance(obs_norm, self.srgb_to_working(normalize_color(color)));
+        tolerance
+          .filter(|&tolerance| distance <= tolerance)
+          .map(|_| (k, distance))
+      })
+      .min_by(|(_, a), (_, b)| a.total_cmp(b))
+      .map(|(k, _)| k)
+  }
+
+  /// The foreground colors in normalized form, in the space colors are unmixed in
+  fn normalized_foreground_colors(&self) -> Vec<NormalizedColor> {
+    self
+      .foreground_colors
+      .iter()
+      .map(|&color| self.srgb_to_working(normalize_color(color)))
+      .collect()
+  }
+
+  /// Remove the background from the pixel at the given position
+  fn process_pixel(
+    &self,
+    x: u32,
+    y: u32,
+    fg_normalized: &[NormalizedColor],
+    strict_mode: bool,
+  ) -> [u8; 4] {
+    denormalize_rgba(self.process_pixel_normalized(x, y, fg_normalized, strict_mode))
+  }
+
+  /// Remove the background from the pixel at the given position, at full precision
+  fn process_pixel_normalized(
+    &self,
+    x: u32,
+    y: u32,
+    fg_normalized: &[NormalizedColor],
+    strict_mode: bool,
+  ) -> NormalizedRgba {
+    if self.is_palette_transparent(self.rgba.get_pixel(x, y)) {
+      return [0.0, 0.0, 0.0, 0.0];
+    }
+    if let Some(removable) = &self.removable {
+      if !removable[(y * self.rgba.width() + x) as usize] {
+        return self.pixel_normalized(x, y);
+      }
+    }
+    let existing = self.pixel_normalized(x, y)[3];
+    if matches!(
+      self.existing_alpha,
+      ExistingAlpha::Min | ExistingAlpha::Multiply
+    ) && existing == 0.0
+    {
+      return [0.0, 0.0, 0.0, 0.0];
+    }
+
+    let background = self.background_at(x, y);
+    let bg_normalized = self.srgb_to_working(normalize_color(background));
+
+    let observed = self.observed_at(x, y, background);
+    if let Some(tolerance) = self.background_tolerance(background) {
+      if self.color_distance(observed, bg_normalized) <= tolerance {
+        return [0.0, 0.0, 0.0, 0.0];
+      }
+    }
+    if self.preserve_shadows {
+      if let Some(opacity) = shadow_opacity(observed, bg_normalized, self.color_threshold) {
+        return [
+          0.0,
+          0.0,
+          0.0,
+          self.existing_alpha.combine(opacity, existing),
+        ];
+      }
+    }
+    let (observed, bg_normalized) = self.blend_mode.to_normal(observed, bg_normalized);
+
+    let [r, g, b, alpha] =
+      if !strict_mode && self.needs_srgb_minimum_alpha(observed, bg_normalized, fg_normalized) {
+        // OKLab spans far more than the sRGB gamut, so the minimum alpha search over all colors
+        // happens in sRGB, where its foreground colors stay displayable
+        let observed = self.observed_srgb_at(x, y, background);
+        let background = normalize_color(background);
+        let [r, g, b, alpha] = if fg_normalized.is_empty() {
+          process_normalized_non_strict_no_fg(
+            observed,
+            background,
+            self.alpha_model,
+            self.background_epsilon,
+          )
+        } else {
+          let (color, alpha) = find_minimum_alpha_for_color(observed, background);
+          [color[0], color[1], color[2], alpha]
+        };
+        let [r, g, b] = self.srgb_to_working([r, g, b]);
+        [r, g, b, alpha]
+      } else if !strict_mode && fg_normalized.is_empty() {
+        process_normalized_non_strict_no_fg(
+          observed,
+          bg_normalized,
+          self.alpha_model,
+          self.background_epsilon,
+        )
+      } else if !strict_mode {
+        process_normalized_non_strict_with_fg(
+          observed,
+          fg_normalized,
+          bg_normalized,
+          self.color_threshold,
+          |a, b| self.color_distance(a, b),
+          self.reconstruction_tolerance,
+          self.unmix_combinations.as_ref(),
+          self.gamut_mapping,
+          self.background_epsilon,
+        )
+      } else if let Some(k) = self.foreground_within_tolerance(observed) {
+        let [r, g, b] = fg_normalized[k];
+        [r, g, b, 1.0]
+      } else {
+        let unmix_result = unmix_normalized_with_depth(
+          observed,
+          fg_normalized,
+          bg_normalized,
+          self.reconstruction_tolerance,
+          self.unmix_combinations.as_ref(),
+        );
+        let (result_color, alpha) =
+          compute_result_color_with_gamut(&unmix_result, fg_normalized, self.gamut_mapping);
+
+        [result_color[0], result_color[1], result_color[2], alpha]
+      };
+    if alpha == 0.0 {
+      return [0.0, 0.0, 0.0, 0.0];
+    }
+    let [r, g, b] = self.working_to_srgb([r, g, b]);
+    [r, g, b, self.existing_alpha.combine(alpha, existing)]
+  }
+}
+
+/// The normalized closeness threshold of the processing options
+fn color_threshold(options: &ProcessImageOptions) -> Result<f64> {
+  let units = options
+    .threshold_units
+    .as_deref()
+    .map(parse_threshold_units)
+    .transpose()
+    .map_err(|e| {
+      Error::new(
+        Status::InvalidArg,
+        format!("Invalid threshold units: {}", e),
+      )
+    })?
+    .unwrap_or_default();
+  if units == ThresholdUnits::EightBit && options.threshold_space.as_deref() == Some("deltaE2000") {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "8-bit threshold units cannot be combined with the \"deltaE2000\" threshold space"
+        .to_string(),
+    ));
+  }
+
+  Ok(
+    options
+      .threshold
+      .map_or(DEFAULT_COLOR_CLOSENESS_THRESHOLD, |threshold| {
+        units.normalize(threshold)
+      }),
+  )
+}
+
+/// Load the input image and resolve its background and foreground colors
+fn prepare_image(options: &ProcessImageOptions) -> Result<PreparedImage> {
+  // Load image from buffer first (needed for auto-detection)
+  let img = load_input(&options.input, DecodeOptions::from_options(options)?)?;
+  // Transparent palette entries are left out of detection and deduction and stay transparent
+  let palette_transparency = input_has_transparent_palette_index(&options.input);
+  prepare_decoded_image(options, img, palette_transparency)
+}
+
+/// Resolve the background and foreground colors of a decoded input image
+fn prepare_decoded_image(
+  options: &ProcessImageOptions,
+  mut img: DynamicImage,
+  palette_transparency: bool,
+) -> Result<PreparedImage> {
+  if img.width() == 0 || img.height() == 0 {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Image has no pixels to process".to_string(),
+    ));
+  }
+
+  // Floating point inputs were tone mapped to sRGB but are unmixed in linear light by default
+  let working_space = match (options.unmix_space.as_deref(), options.linear_light) {
+    (Some(_), Some(true)) => {
+      return Err(Error::new(
+        Status::InvalidArg,
+        "Linear light cannot be combined with an unmix space".to_string(),
+      ));
+    }
+    (Some(space), _) => parse_unmix_space(space)
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid unmix space: {}", e)))?,
+    (None, Some(true)) => WorkingSpace::LinearLight,
+    (None, None) if is_linear_light(&img) => WorkingSpace::LinearLight,
+    (None, _) => WorkingSpace::Srgb,
+  };
+  let blend_mode = options
+    .blend_mode
+    .as_deref()
+    .map(parse_blend_mode)
+    .transpose()
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid blend mode: {}", e)))?
+    .unwrap_or_default();
+  if blend_mode != BlendMode::Normal && working_space == WorkingSpace::Oklab {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Blend modes other than \"normal\" cannot be combined with the \"oklab\" unmix space"
+        .to_string(),
+    ));
+  }
+
+  // Deblock first so detection, deduction and unmixing all see the smoothed image
+  if options.deblock_edges.unwrap_or(false) {
+    img = DynamicImage::ImageRgba8(deblock_jpeg_blocks(&img.to_rgba8()));
+  }
+
+  // Cut off a solid frame before it skews detection
+  let solid_frame = if options.strip_frame.unwrap_or(false) {
+    detect_solid_frame(&img.to_rgba8(), color_threshold(options)?)
+  } else {
+    None
+  };
+  let full_size = (img.width(), img.height());
+  if let Some(frame) = solid_frame {
+    img = crop_frame(&img, frame);
+  }
+
+  let input_alpha_mode = options
+    .input_alpha_mode
+    .as_deref()
+    .map(parse_alpha_mode)
+    .transpose()
+    .map_err(|e| {
+      Error::new(
+        Status::InvalidArg,
+        format!("Invalid input alpha mode: {}", e),
+      )
+    })?
+    .unwrap_or_default();
+
+  let existing_alpha = options
+    .existing_alpha
+    .as_deref()
+    .map(parse_existing_alpha)
+    .transpose()
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid existing alpha: {}", e)))?
+    .unwrap_or_default();
+
+  let alpha_model = options
+    .alpha_model
+    .as_deref()
+    .map(|spec| {
+      parse_alpha_model(
+        spec,
+        options
+          .alpha_opaque_distance
+          .unwrap_or(DEFAULT_ALPHA_OPAQUE_DISTANCE),
+      )
+    })
+    .transpose()
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid alpha model: {}", e)))?
+    .unwrap_or_default();
+
+  // At most one model of a background that varies across the image, instead of a color
+  let adaptive_background = options.adaptive_background.unwrap_or(false);
+  let gradient_background = options.gradient_background.unwrap_or(false);
+  let checkerboard_background = options.checkerboard_background.unwrap_or(false);
+  let tiled_background = options.tiled_background.unwrap_or(false);
+  let background_models: Vec<&str> = [
+    ("Adaptive background", adaptive_background),
+    ("Gradient background", gradient_background),
+    ("Background plate", options.background_plate.is_some()),
+    ("Checkerboard background", checkerboard_background),
+    ("Tiled background", tiled_background),
+  ]
+  .into_iter()
+  .filter_map(|(name, enabled)| enabled.then_some(name))
+  .collect();
+  match background_models[..] {
+    [first, second, ..] => {
+      return Err(Error::new(
+        Status::InvalidArg,
+        format!(
+          "{} cannot be combined with {}",
+          first,
+          second.to_lowercase()
+        ),
+      ));
+    }
+    [model] if options.background_color.is_some() => {
+      return Err(Error::new(
+        Status::InvalidArg,
+        format!(
+          "{} cannot be combined with an explicit background color",
+          model
+        ),
+      ));
+    }
+    _ => {}
+  }
+
+  let background_plate = options
+    .background_plate
+    .as_ref()
+    .map(|plate| load_background_plate(plate, options, full_size, solid_frame))
+    .transpose()?;
+
+  let detection_config = background_detection_config(
+    options.background_luminance_bias.as_deref(),
+    options.background_detection.as_ref(),
+    palette_transparency,
+  )?;
+
+  // Scale the background at the edges to white before anything is detected, and the plate
+  // along with the image
+  let channel_gains = options.auto_white_balance.unwrap_or(false).then(|| {
+    white_balance_gains(detect_background_color_with_config(
+      background_plate.as_ref().unwrap_or(&img),
+      &detection_config,
+    ))
+  });
+  let background_plate = match channel_gains {
+    Some(gains) => {
+      img = apply_channel_gains(&img, gains);
+      background_plate.map(|plate| apply_channel_gains(&plate, gains))
+    }
+    None => background_plate,
+  };
+
+  let empty_list = || {
+    Error::new(
+      Status::InvalidArg,
+      "Background color list must not be empty".to_string(),
+    )
+  };
+  let background_colors = match &options.background_color {
+    None => &[],
+    // An empty list reads as an empty color array, as single colors are tried first
+    Some(Either::A(Either3::C(channels))) if channels.is_empty() => return Err(empty_list()),
+    Some(Either::A(color)) => std::slice::from_ref(color),
+    Some(Either::B(colors)) if colors.is_empty() => return Err(empty_list()),
+    Some(Either::B(colors)) => colors.as_slice(),
+  }
+  .iter()
+  .map(|color| parse_color_input(color, parse_background_spec, |color| color))
+  .collect::<anyhow::Result<Vec<(Color, Option<f64>)>>>()
+  .map_err(|e| {
+    Error::new(
+      Status::InvalidArg,
+      format!("Invalid background color: {}", e),
+    )
+  })?;
+  let (background_colors, background_tolerances): (Vec<_>, Vec<_>) =
+    background_colors.into_iter().unzip();
+
+  // Given colors are sRGB, so they are mapped into the color space of inputs kept unconverted
+  let input_profile = (options.convert_to_srgb == Some(false))
+    .then(|| input_icc_profile(&options.input))
+    .flatten();
+  let to_input_space = |color: Color| match &input_profile {
+    Some(profile) => convert_from_srgb(color, profile).map_err(|e| {
+      Error::new(
+        Status::InvalidArg,
+        format!("Failed to map colors to the input color space: {}", e),
+      )
+    }),
+    None => Ok(color),
+  };
+  let background_colors = background_colors
+    .into_iter()
+    .map(to_input_space)
+    .collect::<Result<Vec<_>>>()?;
+
+  // Determine background color (auto-detect if not specified, from the plate if there is one)
+  let detection_source = background_plate.as_ref().unwrap_or(&img);
+  let chroma_key = if background_colors.is_empty() && options.chroma_key.unwrap_or(false) {
+    detect_background_chroma_key(detection_source, &detection_config)
+  } else {
+    None
+  };
+  let background_color = if let Some(&first) = background_colors.first() {
+    first
+  } else if let Some(key) = &chroma_key {
+    key.color
+  } else {
+    detect_background_color_with_config(detection_source, &detection_config)
+  };
+
+  // Parse foreground color specs (supports "auto" for deduction)
+  let (foreground_specs, mut foreground_tolerances): (Vec<_>, Vec<_>) = options
+    .foreground_colors
+    .as_ref()
+    .unwrap_or(&Vec::new())
+    .iter()
+    .map(|color| {
+      parse_color_input(
+        color,
+        parse_foreground_spec_with_tolerance,
+        ForegroundColorSpec::Known,
+      )
+    })
+    .collect::<anyhow::Result<Vec<(ForegroundColorSpec, Option<f64>)>>>()
+    .map_err(|e| {
+      Error::new(
+        Status::InvalidArg,
+        format!("Invalid foreground color: {}", e),
+      )
+    })?
+    .into_iter()
+    .unzip();
+  let mut foreground_specs = foreground_specs
+    .into_iter()
+    .map(|spec| match spec {
+      ForegroundColorSpec::Known(color) => to_input_space(color).map(ForegroundColorSpec::Known),
+      ForegroundColorSpec::Unknown => Ok(ForegroundColorSpec::Unknown),
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  let color_threshold = color_threshold(options)?;
+
+  // Measure how well a single detected color describes the edges; spatial backgrounds vary
+  // along the edges by design, and so do lit chroma keys
+  let background_uniformity =
+    (options.background_color.is_none() && background_models.is_empty() && chroma_key.is_none())
+      .then(|| {
+        let (matching, samples) =
+          background_uniformity(&img, &detection_config, background_color, color_threshold);
+        matching as f64 / samples.max(1) as f64
+      });
+
+  let reconstruction_tolerance = options
+    .reconstruction_tolerance
+    .unwrap_or(DEFAULT_RECONSTRUCTION_TOLERANCE);
+  if reconstruction_tolerance.is_nan() || reconstruction_tolerance < 0.0 {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!(
+        "Reconstruction tolerance must not be negative (got: {})",
+        reconstruction_tolerance
+      ),
+    ));
+  }
+
+  let background_epsilon = match options.background_epsilon {
+    Some(levels) if levels.is_nan() || levels < 0.0 => {
+      return Err(Error::new(
+        Status::InvalidArg,
+        format!("Background epsilon must not be negative (got: {})", levels),
+      ));
+    }
+    Some(levels) => levels / 255.0,
+    None => DEFAULT_BACKGROUND_EPSILON,
+  };
+
+  let gamut_mapping = options
+    .gamut_mapping
+    .as_deref()
+    .map(parse_gamut_mapping)
+    .transpose()
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid gamut mapping: {}", e)))?
+    .unwrap_or_default();
+  let threshold_space = options
+    .threshold_space
+    .as_deref()
+    .map(parse_threshold_space)
+    .transpose()
+    .map_err(|e| {
+      Error::new(
+        Status::InvalidArg,
+        format!("Invalid threshold space: {}", e),
+      )
+    })?
+    .unwrap_or_default();
+
+  // Put the dominant interior color first, unless it was already specified
+  if options.auto_primary_foreground.unwrap_or(false) {
+    if let Some(primary) = detect_primary_foreground_color(&img, background_color, color_threshold)
+    {
+      let already_known = foreground_specs
+        .iter()
+        .any(|spec| matches!(spec, ForegroundColorSpec::Known(color) if *color == primary));
+      if !already_known {
+        foreground_specs.insert(0, ForegroundColorSpec::Known(primary));
+        foreground_tolerances.insert(0, None);
+      }
+    }
+  }
+
+  let deduction_defaults = DeductionConfig::default();
+  let deduction_config = DeductionConfig {
+    weighting: options
+      .deduction_weighting
+      .as_deref()
+      .map(parse_deduction_weighting)
+      .transpose()
+      .map_err(|e| {
+        Error::new(
+          Status::InvalidArg,
+          format!("Invalid deduction weighting: {}", e),
+        )
+      })?
+      .unwrap_or_default(),
+    max_observed_colors: options
+      .deduction_sample_colors
+      .map_or(deduction_defaults.max_observed_colors, |n| n as usize),
+    skip_transparent_pixels: palette_transparency,
+    max_evaluations: options.deduction_max_evaluations.map(|n| n as usize),
+    time_budget: options
+      .deduction_time_budget_ms
+      .map(|ms| Duration::from_millis(ms as u64)),
+    ..deduction_defaults
+  };
+
+  // Deduce unknown colors if any "auto" specs were provided
+  let foreground_colors = deduce_unknown_colors_with_config(
+    &img,
+    &foreground_specs,
+    background_color,
+    color_threshold,
+    &deduction_config,
+  )
+  .map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to deduce foreground colors: {}", e),
+    )
+  })?;
+  let unmix_combinations = options
+    .unmix_search_depth
+    .map(|depth| ColorCombinations::new(foreground_colors.len(), depth as usize))
+    .transpose()
+    .map_err(|e| {
+      Error::new(
+        Status::InvalidArg,
+        format!("Invalid unmix search depth: {}", e),
+      )
+    })?
+    .flatten();
+
+  // Estimate the background per pixel if requested; the global color is still used for deduction
+  let local_background = if let Some(plate) = background_plate {
+    Some(SpatialBackground::Plate(plate.to_rgb8()))
+  } else if adaptive_background {
+    Some(SpatialBackground::Local(LocalBackgroundModel::from_image(
+      &img,
+      &detection_config,
+    )))
+  } else if gradient_background {
+    Some(SpatialBackground::Gradient(
+      GradientBackgroundModel::from_image(&img, &detection_config),
+    ))
+  } else if checkerboard_background {
+    let model =
+      CheckerboardBackgroundModel::from_image(&img, color_threshold).ok_or_else(|| {
+        Error::new(
+          Status::InvalidArg,
+          "No checkerboard pattern found along the edges of the image".to_string(),
+        )
+      })?;
+    Some(SpatialBackground::Checkerboard(model))
+  } else if tiled_background {
+    let tile_size = options
+      .background_tile_size
+      .unwrap_or(DEFAULT_BACKGROUND_TILE_SIZE);
+    if tile_size == 0 {
+      return Err(Error::new(
+        Status::InvalidArg,
+        "Background tile size must be at least 1".to_string(),
+      ));
+    }
+    Some(SpatialBackground::Tiled(TiledBackgroundModel::from_image(
+      &img,
+      background_color,
+      tile_size,
+    )))
+  } else {
+    None
+  };
+
+  // Keep the full precision of 16-bit and floating point inputs for unmixing
+  let color = img.color();
+  let rgba16 = (color.bytes_per_pixel() > color.channel_count()).then(|| img.to_rgba16());
+
+  let mut prepared = PreparedImage {
+    rgba: img.to_rgba8(),
+    rgba16,
+    background_color,
+    background_palette: background_colors,
+    background_tolerances,
+    foreground_colors,
+    foreground_tolerances,
+    color_threshold,
+    threshold_space,
+    reconstruction_tolerance,
+    unmix_combinations,
+    background_epsilon,
+    gamut_mapping,
+    local_background,
+    input_alpha_mode,
+    existing_alpha,
+    alpha_model,
+    palette_transparency,
+    working_space,
+    blend_mode,
+    removable: None,
+    protect_holes: options.protect_holes.unwrap_or(false),
+    solid_frame,
+    channel_gains,
+    preserve_shadows: options.preserve_shadows.unwrap_or(false),
+    solid_color: false,
+    background_uniformity,
+  };
+  // Such an image is nothing but background, so the whole output ends up transparent
+  prepared.solid_color = options.background_color.is_none()
+    && options.background_plate.is_none()
+    && chroma_key.is_none()
+    && detect_solid(&prepared.rgba).is_some();
+  if options.connected_removal.unwrap_or(false) {
+    prepared.removable = Some(prepared.border_connected_region());
+  }
+  Ok(prepared)
+}
+
+/// Load the background plate of an input, decoded and smoothed the same way as the input
+fn load_background_plate(
+  plate: &ImageInput,
+  options: &ProcessImageOptions,
+  (width, height): (u32, u32),
+  solid_frame: Option<Margins>,
+) -> Result<DynamicImage> {
+  let plate = load_input(plate, DecodeOptions::from_options(options)?).map_err(|e| {
+    Error::new(
+      Status::InvalidArg,
+      format!("Invalid background plate: {}", e.reason),
+    )
+  })?;
+  if (plate.width(), plate.height()) != (width, height) {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!(
+        "Background plate must be the same size as the image ({}x{}, got: {}x{})",
+        width,
+        height,
+        plate.width(),
+        plate.height()
+      ),
+    ));
+  }
+
+  let plate = if options.deblock_edges.unwrap_or(false) {
+    DynamicImage::ImageRgba8(deblock_jpeg_blocks(&plate.to_rgba8()))
+  } else {
+    plate
+  };
+  Ok(match solid_frame {
+    Some(frame) => crop_frame(&plate, frame),
+    None => plate,
+  })
+}
+
+/// The inside of a frame around an image
+fn crop_frame(img: &DynamicImage, frame: Margins) -> DynamicImage {
+  img.crop_imm(
+    frame.left,
+    frame.top,
+    img.width() - frame.left - frame.right,
+    img.height() - frame.top - frame.bottom,
+  )
+}
+
+/// Run the full background removal pipeline and return the (optionally trimmed) RGBA result,
+/// along with how it was cropped from the original image
+fn process_image_to_rgba(options: &ProcessImageOptions) -> Result<(RgbaImage, Crop)> {
+  let prepared = prepare_image(options)?;
+  let output_img = remove_background(&prepared, options.strict_mode);
+  let (output_img, crop) = finish_output(options, output_img)?;
+  Ok((output_img, crop.of_prepared(&prepared)))
+}
+
+/// Remove the background from every pixel of a prepared image
+fn remove_background(prepared: &PreparedImage, strict_mode: bool) -> RgbaImage {
+  let (width, height) = prepared.rgba.dimensions();
+  let fg_normalized = prepared.normalized_foreground_colors();
+
+  let processed_pixels: Vec<[u8; 4]> = (0..width * height)
+    .into_par_iter()
+    .map(|i| prepared.process_pixel(i % width, i / width, &fg_normalized, strict_mode))
+    .collect();
+
+  let mut output_img = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(width, height);
+  for (i, pixel) in output_img.pixels_mut().enumerate() {
+    *pixel = Rgba(processed_pixels[i]);
+  }
+  if prepared.protect_holes {
+    fill_enclosed_holes(&mut output_img, &prepared.rgba);
+  }
+  output_img
+}
+
+/// Run the background removal pipeline on every frame of an animation, returning the frames
+/// and how they were cropped
+///
+/// The background and foreground colors are resolved once, from the first frame, so every
+/// frame is unmixed against the same colors. Trimming crops all frames to the union of their
+/// content so they keep their relative placement.
+///
+/// Every frame is returned whole, to replace the previous one. The source's disposal and blend
+/// operations can't carry over: removing the background makes parts of each frame transparent,
+/// and blending a frame over the previous one would let that one show through there.
+fn process_animation(
+  options: &ProcessImageOptions,
+  frames: Vec<Frame>,
+) -> Result<(Vec<Frame>, Crop)> {
+  let delays: Vec<_> = frames.iter().map(|frame| frame.delay()).collect();
+  let mut frames = frames.into_iter();
+  let Some(first) = frames.next() else {
+    return Ok((Vec::new(), Crop::NONE));
+  };
+
+  // Transparent palette entries are left out of detection and deduction, as for still images
+  let palette_transparency = input_has_transparent_palette_index(&options.input);
+  let mut prepared = prepare_decoded_image(
+    options,
+    DynamicImage::ImageRgba8(first.into_buffer()),
+    palette_transparency,
+  )?;
+
+  let mut processed = vec![outline_output(
+    options,
+    remove_background(&prepared, options.strict_mode),
+  )?];
+  for frame in frames {
+    let mut frame = frame.into_buffer();
+    if options.deblock_edges.unwrap_or(false) {
+      frame = deblock_jpeg_blocks(&frame);
+    }
+    prepared = prepared.with_frame(frame);
+    processed.push(outline_output(
+      options,
+      remove_background(&prepared, options.strict_mode),
+    )?);
+  }
+
+  let mut crop = Crop::NONE;
+  if options.trim {
+    let bounds = processed
+      .iter()
+      .filter_map(content_bounds)
+      .map(|(x, y, w, h)| (x, y, x + w, y + h))
+      .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)));
+    crop = Crop::from_bounds(
+      bounds.map(|(x0, y0, x1, y1)| (x0, y0, x1 - x0, y1 - y0)),
+      processed[0].dimensions(),
+    );
+    processed = processed
+      .iter()
+      .map(|img| match bounds {
+        Some((x0, y0, x1, y1)) => {
+          image::imageops::crop_imm(img, x0, y0, x1 - x0, y1 - y0).to_image()
+        }
+        None => RgbaImage::new(1, 1),
+      })
+      .collect();
+  }
+
+  let frames = processed
+    .into_iter()
+    .zip(delays)
+    .map(|(img, delay)| Frame::from_parts(img, 0, 0, delay))
     .collect();
+  Ok((frames, crop.of_prepared(&prepared)))
+}
 
-  let unmix_result = crate::unmix::UnmixResult { weights, alpha };
-  let (result_color, result_alpha) = compute_result_color(&unmix_result, &fg_normalized);
-  let final_color = denormalize_color(result_color);
+/// Run the background removal pipeline at 16 bits per channel, returning the result and how
+/// it was cropped from the processed image
+fn process_image_to_rgba16(options: &ProcessImageOptions) -> Result<(Rgba16Image, Crop)> {
+  if options.outline.is_some() {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Outlines are not supported with 16-bit output".to_string(),
+    ));
+  }
 
-  RgbaColor {
-    r: final_color[0],
-    g: final_color[1],
-    b: final_color[2],
-    a: (result_alpha * 255.0).round() as u8,
+  let prepared = prepare_image(options)?;
+  let (width, height) = prepared.rgba.dimensions();
+  let fg_normalized = prepared.normalized_foreground_colors();
+
+  let strict_mode = options.strict_mode;
+  let processed_pixels: Vec<u16> = (0..width * height)
+    .into_par_iter()
+    .flat_map_iter(|i| {
+      denormalize_rgba16(prepared.process_pixel_normalized(
+        i % width,
+        i / width,
+        &fg_normalized,
+        strict_mode,
+      ))
+    })
+    .collect();
+  let mut output_img = Rgba16Image::from_raw(width, height, processed_pixels)
+    .expect("one pixel was processed per input pixel");
+  if prepared.protect_holes {
+    let original = prepared
+      .rgba16
+      .clone()
+      .unwrap_or_else(|| DynamicImage::ImageRgba8(prepared.rgba.clone()).to_rgba16());
+    fill_enclosed_holes(&mut output_img, &original);
+  }
+
+  if !options.trim {
+    return Ok((output_img, Crop::NONE.of_prepared(&prepared)));
   }
+
+  let crop = Crop::from_bounds(content_bounds(&output_img), output_img.dimensions());
+  Ok((trim_to_content(&output_img), crop.of_prepared(&prepared)))
 }
 
-#[napi]
-/// Composite an RGBA pixel over an RGB background color
-///
-/// If the input pixel is translucent (alpha < 255), this pre-composes it over
-/// the background color to produce an opaque equivalent.
-///
-/// # Arguments
-/// * `pixel` - The RGBA pixel color
-/// * `background` - The background RGB color
+/// Run the background removal pipeline tile by tile, passing each finished tile, encoded in
+/// the output format, to `on_tile`, and encode the full image if `full_image` is set
+fn process_image_by_tiles(
+  options: &ProcessImageOptions,
+  tile_size: u32,
+  full_image: bool,
+  on_tile: &BoundedCallback<ProcessedTile>,
+) -> Result<Option<Vec<u8>>> {
+  if options.protect_holes.unwrap_or(false) {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Hole protection is not supported with tiled processing".to_string(),
+    ));
+  }
+
+  let output_format = output_format(options)?;
+  let high_precision = output_bit_depth(options)? == 16;
+  if high_precision && options.outline.is_some() {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Outlines are not supported with 16-bit output".to_string(),
+    ));
+  }
+
+  let prepared = prepare_image(options)?;
+  let dimensions = prepared.rgba.dimensions();
+  let fg_normalized = prepared.normalized_foreground_colors();
+  let strict_mode = options.strict_mode;
+  let pixel = |x, y| prepared.process_pixel_normalized(x, y, &fg_normalized, strict_mode);
+
+  if high_precision {
+    let Some(output_img) = process_tiles(
+      dimensions,
+      tile_size,
+      full_image,
+      on_tile,
+      |x, y| Rgba(denormalize_rgba16(pixel(x, y))),
+      |tile| encode_output16(options, output_format, tile, Crop::NONE),
+    )?
+    else {
+      return Ok(None);
+    };
+
+    let (output_img, crop) = if options.trim {
+      let crop = Crop::from_bounds(content_bounds(&output_img), output_img.dimensions());
+      (trim_to_content(&output_img), crop)
+    } else {
+      (output_img, Crop::NONE)
+    };
+    let crop = crop.of_prepared(&prepared);
+    return encode_output16(options, output_format, &output_img, crop).map(Some);
+  }
+
+  let Some(output_img) = process_tiles(
+    dimensions,
+    tile_size,
+    full_image,
+    on_tile,
+    |x, y| Rgba(denormalize_rgba(pixel(x, y))),
+    |tile| encode_output(options, output_format, tile, Crop::NONE),
+  )?
+  else {
+    return Ok(None);
+  };
+
+  let (output_img, crop) = finish_output(options, output_img)?;
+  encode_output(
+    options,
+    output_format,
+    &output_img,
+    crop.of_prepared(&prepared),
+  )
+  .map(Some)
+}
+
+/// Process the tiles of an image in parallel, passing each one to `on_tile` as `encode`
+/// encodes it, and put them together into the full image if `full_image` is set
+fn process_tiles<T: Primitive + Send + Sync>(
+  (width, height): (u32, u32),
+  tile_size: u32,
+  full_image: bool,
+  on_tile: &BoundedCallback<ProcessedTile>,
+  pixel: impl Fn(u32, u32) -> Rgba<T> + Sync,
+  encode: impl Fn(&ImageBuffer<Rgba<T>, Vec<T>>) -> Result<Vec<u8>> + Sync,
+) -> Result<Option<ImageBuffer<Rgba<T>, Vec<T>>>>
+where
+  Rgba<T>: Pixel<Subpixel = T>,
+{
+  let origins: Vec<(u32, u32)> = (0..height)
+    .step_by(tile_size as usize)
+    .flat_map(|y| (0..width).step_by(tile_size as usize).map(move |x| (x, y)))
+    .collect();
+
+  let tiles = origins
+    .into_par_iter()
+    .map(|(x, y)| {
+      let (tile_width, tile_height) = (tile_size.min(width - x), tile_size.min(height - y));
+      let tile = ImageBuffer::from_fn(tile_width, tile_height, |tx, ty| pixel(x + tx, y + ty));
+
+      let status = on_tile.call(
+        Ok(ProcessedTile {
+          x,
+          y,
+          width: tile_width,
+          height: tile_height,
+          data: encode(&tile)?.into(),
+        }),
+        ThreadsafeFunctionCallMode::Blocking,
+      );
+      if status != Status::Ok {
+        return Err(Error::new(
+          Status::GenericFailure,
+          format!("Failed to pass a tile to JavaScript: {}", status),
+        ));
+      }
+
+      Ok(full_image.then_some((x, y, tile)))
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  if !full_image {
+    return Ok(None);
+  }
+
+  let mut output_img = ImageBuffer::new(width, height);
+  for (x, y, tile) in tiles.into_iter().flatten() {
+    image::imageops::replace(&mut output_img, &tile, x as i64, y as i64);
+  }
+  Ok(Some(output_img))
+}
+
+/// Apply the outline and trimming steps to a processed image, returning the result and how
+/// it was cropped from the processed image
+fn finish_output(
+  options: &ProcessImageOptions,
+  output_img: RgbaImage,
+) -> Result<(RgbaImage, Crop)> {
+  let output_img = outline_output(options, output_img)?;
+
+  if !options.trim {
+    return Ok((output_img, Crop::NONE));
+  }
+
+  let crop = Crop::from_bounds(content_bounds(&output_img), output_img.dimensions());
+  Ok((trim_to_content(&output_img), crop))
+}
+
+/// Where an output image sat in the input before `trim` and `strip_frame` cropped it, and
+/// how reliably its background was detected
+#[derive(Clone, Copy)]
+struct Crop {
+  /// The position of the output's top-left corner, in pixels
+  offset: (u32, u32),
+  /// Whether `trim` cut off any rows or columns
+  trimmed: bool,
+  /// The solid frame cut off the input, if any
+  frame: Option<Margins>,
+  /// Whether the input is a single solid color that was detected as the background
+  solid_color: bool,
+  /// The share of edge samples matching the detected background color, if a single color was
+  /// detected
+  background_uniformity: Option<f64>,
+}
+
+impl Crop {
+  /// The crop of an image that was not trimmed
+  const NONE: Crop = Crop {
+    offset: (0, 0),
+    trimmed: false,
+    frame: None,
+    solid_color: false,
+    background_uniformity: None,
+  };
+
+  /// The crop of an image processed inside a solid frame
+  fn within_frame(self, frame: Option<Margins>) -> Self {
+    match frame {
+      Some(margins) => Crop {
+        offset: (self.offset.0 + margins.left, self.offset.1 + margins.top),
+        frame,
+        ..self
+      },
+      None => self,
+    }
+  }
+
+  /// The crop of an image processed from a prepared input
+  fn of_prepared(self, prepared: &PreparedImage) -> Self {
+    Crop {
+      solid_color: prepared.solid_color,
+      background_uniformity: prepared.background_uniformity,
+      ..self.within_frame(prepared.solid_frame)
+    }
+  }
+
+  /// The crop `trim_to_content` makes given the content bounds of an image and its size
+  fn from_bounds(bounds: Option<(u32, u32, u32, u32)>, (width, height): (u32, u32)) -> Self {
+    match bounds {
+      Some((x, y, w, h)) => Crop {
+        offset: (x, y),
+        trimmed: (w, h) != (width, height),
+        frame: None,
+        solid_color: false,
+        background_uniformity: None,
+      },
+      // Fully transparent images are reduced to a single pixel
+      None => Crop {
+        offset: (0, 0),
+        trimmed: (width, height) != (1, 1),
+        frame: None,
+        solid_color: false,
+        background_uniformity: None,
+      },
+    }
+  }
+}
+
+/// Draw the requested outline around a processed image, if any
+fn outline_output(options: &ProcessImageOptions, output_img: RgbaImage) -> Result<RgbaImage> {
+  Ok(if let Some(outline) = &options.outline {
+    let stroke_color = parse_css_color(&outline.color)
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid outline color: {}", e)))?;
+    let opacity = outline.opacity.unwrap_or(1.0);
+    if !(0.0..=1.0).contains(&opacity) {
+      return Err(Error::new(
+        Status::InvalidArg,
+        format!(
+          "Outline opacity must be between 0.0 and 1.0 (got: {})",
+          opacity
+        ),
+      ));
+    }
+    apply_outline(&output_img, stroke_color, outline.width, opacity)
+  } else {
+    output_img
+  })
+}
+
+/// The alphas that composite layers of the given unmix weights, first to last, into the mix
 ///
-/// # Returns
-/// The composited RGB color
-pub fn composite_over_background(pixel: RgbaColor, background: RgbColor) -> RgbColor {
-  let rgba_pixel = Rgba([pixel.r, pixel.g, pixel.b, pixel.a]);
-  let bg_color: Color = [background.r, background.g, background.b];
-  let result = composite_pixel_over_background(&rgba_pixel, bg_color);
-  RgbColor {
-    r: result[0],
-    g: result[1],
-    b: result[2],
+/// Every layer covers a share of whatever the layers above it let through, so a layer's alpha
+/// is its weight over what is left of the pixel once the weights above it are taken out.
+fn stacked_alphas(weights: &[f64]) -> Vec<f64> {
+  let mut alphas = vec![0.0; weights.len()];
+  let mut above = 0.0;
+  for (alpha, &weight) in alphas.iter_mut().zip(weights).rev() {
+    *alpha = if above < 1.0 {
+      (weight / (1.0 - above)).min(1.0)
+    } else {
+      0.0
+    };
+    above += weight;
   }
+  alphas
 }
 
-#[napi]
-/// Get the default threshold for color closeness
+/// Unmix every pixel against the foreground colors and build one layer per color
 ///
-/// # Returns
-/// The default threshold (0.05 = 5% of max RGB distance)
-pub fn get_default_threshold() -> f64 {
-  DEFAULT_COLOR_CLOSENESS_THRESHOLD
+/// Each layer has its foreground color as RGB and that color's unmix weight as alpha. With
+/// `stacked`, the alphas are instead those that composite the layers over the background,
+/// first to last, into the input (see `stacked_alphas`). The layers are returned along with
+/// how they were cropped.
+fn process_image_to_layers(
+  options: &ProcessImageOptions,
+  stacked: bool,
+) -> Result<(Vec<RgbaImage>, Crop)> {
+  let prepared = prepare_image(options)?;
+  let (width, height) = prepared.rgba.dimensions();
+
+  if prepared.foreground_colors.is_empty() {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Layer separation requires at least one foreground color".to_string(),
+    ));
+  }
+
+  let weights: Vec<Vec<f64>> = unmix_foreground_weights(&prepared)
+    .into_iter()
+    .map(|weights| {
+      if stacked {
+        stacked_alphas(&weights)
+      } else {
+        weights
+      }
+    })
+    .collect();
+
+  let layers: Vec<RgbaImage> = prepared
+    .foreground_colors
+    .iter()
+    .enumerate()
+    .map(|(k, &[r, g, b])| {
+      RgbaImage::from_fn(width, height, |x, y| {
+        let weight = weights[(y * width + x) as usize][k];
+        Rgba([r, g, b, (weight * 255.0).round().clamp(0.0, 255.0) as u8])
+      })
+    })
+    .collect();
+
+  if !options.trim {
+    return Ok((layers, Crop::NONE.of_prepared(&prepared)));
+  }
+
+  // Crop every layer to the union of their content so they stay aligned
+  let bounds =
+    layers
+      .iter()
+      .filter_map(content_bounds)
+      .reduce(|(ax, ay, aw, ah), (bx, by, bw, bh)| {
+        let (x, y) = (ax.min(bx), ay.min(by));
+        let (right, bottom) = ((ax + aw).max(bx + bw), (ay + ah).max(by + bh));
+        (x, y, right - x, bottom - y)
+      });
+
+  let crop = Crop::from_bounds(bounds, (width, height)).of_prepared(&prepared);
+  let layers = match bounds {
+    Some((x, y, w, h)) => layers
+      .iter()
+      .map(|layer| image::imageops::crop_imm(layer, x, y, w, h).to_image())
+      .collect(),
+    None => layers
+      .iter()
+      .map(|_| RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 0])))
+      .collect(),
+  };
+  Ok((layers, crop))
 }
 
-fn process_image_internal(options: &ProcessImageOptions) -> Result<Vec<u8>> {
-  // Load image from buffer first (needed for auto-detection)
-  let img = image::load_from_memory(&options.input)
-    .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to load image: {}", e)))?;
+/// Unmix every pixel against the foreground colors, returning each pixel's weights in row order
+fn unmix_foreground_weights(prepared: &PreparedImage) -> Vec<Vec<f64>> {
+  let width = prepared.rgba.width();
+  let fg_normalized = prepared.normalized_foreground_colors();
+
+  let pixels: Vec<_> = prepared.rgba.pixels().collect();
+  pixels
+    .par_iter()
+    .enumerate()
+    .map(|(i, pixel)| {
+      if prepared.is_palette_transparent(pixel) {
+        return vec![0.0; fg_normalized.len()];
+      }
+
+      let (x, y) = (i as u32 % width, i as u32 / width);
+      let background = prepared.background_at(x, y);
+      let (observed, bg_normalized) = prepared.blend_mode.to_normal(
+        prepared.observed_at(x, y, background),
+        prepared.srgb_to_working(normalize_color(background)),
+      );
+      if let Some(k) = prepared.foreground_within_tolerance(observed) {
+        let mut weights = vec![0.0; fg_normalized.len()];
+        weights[k] = 1.0;
+        return weights;
+      }
 
-  // Determine background color (auto-detect if not specified)
-  let background_color = if let Some(bg_hex) = &options.background_color {
-    parse_hex_color(bg_hex).map_err(|e| {
+      unmix_normalized_with_depth(
+        observed,
+        &fg_normalized,
+        bg_normalized,
+        prepared.reconstruction_tolerance,
+        prepared.unmix_combinations.as_ref(),
+      )
+      .weights
+    })
+    .collect()
+}
+
+/// Build the background detection config for an optional luminance bias and sampling options
+fn background_detection_config(
+  luminance_bias: Option<&str>,
+  detection: Option<&BackgroundDetectionOptions>,
+  skip_transparent_pixels: bool,
+) -> Result<BackgroundDetectionConfig> {
+  let luminance_bias = luminance_bias
+    .map(parse_luminance_bias)
+    .transpose()
+    .map_err(|e| {
       Error::new(
         Status::InvalidArg,
-        format!("Invalid background color: {}", e),
+        format!("Invalid background luminance bias: {}", e),
       )
     })?
-  } else {
-    detect_bg(&img)
-  };
+    .unwrap_or_default();
 
-  // Parse foreground color specs (supports "auto" for deduction)
-  let foreground_specs = options
-    .foreground_colors
-    .as_ref()
-    .unwrap_or(&Vec::new())
-    .iter()
-    .map(|c| parse_foreground_spec(c))
-    .collect::<anyhow::Result<Vec<ForegroundColorSpec>>>()
+  let defaults = BackgroundDetectionConfig::default();
+  let edge_sample_interval = detection
+    .and_then(|detection| detection.edge_sample_interval)
+    .unwrap_or(defaults.edge_sample_interval);
+  if edge_sample_interval == 0 {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "Edge sample interval must be at least 1".to_string(),
+    ));
+  }
+
+  let strategy = detection
+    .and_then(|detection| detection.strategy.as_deref())
+    .map(|spec| {
+      parse_detection_strategy(
+        spec,
+        detection
+          .and_then(|detection| detection.border_depth)
+          .unwrap_or(DEFAULT_BORDER_DEPTH),
+      )
+    })
+    .transpose()
     .map_err(|e| {
       Error::new(
         Status::InvalidArg,
-        format!("Invalid foreground color: {}", e),
+        format!("Invalid detection strategy: {}", e),
       )
-    })?;
+    })?
+    .unwrap_or_default();
 
-  let color_threshold = options
-    .threshold
-    .unwrap_or(DEFAULT_COLOR_CLOSENESS_THRESHOLD);
+  let estimator = detection
+    .and_then(|detection| detection.estimator.as_deref())
+    .map(parse_background_estimator)
+    .transpose()
+    .map_err(|e| {
+      Error::new(
+        Status::InvalidArg,
+        format!("Invalid background estimator: {}", e),
+      )
+    })?
+    .unwrap_or_default();
 
-  // Deduce unknown colors if any "auto" specs were provided
-  let foreground_colors =
-    deduce_unknown_colors(&img, &foreground_specs, background_color, color_threshold).map_err(
-      |e| {
-        Error::new(
-          Status::GenericFailure,
-          format!("Failed to deduce foreground colors: {}", e),
-        )
+  let excluded_regions = detection
+    .and_then(|detection| detection.exclude_regions.as_ref())
+    .map(|regions| {
+      regions
+        .iter()
+        .map(|region| Region {
+          x: region.x,
+          y: region.y,
+          width: region.width,
+          height: region.height,
+        })
+        .collect()
+    })
+    .unwrap_or_default();
+
+  Ok(BackgroundDetectionConfig {
+    strategy,
+    edge_sample_interval,
+    estimator,
+    luminance_bias,
+    skip_transparent_pixels,
+    excluded_regions,
+  })
+}
+
+/// Parse a color option: strings go through `parse_spec`, while RGB objects and arrays are
+/// taken exactly, without a tolerance
+fn parse_color_input<T, P>(
+  color: &ColorInput,
+  parse_spec: P,
+  exact: fn(Color) -> T,
+) -> anyhow::Result<(T, Option<f64>)>
+where
+  P: Fn(&str) -> anyhow::Result<(T, Option<f64>)>,
+{
+  let color = match color {
+    Either3::A(spec) => return parse_spec(spec),
+    Either3::B(RgbColor { r, g, b }) => [*r, *g, *b],
+    Either3::C(channels) => <[u8; 3]>::try_from(channels.as_slice()).map_err(|_| {
+      anyhow::anyhow!(
+        "Color arrays must hold 3 channels (got: {})",
+        channels.len()
+      )
+    })?,
+  };
+  Ok((exact(color), None))
+}
+
+/// The encoded bytes of an input, decoding data URLs, or `None` for raw RGBA pixels
+fn encoded_input(input: &ImageInput) -> Result<Option<Cow<'_, [u8]>>> {
+  match input {
+    Either3::A(buffer) => Ok(Some(Cow::Borrowed(buffer))),
+    Either3::B(_) => Ok(None),
+    Either3::C(url) => parse_data_url(url)
+      .map(|buffer| Some(Cow::Owned(buffer)))
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to load image: {}", e))),
+  }
+}
+
+/// The ICC profile embedded in an encoded input, if any
+fn input_icc_profile(input: &ImageInput) -> Option<Vec<u8>> {
+  let buffer = encoded_input(input).ok()??;
+  let mut decoder = image::ImageReader::new(Cursor::new(&buffer[..]))
+    .with_guessed_format()
+    .ok()?
+    .into_decoder()
+    .ok()?;
+  decoder.icc_profile().ok()?
+}
+
+/// How encoded inputs are decoded
+#[derive(Clone, Copy)]
+struct DecodeOptions {
+  /// Whether to rotate and flip the image as its EXIF orientation says
+  auto_orient: bool,
+  /// Whether to convert the image from its embedded ICC profile to sRGB
+  to_srgb: bool,
+  /// How floating point images are brought into range
+  tone_mapping: ToneMapping,
+  /// The size SVG inputs are rasterized at
+  svg_size: SvgSize,
+}
+
+impl Default for DecodeOptions {
+  fn default() -> Self {
+    Self {
+      auto_orient: true,
+      to_srgb: true,
+      tone_mapping: ToneMapping::default(),
+      svg_size: SvgSize::default(),
+    }
+  }
+}
+
+impl DecodeOptions {
+  /// The decoding settings of the processing options
+  fn from_options(options: &ProcessImageOptions) -> Result<Self> {
+    let tone_mapping = options
+      .hdr_tone_mapping
+      .as_deref()
+      .map(parse_tone_mapping)
+      .transpose()
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid tone mapping: {}", e)))?
+      .unwrap_or_default();
+    Ok(Self {
+      auto_orient: options.auto_orient.unwrap_or(true),
+      to_srgb: options.convert_to_srgb.unwrap_or(true),
+      tone_mapping,
+      svg_size: SvgSize {
+        width: options.svg_width,
+        height: options.svg_height,
+        dpi: options.svg_dpi,
       },
-    )?;
+    })
+  }
+}
 
-  let rgba = img.to_rgba8();
-  let (width, height) = rgba.dimensions();
+/// Load an input image, decoding it unless it is given as raw RGBA pixels
+fn load_input(input: &ImageInput, decode: DecodeOptions) -> Result<DynamicImage> {
+  match input {
+    Either3::A(buffer) => decode_image(buffer, decode)
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to load image: {}", e))),
+    Either3::C(url) => parse_data_url(url)
+      .and_then(|buffer| decode_image(&buffer, decode))
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to load image: {}", e))),
+    Either3::B(raw) => {
+      let expected = raw.width as usize * raw.height as usize * 4;
+      RgbaImage::from_raw(raw.width, raw.height, raw.data.to_vec())
+        .filter(|_| raw.data.len() == expected)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| {
+          Error::new(
+            Status::InvalidArg,
+            format!(
+              "Raw image data must be {} bytes for {}x{} RGBA pixels (got: {})",
+              expected,
+              raw.width,
+              raw.height,
+              raw.data.len()
+            ),
+          )
+        })
+    }
+  }
+}
 
-  let fg_normalized: Vec<NormalizedColor> = foreground_colors
-    .iter()
-    .map(|&color| normalize_color(color))
-    .collect();
+/// Decode an image, applying its EXIF orientation and converting it to sRGB if requested
+///
+/// Floating point images (OpenEXR, Radiance HDR) are scene-linear; they are tone mapped and
+/// encoded as sRGB so they can be processed like any other input. SVG documents are rasterized.
+fn decode_image(buffer: &[u8], decode: DecodeOptions) -> anyhow::Result<DynamicImage> {
+  if is_svg(buffer) {
+    return rasterize_svg(buffer, decode.svg_size).map(DynamicImage::ImageRgba8);
+  }
 
-  let bg_normalized = normalize_color(background_color);
+  let mut decoder = image::ImageReader::new(Cursor::new(buffer))
+    .with_guessed_format()?
+    .into_decoder()?;
+  let orientation = if decode.auto_orient {
+    decoder.orientation()?
+  } else {
+    image::metadata::Orientation::NoTransforms
+  };
+  let icc_profile = if decode.to_srgb {
+    decoder.icc_profile()?
+  } else {
+    None
+  };
 
-  let pixels: Vec<_> = rgba.pixels().collect();
-  let processed_pixels: Vec<[u8; 4]> = if !options.strict_mode && foreground_colors.is_empty() {
-    pixels
-      .par_iter()
-      .map(|pixel| {
-        let observed = composite_pixel_over_background(pixel, background_color);
-        process_pixel_non_strict_no_fg(observed, bg_normalized)
-      })
-      .collect()
-  } else if !options.strict_mode {
-    pixels
-      .par_iter()
-      .map(|pixel| {
-        let observed = composite_pixel_over_background(pixel, background_color);
-        process_pixel_non_strict_with_fg(observed, &fg_normalized, bg_normalized, color_threshold)
-      })
-      .collect()
+  // The decoder reads all four-component JPEGs as inverted CMYK, without their profile
+  let mut img = if is_cmyk_jpeg(buffer) {
+    DynamicImage::ImageRgb8(decode_cmyk_jpeg(buffer, icc_profile.as_deref())?)
   } else {
-    pixels
-      .par_iter()
-      .map(|pixel| {
-        let observed = composite_pixel_over_background(pixel, background_color);
-        let unmix_result = unmix_colors(observed, &fg_normalized, bg_normalized);
-        let (result_color, alpha) = compute_result_color(&unmix_result, &fg_normalized);
-
-        let final_color = denormalize_color(result_color);
-        [
-          final_color[0],
-          final_color[1],
-          final_color[2],
-          (alpha * 255.0).round() as u8,
-        ]
-      })
-      .collect()
+    DynamicImage::from_decoder(decoder)?
   };
+  img.apply_orientation(orientation);
+  if is_linear_light(&img) {
+    img = tone_map_to_srgb(img, decode.tone_mapping);
+  }
+  match icc_profile {
+    Some(profile) => convert_to_srgb(img, &profile),
+    None => Ok(img),
+  }
+}
 
-  let mut output_img = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(width, height);
-  for (i, pixel) in output_img.pixels_mut().enumerate() {
-    *pixel = Rgba(processed_pixels[i]);
+/// Whether a decoded image holds scene-linear floating point data rather than sRGB
+fn is_linear_light(img: &DynamicImage) -> bool {
+  matches!(
+    img,
+    DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+  )
+}
+
+/// The frames of an animated input, each covering the whole canvas
+struct Animation {
+  /// The container format of the input, GIF, PNG or WebP
+  format: image::ImageFormat,
+  /// The fully composited frames, with their delays, oriented and converted to sRGB as the
+  /// decode options say
+  frames: Vec<Frame>,
+  /// How often the animation is played (0 = forever)
+  plays: u32,
+}
+
+/// Decode an animated GIF, APNG or WebP input, or return `None` for any other input, including
+/// single-frame animations
+///
+/// The decoders composite each frame onto the canvas, so the source's disposal and blend
+/// operations are already applied. The EXIF orientation and ICC profile of the input apply to
+/// every frame alike.
+fn load_animation(input: &ImageInput, decode: DecodeOptions) -> Result<Option<Animation>> {
+  let Some(buffer) = encoded_input(input)? else {
+    return Ok(None);
+  };
+  let load_error =
+    |e: String| Error::new(Status::InvalidArg, format!("Failed to load image: {}", e));
+
+  let (format, frames, plays) = match image::guess_format(&buffer).ok() {
+    Some(format @ image::ImageFormat::Gif) => {
+      let frames = GifDecoder::new(Cursor::new(&buffer[..]))
+        .and_then(|decoder| decoder.into_frames().collect_frames())
+        .map_err(|e| load_error(e.to_string()))?;
+      // The GIF loop count is the number of repetitions after the first play
+      let plays = match gif::DecodeOptions::new()
+        .read_info(Cursor::new(&buffer[..]))
+        .map_err(|e| load_error(e.to_string()))?
+        .repeat()
+      {
+        gif::Repeat::Finite(n) => n as u32 + 1,
+        gif::Repeat::Infinite => 0,
+      };
+      (format, frames, plays)
+    }
+    Some(format @ image::ImageFormat::Png) => {
+      let decoder =
+        PngDecoder::new(Cursor::new(&buffer[..])).map_err(|e| load_error(e.to_string()))?;
+      if !decoder.is_apng().map_err(|e| load_error(e.to_string()))? {
+        return Ok(None);
+      }
+      let plays = png::Decoder::new(Cursor::new(&buffer[..]))
+        .read_info()
+        .map_err(|e| load_error(e.to_string()))?
+        .info()
+        .animation_control
+        .map_or(0, |control| control.num_plays);
+      let frames = decoder
+        .apng()
+        .and_then(|decoder| decoder.into_frames().collect_frames())
+        .map_err(|e| load_error(e.to_string()))?;
+      (format, frames, plays)
+    }
+    Some(format @ image::ImageFormat::WebP) => {
+      let decoder =
+        WebPDecoder::new(Cursor::new(&buffer[..])).map_err(|e| load_error(e.to_string()))?;
+      if !decoder.has_animation() {
+        return Ok(None);
+      }
+      let plays = match image_webp::WebPDecoder::new(Cursor::new(&buffer[..]))
+        .map_err(|e| load_error(e.to_string()))?
+        .loop_count()
+      {
+        image_webp::LoopCount::Forever => 0,
+        image_webp::LoopCount::Times(n) => n.get() as u32,
+      };
+      let frames = decoder
+        .into_frames()
+        .collect_frames()
+        .map_err(|e| load_error(e.to_string()))?;
+      (format, frames, plays)
+    }
+    _ => return Ok(None),
+  };
+
+  if frames.len() < 2 {
+    return Ok(None);
   }
 
-  let final_img = if options.trim {
-    trim_to_content(&output_img)
+  let mut decoder = image::ImageReader::new(Cursor::new(&buffer[..]))
+    .with_guessed_format()
+    .map_err(|e| load_error(e.to_string()))?
+    .into_decoder()
+    .map_err(|e| load_error(e.to_string()))?;
+  let orientation = if decode.auto_orient {
+    decoder
+      .orientation()
+      .map_err(|e| load_error(e.to_string()))?
   } else {
-    output_img
+    image::metadata::Orientation::NoTransforms
+  };
+  let icc_profile = if decode.to_srgb {
+    decoder
+      .icc_profile()
+      .map_err(|e| load_error(e.to_string()))?
+  } else {
+    None
+  };
+  let frames = if orientation == image::metadata::Orientation::NoTransforms && icc_profile.is_none()
+  {
+    frames
+  } else {
+    frames
+      .into_iter()
+      .map(|frame| {
+        let delay = frame.delay();
+        let mut img = DynamicImage::ImageRgba8(frame.into_buffer());
+        img.apply_orientation(orientation);
+        if let Some(profile) = &icc_profile {
+          img = convert_to_srgb(img, profile).map_err(|e| load_error(e.to_string()))?;
+        }
+        Ok(Frame::from_parts(img.to_rgba8(), 0, 0, delay))
+      })
+      .collect::<Result<Vec<_>>>()?
+  };
+
+  Ok(Some(Animation {
+    format,
+    frames,
+    plays,
+  }))
+}
+
+/// Check whether an encoded input stores grayscale pixels, with or without alpha
+///
+/// Only reads the image header.
+fn input_is_grayscale(input: &ImageInput) -> bool {
+  let Ok(Some(buffer)) = encoded_input(input) else {
+    return false;
+  };
+  image::ImageReader::new(Cursor::new(&buffer[..]))
+    .with_guessed_format()
+    .ok()
+    .and_then(|reader| reader.into_decoder().ok())
+    .is_some_and(|decoder| !decoder.color_type().has_color())
+}
+
+/// Check whether an encoded input is an indexed PNG with a transparent palette entry
+fn input_has_transparent_palette_index(input: &ImageInput) -> bool {
+  matches!(encoded_input(input), Ok(Some(buffer)) if has_transparent_palette_index(&buffer))
+}
+
+/// Check whether the input is an indexed PNG with a transparent palette entry (`tRNS` chunk)
+///
+/// Pixels using such an entry are transparent by design, so their RGB values are meaningless.
+fn has_transparent_palette_index(input: &[u8]) -> bool {
+  png::Decoder::new(Cursor::new(input))
+    .read_info()
+    .map(|reader| {
+      let info = reader.info();
+      info.color_type == png::ColorType::Indexed && info.trns.is_some()
+    })
+    .unwrap_or(false)
+}
+
+/// Parse the requested output format of the processing options
+fn output_format(options: &ProcessImageOptions) -> Result<OutputFormat> {
+  let png_settings = PngSettings {
+    srgb: options.tag_srgb.unwrap_or(false),
+    grayscale: input_is_grayscale(&options.input),
+    ..png_settings(
+      options.png_compression.as_deref(),
+      options.png_filter.as_deref(),
+      options.png_interlaced,
+    )?
+  };
+  let palette_settings = parse_palette_settings(options.palette_colors, options.palette_dither)
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid output format: {}", e)))?;
+  let webp_settings = webp_settings(options)?;
+  let avif_settings = avif_settings(options)?;
+  let jpeg_settings =
+    parse_jpeg_settings(options.jpeg_quality, options.jpeg_matte_color.as_deref())
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid output format: {}", e)))?;
+
+  Ok(
+    options
+      .output_format
+      .as_deref()
+      .map(|spec| {
+        parse_output_format(
+          spec,
+          png_settings,
+          palette_settings,
+          webp_settings,
+          avif_settings,
+          jpeg_settings,
+        )
+      })
+      .transpose()
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid output format: {}", e)))?
+      .unwrap_or(OutputFormat::Png(png_settings)),
+  )
+}
+
+/// Parse an output format with the default settings of every format but PNG, PNG if none
+fn default_output_format(spec: Option<&str>, png_settings: PngSettings) -> Result<OutputFormat> {
+  Ok(
+    spec
+      .map(|spec| {
+        parse_output_format(
+          spec,
+          png_settings,
+          parse_palette_settings(None, None)?,
+          WebPSettings::default(),
+          AvifSettings::default(),
+          JpegSettings::default(),
+        )
+      })
+      .transpose()
+      .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid output format: {}", e)))?
+      .unwrap_or(OutputFormat::Png(png_settings)),
+  )
+}
+
+/// Parse PNG encoder settings
+fn png_settings(
+  compression: Option<&str>,
+  filter: Option<&str>,
+  interlaced: Option<bool>,
+) -> Result<PngSettings> {
+  parse_png_settings(compression, filter, interlaced)
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid output format: {}", e)))
+}
+
+/// Parse the WebP settings of the processing options
+fn webp_settings(options: &ProcessImageOptions) -> Result<WebPSettings> {
+  parse_webp_settings(options.webp_lossless, options.webp_quality)
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid output format: {}", e)))
+}
+
+/// Parse the AVIF settings of the processing options
+fn avif_settings(options: &ProcessImageOptions) -> Result<AvifSettings> {
+  parse_avif_settings(options.avif_quality, options.avif_speed)
+    .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid output format: {}", e)))
+}
+
+/// The ancillary chunks of PNG output: those kept from a PNG input, and the crop offset if
+/// requested
+fn png_metadata(options: &ProcessImageOptions, crop: Crop) -> PngMetadata {
+  let mut metadata = match encoded_input(&options.input) {
+    Ok(Some(buffer)) if options.preserve_metadata.unwrap_or(true) => PngMetadata::read(&buffer),
+    _ => PngMetadata::default(),
+  };
+  if options.trim && options.embed_crop_offset.unwrap_or(false) {
+    metadata.offset = Some(crop.offset);
+  }
+  metadata
+}
+
+/// Encode a processed image in the output format, with the metadata of `png_metadata` for PNG
+fn encode_output(
+  options: &ProcessImageOptions,
+  output_format: OutputFormat,
+  img: &RgbaImage,
+  crop: Crop,
+) -> Result<Vec<u8>> {
+  let encoded = match output_format {
+    OutputFormat::Png(settings) => encode_png_rgba(img, settings, &png_metadata(options, crop)),
+    _ => encode_rgba(img, output_format),
   };
 
+  encoded.map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })
+}
+
+/// Encode a processed 16-bit image in the output format, with the metadata of `png_metadata`
+/// for PNG
+fn encode_output16(
+  options: &ProcessImageOptions,
+  output_format: OutputFormat,
+  img: &Rgba16Image,
+  crop: Crop,
+) -> Result<Vec<u8>> {
+  check_16_bit_output(output_format)?;
+
+  let encoded = match output_format {
+    OutputFormat::Png(settings) => encode_png_rgba16(img, settings, &png_metadata(options, crop)),
+    _ => encode_rgba16(img, output_format),
+  };
+
+  encoded.map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })
+}
+
+/// Encode a grayscale map of a processed image in the output format, with the metadata of
+/// `png_metadata` for PNG
+fn encode_output_gray(
+  options: &ProcessImageOptions,
+  output_format: OutputFormat,
+  img: &GrayImage,
+  crop: Crop,
+) -> Result<Vec<u8>> {
+  encode_gray(img, output_format, &png_metadata(options, crop)).map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })
+}
+
+/// Encode a 16-bit grayscale map of a processed image in the output format, with the metadata
+/// of `png_metadata` for PNG
+fn encode_output_gray16(
+  options: &ProcessImageOptions,
+  output_format: OutputFormat,
+  img: &Gray16Image,
+  crop: Crop,
+) -> Result<Vec<u8>> {
+  check_16_bit_output(output_format)?;
+
+  encode_gray16(img, output_format, &png_metadata(options, crop)).map_err(|e| {
+    Error::new(
+      Status::GenericFailure,
+      format!("Failed to write output image: {}", e),
+    )
+  })
+}
+
+/// Reject output formats that can't hold 16 bits per channel
+fn check_16_bit_output(output_format: OutputFormat) -> Result<()> {
+  if matches!(
+    output_format,
+    OutputFormat::WebP(_)
+      | OutputFormat::Avif(_)
+      | OutputFormat::Jpeg(_)
+      | OutputFormat::Apng(_)
+      | OutputFormat::Gif
+      | OutputFormat::Png(PngSettings {
+        palette: Some(_),
+        ..
+      })
+  ) {
+    return Err(Error::new(
+      Status::InvalidArg,
+      "16-bit output is only supported for png, tiff and raw output".to_string(),
+    ));
+  }
+
+  Ok(())
+}
+
+/// Parse the output bit depth, 8 or 16
+fn output_bit_depth(options: &ProcessImageOptions) -> Result<u32> {
+  match options.output_bit_depth.unwrap_or(8) {
+    depth @ (8 | 16) => Ok(depth),
+    depth => Err(Error::new(
+      Status::InvalidArg,
+      format!("Output bit depth must be 8 or 16 (got: {})", depth),
+    )),
+  }
+}
+
+/// Encode an image as PNG
+fn encode_png(img: &DynamicImage) -> Result<Vec<u8>> {
   let mut buffer = Cursor::new(Vec::new());
-  final_img
+  img
     .write_to(&mut buffer, image::ImageFormat::Png)
     .map_err(|e| {
       Error::new(
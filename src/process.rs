@@ -1,10 +1,200 @@
 // based on https://github.com/benface/bgone/blob/b362931f37252301f0f8dec183b2072f415b9b5f/src/lib.rs
 
-use crate::color::{denormalize_color, normalize_color, Color, NormalizedColor};
-use crate::unmix::{compute_result_color, is_color_close_to_foreground, unmix_colors};
-use image::{ImageBuffer, Rgba};
+use crate::background::flood_fill_from_border;
+use crate::color::{
+  denormalize_color, denormalize_rgba, normalize_color, Color, NormalizedColor, NormalizedRgba,
+  ThresholdSpace,
+};
+use crate::unmix::{
+  compute_result_color_with_gamut, is_color_close_to_foreground, unmix_normalized_with_depth,
+  ColorCombinations, GamutMapping,
+};
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Pixel, Primitive, Rgba, RgbaImage};
 use nalgebra::Vector3;
 
+/// How the color channels of a translucent input pixel relate to its alpha
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+  /// Color channels are independent of alpha (the PNG convention)
+  #[default]
+  Straight,
+  /// Color channels have already been multiplied by alpha, as exported by some canvases
+  Premultiplied,
+}
+
+/// Parse an input alpha mode
+/// Supports: "straight", "premultiplied"
+pub fn parse_alpha_mode(spec: &str) -> anyhow::Result<AlphaMode> {
+  match spec {
+    "straight" => Ok(AlphaMode::Straight),
+    "premultiplied" => Ok(AlphaMode::Premultiplied),
+    _ => anyhow::bail!(
+      "Alpha mode must be one of \"straight\" or \"premultiplied\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// How the alpha an input pixel already has is combined with the alpha computed for it
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExistingAlpha {
+  /// Composite translucent pixels over the background and unmix the result
+  #[default]
+  Composite,
+  /// Unmix the pixel's color as if it were opaque, discarding its alpha
+  Ignore,
+  /// Unmix the pixel's color as if it were opaque, then keep the lower of the two alphas
+  Min,
+  /// Unmix the pixel's color as if it were opaque, then multiply the two alphas
+  Multiply,
+}
+
+impl ExistingAlpha {
+  /// Combine the alpha computed for a pixel with the alpha it had in the input
+  pub fn combine(self, computed: f64, existing: f64) -> f64 {
+    match self {
+      ExistingAlpha::Composite | ExistingAlpha::Ignore => computed,
+      ExistingAlpha::Min => computed.min(existing),
+      ExistingAlpha::Multiply => computed * existing,
+    }
+  }
+}
+
+/// Parse how existing alpha is treated
+/// Supports: "ignore", "composite", "min", "multiply"
+pub fn parse_existing_alpha(spec: &str) -> anyhow::Result<ExistingAlpha> {
+  match spec {
+    "ignore" => Ok(ExistingAlpha::Ignore),
+    "composite" => Ok(ExistingAlpha::Composite),
+    "min" => Ok(ExistingAlpha::Min),
+    "multiply" => Ok(ExistingAlpha::Multiply),
+    _ => anyhow::bail!(
+      "Existing alpha must be one of \"ignore\", \"composite\", \"min\" or \"multiply\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// Default distance from the background (normalized RGB) at which the distance alpha model is
+/// opaque
+pub const DEFAULT_ALPHA_OPAQUE_DISTANCE: f64 = 0.5;
+
+/// Default largest per-channel difference (normalized) below which a pixel counts as the
+/// background itself in non-strict mode
+pub const DEFAULT_BACKGROUND_EPSILON: f64 = 1e-6;
+
+/// Whether every channel of a color differs from the background by less than `epsilon`
+fn is_background(color: NormalizedColor, background: NormalizedColor, epsilon: f64) -> bool {
+  color
+    .iter()
+    .zip(background)
+    .all(|(c, b)| (c - b).abs() < epsilon)
+}
+
+/// How alpha is chosen for pixels in non-strict mode without foreground colors
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum AlphaModel {
+  /// The smallest alpha that reconstructs the pixel, giving maximally transparent results
+  #[default]
+  MinAlpha,
+  /// Alpha grows linearly with the distance from the background, reaching 1.0 at
+  /// `opaque_distance`, for a softer and more gradual matte
+  Distance { opaque_distance: f64 },
+}
+
+/// Parse an alpha model
+/// Supports: "min_alpha", "distance" (opaque at `opaque_distance`)
+pub fn parse_alpha_model(spec: &str, opaque_distance: f64) -> anyhow::Result<AlphaModel> {
+  match spec {
+    "min_alpha" => Ok(AlphaModel::MinAlpha),
+    "distance" => {
+      if opaque_distance.is_nan() || opaque_distance <= 0.0 {
+        anyhow::bail!(
+          "Alpha opaque distance must be greater than 0.0 (got: {})",
+          opaque_distance
+        );
+      }
+      Ok(AlphaModel::Distance { opaque_distance })
+    }
+    _ => anyhow::bail!(
+      "Alpha model must be one of \"min_alpha\" or \"distance\" (got: {})",
+      spec
+    ),
+  }
+}
+
+/// How the foreground was blended onto the background
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+  /// Straight alpha-over compositing
+  #[default]
+  Normal,
+  /// Additive blending, which only ever brightens the background (glows, lens flares)
+  Add,
+  /// Screen blending, the additive look without clipping at white
+  Screen,
+  /// Multiply blending, which only ever darkens the background (ink, pencil, stamps on paper)
+  Multiply,
+}
+
+impl BlendMode {
+  /// Rewrite a color observed over `background` as the equivalent alpha-over blend, returning
+  /// the color and background to unmix
+  ///
+  /// Adding or screening `fg * alpha` onto a background brightens it just as compositing the
+  /// foreground over black would, so those modes unmix the brightening over black. Likewise,
+  /// multiplying the background by ink of density `alpha` darkens it as compositing the ink
+  /// over white would, so multiply unmixes the darkening over white.
+  pub fn to_normal(
+    self,
+    observed: NormalizedColor,
+    background: NormalizedColor,
+  ) -> (NormalizedColor, NormalizedColor) {
+    match self {
+      BlendMode::Normal => (observed, background),
+      BlendMode::Add => (
+        std::array::from_fn(|i| (observed[i] - background[i]).max(0.0)),
+        [0.0; 3],
+      ),
+      BlendMode::Screen => (
+        std::array::from_fn(|i| {
+          if background[i] < 1.0 {
+            (1.0 - (1.0 - observed[i]) / (1.0 - background[i])).max(0.0)
+          } else {
+            0.0
+          }
+        }),
+        [0.0; 3],
+      ),
+      BlendMode::Multiply => (
+        std::array::from_fn(|i| {
+          if background[i] > 0.0 {
+            (observed[i] / background[i]).min(1.0)
+          } else {
+            1.0
+          }
+        }),
+        [1.0; 3],
+      ),
+    }
+  }
+}
+
+/// Parse a blend mode
+/// Supports: "normal", "add", "screen", "multiply"
+pub fn parse_blend_mode(spec: &str) -> anyhow::Result<BlendMode> {
+  match spec {
+    "normal" => Ok(BlendMode::Normal),
+    "add" => Ok(BlendMode::Add),
+    "screen" => Ok(BlendMode::Screen),
+    "multiply" => Ok(BlendMode::Multiply),
+    _ => anyhow::bail!(
+      "Blend mode must be one of \"normal\", \"add\", \"screen\" or \"multiply\" (got: {})",
+      spec
+    ),
+  }
+}
+
 /// Composite a pixel over a background color to handle existing alpha channels
 ///
 /// If the input pixel is translucent (alpha < 255), this pre-composes it over
@@ -13,6 +203,27 @@ use nalgebra::Vector3;
 ///
 /// Formula: result = foreground * alpha + background * (1 - alpha)
 pub fn composite_pixel_over_background(pixel: &Rgba<u8>, background: Color) -> Color {
+  composite_pixel_over_background_with_mode(pixel, background, AlphaMode::Straight)
+}
+
+/// Composite a pixel over a background color, interpreting its color channels per `mode`
+///
+/// Premultiplied pixels already carry `foreground * alpha`, so only the background term
+/// is added: result = color + background * (1 - alpha). Treating them as straight would
+/// multiply by alpha twice and darken every translucent edge.
+///
+/// # Arguments
+/// * `pixel` - The RGBA pixel
+/// * `background` - The background color
+/// * `mode` - Whether the pixel's color channels are straight or premultiplied
+///
+/// # Returns
+/// The opaque composited color
+pub fn composite_pixel_over_background_with_mode(
+  pixel: &Rgba<u8>,
+  background: Color,
+  mode: AlphaMode,
+) -> Color {
   let alpha = pixel[3] as f64 / 255.0;
 
   if alpha >= 1.0 {
@@ -30,15 +241,40 @@ pub fn composite_pixel_over_background(pixel: &Rgba<u8>, background: Color) -> C
       pixel[1] as f64 / 255.0,
       pixel[2] as f64 / 255.0,
     ];
+    let fg_weight = match mode {
+      AlphaMode::Straight => alpha,
+      AlphaMode::Premultiplied => 1.0,
+    };
 
     [
-      ((fg_norm[0] * alpha + bg_norm[0] * (1.0 - alpha)) * 255.0).round() as u8,
-      ((fg_norm[1] * alpha + bg_norm[1] * (1.0 - alpha)) * 255.0).round() as u8,
-      ((fg_norm[2] * alpha + bg_norm[2] * (1.0 - alpha)) * 255.0).round() as u8,
+      ((fg_norm[0] * fg_weight + bg_norm[0] * (1.0 - alpha)).min(1.0) * 255.0).round() as u8,
+      ((fg_norm[1] * fg_weight + bg_norm[1] * (1.0 - alpha)).min(1.0) * 255.0).round() as u8,
+      ((fg_norm[2] * fg_weight + bg_norm[2] * (1.0 - alpha)).min(1.0) * 255.0).round() as u8,
     ]
   }
 }
 
+/// Composite a normalized pixel over a normalized background color, per `mode`
+///
+/// Like `composite_pixel_over_background_with_mode`, but without rounding to 8 bits, for
+/// inputs with more than 8 bits per channel.
+pub fn composite_normalized_over_background(
+  pixel: NormalizedRgba,
+  background: NormalizedColor,
+  mode: AlphaMode,
+) -> NormalizedColor {
+  let alpha = pixel[3];
+  if alpha >= 1.0 {
+    return [pixel[0], pixel[1], pixel[2]];
+  }
+
+  let fg_weight = match mode {
+    AlphaMode::Straight => alpha,
+    AlphaMode::Premultiplied => 1.0,
+  };
+  [0, 1, 2].map(|i| (pixel[i] * fg_weight + background[i] * (1.0 - alpha)).min(1.0))
+}
+
 /// Find the minimum alpha value that produces a valid foreground color
 ///
 /// Given an observed color and background, this function finds the minimum alpha
@@ -46,108 +282,36 @@ pub fn composite_pixel_over_background(pixel: &Rgba<u8>, background: Color) -> C
 /// (all RGB components in [0, 1]) that satisfies:
 /// observed = alpha * foreground + (1 - alpha) * background
 ///
-/// Returns (foreground_color, alpha) or None if no valid solution exists
+/// Solved for the foreground, each channel is `bg + (observed - bg) / alpha`, which moves
+/// away from the background as alpha shrinks until it reaches 0 or 1. The minimum alpha is
+/// therefore the largest alpha at which a channel hits that boundary.
+///
+/// Returns (foreground_color, alpha), with alpha 0.0 if the observed color is the background
 pub fn find_minimum_alpha_for_color(
   obs_norm: NormalizedColor,
   background: NormalizedColor,
-) -> Option<(NormalizedColor, f64)> {
-  let mut best_alpha = 1.0;
-  let mut best_fg = obs_norm;
-
-  // For truly minimal alpha, we need to consider different foreground colors.
-  // The optimal foreground often has components at the extremes (0 or 1).
-  // We'll try all 8 combinations of extreme values, plus the computed values.
-
-  // First, let's compute the minimum alpha needed for each channel independently
-  // For each channel i: observed[i] = alpha * fg[i] + (1 - alpha) * bg[i]
-  // If fg[i] = 0: alpha = (bg[i] - observed[i]) / bg[i] (if bg[i] != 0)
-  // If fg[i] = 1: alpha = (observed[i] - bg[i]) / (1 - bg[i]) (if bg[i] != 1)
-
-  // Try all combinations of extreme foreground values (0 or 1 for each channel)
-  for r_extreme in &[0.0, 1.0] {
-    for g_extreme in &[0.0, 1.0] {
-      for b_extreme in &[0.0, 1.0] {
-        let fg_candidate = [*r_extreme, *g_extreme, *b_extreme];
-
-        // Calculate required alpha for this foreground color
-        // observed = alpha * foreground + (1 - alpha) * background
-        // alpha = (observed - background) / (foreground - background)
-
-        let mut alpha_needed = 0.0;
-        let mut valid = true;
-
-        let mut first_alpha_set = false;
-
-        for i in 0..3 {
-          let denom = fg_candidate[i] - background[i];
-          if denom.abs() < 1e-10 {
-            // fg[i] ≈ bg[i], check if observed[i] ≈ bg[i] too
-            if (obs_norm[i] - background[i]).abs() > 1e-10 {
-              valid = false;
-              break;
-            }
-            // Any alpha works for this channel, continue
-          } else {
-            let alpha_i = (obs_norm[i] - background[i]) / denom;
-            if !first_alpha_set {
-              alpha_needed = alpha_i;
-              first_alpha_set = true;
-            } else if (alpha_i - alpha_needed).abs() > 1e-10 {
-              // Different channels require different alphas - invalid
-              valid = false;
-              break;
-            }
-          }
-        }
-
-        if valid
-          && first_alpha_set
-          && alpha_needed > 0.0
-          && alpha_needed <= 1.0
-          && alpha_needed < best_alpha
-        {
-          // Verify the solution
-          let mut reconstructed_valid = true;
-          for i in 0..3 {
-            let reconstructed =
-              alpha_needed * fg_candidate[i] + (1.0 - alpha_needed) * background[i];
-            if (reconstructed - obs_norm[i]).abs() > 1e-10 {
-              reconstructed_valid = false;
-              break;
-            }
-          }
-
-          if reconstructed_valid {
-            best_alpha = alpha_needed;
-            best_fg = fg_candidate;
-          }
-        }
+) -> (NormalizedColor, f64) {
+  let alpha = (0..3)
+    .map(|i| {
+      let delta = obs_norm[i] - background[i];
+      if delta > 0.0 {
+        delta / (1.0 - background[i])
+      } else if delta < 0.0 {
+        -delta / background[i]
+      } else {
+        0.0
       }
-    }
-  }
-
-  // Also try the direct computation approach with fine-grained alpha search
-  for alpha_int in 1..=1000 {
-    let alpha = alpha_int as f64 / 1000.0;
-
-    if alpha >= best_alpha {
-      break; // No point checking higher alphas
-    }
-
-    // Calculate the required foreground color for this alpha
-    let fg_r = (obs_norm[0] - (1.0 - alpha) * background[0]) / alpha;
-    let fg_g = (obs_norm[1] - (1.0 - alpha) * background[1]) / alpha;
-    let fg_b = (obs_norm[2] - (1.0 - alpha) * background[2]) / alpha;
-
-    // Check if this foreground color is valid (all components in [0, 1])
-    if (0.0..=1.0).contains(&fg_r) && (0.0..=1.0).contains(&fg_g) && (0.0..=1.0).contains(&fg_b) {
-      best_alpha = alpha;
-      best_fg = [fg_r, fg_g, fg_b];
-      break; // This is the minimum alpha with direct computation
-    }
+    })
+    .fold(0.0, f64::max)
+    .min(1.0);
+  if alpha == 0.0 {
+    return (obs_norm, 0.0);
   }
 
-  Some((best_fg, best_alpha))
+  // observed = alpha * fg + (1 - alpha) * bg, solved for fg
+  let fg =
+    [0, 1, 2].map(|i| (background[i] + (obs_norm[i] - background[i]) / alpha).clamp(0.0, 1.0));
+  (fg, alpha)
 }
 
 /// Process a pixel in non-strict mode without foreground colors
@@ -160,30 +324,126 @@ pub fn find_minimum_alpha_for_color(
 /// 2. A valid foreground color has all RGB components in [0, 1] range
 /// 3. Always produces perfect reconstruction of the original image
 pub fn process_pixel_non_strict_no_fg(observed: Color, background: NormalizedColor) -> [u8; 4] {
-  let obs_norm = normalize_color(observed);
+  process_pixel_non_strict_no_fg_with_model(observed, background, AlphaModel::MinAlpha)
+}
 
-  // If the observed color is exactly the background, it's fully transparent
-  if (obs_norm[0] - background[0]).abs() < 1e-6
-    && (obs_norm[1] - background[1]).abs() < 1e-6
-    && (obs_norm[2] - background[2]).abs() < 1e-6
-  {
-    return [0, 0, 0, 0];
+/// Process a pixel in non-strict mode without foreground colors using the given alpha model
+///
+/// With `AlphaModel::Distance`, alpha is the distance from the background divided by the
+/// opaque distance, but never less than the minimum alpha, so every pixel is still
+/// reconstructed exactly with a valid foreground color.
+pub fn process_pixel_non_strict_no_fg_with_model(
+  observed: Color,
+  background: NormalizedColor,
+  model: AlphaModel,
+) -> [u8; 4] {
+  denormalize_rgba(process_normalized_non_strict_no_fg(
+    normalize_color(observed),
+    background,
+    model,
+    DEFAULT_BACKGROUND_EPSILON,
+  ))
+}
+
+/// Process a normalized pixel in non-strict mode without foreground colors
+///
+/// See `process_pixel_non_strict_no_fg_with_model`; the result is not rounded to 8 bits, and
+/// pixels within `background_epsilon` of the background (see `is_background`) are fully
+/// transparent.
+pub fn process_normalized_non_strict_no_fg(
+  obs_norm: NormalizedColor,
+  background: NormalizedColor,
+  model: AlphaModel,
+  background_epsilon: f64,
+) -> NormalizedRgba {
+  // If the observed color is the background, it's fully transparent
+  if is_background(obs_norm, background, background_epsilon) {
+    return [0.0, 0.0, 0.0, 0.0];
   }
 
   // Find the optimal alpha and foreground color
-  let (best_fg, best_alpha) = find_minimum_alpha_for_color(obs_norm, background).unwrap_or({
-    // If we didn't find a valid solution with alpha <= 1.0, something is wrong
-    // Fall back to using alpha = 1.0
-    (obs_norm, 1.0)
-  });
+  let (min_fg, min_alpha) = find_minimum_alpha_for_color(obs_norm, background);
+
+  let (best_fg, best_alpha) = match model {
+    AlphaModel::MinAlpha => (min_fg, min_alpha),
+    AlphaModel::Distance { opaque_distance } => {
+      let distance = (0..3)
+        .map(|i| (obs_norm[i] - background[i]).powi(2))
+        .sum::<f64>()
+        .sqrt();
+      let alpha = (distance / opaque_distance).min(1.0);
+
+      if alpha <= min_alpha {
+        (min_fg, min_alpha)
+      } else {
+        // observed = alpha * fg + (1 - alpha) * bg, solved for fg
+        let fg = [0, 1, 2]
+          .map(|i| ((obs_norm[i] - (1.0 - alpha) * background[i]) / alpha).clamp(0.0, 1.0));
+        (fg, alpha)
+      }
+    }
+  };
+
+  [best_fg[0], best_fg[1], best_fg[2], best_alpha]
+}
+
+/// Process a pixel against its counterpart in a clean reference shot of the background
+///
+/// Pixels closer to the reference than `noise_threshold` (normalized RGB distance) are
+/// considered unchanged and become fully transparent. Otherwise alpha grows with the
+/// difference from the reference, reaching 1.0 at `opaque_distance`, and the color is
+/// recovered by inverting the alpha blend (see `AlphaModel::Distance`).
+pub fn process_pixel_with_reference(
+  observed: Color,
+  background: NormalizedColor,
+  noise_threshold: f64,
+  opaque_distance: f64,
+) -> [u8; 4] {
+  let obs_norm = normalize_color(observed);
+  let difference = (0..3)
+    .map(|i| (obs_norm[i] - background[i]).powi(2))
+    .sum::<f64>()
+    .sqrt();
+  if difference < noise_threshold {
+    return [0, 0, 0, 0];
+  }
 
-  let final_color = denormalize_color(best_fg);
-  [
-    final_color[0],
-    final_color[1],
-    final_color[2],
-    (best_alpha * 255.0).round() as u8,
-  ]
+  process_pixel_non_strict_no_fg_with_model(
+    observed,
+    background,
+    AlphaModel::Distance { opaque_distance },
+  )
+}
+
+/// Most opaque a shadow gets; darker pixels are left to unmixing as likely subject content
+const MAX_SHADOW_OPACITY: f64 = 0.8;
+
+/// Detect a soft shadow cast on the background
+///
+/// A shadow darkens the background without changing its hue, so the observed color is the
+/// background scaled by some factor `k < 1`. Black at opacity `1 - k` composited over the
+/// background reproduces it exactly.
+///
+/// # Returns
+/// The opacity of black that reproduces the pixel, or `None` if the pixel is not a darker
+/// version of the background within `threshold` (normalized RGB distance)
+pub fn shadow_opacity(
+  obs_norm: NormalizedColor,
+  background: NormalizedColor,
+  threshold: f64,
+) -> Option<f64> {
+  let background_energy = (0..3).map(|i| background[i].powi(2)).sum::<f64>();
+  if background_energy == 0.0 {
+    return None;
+  }
+
+  let k = (0..3).map(|i| obs_norm[i] * background[i]).sum::<f64>() / background_energy;
+  let residual = (0..3)
+    .map(|i| (obs_norm[i] - k * background[i]).powi(2))
+    .sum::<f64>()
+    .sqrt();
+  let opacity = 1.0 - k;
+  (residual <= threshold && opacity > 0.0 && opacity <= MAX_SHADOW_OPACITY).then_some(opacity)
 }
 
 /// Process a pixel in non-strict mode with foreground colors
@@ -200,70 +460,94 @@ pub fn process_pixel_non_strict_no_fg(observed: Color, background: NormalizedCol
 /// This allows the tool to preserve colors like glows and gradients that aren't
 /// close to the specified foreground colors, while still optimizing for the
 /// specified colors when appropriate.
+///
+/// `reconstruction_tolerance` is passed on to the unmixing of pixels close to the
+/// foreground colors (see `unmix_colors_with_tolerance`), and `gamut_mapping` decides how
+/// their result color is brought back into range (see `compute_result_color_with_gamut`).
+/// `threshold_space` decides how closeness to the foreground colors is measured.
 pub fn process_pixel_non_strict_with_fg(
   observed: Color,
   foreground_colors: &[NormalizedColor],
   background: NormalizedColor,
   threshold: f64,
+  threshold_space: ThresholdSpace,
+  reconstruction_tolerance: f64,
+  gamut_mapping: GamutMapping,
 ) -> [u8; 4] {
-  let obs_norm = normalize_color(observed);
-  let obs_vec = Vector3::new(obs_norm[0] as f64, obs_norm[1] as f64, obs_norm[2] as f64);
+  denormalize_rgba(process_normalized_non_strict_with_fg(
+    normalize_color(observed),
+    foreground_colors,
+    background,
+    threshold,
+    |a, b| threshold_space.distance(a, b),
+    reconstruction_tolerance,
+    None,
+    gamut_mapping,
+    DEFAULT_BACKGROUND_EPSILON,
+  ))
+}
 
-  // If the observed color is exactly the background, it's fully transparent
-  if (obs_norm[0] - background[0]).abs() < 1e-6
-    && (obs_norm[1] - background[1]).abs() < 1e-6
-    && (obs_norm[2] - background[2]).abs() < 1e-6
-  {
-    return [0, 0, 0, 0];
+/// Process a normalized pixel in non-strict mode with foreground colors
+///
+/// See `process_pixel_non_strict_with_fg`; the result is not rounded to 8 bits, closeness
+/// is measured with `distance`, so colors outside sRGB can be compared in their own space,
+/// pixels within `background_epsilon` of the background are fully transparent, and pixels
+/// close to the foreground colors are unmixed into one of the `combinations` of them, if
+/// given (see `unmix_normalized_with_depth`).
+#[allow(clippy::too_many_arguments)]
+pub fn process_normalized_non_strict_with_fg(
+  obs_norm: NormalizedColor,
+  foreground_colors: &[NormalizedColor],
+  background: NormalizedColor,
+  threshold: f64,
+  distance: impl Fn(NormalizedColor, NormalizedColor) -> f64,
+  reconstruction_tolerance: f64,
+  combinations: Option<&ColorCombinations>,
+  gamut_mapping: GamutMapping,
+  background_epsilon: f64,
+) -> NormalizedRgba {
+  let obs_vec = Vector3::from(obs_norm);
+
+  // If the observed color is the background, it's fully transparent
+  if is_background(obs_norm, background, background_epsilon) {
+    return [0.0, 0.0, 0.0, 0.0];
   }
 
   // Check if this pixel is close to any foreground color
-  let close_to_fg = is_color_close_to_foreground(obs_vec, foreground_colors, background, threshold);
+  let close_to_fg =
+    is_color_close_to_foreground(obs_vec, foreground_colors, background, threshold, distance);
 
   if close_to_fg {
     // Use the standard unmixing algorithm optimized for high opacity
-    let unmix_result = unmix_colors(observed, foreground_colors, background);
-    let (result_color, alpha) = compute_result_color(&unmix_result, foreground_colors);
-    let final_color = denormalize_color(result_color);
-    [
-      final_color[0],
-      final_color[1],
-      final_color[2],
-      (alpha * 255.0).round() as u8,
-    ]
+    let unmix_result = unmix_normalized_with_depth(
+      obs_norm,
+      foreground_colors,
+      background,
+      reconstruction_tolerance,
+      combinations,
+    );
+    let (result_color, alpha) =
+      compute_result_color_with_gamut(&unmix_result, foreground_colors, gamut_mapping);
+    [result_color[0], result_color[1], result_color[2], alpha]
   } else {
     // Not close to any foreground color - find ANY color that works with minimal alpha
-    let obs_norm = normalize_color(observed);
-
-    // Find the optimal alpha and foreground color
-    let (best_fg, best_alpha) = find_minimum_alpha_for_color(obs_norm, background).unwrap_or({
-      // If we didn't find a valid solution with alpha <= 1.0, something is wrong
-      // Fall back to using alpha = 1.0
-      (obs_norm, 1.0)
-    });
+    let (best_fg, best_alpha) = find_minimum_alpha_for_color(obs_norm, background);
 
-    let final_color = denormalize_color(best_fg);
-    [
-      final_color[0],
-      final_color[1],
-      final_color[2],
-      (best_alpha * 255.0).round() as u8,
-    ]
+    [best_fg[0], best_fg[1], best_fg[2], best_alpha]
   }
 }
 
-/// Trim an image by cropping to the bounding box of non-transparent pixels.
+/// Find the bounding box of all pixels with alpha > 0.
 ///
-/// Finds the bounding box of all pixels with alpha > 0 and crops the image
-/// to that region. If all pixels are transparent, returns a 1x1 transparent image.
-pub fn trim_to_content(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+/// Returns `(x, y, width, height)`, or `None` if every pixel is transparent.
+pub fn content_bounds<T: Primitive>(
+  img: &ImageBuffer<Rgba<T>, Vec<T>>,
+) -> Option<(u32, u32, u32, u32)>
+where
+  Rgba<T>: Pixel<Subpixel = T>,
+{
   let (width, height) = img.dimensions();
 
-  if width == 0 || height == 0 {
-    return ImageBuffer::new(1, 1);
-  }
-
-  // Find bounding box of non-transparent pixels
   let mut min_x = width;
   let mut min_y = height;
   let mut max_x = 0u32;
@@ -272,7 +556,7 @@ pub fn trim_to_content(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba
   for y in 0..height {
     for x in 0..width {
       let pixel = img.get_pixel(x, y);
-      if pixel[3] > 0 {
+      if pixel[3] > T::DEFAULT_MIN_VALUE {
         // Non-transparent pixel
         min_x = min_x.min(x);
         min_y = min_y.min(y);
@@ -282,28 +566,422 @@ pub fn trim_to_content(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba
     }
   }
 
-  // If no non-transparent pixels found, return a 1x1 transparent image
   if max_x < min_x || max_y < min_y {
-    return ImageBuffer::from_pixel(1, 1, Rgba([0, 0, 0, 0]));
+    return None;
   }
 
-  // Calculate new dimensions (inclusive bounds, so add 1)
-  let new_width = max_x - min_x + 1;
-  let new_height = max_y - min_y + 1;
+  // Inclusive bounds, so add 1
+  Some((min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
+
+/// Trim an image by cropping to the bounding box of non-transparent pixels.
+///
+/// Finds the bounding box of all pixels with alpha > 0 and crops the image
+/// to that region. If all pixels are transparent, returns a 1x1 transparent image.
+pub fn trim_to_content<T: Primitive + 'static>(
+  img: &ImageBuffer<Rgba<T>, Vec<T>>,
+) -> ImageBuffer<Rgba<T>, Vec<T>>
+where
+  Rgba<T>: Pixel<Subpixel = T>,
+{
+  let (width, height) = img.dimensions();
 
-  // If no trimming needed, return a clone
-  if new_width == width && new_height == height {
-    return img.clone();
+  if width == 0 || height == 0 {
+    return ImageBuffer::new(1, 1);
   }
 
-  // Create cropped image
-  let mut trimmed = ImageBuffer::new(new_width, new_height);
-  for y in 0..new_height {
-    for x in 0..new_width {
-      let src_pixel = img.get_pixel(min_x + x, min_y + y);
-      trimmed.put_pixel(x, y, *src_pixel);
+  match content_bounds(img) {
+    // If no non-transparent pixels found, return a 1x1 transparent image
+    None => ImageBuffer::from_pixel(1, 1, Rgba([T::DEFAULT_MIN_VALUE; 4])),
+    // If no trimming needed, return a clone
+    Some((0, 0, new_width, new_height)) if new_width == width && new_height == height => {
+      img.clone()
+    }
+    Some((x, y, new_width, new_height)) => {
+      image::imageops::crop_imm(img, x, y, new_width, new_height).to_image()
+    }
+  }
+}
+
+/// Restore the see-through holes that opaque content fully encloses
+///
+/// Flood fills (4-connected) the pixels that are not fully opaque from the border of the
+/// processed image. Any such pixel the fill can't reach is walled in by opaque content, like
+/// the counter of a letter or the white of an eye, and gets its original pixel back.
+pub fn fill_enclosed_holes<T: Primitive>(
+  output: &mut ImageBuffer<Rgba<T>, Vec<T>>,
+  original: &ImageBuffer<Rgba<T>, Vec<T>>,
+) where
+  Rgba<T>: Pixel<Subpixel = T>,
+{
+  let (width, height) = output.dimensions();
+  if width == 0 || height == 0 {
+    return;
+  }
+
+  let reachable = flood_fill_from_border(width, height, |x, y| {
+    output.get_pixel(x, y)[3] < T::DEFAULT_MAX_VALUE
+  });
+  for (i, pixel) in output.pixels_mut().enumerate() {
+    if pixel[3] < T::DEFAULT_MAX_VALUE && !reachable[i] {
+      let (x, y) = (i as u32 % width, i as u32 / width);
+      *pixel = *original.get_pixel(x, y);
+    }
+  }
+}
+
+/// Compute the mean color of an image, weighting each pixel's RGB by its alpha.
+///
+/// Transparent pixels don't contribute, so on a processed image this is the average
+/// color of the subject alone. Returns `None` if every pixel is transparent.
+pub fn alpha_weighted_average_color(img: &RgbaImage) -> Option<Color> {
+  let mut sums = [0.0f64; 3];
+  let mut total_alpha = 0.0f64;
+  for pixel in img.pixels() {
+    let alpha = pixel[3] as f64;
+    for i in 0..3 {
+      sums[i] += pixel[i] as f64 * alpha;
     }
+    total_alpha += alpha;
   }
 
-  trimmed
+  if total_alpha == 0.0 {
+    return None;
+  }
+
+  Some(sums.map(|sum| (sum / total_alpha).round() as u8))
+}
+
+/// Grow the alpha mask of an image by `radius` pixels.
+///
+/// Each output value is the largest nearby alpha, attenuated by its distance beyond
+/// `radius` so the grown edge stays anti-aliased. Costs `O(width * height * radius^2)`.
+pub fn dilate_alpha(img: &RgbaImage, radius: u32) -> GrayImage {
+  let (width, height) = img.dimensions();
+  let reach = radius as i64 + 1;
+
+  GrayImage::from_fn(width, height, |x, y| {
+    let mut best = 0.0f64;
+
+    for dy in -reach..=reach {
+      for dx in -reach..=reach {
+        let (sx, sy) = (x as i64 + dx, y as i64 + dy);
+        if sx < 0 || sy < 0 || sx >= width as i64 || sy >= height as i64 {
+          continue;
+        }
+
+        // Full coverage within the radius, fading out over the next pixel
+        let distance = ((dx * dx + dy * dy) as f64).sqrt();
+        let coverage = (radius as f64 + 1.0 - distance).clamp(0.0, 1.0);
+        let alpha = img.get_pixel(sx as u32, sy as u32)[3] as f64 * coverage;
+        best = best.max(alpha);
+      }
+    }
+
+    Luma([best.round() as u8])
+  })
+}
+
+/// Draw an outline of the given color around the non-transparent content of an image.
+///
+/// The alpha mask is dilated by `width` pixels and filled with the stroke color, then
+/// the original image is composited on top so only the ring around the content (and
+/// whatever shows through translucent edges) takes the stroke color. The canvas grows
+/// by `width` pixels on every side so the stroke is never clipped at the image bounds.
+pub fn apply_outline(img: &RgbaImage, color: Color, width: u32, opacity: f64) -> RgbaImage {
+  let (img_width, img_height) = img.dimensions();
+
+  // Pad the canvas so content touching the edges still gets a full stroke
+  let mut padded = RgbaImage::new(img_width + 2 * width, img_height + 2 * width);
+  image::imageops::replace(&mut padded, img, width as i64, width as i64);
+
+  let dilated = dilate_alpha(&padded, width);
+  let stroke = normalize_color(color);
+
+  let mut output = padded.clone();
+  for (x, y, pixel) in output.enumerate_pixels_mut() {
+    let src = padded.get_pixel(x, y);
+    let src_alpha = src[3] as f64 / 255.0;
+    let stroke_alpha = dilated.get_pixel(x, y)[0] as f64 / 255.0 * opacity;
+
+    // Source over stroke (straight alpha)
+    let out_alpha = src_alpha + stroke_alpha * (1.0 - src_alpha);
+    if out_alpha <= 0.0 {
+      *pixel = Rgba([0, 0, 0, 0]);
+      continue;
+    }
+
+    let src_norm = normalize_color([src[0], src[1], src[2]]);
+    let blended = [
+      (src_norm[0] * src_alpha + stroke[0] * stroke_alpha * (1.0 - src_alpha)) / out_alpha,
+      (src_norm[1] * src_alpha + stroke[1] * stroke_alpha * (1.0 - src_alpha)) / out_alpha,
+      (src_norm[2] * src_alpha + stroke[2] * stroke_alpha * (1.0 - src_alpha)) / out_alpha,
+    ];
+    let final_color = denormalize_color(blended);
+
+    *pixel = Rgba([
+      final_color[0],
+      final_color[1],
+      final_color[2],
+      (out_alpha * 255.0).round() as u8,
+    ]);
+  }
+
+  output
+}
+
+/// Reduce an image to a palette of at most `max_colors` RGBA colors.
+///
+/// Fully transparent pixels are kept out of the palette and stay fully transparent, so
+/// the palette is spent on visible content. If the image has no more distinct visible
+/// colors than `max_colors` they are used exactly; otherwise the palette is learned
+/// with NeuQuant. With `dither`, the quantization error (alpha included) is spread to
+/// the neighbouring visible pixels using Floyd-Steinberg weights.
+pub fn quantize_rgba(img: &RgbaImage, max_colors: usize, dither: bool) -> RgbaImage {
+  let (width, height) = img.dimensions();
+  let visible: Vec<u8> = img
+    .pixels()
+    .filter(|pixel| pixel[3] > 0)
+    .flat_map(|pixel| pixel.0)
+    .collect();
+
+  let mut distinct: Vec<[u8; 4]> = visible
+    .chunks_exact(4)
+    .map(|c| [c[0], c[1], c[2], c[3]])
+    .collect();
+  distinct.sort_unstable();
+  distinct.dedup();
+
+  let palette: Vec<[u8; 4]> = if distinct.len() <= max_colors {
+    distinct
+  } else {
+    color_quant::NeuQuant::new(10, max_colors, &visible)
+      .color_map_rgba()
+      .chunks_exact(4)
+      .map(|c| [c[0], c[1], c[2], c[3]])
+      .collect()
+  };
+
+  let nearest = |value: [f64; 4]| -> [u8; 4] {
+    *palette
+      .iter()
+      .min_by(|a, b| {
+        let dist =
+          |entry: &[u8; 4]| -> f64 { (0..4).map(|i| (entry[i] as f64 - value[i]).powi(2)).sum() };
+        dist(a).total_cmp(&dist(b))
+      })
+      .expect("palette has at least one color for a visible pixel")
+  };
+
+  let mut output = RgbaImage::new(width, height);
+  let mut errors = vec![[0.0f64; 4]; (width * height) as usize];
+
+  for y in 0..height {
+    for x in 0..width {
+      let pixel = img.get_pixel(x, y);
+      if pixel[3] == 0 {
+        output.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+        continue;
+      }
+
+      let index = (y * width + x) as usize;
+      let value: [f64; 4] =
+        std::array::from_fn(|i| (pixel[i] as f64 + errors[index][i]).clamp(0.0, 255.0));
+      let quantized = nearest(value);
+      output.put_pixel(x, y, Rgba(quantized));
+
+      if !dither {
+        continue;
+      }
+
+      let error: [f64; 4] = std::array::from_fn(|i| value[i] - quantized[i] as f64);
+      let neighbours = [
+        (1i64, 0i64, 7.0 / 16.0),
+        (-1, 1, 3.0 / 16.0),
+        (0, 1, 5.0 / 16.0),
+        (1, 1, 1.0 / 16.0),
+      ];
+      for (dx, dy, share) in neighbours {
+        let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+        if nx < 0 || nx >= width as i64 || ny >= height as i64 {
+          continue;
+        }
+        // Transparent pixels stay transparent, so they don't absorb any error
+        if img.get_pixel(nx as u32, ny as u32)[3] == 0 {
+          continue;
+        }
+        let neighbour = &mut errors[(ny as u32 * width + nx as u32) as usize];
+        for i in 0..4 {
+          neighbour[i] += error[i] * share;
+        }
+      }
+    }
+  }
+
+  output
+}
+
+/// Scale the content of an image to fit a canvas of exactly `width` x `height`, centered.
+///
+/// The image is trimmed to its non-transparent content, which is then scaled (up or down,
+/// keeping its aspect ratio) to fit inside the canvas minus a margin of `margin_ratio`
+/// times the canvas size on every side. Resampling uses Lanczos3 on premultiplied colors
+/// so transparent pixels don't bleed dark fringes into the edges.
+pub fn fit_to_canvas(img: &RgbaImage, width: u32, height: u32, margin_ratio: f64) -> RgbaImage {
+  let mut canvas = RgbaImage::new(width, height);
+
+  let Some((x, y, content_width, content_height)) = content_bounds(img) else {
+    return canvas;
+  };
+  let content = image::imageops::crop_imm(img, x, y, content_width, content_height).to_image();
+
+  let available_width = width as f64 * (1.0 - 2.0 * margin_ratio);
+  let available_height = height as f64 * (1.0 - 2.0 * margin_ratio);
+  let scale =
+    (available_width / content_width as f64).min(available_height / content_height as f64);
+  let scaled_width = ((content_width as f64 * scale).round() as u32).clamp(1, width);
+  let scaled_height = ((content_height as f64 * scale).round() as u32).clamp(1, height);
+
+  // Premultiply so fully transparent pixels carry no color into the interpolation
+  let premultiplied = RgbaImage::from_fn(content_width, content_height, |px, py| {
+    let [r, g, b, a] = content.get_pixel(px, py).0;
+    let premultiply = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+    Rgba([premultiply(r), premultiply(g), premultiply(b), a])
+  });
+  let scaled = image::imageops::resize(
+    &premultiplied,
+    scaled_width,
+    scaled_height,
+    image::imageops::FilterType::Lanczos3,
+  );
+
+  let offset_x = (width - scaled_width) / 2;
+  let offset_y = (height - scaled_height) / 2;
+  for (px, py, pixel) in scaled.enumerate_pixels() {
+    let [r, g, b, a] = pixel.0;
+    let unpremultiply = |c: u8| {
+      if a == 0 {
+        0
+      } else {
+        ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+      }
+    };
+    canvas.put_pixel(
+      offset_x + px,
+      offset_y + py,
+      Rgba([unpremultiply(r), unpremultiply(g), unpremultiply(b), a]),
+    );
+  }
+
+  canvas
+}
+
+/// Size of the JPEG block grid that `deblock_jpeg_blocks` smooths
+const JPEG_BLOCK_SIZE: u32 = 8;
+
+/// Largest step (0-255) across a block boundary that is treated as a compression artifact
+const DEBLOCK_MAX_STEP: i32 = 20;
+
+/// Largest step (0-255) between the two pixels on either side of a block boundary for that
+/// side to count as flat
+const DEBLOCK_MAX_FLATNESS: i32 = 6;
+
+/// Smooth the 8x8 block boundaries left by JPEG compression.
+///
+/// JPEG blocks are aligned to the image origin. At every block boundary, a small step in a
+/// color channel between two otherwise flat sides is an artifact rather than a real edge,
+/// and is spread over the two pixels on each side. Larger steps are left alone, so the
+/// subject's edges stay sharp while the blocky ringing around them, which unmixing would
+/// turn into a ragged translucent fringe, is flattened. Alpha is not changed.
+pub fn deblock_jpeg_blocks(img: &RgbaImage) -> RgbaImage {
+  let (width, height) = img.dimensions();
+  let mut output = img.clone();
+
+  // Filter the vertical boundaries, then the horizontal ones on the result
+  for x in (JPEG_BLOCK_SIZE..width.saturating_sub(1)).step_by(JPEG_BLOCK_SIZE as usize) {
+    for y in 0..height {
+      deblock_boundary(&mut output, [(x - 2, y), (x - 1, y), (x, y), (x + 1, y)]);
+    }
+  }
+  for y in (JPEG_BLOCK_SIZE..height.saturating_sub(1)).step_by(JPEG_BLOCK_SIZE as usize) {
+    for x in 0..width {
+      deblock_boundary(&mut output, [(x, y - 2), (x, y - 1), (x, y), (x, y + 1)]);
+    }
+  }
+
+  output
+}
+
+/// Smooth one block boundary given as the pixels `[p1, p0, q0, q1]` across it
+fn deblock_boundary(img: &mut RgbaImage, positions: [(u32, u32); 4]) {
+  let [p1, p0, q0, q1] = positions.map(|(x, y)| *img.get_pixel(x, y));
+
+  for channel in 0..3 {
+    let [p1v, p0v, q0v, q1v] = [p1, p0, q0, q1].map(|pixel| pixel[channel] as i32);
+    let step = q0v - p0v;
+    if step == 0
+      || step.abs() >= DEBLOCK_MAX_STEP
+      || (p1v - p0v).abs() >= DEBLOCK_MAX_FLATNESS
+      || (q1v - q0v).abs() >= DEBLOCK_MAX_FLATNESS
+    {
+      continue;
+    }
+
+    // Turns a flat step into an even ramp over the four pixels
+    let delta = (4 * step + (p1v - q1v)) as f64 / 8.0;
+    let adjustments = [delta / 2.0, delta, -delta, -delta / 2.0];
+    for (&(x, y), adjustment) in positions.iter().zip(adjustments) {
+      let pixel = img.get_pixel_mut(x, y);
+      pixel[channel] = (pixel[channel] as f64 + adjustment)
+        .round()
+        .clamp(0.0, 255.0) as u8;
+    }
+  }
+}
+
+/// The largest gain automatic white balance applies to a channel
+const MAX_WHITE_BALANCE_GAIN: f64 = 2.0;
+
+/// The per-channel gains that scale a background color to white
+///
+/// Gains are capped at `MAX_WHITE_BALANCE_GAIN`, so a dark background is brightened rather
+/// than blown out to white.
+pub fn white_balance_gains(background: Color) -> [f64; 3] {
+  background.map(|c| (255.0 / c.max(1) as f64).min(MAX_WHITE_BALANCE_GAIN))
+}
+
+/// Multiply the color channels of an image by per-channel gains, clipping at full intensity
+///
+/// 16-bit and floating point images keep their precision. Alpha is not changed.
+pub fn apply_channel_gains(img: &DynamicImage, gains: [f64; 3]) -> DynamicImage {
+  let color = img.color();
+  match img {
+    DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+      let mut rgba = img.to_rgba32f();
+      for pixel in rgba.pixels_mut() {
+        for (channel, gain) in pixel.0.iter_mut().zip(gains) {
+          *channel = (*channel as f64 * gain).min(1.0) as f32;
+        }
+      }
+      DynamicImage::ImageRgba32F(rgba)
+    }
+    _ if color.bytes_per_pixel() > color.channel_count() => {
+      let mut rgba = img.to_rgba16();
+      for pixel in rgba.pixels_mut() {
+        for (channel, gain) in pixel.0.iter_mut().zip(gains) {
+          *channel = (*channel as f64 * gain).round().min(65535.0) as u16;
+        }
+      }
+      DynamicImage::ImageRgba16(rgba)
+    }
+    _ => {
+      let mut rgba = img.to_rgba8();
+      for pixel in rgba.pixels_mut() {
+        for (channel, gain) in pixel.0.iter_mut().zip(gains) {
+          *channel = (*channel as f64 * gain).round().min(255.0) as u8;
+        }
+      }
+      DynamicImage::ImageRgba8(rgba)
+    }
+  }
 }
@@ -0,0 +1,81 @@
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use resvg::{tiny_skia, usvg};
+
+/// The resolution SVG user units (CSS pixels) are defined at
+const CSS_DPI: f64 = 96.0;
+
+/// The largest width or height an SVG is rasterized at, to keep huge sizes from exhausting memory
+const MAX_SVG_SIZE: u32 = 16384;
+
+/// The size an SVG input is rasterized at
+#[derive(Clone, Copy, Default)]
+pub struct SvgSize {
+  /// The target width in pixels
+  pub width: Option<u32>,
+  /// The target height in pixels
+  pub height: Option<u32>,
+  /// The target resolution, scaling the SVG's own size when no width or height is given
+  /// (default: 96, one pixel per CSS pixel)
+  pub dpi: Option<f64>,
+}
+
+/// Whether the bytes look like an SVG document (plain, not gzipped)
+pub fn is_svg(data: &[u8]) -> bool {
+  let head = &data[..data.len().min(4096)];
+  let text = String::from_utf8_lossy(head);
+  let text = text.trim_start_matches('\u{feff}').trim_start();
+  text.starts_with('<') && text.contains("<svg")
+}
+
+/// Rasterize an SVG document to straight-alpha RGBA pixels
+///
+/// The SVG keeps its aspect ratio: with only a width or a height the other side follows, and
+/// with both it is fitted inside them.
+pub fn rasterize_svg(data: &[u8], size: SvgSize) -> Result<RgbaImage> {
+  let tree = usvg::Tree::from_data(data, &usvg::Options::default())
+    .map_err(|e| anyhow::anyhow!("Invalid SVG: {}", e))?;
+  let (svg_width, svg_height) = (tree.size().width() as f64, tree.size().height() as f64);
+
+  let scale = match (size.width, size.height) {
+    (Some(width), Some(height)) => (width as f64 / svg_width).min(height as f64 / svg_height),
+    (Some(width), None) => width as f64 / svg_width,
+    (None, Some(height)) => height as f64 / svg_height,
+    (None, None) => {
+      let dpi = size.dpi.unwrap_or(CSS_DPI);
+      if !(dpi.is_finite() && dpi > 0.0) {
+        bail!("SVG DPI must be positive (got: {})", dpi);
+      }
+      dpi / CSS_DPI
+    }
+  };
+  let width = (svg_width * scale).round().max(1.0);
+  let height = (svg_height * scale).round().max(1.0);
+  if width > MAX_SVG_SIZE as f64 || height > MAX_SVG_SIZE as f64 {
+    bail!(
+      "SVG would be rasterized at {}x{} pixels, more than the maximum of {} per side",
+      width,
+      height,
+      MAX_SVG_SIZE
+    );
+  }
+
+  let mut pixmap = tiny_skia::Pixmap::new(width as u32, height as u32)
+    .context("SVG must be rasterized at a non-zero size")?;
+  resvg::render(
+    &tree,
+    tiny_skia::Transform::from_scale(scale as f32, scale as f32),
+    &mut pixmap.as_mut(),
+  );
+
+  let (width, height) = (pixmap.width(), pixmap.height());
+  let pixels = pixmap
+    .pixels()
+    .iter()
+    .flat_map(|pixel| {
+      let color = pixel.demultiply();
+      [color.red(), color.green(), color.blue(), color.alpha()]
+    })
+    .collect();
+  Ok(RgbaImage::from_raw(width, height, pixels).expect("one pixel was read per pixmap pixel"))
+}
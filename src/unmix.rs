@@ -1,20 +1,66 @@
 // based on https://github.com/benface/bgone/blob/b362931f37252301f0f8dec183b2072f415b9b5f/src/unmix.rs
 
-use crate::color::{Color, NormalizedColor};
+use crate::color::{normalize_color, Color, NormalizedColor};
 use nalgebra::{DMatrix, DVector, Vector3};
 
 /// Small epsilon value for numerical stability in floating point comparisons
 const EPSILON: f64 = 1e-10;
 
+/// Singular values below this are treated as zero when checking the rank of the unmixing matrix
+const RANK_EPSILON: f64 = 1e-6;
+
 /// Default threshold for color closeness in non-strict mode (0.05 = 5% of max RGB distance)
 pub const DEFAULT_COLOR_CLOSENESS_THRESHOLD: f64 = 0.05;
 
+/// Default maximum reconstruction error for opaque solutions in optimized unmixing
+pub const DEFAULT_RECONSTRUCTION_TOLERANCE: f64 = 0.01;
+
+/// Weight of the row enforcing the sum of the weights in the least-squares system
+const SUM_CONSTRAINT_WEIGHT: f64 = 1e2;
+
+/// Smallest rise in opacity worth another solve when trading colors for a more opaque mix
+const ALPHA_PRECISION: f64 = 1e-4;
+
+/// Reconstruction error allowed on top of the closest reconstruction's when trading colors
+/// for a more opaque mix, absorbing rounding errors
+const ERROR_SLACK: f64 = 1e-9;
+
+/// Most combinations of foreground colors a pixel is unmixed into with a limited search depth
+pub const MAX_UNMIX_COMBINATIONS: usize = 256;
+
+/// How out-of-gamut result colors are brought back into the [0, 1] range
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum GamutMapping {
+  /// Clamp each channel independently, which can shift the hue of saturated colors
+  #[default]
+  Clamp,
+  /// Pull the color towards its gray of equal luma until it fits, preserving the hue
+  Scale,
+}
+
+/// Parse a gamut mapping
+/// Supports: "clamp", "scale"
+pub fn parse_gamut_mapping(spec: &str) -> anyhow::Result<GamutMapping> {
+  match spec {
+    "clamp" => Ok(GamutMapping::Clamp),
+    "scale" => Ok(GamutMapping::Scale),
+    _ => anyhow::bail!(
+      "Gamut mapping must be one of \"clamp\" or \"scale\" (got: {})",
+      spec
+    ),
+  }
+}
+
 /// Result of color unmixing: weights for each foreground color and overall alpha
 pub struct UnmixResult {
   /// Weight for each foreground color (sums to 1.0 or less)
   pub weights: Vec<f64>,
   /// Overall alpha value (0.0 = fully transparent, 1.0 = fully opaque)
   pub alpha: f64,
+  /// Distance (normalized RGB) between the observed color and the color the weights
+  /// reconstruct over the background; large values mean the foreground colors don't explain
+  /// the observed color
+  pub reconstruction_error: f64,
 }
 
 /// Unmix an observed color into foreground components
@@ -26,7 +72,175 @@ pub fn unmix_colors(
   foreground_colors: &[NormalizedColor],
   background: NormalizedColor,
 ) -> UnmixResult {
-  unmix_colors_internal(observed, foreground_colors, background, true)
+  unmix_colors_with_tolerance(
+    observed,
+    foreground_colors,
+    background,
+    DEFAULT_RECONSTRUCTION_TOLERANCE,
+  )
+}
+
+/// Unmix an observed color, accepting higher-opacity solutions within `reconstruction_tolerance`
+///
+/// With several foreground colors, an opaque mix is preferred over the closest, translucent
+/// reconstruction when it reconstructs the observed color to within
+/// `reconstruction_tolerance` (Euclidean distance in normalized RGB). Noisy or
+/// lossy-compressed inputs rarely reconstruct exactly, so raising the tolerance lets them
+/// keep their opaque, clean colors. The trade-off is that the output color may drift from
+/// the input by up to the tolerance, and subtle shades get snapped to the palette.
+pub fn unmix_colors_with_tolerance(
+  observed: Color,
+  foreground_colors: &[NormalizedColor],
+  background: NormalizedColor,
+  reconstruction_tolerance: f64,
+) -> UnmixResult {
+  unmix_normalized_with_tolerance(
+    normalize_color(observed),
+    foreground_colors,
+    background,
+    reconstruction_tolerance,
+  )
+}
+
+/// Unmix a normalized observed color (see `unmix_colors_with_tolerance`)
+///
+/// Keeps the full precision of inputs with more than 8 bits per channel.
+pub fn unmix_normalized_with_tolerance(
+  observed: NormalizedColor,
+  foreground_colors: &[NormalizedColor],
+  background: NormalizedColor,
+  reconstruction_tolerance: f64,
+) -> UnmixResult {
+  unmix_normalized_internal(
+    observed,
+    foreground_colors,
+    background,
+    true,
+    reconstruction_tolerance,
+  )
+}
+
+/// The combinations of foreground colors a pixel is unmixed into when the number of colors
+/// per pixel is limited, enumerated once per image
+pub struct ColorCombinations {
+  /// The number of colors in each combination
+  size: usize,
+  /// The color indices of every combination, one combination after another
+  indices: Vec<usize>,
+}
+
+impl ColorCombinations {
+  /// Enumerate every combination of `max_colors` out of `color_count` foreground colors
+  ///
+  /// Returns `None` if `max_colors` is at least `color_count`, since all colors are then
+  /// unmixed at once. A limit of 0, or one that gives more than `MAX_UNMIX_COMBINATIONS`
+  /// combinations, is rejected.
+  pub fn new(color_count: usize, max_colors: usize) -> anyhow::Result<Option<Self>> {
+    if max_colors == 0 {
+      anyhow::bail!("Unmix search depth must be at least 1");
+    }
+    if max_colors >= color_count {
+      return Ok(None);
+    }
+
+    let count = (0..max_colors.min(color_count - max_colors)).fold(1usize, |count, i| {
+      count.saturating_mul(color_count - i) / (i + 1)
+    });
+    if count > MAX_UNMIX_COMBINATIONS {
+      anyhow::bail!(
+        "Unmix search depth {} gives {} combinations of the {} foreground colors, more than \
+         the limit of {}",
+        max_colors,
+        count,
+        color_count,
+        MAX_UNMIX_COMBINATIONS
+      );
+    }
+
+    let mut indices = Vec::with_capacity(count * max_colors);
+    let mut combination: Vec<usize> = (0..max_colors).collect();
+    loop {
+      indices.extend_from_slice(&combination);
+      // Advance the last index that can still move, and reset the ones after it
+      let Some(i) = (0..max_colors)
+        .rev()
+        .find(|&i| combination[i] < color_count - max_colors + i)
+      else {
+        break;
+      };
+      combination[i] += 1;
+      for j in i + 1..max_colors {
+        combination[j] = combination[j - 1] + 1;
+      }
+    }
+
+    Ok(Some(ColorCombinations {
+      size: max_colors,
+      indices,
+    }))
+  }
+}
+
+/// Unmix a normalized observed color into one combination of the foreground colors
+///
+/// Every combination (see `ColorCombinations`) is unmixed (see
+/// `unmix_normalized_with_tolerance`) and the best result kept: the closest opaque mix within
+/// `reconstruction_tolerance` if there is one, otherwise the closest reconstruction, the most
+/// opaque one on ties. Without combinations, all colors are unmixed at once.
+pub fn unmix_normalized_with_depth(
+  observed: NormalizedColor,
+  foreground_colors: &[NormalizedColor],
+  background: NormalizedColor,
+  reconstruction_tolerance: f64,
+  combinations: Option<&ColorCombinations>,
+) -> UnmixResult {
+  let unmix_all = || {
+    unmix_normalized_with_tolerance(
+      observed,
+      foreground_colors,
+      background,
+      reconstruction_tolerance,
+    )
+  };
+  let Some(combinations) = combinations else {
+    return unmix_all();
+  };
+
+  let fits = |result: &UnmixResult| {
+    result.alpha >= 1.0 - ALPHA_PRECISION && result.reconstruction_error < reconstruction_tolerance
+  };
+  let mut subset = Vec::with_capacity(combinations.size);
+  let mut best: Option<(UnmixResult, &[usize])> = None;
+  for indices in combinations.indices.chunks_exact(combinations.size) {
+    subset.clear();
+    subset.extend(indices.iter().map(|&i| foreground_colors[i]));
+    let candidate =
+      unmix_normalized_with_tolerance(observed, &subset, background, reconstruction_tolerance);
+
+    let better = match &best {
+      None => true,
+      Some((best, _)) => match (fits(&candidate), fits(best)) {
+        (true, false) => true,
+        (false, true) => false,
+        _ if (candidate.reconstruction_error - best.reconstruction_error).abs() <= ERROR_SLACK => {
+          candidate.alpha > best.alpha
+        }
+        _ => candidate.reconstruction_error < best.reconstruction_error,
+      },
+    };
+    if better {
+      best = Some((candidate, indices));
+    }
+  }
+
+  let Some((best, indices)) = best else {
+    return unmix_all();
+  };
+  let mut weights = vec![0.0; foreground_colors.len()];
+  for (k, &i) in indices.iter().enumerate() {
+    weights[i] = best.weights[k];
+  }
+  UnmixResult { weights, ..best }
 }
 
 /// Internal unmix function with opacity optimization control
@@ -35,22 +249,42 @@ pub(crate) fn unmix_colors_internal(
   foreground_colors: &[NormalizedColor],
   background: NormalizedColor,
   optimize_opacity: bool,
+  reconstruction_tolerance: f64,
 ) -> UnmixResult {
-  let observed = Vector3::new(
-    observed[0] as f64 / 255.0,
-    observed[1] as f64 / 255.0,
-    observed[2] as f64 / 255.0,
-  );
+  unmix_normalized_internal(
+    normalize_color(observed),
+    foreground_colors,
+    background,
+    optimize_opacity,
+    reconstruction_tolerance,
+  )
+}
+
+/// Internal unmix function on a normalized observed color
+fn unmix_normalized_internal(
+  observed: NormalizedColor,
+  foreground_colors: &[NormalizedColor],
+  background: NormalizedColor,
+  optimize_opacity: bool,
+  reconstruction_tolerance: f64,
+) -> UnmixResult {
+  let observed = Vector3::from(observed);
 
   match foreground_colors.len() {
     0 => UnmixResult {
       weights: vec![],
       alpha: 0.0,
+      reconstruction_error: (observed - Vector3::from(background)).norm(),
     },
     1 => unmix_single_color(observed, foreground_colors[0], background),
     _ => {
       if optimize_opacity {
-        unmix_multiple_colors_optimized(observed, foreground_colors, background)
+        unmix_multiple_colors_optimized(
+          observed,
+          foreground_colors,
+          background,
+          reconstruction_tolerance,
+        )
       } else {
         unmix_multiple_colors_simple(observed, foreground_colors, background)
       }
@@ -83,6 +317,7 @@ fn unmix_single_color(
   UnmixResult {
     weights: vec![weight],
     alpha: weight,
+    reconstruction_error: (weight * fg + (1.0 - weight) * bg - observed).norm(),
   }
 }
 
@@ -94,15 +329,17 @@ fn unmix_multiple_colors_simple(
 ) -> UnmixResult {
   let n = foreground_colors.len();
 
-  // Build matrix where columns are (fg_i - bg)
-  let mut matrix_data = Vec::with_capacity(3 * n);
-  for fg in foreground_colors {
-    matrix_data.push(fg[0] - background[0]);
-    matrix_data.push(fg[1] - background[1]);
-    matrix_data.push(fg[2] - background[2]);
+  // Build matrix where columns are (fg_i - bg), skipping redundant colors
+  let active = independent_foreground_indices(foreground_colors, background);
+  if active.is_empty() {
+    return UnmixResult {
+      weights: vec![0.0; n],
+      alpha: 0.0,
+      reconstruction_error: (observed - Vector3::from(background)).norm(),
+    };
   }
 
-  let a = DMatrix::from_column_slice(3, n, &matrix_data);
+  let a = difference_matrix(foreground_colors, &active, background);
   let b = observed - Vector3::from_row_slice(&background);
   let b_vec = DVector::from_column_slice(&[b[0], b[1], b[2]]);
 
@@ -110,7 +347,11 @@ fn unmix_multiple_colors_simple(
   let weights = match a.pseudo_inverse(EPSILON) {
     Ok(a_inv) => {
       let solution = a_inv * b_vec;
-      solution.iter().map(|&w| w.max(0.0)).collect()
+      let mut weights = vec![0.0; n];
+      for (k, &i) in active.iter().enumerate() {
+        weights[i] = solution[k].max(0.0);
+      }
+      weights
     }
     Err(_) => {
       // Fallback: use only first color
@@ -131,161 +372,314 @@ fn unmix_multiple_colors_simple(
   };
 
   UnmixResult {
+    reconstruction_error: reconstruction_error(
+      observed,
+      &final_weights,
+      foreground_colors,
+      background,
+    ),
     weights: final_weights,
     alpha,
   }
 }
 
-/// Unmix when there are multiple foreground colors using least squares
+/// Unmix when there are multiple foreground colors
 /// Optimizes for maximum opacity while maintaining color accuracy.
 ///
-/// This function tries multiple approaches to find the solution with maximum
-/// opacity that still accurately reconstructs the observed color:
-/// 1. Standard least squares (all colors)
-/// 2. Single colors (maximum possible opacity)
-/// 3. Pairs of colors (compromise between opacity and flexibility)
-///
-/// All solutions are verified to ensure they reconstruct the original color
-/// within a small error threshold.
+/// The weights are found with a non-negative least squares solver under the constraint
+/// that they sum to at most 1, considering any combination of the foreground colors. The
+/// closest opaque mix is preferred when it reconstructs the observed color to within
+/// `reconstruction_tolerance`; otherwise the most opaque of the closest mixes is used.
 fn unmix_multiple_colors_optimized(
   observed: Vector3<f64>,
   foreground_colors: &[NormalizedColor],
   background: NormalizedColor,
+  reconstruction_tolerance: f64,
 ) -> UnmixResult {
   let n = foreground_colors.len();
   let bg = Vector3::from_row_slice(&background);
   let target = observed - bg;
 
-  // Try different approaches to find the one with maximum opacity
-  let mut best_weights = vec![0.0; n];
-  let mut best_alpha = 0.0;
-
-  // Approach 1: Standard least squares solution
-  let mut matrix_data = Vec::with_capacity(3 * n);
-  for fg in foreground_colors {
-    matrix_data.push(fg[0] - background[0]);
-    matrix_data.push(fg[1] - background[1]);
-    matrix_data.push(fg[2] - background[2]);
+  // Colors equal to the background could take any weight without changing the result
+  let active: Vec<usize> = (0..n)
+    .filter(|&i| (Vector3::from_row_slice(&foreground_colors[i]) - bg).norm() > EPSILON)
+    .collect();
+  if active.is_empty() {
+    return UnmixResult {
+      weights: vec![0.0; n],
+      alpha: 0.0,
+      reconstruction_error: target.norm(),
+    };
   }
-
-  let a = DMatrix::from_column_slice(3, n, &matrix_data);
-  let b_vec = DVector::from_column_slice(&[target[0], target[1], target[2]]);
-
-  if let Ok(a_inv) = a.pseudo_inverse(EPSILON) {
-    let solution = a_inv * b_vec.clone();
-    let weights: Vec<f64> = solution.iter().map(|&w| w.max(0.0)).collect();
-    let sum: f64 = weights.iter().sum();
-
-    if sum > 0.0 {
-      let alpha = sum.min(1.0);
-      if alpha > best_alpha {
-        best_weights = if sum > 1.0 {
-          weights.iter().map(|w| w / sum).collect()
-        } else {
-          weights
-        };
-        best_alpha = alpha;
+  let a = difference_matrix(foreground_colors, &active, background);
+  let k = active.len();
+
+  // The sum constraint is enforced by a heavily weighted extra row. A slack column makes
+  // it an inequality for the closest reconstruction.
+  let mut constrained = DMatrix::zeros(4, k + 1);
+  constrained.view_mut((0, 1), (3, k)).copy_from(&a);
+  constrained.row_mut(3).fill(SUM_CONSTRAINT_WEIGHT);
+  let rhs = |alpha: f64| {
+    DVector::from_column_slice(&[
+      target[0],
+      target[1],
+      target[2],
+      SUM_CONSTRAINT_WEIGHT * alpha,
+    ])
+  };
+  let b = DVector::from_column_slice(target.as_slice());
+  let error = |weights: &DVector<f64>| (&a * weights - &b).norm();
+
+  let gram = constrained.tr_mul(&constrained);
+  let closest = nnls(&gram, &constrained.tr_mul(&rhs(1.0)))
+    .rows(1, k)
+    .into_owned();
+  let closest_alpha = closest.sum().min(1.0);
+  let closest_error = error(&closest);
+
+  let equality = constrained.columns(1, k);
+  let equality_gram = gram.view((1, 1), (k, k)).into_owned();
+  let solve = |alpha: f64| nnls(&equality_gram, &equality.tr_mul(&rhs(alpha)));
+
+  let mut best = closest;
+  let opaque = (closest_alpha < 1.0).then(|| solve(1.0));
+  match opaque {
+    Some(opaque) if error(&opaque) < reconstruction_tolerance => best = opaque,
+    _ => {
+      // The closest reconstruction is unique, but with more colors than channels several
+      // mixes give it: trade colors for the most opaque one, solving again whenever a
+      // weight reaches zero in case other colors allow going further
+      let mut alpha = closest_alpha;
+      for _ in 0..=2 * k {
+        let next = (alpha + opacity_headroom(&a, &equality_gram, &best)).min(1.0);
+        if next - alpha < ALPHA_PRECISION {
+          break;
+        }
+        let weights = solve(next);
+        if error(&weights) > closest_error + ERROR_SLACK {
+          break;
+        }
+        best = weights;
+        alpha = next;
       }
     }
   }
 
-  // Approach 2: Try each color individually to see if any single color achieves higher opacity
-  for (i, fg) in foreground_colors.iter().enumerate() {
-    let fg_vec = Vector3::from_row_slice(fg);
-    let fg_minus_bg = fg_vec - bg;
+  let sum = best.sum();
+  let mut weights = vec![0.0; n];
+  for (j, &i) in active.iter().enumerate() {
+    weights[i] = if sum > 1.0 { best[j] / sum } else { best[j] };
+  }
 
-    if fg_minus_bg.norm() > EPSILON {
-      let dot = target.dot(&fg_minus_bg);
-      let norm_sq = fg_minus_bg.dot(&fg_minus_bg);
-      let weight = (dot / norm_sq).clamp(0.0, 1.0);
+  UnmixResult {
+    reconstruction_error: reconstruction_error(observed, &weights, foreground_colors, background),
+    weights,
+    alpha: sum.min(1.0),
+  }
+}
 
-      // Verify the reconstructed color is close to the observed color
-      let reconstructed = weight * fg_vec + (1.0 - weight) * bg;
-      let error = (reconstructed - observed).norm();
+/// Distance between the observed color and the color `weights` reconstruct over the background
+fn reconstruction_error(
+  observed: Vector3<f64>,
+  weights: &[f64],
+  foreground_colors: &[NormalizedColor],
+  background: NormalizedColor,
+) -> f64 {
+  let bg = Vector3::from(background);
+  let reconstructed = weights
+    .iter()
+    .zip(foreground_colors)
+    .fold(bg, |color, (weight, fg)| {
+      color + *weight * (Vector3::from(*fg) - bg)
+    });
+  (reconstructed - observed).norm()
+}
 
-      // Only accept if the reconstruction error is small
-      if weight > best_alpha && error < 0.01 {
-        best_weights = vec![0.0; n];
-        best_weights[i] = weight;
-        best_alpha = weight;
-      }
-    }
+/// How far the opacity of `weights` can rise on the colors they use without changing the
+/// reconstructed color, before one of the weights reaches zero
+///
+/// On a fixed set of colors, the weights move linearly with the opacity; the reconstruction
+/// only stays the same if that move is invisible, which takes more colors than channels.
+fn opacity_headroom(a: &DMatrix<f64>, gram: &DMatrix<f64>, weights: &DVector<f64>) -> f64 {
+  let support: Vec<usize> = (0..weights.len()).filter(|&j| weights[j] > 0.0).collect();
+  if support.is_empty() {
+    return 0.0;
   }
+  let direction = solve_normal_equations(
+    gram.select_rows(&support).select_columns(&support),
+    DVector::from_element(support.len(), SUM_CONSTRAINT_WEIGHT.powi(2)),
+  );
+  if (a.select_columns(&support) * &direction).norm() > RANK_EPSILON {
+    return 0.0;
+  }
+
+  support
+    .iter()
+    .enumerate()
+    .filter(|&(k, _)| direction[k] < 0.0)
+    .map(|(k, &j)| weights[j] / -direction[k])
+    .fold(f64::INFINITY, f64::min)
+}
+
+/// Solve `min ||a x - b||` subject to `x >= 0` (Lawson–Hanson active set method)
+///
+/// Takes the normal equations of the problem, `gram = aᵀa` and `atb = aᵀb`, so that systems
+/// sharing a matrix only compute its Gram matrix once.
+fn nnls(gram: &DMatrix<f64>, atb: &DVector<f64>) -> DVector<f64> {
+  let n = gram.ncols();
+  let tolerance = EPSILON * gram.amax().max(1.0);
+  let mut x = DVector::zeros(n);
+  let mut passive = vec![false; n];
+
+  // Each outer iteration adds a variable; the cap only guards against cycling
+  for _ in 0..3 * n {
+    let gradient = atb - gram * &x;
+    let next = (0..n)
+      .filter(|&j| !passive[j] && gradient[j] > tolerance)
+      .max_by(|&i, &j| gradient[i].total_cmp(&gradient[j]));
+    let Some(next) = next else {
+      break;
+    };
+    passive[next] = true;
+
+    loop {
+      let indices: Vec<usize> = (0..n).filter(|&j| passive[j]).collect();
+      let z = solve_normal_equations(
+        gram.select_rows(&indices).select_columns(&indices),
+        atb.select_rows(&indices),
+      );
+
+      if z.iter().all(|&v| v > EPSILON) {
+        x.fill(0.0);
+        for (k, &j) in indices.iter().enumerate() {
+          x[j] = z[k];
+        }
+        break;
+      }
 
-  // Approach 3: Try pairs of colors for better opacity
-  if n >= 2 && best_alpha < 0.99 {
-    for i in 0..n {
-      for j in (i + 1)..n {
-        // Build 3x2 matrix for this pair
-        let fg_i = foreground_colors[i];
-        let fg_j = foreground_colors[j];
-        let pair_matrix = DMatrix::from_column_slice(
-          3,
-          2,
-          &[
-            fg_i[0] - background[0],
-            fg_j[0] - background[0],
-            fg_i[1] - background[1],
-            fg_j[1] - background[1],
-            fg_i[2] - background[2],
-            fg_j[2] - background[2],
-          ],
-        );
-
-        if let Ok(pair_inv) = pair_matrix.pseudo_inverse(EPSILON) {
-          let pair_solution = pair_inv * b_vec.clone();
-          let w_i = pair_solution[0].max(0.0);
-          let w_j = pair_solution[1].max(0.0);
-          let sum = w_i + w_j;
-
-          if sum > 0.0 {
-            let alpha = sum.min(1.0);
-
-            // Verify the reconstruction is accurate
-            let normalized_wi = if sum > 1.0 { w_i / sum } else { w_i };
-            let normalized_wj = if sum > 1.0 { w_j / sum } else { w_j };
-            let reconstructed = normalized_wi * Vector3::from_row_slice(&fg_i)
-              + normalized_wj * Vector3::from_row_slice(&fg_j)
-              + (1.0 - normalized_wi - normalized_wj) * bg;
-            let error = (reconstructed - observed).norm();
-
-            // Only accept if reconstruction is accurate AND alpha is better
-            if alpha > best_alpha && error < 0.01 {
-              best_weights = vec![0.0; n];
-              if sum > 1.0 {
-                best_weights[i] = w_i / sum;
-                best_weights[j] = w_j / sum;
-                best_alpha = 1.0;
-              } else {
-                best_weights[i] = w_i;
-                best_weights[j] = w_j;
-                best_alpha = alpha;
-              }
-            }
-          }
+      // Move towards the unconstrained solution until a variable hits zero, then drop it
+      let step = indices
+        .iter()
+        .enumerate()
+        .filter(|&(k, _)| z[k] <= EPSILON)
+        .map(|(k, &j)| x[j] / (x[j] - z[k]).max(EPSILON))
+        .fold(1.0, f64::min);
+      for (k, &j) in indices.iter().enumerate() {
+        x[j] += step * (z[k] - x[j]);
+        if x[j] <= EPSILON {
+          x[j] = 0.0;
+          passive[j] = false;
         }
       }
+      if !passive.contains(&true) {
+        break;
+      }
     }
   }
 
-  UnmixResult {
-    weights: best_weights,
-    alpha: best_alpha,
+  x
+}
+
+/// Solve the normal equations `gram x = atb`, taking the minimum-norm solution if they are
+/// singular
+fn solve_normal_equations(gram: DMatrix<f64>, atb: DVector<f64>) -> DVector<f64> {
+  match gram.clone().cholesky() {
+    Some(cholesky) => cholesky.solve(&atb),
+    None => gram
+      .svd(true, true)
+      .solve(&atb, EPSILON)
+      .unwrap_or_else(|_| DVector::zeros(atb.len())),
+  }
+}
+
+/// Build the unmixing matrix whose columns are `fg_i - bg` for the given color indices
+fn difference_matrix(
+  foreground_colors: &[NormalizedColor],
+  indices: &[usize],
+  background: NormalizedColor,
+) -> DMatrix<f64> {
+  let mut matrix_data = Vec::with_capacity(3 * indices.len());
+  for &i in indices {
+    let fg = foreground_colors[i];
+    matrix_data.push(fg[0] - background[0]);
+    matrix_data.push(fg[1] - background[1]);
+    matrix_data.push(fg[2] - background[2]);
   }
+
+  DMatrix::from_column_slice(3, indices.len(), &matrix_data)
 }
 
-/// Calculate the Euclidean distance between two colors in RGB space
-fn color_distance(color1: Vector3<f64>, color2: Vector3<f64>) -> f64 {
-  (color1 - color2).norm()
+/// The foreground colors `unmix_colors_internal` leaves out of its solution
+///
+/// Only the least-squares unmixing (`optimize_opacity` off) with more than one foreground
+/// color drops colors, namely those `independent_foreground_indices` doesn't keep. Their
+/// weights are always 0.
+///
+/// # Returns
+/// The indices of the dropped colors, in palette order
+pub(crate) fn dropped_foreground_indices(
+  foreground_colors: &[NormalizedColor],
+  background: NormalizedColor,
+  optimize_opacity: bool,
+) -> Vec<usize> {
+  if optimize_opacity || foreground_colors.len() < 2 {
+    return Vec::new();
+  }
+
+  let kept = independent_foreground_indices(foreground_colors, background);
+  (0..foreground_colors.len())
+    .filter(|i| !kept.contains(i))
+    .collect()
+}
+
+/// Select the foreground colors to use in the least-squares unmixing system
+///
+/// If the `fg - bg` directions of the palette span fewer dimensions than they could
+/// (e.g. a color and a darker shade of it over black), the system is rank-deficient and
+/// the pseudo-inverse picks one of infinitely many solutions, which varies between
+/// neighbouring pixels. In that case colors are kept greedily in palette order as long as
+/// they add a new direction. Palettes of more than 3 colors that span the full RGB space
+/// are left as is.
+///
+/// # Returns
+/// The indices of the colors to keep
+fn independent_foreground_indices(
+  foreground_colors: &[NormalizedColor],
+  background: NormalizedColor,
+) -> Vec<usize> {
+  let n = foreground_colors.len();
+  let all: Vec<usize> = (0..n).collect();
+
+  if n == 0 {
+    return all;
+  }
+
+  if difference_matrix(foreground_colors, &all, background).rank(RANK_EPSILON) >= n.min(3) {
+    return all;
+  }
+
+  let mut kept: Vec<usize> = Vec::new();
+  for i in 0..n {
+    kept.push(i);
+    if difference_matrix(foreground_colors, &kept, background).rank(RANK_EPSILON) < kept.len() {
+      kept.pop();
+    }
+  }
+
+  kept
 }
 
 /// Check if an observed color is "close enough" to any foreground color when unmixed
 /// Returns true if the color can be primarily represented by one of the foreground colors
+///
+/// `distance` measures how far a reconstruction is from the observed color (see
+/// `ThresholdSpace::distance`).
 pub fn is_color_close_to_foreground(
   observed: Vector3<f64>,
   foreground_colors: &[NormalizedColor],
   background: NormalizedColor,
   threshold: f64,
+  distance: impl Fn(NormalizedColor, NormalizedColor) -> f64,
 ) -> bool {
   // Try unmixing with each individual foreground color
   for fg in foreground_colors {
@@ -304,7 +698,7 @@ pub fn is_color_close_to_foreground(
       let reconstructed = weight * fg_vec + (1.0 - weight) * bg_vec;
 
       // Check if the reconstruction is close to the observed color
-      if color_distance(reconstructed, observed) < threshold {
+      if distance(reconstructed.into(), observed.into()) < threshold {
         return true;
       }
     }
@@ -317,6 +711,15 @@ pub fn is_color_close_to_foreground(
 pub fn compute_result_color(
   unmix_result: &UnmixResult,
   foreground_colors: &[NormalizedColor],
+) -> (NormalizedColor, f64) {
+  compute_result_color_with_gamut(unmix_result, foreground_colors, GamutMapping::Clamp)
+}
+
+/// Compute the final color from unmixing results, mapping it into gamut as requested
+pub fn compute_result_color_with_gamut(
+  unmix_result: &UnmixResult,
+  foreground_colors: &[NormalizedColor],
+  gamut_mapping: GamutMapping,
 ) -> (NormalizedColor, f64) {
   if unmix_result.alpha == 0.0 {
     return ([0.0, 0.0, 0.0], 0.0);
@@ -340,5 +743,30 @@ pub fn compute_result_color(
     result[2] /= sum_weights;
   }
 
+  let result = match gamut_mapping {
+    GamutMapping::Clamp => result.map(|c| c.clamp(0.0, 1.0)),
+    GamutMapping::Scale => scale_into_gamut(result),
+  };
+
   (result, unmix_result.alpha)
 }
+
+/// Move a color towards the gray of equal luma just enough to fit in [0, 1]
+///
+/// Every channel keeps its offset from the gray in the same proportion, so the hue is
+/// preserved and only the saturation is reduced.
+fn scale_into_gamut(color: NormalizedColor) -> NormalizedColor {
+  let luma = (0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]).clamp(0.0, 1.0);
+
+  let mut factor: f64 = 1.0;
+  for &c in &color {
+    let offset = c - luma;
+    if c > 1.0 {
+      factor = factor.min((1.0 - luma) / offset);
+    } else if c < 0.0 {
+      factor = factor.min(-luma / offset);
+    }
+  }
+
+  color.map(|c| (luma + (c - luma) * factor).clamp(0.0, 1.0))
+}